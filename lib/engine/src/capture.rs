@@ -0,0 +1,144 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::renderer::{PixelFormat, PixelRegion, Renderer};
+
+/// Saves frames drawn to the main window as png files, either a single
+/// screenshot or a numbered sequence of frames that can be made into a video
+#[derive(Debug)]
+pub struct FrameCapture {
+    /// The directory that screenshots are saved in
+    pub screenshot_dir: PathBuf,
+
+    /// Whether to save a screenshot at the end of the current frame
+    screenshot: bool,
+
+    sequence: Option<FrameSequence>,
+
+    /// The error from the last frame that could not be saved
+    error: Option<String>,
+}
+
+/// A sequence of frames being captured
+#[derive(Debug)]
+struct FrameSequence {
+    dir: PathBuf,
+
+    /// The time between each frame in seconds
+    timestep: f32,
+
+    /// The number of the next frame to be saved
+    frame: u32,
+}
+
+impl Default for FrameCapture {
+    fn default() -> Self {
+        Self {
+            screenshot_dir: PathBuf::from("screenshots"),
+            screenshot: false,
+            sequence: None,
+            error: None,
+        }
+    }
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save the current frame when it has finished rendering, it is named
+    /// using the time it was taken
+    pub fn screenshot(&mut self) {
+        self.screenshot = true;
+        self.error = None;
+    }
+
+    /// Start saving every frame into a directory, named `frame_00000.png`,
+    /// `frame_00001.png`, etc.  While capturing, each frame advances the game
+    /// by exactly `1 / fps` seconds, however long it took to render, so the
+    /// frames play back at the correct speed.
+    pub fn start_sequence(&mut self, dir: impl Into<PathBuf>, fps: u32) {
+        debug_assert!(fps > 0);
+
+        self.sequence = Some(FrameSequence {
+            dir: dir.into(),
+            timestep: 1.0 / fps as f32,
+            frame: 0,
+        });
+        self.error = None;
+    }
+
+    /// Stop capturing a frame sequence, returns the number of frames saved
+    pub fn stop_sequence(&mut self) -> Option<u32> {
+        self.sequence.take().map(|sequence| sequence.frame)
+    }
+
+    /// Is a frame sequence currently being captured
+    pub fn is_capturing_sequence(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Why the last screenshot or sequence frame could not be saved, cleared
+    /// when a new screenshot or sequence is started
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// The time each frame should advance the game by, if it is fixed
+    pub(crate) fn timestep(&self) -> Option<f32> {
+        self.sequence.as_ref().map(|sequence| sequence.timestep)
+    }
+
+    /// Save the frame that has just been rendered, if requested, must be
+    /// called before the window is swapped and with the window bound as the
+    /// render target.  A frame that cannot be saved does not stop the game,
+    /// the error is printed and kept to be shown, see [`FrameCapture::error`]
+    pub(crate) fn capture(&mut self, renderer: &mut Renderer, size: (u32, u32)) {
+        if let Err(e) = self.save(renderer, size) {
+            let error = format!("Unable to save captured frame: {}", e);
+            eprintln!("{}", error);
+            self.error = Some(error);
+        }
+    }
+
+    fn save(&mut self, renderer: &mut Renderer, size: (u32, u32)) -> Result<()> {
+        if !self.screenshot && self.sequence.is_none() {
+            return Ok(());
+        }
+
+        let pixels =
+            renderer.read_pixels(PixelRegion::new(0, 0, size.0, size.1), PixelFormat::Rgba8)?;
+
+        if self.screenshot {
+            self.screenshot = false;
+
+            let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let name = format!(
+                "screenshot-{}-{:03}.png",
+                time.as_secs(),
+                time.subsec_millis()
+            );
+
+            fs::create_dir_all(&self.screenshot_dir)?;
+            pixels.save_png(self.screenshot_dir.join(name))?;
+        }
+
+        if let Some(sequence) = &mut self.sequence {
+            fs::create_dir_all(&sequence.dir)?;
+            pixels.save_png(
+                sequence
+                    .dir
+                    .join(format!("frame_{:05}.png", sequence.frame)),
+            )?;
+            sequence.frame += 1;
+        }
+
+        Ok(())
+    }
+}
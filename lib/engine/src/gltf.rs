@@ -15,7 +15,7 @@ use serde_json::Value;
 use serde_repr::Deserialize_repr;
 use thiserror::Error;
 
-use crate::renderer::{shader::Type, IndexType};
+use crate::renderer::{shader::Type, IndexType, VertexComponentType};
 use crate::resources::{Error as ResourceError, Resources};
 
 #[derive(Debug, Error)]
@@ -307,6 +307,20 @@ impl ComponentType {
             Float => 4,
         }
     }
+
+    /// convert a type to the renderer vertex component type
+    pub fn vertex_component(&self) -> VertexComponentType {
+        use ComponentType::*;
+
+        match self {
+            Byte => VertexComponentType::I8,
+            UnsignedByte => VertexComponentType::U8,
+            Short => VertexComponentType::I16,
+            UnsignedShort => VertexComponentType::U16,
+            UnsignedInt => VertexComponentType::U32,
+            Float => VertexComponentType::F32,
+        }
+    }
 }
 
 /// How the elements of an accessor are layed out
@@ -350,6 +364,18 @@ impl AccessorType {
             AccessorType::Mat4 => Type::Mat4,
         }
     }
+
+    /// The shader type of an accessor holding unsigned integers that are not
+    /// normalized, e.g. joint indices.  Matrices are read as floats.
+    pub fn to_unsigned_shader_type(&self) -> Type {
+        match self {
+            AccessorType::Scalar => Type::UnsignedInteger,
+            AccessorType::Vec2 => Type::UVec2,
+            AccessorType::Vec3 => Type::UVec3,
+            AccessorType::Vec4 => Type::UVec4,
+            ty => ty.to_shader_type(),
+        }
+    }
 }
 
 /// Specifies how a sparse buffer view is accessed, used in Accessors
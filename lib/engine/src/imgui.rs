@@ -3,7 +3,8 @@ use std::{marker::PhantomData, time::Instant};
 use crate::{
     renderer::{
         shader::{BuiltinVariable, Expression, Program, Type},
-        PipelineId, TextureId,
+        BlendMode, CullingMode, DepthTesting, Owned, PipelineDescriptor, PipelineId, PipelineState,
+        StencilTesting, TextureId, VertexComponentType, VertexFormat,
     },
     texture::{Texture, TextureOptions},
     window::{
@@ -14,6 +15,7 @@ use crate::{
     CallOrder, EngineStateRef, EventResult, Layer,
 };
 use anyhow::Result;
+use imgui::im_str;
 
 pub struct ImguiLayer<T: Layer> {
     context: imgui::Context,
@@ -21,6 +23,10 @@ pub struct ImguiLayer<T: Layer> {
     renderer: imgui_opengl_renderer::Renderer,
     current_cursor: SystemCursors,
 
+    /// The error from the last failed chrome trace export, shown in the
+    /// profiler window
+    trace_error: Option<String>,
+
     _new_renderer: ImguiRenderer,
 
     _child: PhantomData<T>,
@@ -72,6 +78,7 @@ impl<T: Layer + 'static> Layer for ImguiLayer<T> {
             frame_time,
             renderer,
             current_cursor: SystemCursors::Arrow,
+            trace_error: None,
             _new_renderer: new_renderer,
             _child: PhantomData::default(),
         })
@@ -204,6 +211,7 @@ impl<T: Layer + 'static> Layer for ImguiLayer<T> {
         let ui = self.context.frame();
 
         ui.show_demo_window(&mut true);
+        profiler_window(&ui, state, &mut self.trace_error);
 
         if !ui
             .io()
@@ -233,6 +241,9 @@ impl<T: Layer + 'static> Layer for ImguiLayer<T> {
 
         self.renderer.render(ui);
 
+        // imgui's renderer changes gl state without the engine knowing
+        state.invalidate_state_cache();
+
         Ok(())
     }
 
@@ -247,6 +258,62 @@ impl<T: Layer + 'static> Layer for ImguiLayer<T> {
     }
 }
 
+/// Show the timings of the latest profiled frame
+fn profiler_window(ui: &imgui::Ui, state: &EngineStateRef, trace_error: &mut Option<String>) {
+    let profiler = &state.profiler;
+
+    imgui::Window::new(im_str!("Profiler")).build(ui, || {
+        let frame_times: Vec<_> = profiler
+            .history()
+            .map(|frame| frame.duration.as_secs_f32() * 1000.0)
+            .collect();
+        ui.plot_lines(im_str!("Frame time (ms)"), &frame_times)
+            .graph_size([0.0, 60.0])
+            .build();
+
+        ui.text(state.frame_stats.to_string());
+
+        if ui.button(im_str!("Export Chrome trace"), [0.0, 0.0]) {
+            *trace_error = profiler
+                .write_chrome_trace("trace.json")
+                .err()
+                .map(|e| format!("Unable to export trace: {}", e));
+        }
+
+        if let Some(error) = trace_error {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], error.as_str());
+        }
+
+        if let Some(error) = state.capture.error() {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+        }
+
+        let frame = match profiler.latest() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        ui.separator();
+        ui.text(format!("Frame {}", frame.frame));
+
+        let scopes = [("CPU", Some(&frame.cpu)), ("GPU", frame.gpu.as_ref())];
+        for (label, scopes) in scopes.iter() {
+            ui.separator();
+            ui.text(label);
+
+            for scope in scopes.iter().copied().flatten() {
+                ui.text(format!(
+                    "{:indent$}{}: {:.3} ms",
+                    "",
+                    scope.name,
+                    scope.duration.as_secs_f64() * 1000.0,
+                    indent = scope.depth * 2
+                ));
+            }
+        }
+    });
+}
+
 struct ImguiClipboard(Box<dyn Clipboard>);
 
 impl imgui::ClipboardBackend for ImguiClipboard {
@@ -261,11 +328,11 @@ impl imgui::ClipboardBackend for ImguiClipboard {
 
 struct ImguiRenderer {
     /// The imgui rendering pipeline, is always the same shader
-    _program: PipelineId,
+    _program: Owned<PipelineId>,
 
     /// The currently avaliable textures for imgui, if imgui needs a texture,
     /// then it needs to be in this vec.  Item 0 is the font atlas
-    _textures: Vec<TextureId>,
+    _textures: Vec<Owned<TextureId>>,
 }
 
 impl ImguiRenderer {
@@ -298,7 +365,21 @@ impl ImguiRenderer {
         });
         program.ok()?;
 
-        let program = state.load_pipeline(program)?;
+        let pipeline_state = PipelineState {
+            culling: CullingMode::None,
+            depth: DepthTesting::None,
+            blend: BlendMode::Alpha,
+            stencil: StencilTesting::None,
+        };
+        // imgui's vertex colors are packed into 4 bytes
+        let mut descriptor = PipelineDescriptor::new(program, pipeline_state);
+        descriptor.vertex_formats = vec![
+            VertexFormat::new(VertexComponentType::F32, false),
+            VertexFormat::new(VertexComponentType::F32, false),
+            VertexFormat::new(VertexComponentType::U8, true),
+        ];
+        let program = state.load_pipeline(descriptor)?;
+        let program = state.renderer.owned(program);
 
         let textures = {
             // load the font atlas, could use an alpha8 texture, to be more
@@ -324,7 +405,7 @@ impl ImguiRenderer {
 
             atlas.tex_id = 0.into();
 
-            vec![tex]
+            vec![state.renderer.owned(tex)]
         };
 
         Ok(Self {
@@ -47,6 +47,11 @@ pub trait Layer {
     /// Run the rendering for this layer
     fn render(&mut self, state: &mut EngineStateRef) -> Result<()>;
 
+    /// The name of the layer, used to label its profiling scopes
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
     /// The order that the layer should be updated it, it is assumed that this
     /// is a const fn, but that cannot be expressed in the trait.  Default is
     /// standard update order.
@@ -1,8 +1,10 @@
 pub mod bound;
+pub mod capture;
 pub mod data;
 pub mod gltf;
 pub mod imgui;
 pub mod model;
+pub mod profiler;
 pub mod renderer;
 pub mod resources;
 pub mod scene;
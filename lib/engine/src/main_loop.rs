@@ -5,10 +5,13 @@ use std::{
 };
 
 use crate::{
-    renderer::Renderer,
+    capture::FrameCapture,
+    profiler::Profiler,
+    renderer::{Renderer, RendererStats},
     window::{
         event::Event,
         input::{InputState, KeyState},
+        scancode::Scancode,
         window::{Window, WindowConfig},
     },
     CallOrder, EventResult, Layer,
@@ -25,17 +28,28 @@ pub struct EngineState {
     /// The total time the program has been running in seconds
     pub run_time: f32,
 
-    /// The operating system window being used
-    pub window: Box<dyn Window>,
+    /// Cpu and gpu timings of recent frames
+    pub profiler: Profiler,
+
+    /// The work submitted to the renderer during the last complete frame
+    pub frame_stats: RendererStats,
 
-    /// The current renderer
+    /// Screenshots and frame sequences to save, pressing F12 takes a
+    /// screenshot
+    pub capture: FrameCapture,
+
+    /// The current renderer, declared before the window so that it is dropped
+    /// while the window's context is still current
     pub renderer: Renderer,
+
+    /// The operating system window being used
+    pub window: Box<dyn Window>,
 }
 
-// For now i'm allowing direct access to the renderer.  When all commands use
-// the renderer, then it can be moved to using command lists and mpsc channels,
-// allowing multi-threading.  That cannot be done now due to raw gl calls being
-// mixed with renderer calls.
+// Work on other threads can record command buffers and send them using
+// `Renderer::sender`, they are submitted at the start of each frame.  Direct
+// access to the renderer is still allowed as raw gl calls (e.g. imgui) are
+// still mixed with renderer calls, so they have to happen on the main thread.
 impl Deref for EngineState {
     type Target = Renderer;
 
@@ -137,6 +151,9 @@ impl MainLoop {
             window: Box::new(window),
             inputs: Default::default(),
             run_time: 0.0,
+            profiler: Profiler::new(),
+            frame_stats: RendererStats::default(),
+            capture: FrameCapture::new(),
         };
 
         // vec capacity 4 is completely arbitary, could increase/decrease later
@@ -190,11 +207,18 @@ impl MainLoop {
             let frame_time = (new_time - current_time).as_secs_f32();
             current_time = new_time;
 
+            // captured frame sequences use a fixed time step, so they play
+            // back at the right speed however slow saving each frame is
+            let frame_time = self.state.capture.timestep().unwrap_or(frame_time);
+
             accumulator += frame_time;
 
+            self.state.profiler.begin_frame();
+
             self.state.window.update_mouse(&mut self.state.inputs);
 
             // poll for events
+            self.state.profiler.begin_scope("events");
             'event: while let Some(event) = self.state.window.event() {
                 for &layer in &self.update_order {
                     let mut state = EngineStateRef {
@@ -216,10 +240,14 @@ impl MainLoop {
                     break 'main;
                 }
             }
+            self.state.profiler.end_scope();
 
             // run updates
+            self.state.profiler.begin_scope("update");
             while accumulator >= DT {
                 for &layer in &self.update_order {
+                    self.state.profiler.begin_scope(self.layers[layer].name());
+
                     let mut state = EngineStateRef {
                         state: &mut self.state,
                         layer_push: &mut layer_push,
@@ -227,14 +255,28 @@ impl MainLoop {
                         layer_id: layer,
                     };
                     self.layers[layer].update(&mut state, DT);
+
+                    self.state.profiler.end_scope();
                 }
 
                 accumulator -= DT;
                 self.state.run_time += DT;
             }
+            self.state.profiler.end_scope();
+
+            // run any command buffers sent from other threads, so their
+            // resources are available when rendering this frame
+            self.state.profiler.begin_scope("submit queued");
+            self.state.renderer.submit_queued()?;
+            self.state.profiler.end_scope();
 
-            // render a scene
+            // render a scene, each layer is timed on both the cpu and gpu
+            self.state.profiler.begin_scope("render");
             for &layer in &self.render_order {
+                let name = self.layers[layer].name();
+                self.state.profiler.begin_scope(name);
+                self.state.renderer.begin_scope(name);
+
                 let mut state = EngineStateRef {
                     state: &mut self.state,
                     layer_push: &mut layer_push,
@@ -242,7 +284,11 @@ impl MainLoop {
                     layer_id: layer,
                 };
                 self.layers[layer].render(&mut state)?;
+
+                self.state.renderer.end_scope();
+                self.state.profiler.end_scope();
             }
+            self.state.profiler.end_scope();
 
             // update layers
             if !layer_pop.is_empty() || !layer_push.is_empty() {
@@ -261,7 +307,20 @@ impl MainLoop {
                 layer_pop.clear();
             }
 
+            self.state.profiler.begin_scope("capture");
+            let size = self.state.renderer.screen_viewport();
+            self.state.capture.capture(&mut self.state.renderer, size);
+            self.state.profiler.end_scope();
+
+            self.state.profiler.begin_scope("swap");
             self.state.window.swap_window();
+            self.state.profiler.end_scope();
+
+            self.state.frame_stats = self.state.renderer.reset_stats();
+
+            let frame = self.state.profiler.frame();
+            let gpu = self.state.renderer.end_profile_frame(frame);
+            self.state.profiler.end_frame(gpu);
         }
 
         Ok(())
@@ -304,6 +363,16 @@ fn default_event_handler(state: &mut EngineState, event: &Event) -> EventResult
     match event {
         Event::Quit { .. } => return EventResult::Exit,
         Event::KeyDown { key, .. } => {
+            // ignore key repeats, only the first press takes a screenshot
+            if *key == Scancode::F12
+                && !matches!(
+                    state.inputs.key_state(*key),
+                    KeyState::Down | KeyState::Hold
+                )
+            {
+                state.capture.screenshot();
+            }
+
             state.inputs.set_key_state(*key, KeyState::Down);
         }
         Event::KeyUp { key, .. } => {
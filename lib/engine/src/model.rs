@@ -8,12 +8,14 @@ use crate::{
         shader::{
             BuiltinVariable, Expression, FunctionContext, Program, ShaderCreationError, Type,
         },
-        DrawingMode, IndexBufferId, IndexType, PipelineId, Renderer, TextureId, VertexBufferId,
+        BlendMode, CapabilityError, CommandSender, DepthTesting, DepthTestingFunction,
+        DrawArraysIndirectCommand, DrawCall, DrawElementsIndirectCommand, DrawItem, DrawQueue,
+        DrawingMode, IndexBufferId, IndexType, IndirectBufferId, PipelineDescriptor, PipelineId,
+        PipelineState, Renderer, StorageBufferId, TextureId, VertexBufferId, VertexFormat,
     },
     resources::{Error as ResourceError, Resources},
     texture,
     texture::Texture,
-    EngineStateRef,
 };
 use anyhow::Result;
 use nalgebra_glm as glm;
@@ -62,6 +64,13 @@ pub enum ModelError {
         inner: crate::texture::TextureError,
     },
 
+    #[error("Texture {index} cannot be used by the renderer: {inner}")]
+    UnsupportedTexture {
+        index: usize,
+        #[source]
+        inner: CapabilityError,
+    },
+
     #[error("No image provided for texture")]
     NoImage,
 
@@ -112,10 +121,27 @@ pub enum ModelError {
 
     #[error("Unable to convert shader to native type:\n{source}")]
     NativeShader { source: anyhow::Error },
+
+    #[error("Indirect rendering used before calling Model::load_indirect")]
+    IndirectNotLoaded,
+
+    #[error("Indirect rendering needs the {name} extension, which the renderer does not support")]
+    MissingExtension { name: &'static str },
+}
+
+/// The data of a gltf image, before it is loaded as a texture
+#[derive(Debug)]
+enum ImageData {
+    /// An image file that can be decoded by [`image`]
+    Encoded(Vec<u8>),
+
+    /// A KTX2 container of compressed blocks
+    Ktx2(Vec<u8>),
 }
 
 /// A 3d gltf model, including all its data.  Not dependant upon any rendering
-/// backend
+/// backend.  Its gpu resources are unloaded when it is dropped, the next time
+/// the renderer submits queued command buffers.
 #[derive(Debug)]
 pub struct Model {
     scenes: Vec<Scene>,
@@ -131,6 +157,17 @@ pub struct Model {
     pub(crate) gpu_buffers: Vec<GPUBuffer>,
     gpu_textures: Vec<renderer::TextureId>,
     gpu_pipelines: Vec<Vec<GPUPrimitive>>,
+
+    /// Every pipeline loaded by the model, primitives that would create the
+    /// same pipeline share one
+    pipelines: HashMap<PipelineKey, PipelineId>,
+
+    /// The resources used by [`Model::render_indirect`], None until they are
+    /// loaded by [`Model::load_indirect`]
+    indirect: Option<IndirectDraws>,
+
+    /// Used to unload the gpu resources when the model is dropped
+    unloader: CommandSender,
 }
 
 impl Model {
@@ -190,6 +227,9 @@ impl Model {
             gpu_buffers: Vec::with_capacity(0),
             gpu_textures: Vec::with_capacity(0),
             gpu_pipelines: Vec::with_capacity(0),
+            pipelines: HashMap::new(),
+            indirect: None,
+            unloader: renderer.sender(),
         };
 
         model.check_load_accessors()?;
@@ -204,7 +244,7 @@ impl Model {
         res: &Resources,
         gltf: &gltf::Model,
         buffers: &[Buffer],
-    ) -> Result<Vec<u8>, ModelError> {
+    ) -> Result<ImageData, ModelError> {
         let data = match img.uri {
             Some(ref uri) => res.load_bytes(uri).map_err(|e| ModelError::ImageLoad {
                 name: uri.to_string(),
@@ -243,13 +283,22 @@ impl Model {
             }
         };
 
-        Ok(data)
+        // KTX2 images are used by KHR_texture_basisu, the mime type is optional
+        // for images loaded from a uri
+        let ktx2 = img.mime_type == "image/ktx2"
+            || matches!(&img.uri, Some(uri) if uri.ends_with(".ktx2"));
+
+        if ktx2 {
+            Ok(ImageData::Ktx2(data))
+        } else {
+            Ok(ImageData::Encoded(data))
+        }
     }
 
     fn load_texture(
         tex: &gltf::Texture,
         gltf: &gltf::Model,
-        images: &[Vec<u8>],
+        images: &[ImageData],
     ) -> Result<Texture, ModelError> {
         let default = gltf::Sampler::default();
         let sampler = if let Some(idx) = tex.sampler {
@@ -266,11 +315,26 @@ impl Model {
             ..Default::default()
         };
 
-        let source = tex.source.ok_or(ModelError::NoImage)?;
-        let data = &images[source];
+        // prefer the compressed image from KHR_texture_basisu if there is one
+        let basisu = tex
+            .extensions
+            .get("KHR_texture_basisu")
+            .and_then(|ext| ext.get("source"))
+            .and_then(|source| source.as_u64())
+            .map(|source| source as usize);
+
+        let source = basisu.or(tex.source).ok_or(ModelError::NoImage)?;
+        let image = images.get(source).ok_or(ModelError::BadIndex {
+            array: "images",
+            got: source,
+            max: images.len(),
+        })?;
 
-        let tex = Texture::from_encoding_config(data, sampler)
-            .map_err(|e| ModelError::Texture { inner: e })?;
+        let tex = match image {
+            ImageData::Encoded(data) => Texture::from_encoding_config(data, sampler),
+            ImageData::Ktx2(data) => Texture::from_ktx2_config(data, sampler),
+        }
+        .map_err(|e| ModelError::Texture { inner: e })?;
 
         Ok(tex)
     }
@@ -506,76 +570,326 @@ impl Model {
             .map(|tex| Model::load_texture(tex, &self.gltf, &images))
             .collect::<Result<Vec<_>, _>>()?;
 
+        // give a useful error for textures the backend cannot load, e.g. a
+        // compressed format that the driver does not support
+        for (index, texture) in textures.iter().enumerate() {
+            renderer
+                .capabilities()
+                .check_texture(texture)
+                .map_err(|inner| ModelError::UnsupportedTexture { index, inner })?;
+        }
+
         self.gpu_textures = textures
             .into_iter()
             .map(|tex| renderer.load_texture(tex))
             .collect();
 
-        self.gpu_pipelines = Vec::with_capacity(self.gltf.meshes.len());
+        // the cache is put back before returning any error, so that the
+        // pipelines already loaded are unloaded when the model is dropped
+        let mut cache = std::mem::take(&mut self.pipelines);
+        let result = self.load_primitives(renderer, &mut cache);
+        self.pipelines = cache;
+
+        self.gpu_pipelines = result?;
+
+        Ok(())
+    }
+
+    fn load_primitives(
+        &self,
+        renderer: &mut Renderer,
+        cache: &mut HashMap<PipelineKey, PipelineId>,
+    ) -> Result<Vec<Vec<GPUPrimitive>>, ModelError> {
+        let mut meshes = Vec::with_capacity(self.gltf.meshes.len());
 
         for mesh in &self.gltf.meshes {
-            let mut pipelines = Vec::with_capacity(mesh.primitives.len());
+            let mut primitives = Vec::with_capacity(mesh.primitives.len());
 
             for prim in &mesh.primitives {
-                pipelines.push(GPUPrimitive::new(prim, self, renderer)?);
+                primitives.push(GPUPrimitive::new(prim, self, renderer, cache)?);
             }
 
-            self.gpu_pipelines.push(pipelines);
+            meshes.push(primitives);
         }
 
-        Ok(())
+        Ok(meshes)
     }
 
     pub fn render(
         &self,
-        state: &mut EngineStateRef,
+        renderer: &mut Renderer,
         proj: &glm::Mat4,
         view: &glm::Mat4,
     ) -> Result<()> {
+        let mut queue = DrawQueue::new();
+        self.queue(&mut queue, proj, view);
+        queue.submit(renderer)?;
+
+        // depth writes have to be re-enabled for the depth buffer to be cleared
+        renderer.blending(BlendMode::None);
+        renderer.depth_testing(DepthTesting::Default);
+
+        Ok(())
+    }
+
+    /// Load the pipelines and buffers needed to draw the scene using
+    /// [`Model::render_indirect`].  Every node's model matrix is stored in a
+    /// storage buffer, so that all the nodes drawing a primitive can be packed
+    /// into one indirect draw, with each draw's base instance indexing its
+    /// matrix.  The commands of every primitive sharing a pipeline are
+    /// uploaded once, into one indirect buffer.
+    pub fn load_indirect(&mut self, renderer: &mut Renderer) -> Result<(), ModelError> {
+        if self.indirect.is_some() {
+            return Ok(());
+        }
+
+        // the shaders read the base instance, which is not core until OpenGl 4.6
+        if !renderer.capabilities().has_extension(DRAW_PARAMETERS) {
+            return Err(ModelError::MissingExtension {
+                name: DRAW_PARAMETERS,
+            });
+        }
+
         let scene_idx = self.gltf.scene.unwrap_or(0);
         let scene = &self.scenes[scene_idx];
 
-        for node_idx in &scene.root_nodes {
-            let node = &scene.nodes[*node_idx];
-            self.render_node(node, scene, state, proj, view)?;
+        // the model matrix of every node using each mesh
+        let mut instances = vec![vec![]; self.gltf.meshes.len()];
+        for node in scene.nodes.values() {
+            if let Some(mesh_id) = node.mesh_id {
+                instances[mesh_id].push(node.global_matrix);
+            }
         }
 
+        // pipelines loaded before an error are unloaded, so that a failed
+        // load does not leave anything behind
+        let loaded: Vec<PipelineId> = self.pipelines.values().copied().collect();
+        let mut cache = std::mem::take(&mut self.pipelines);
+        let result = self.indirect_batches(renderer, &mut cache, &instances);
+        self.pipelines = cache;
+
+        let batches = match result {
+            Ok(batches) => batches,
+            Err(e) => {
+                self.pipelines.retain(|_, pipeline| {
+                    loaded.contains(pipeline) || renderer.unload_pipeline(*pipeline).is_err()
+                });
+                return Err(e);
+            }
+        };
+
+        let batches = batches
+            .into_iter()
+            .map(|batch| batch.load(renderer))
+            .collect();
+
+        let matrices: Vec<u8> = instances
+            .iter()
+            .flatten()
+            .flat_map(|matrix| matrix.as_slice().to_vec())
+            .flat_map(|f| f.to_ne_bytes().to_vec())
+            .collect();
+        let matrices = renderer.load_storage_buffer(&matrices);
+
+        self.indirect = Some(IndirectDraws { matrices, batches });
+
         Ok(())
     }
 
-    fn render_node(
+    /// Load the pipeline of every drawn primitive and group the primitives by
+    /// pipeline, primitives with the same pipeline and bindings are drawn by a
+    /// single draw
+    fn indirect_batches(
         &self,
-        node: &Node,
-        scene: &Scene,
-        state: &mut EngineStateRef,
+        renderer: &mut Renderer,
+        cache: &mut HashMap<PipelineKey, PipelineId>,
+        instances: &[Vec<glm::Mat4>],
+    ) -> Result<Vec<PendingBatch>, ModelError> {
+        let mut batches: Vec<PendingBatch> = vec![];
+
+        let mut base_instance = 0;
+        for (mesh_id, matrices) in instances.iter().enumerate() {
+            if matrices.is_empty() {
+                continue;
+            }
+
+            let mesh = &self.gltf.meshes[mesh_id];
+            for (idx, (prim, gpu_prim)) in mesh
+                .primitives
+                .iter()
+                .zip(&self.gpu_pipelines[mesh_id])
+                .enumerate()
+            {
+                let program = GPUPrimitive::create_shader(prim, self, true)?;
+                let pipeline = load_pipeline(
+                    renderer,
+                    cache,
+                    program,
+                    gpu_prim.state,
+                    &gpu_prim.vertex_formats,
+                )?;
+
+                let batch = match batches.iter().position(|batch| batch.pipeline == pipeline) {
+                    Some(batch) => batch,
+                    None => {
+                        batches.push(PendingBatch {
+                            pipeline,
+                            blend: gpu_prim.blend,
+                            draws: vec![],
+                        });
+                        batches.len() - 1
+                    }
+                };
+
+                let draws = &mut batches[batch].draws;
+                let draw = draws.iter().position(|(draw, _)| {
+                    gpu_prim.same_bindings(&self.gpu_pipelines[draw.mesh_id][draw.primitive])
+                });
+                let draw = match draw {
+                    Some(draw) => draw,
+                    None => {
+                        let draw = IndirectDraw {
+                            mesh_id,
+                            primitive: idx,
+                            offset: 0,
+                            count: 0,
+                        };
+                        draws.push((draw, vec![]));
+                        draws.len() - 1
+                    }
+                };
+
+                let (draw, commands) = &mut draws[draw];
+                for base_instance in base_instance..base_instance + matrices.len() as u32 {
+                    match &gpu_prim.indicies {
+                        Some(indices) => commands.extend(
+                            DrawElementsIndirectCommand {
+                                count: indices.count as _,
+                                instances: 1,
+                                first_index: (indices.offset / indices.item_type.size()) as _,
+                                base_vertex: 0,
+                                base_instance,
+                            }
+                            .to_bytes(),
+                        ),
+                        None => commands.extend(
+                            DrawArraysIndirectCommand {
+                                count: gpu_prim.vertex_count as _,
+                                instances: 1,
+                                first: 0,
+                                base_instance,
+                            }
+                            .to_bytes(),
+                        ),
+                    }
+                }
+                draw.count += matrices.len();
+            }
+
+            base_instance += matrices.len() as u32;
+        }
+
+        // translucent primitives are drawn last so that they are blended
+        // with everything behind them
+        batches.sort_by_key(|batch| batch.blend);
+
+        Ok(batches)
+    }
+
+    /// Draw the scene using one indirect draw per pipeline and set of
+    /// bindings, rather than one draw per primitive for each node using it.
+    /// Translucent primitives are drawn after opaque ones, but are not sorted
+    /// by depth.
+    pub fn render_indirect(
+        &self,
+        renderer: &mut Renderer,
         proj: &glm::Mat4,
         view: &glm::Mat4,
     ) -> Result<()> {
-        if let Some(mesh_id) = node.mesh_id {
-            self.render_mesh(mesh_id, state, &node.global_matrix, proj, view)?;
-        }
+        let indirect = self
+            .indirect
+            .as_ref()
+            .ok_or(ModelError::IndirectNotLoaded)?;
+
+        for batch in &indirect.batches {
+            let mut pipeline = renderer.bind_pipeline(batch.pipeline)?;
+            pipeline.bind_matrix("view", *view)?;
+            pipeline.bind_matrix("projection", *proj)?;
+            pipeline.bind_storage_buffer("model_matrices", indirect.matrices)?;
+
+            for draw in &batch.draws {
+                let prim = &self.gpu_pipelines[draw.mesh_id][draw.primitive];
+
+                if let Some(tex) = prim.base_color_texidx {
+                    pipeline.bind_texture("base_color", tex)?;
+                }
 
-        for child in &node.children {
-            let node = &scene.nodes[*child];
-            self.render_node(node, scene, state, proj, view)?;
+                if let Some(color) = prim.base_color_factor {
+                    pipeline.bind_uniform("base_color_factor", color)?;
+                }
+
+                pipeline.bind_vertex_arrays(
+                    &prim.vertex_buffers,
+                    &prim.vertex_offsets,
+                    &prim.vertex_strides,
+                )?;
+
+                match &prim.indicies {
+                    Some(indices) => pipeline.multi_draw_elements_indirect_buffer(
+                        prim.draw_mode,
+                        indices.buffer,
+                        indices.item_type,
+                        batch.commands,
+                        draw.offset,
+                        draw.count,
+                    )?,
+                    None => pipeline.draw_indirect_buffer(
+                        prim.draw_mode,
+                        batch.commands,
+                        draw.offset,
+                        draw.count,
+                    )?,
+                }
+            }
         }
 
+        // depth writes have to be re-enabled for the depth buffer to be cleared
+        renderer.blending(BlendMode::None);
+        renderer.depth_testing(DepthTesting::Default);
+
         Ok(())
     }
 
-    fn render_mesh(
-        &self,
-        mesh_id: usize,
-        state: &mut EngineStateRef,
-        model_mat: &glm::Mat4,
+    /// Add a draw item for every primitive in the scene to a draw queue, so
+    /// that they can be sorted with other draws before being drawn
+    pub fn queue<'a>(&'a self, queue: &mut DrawQueue<'a>, proj: &glm::Mat4, view: &glm::Mat4) {
+        let scene_idx = self.gltf.scene.unwrap_or(0);
+        let scene = &self.scenes[scene_idx];
+
+        for node_idx in &scene.root_nodes {
+            let node = &scene.nodes[*node_idx];
+            self.queue_node(node, scene, queue, proj, view);
+        }
+    }
+
+    fn queue_node<'a>(
+        &'a self,
+        node: &Node,
+        scene: &Scene,
+        queue: &mut DrawQueue<'a>,
         proj: &glm::Mat4,
         view: &glm::Mat4,
-    ) -> Result<()> {
-        for prim in &self.gpu_pipelines[mesh_id] {
-            prim.render(state, view, proj, model_mat)?;
+    ) {
+        if let Some(mesh_id) = node.mesh_id {
+            for prim in &self.gpu_pipelines[mesh_id] {
+                queue.push(prim.draw_item(view, proj, &node.global_matrix));
+            }
         }
 
-        Ok(())
+        for child in &node.children {
+            let node = &scene.nodes[*child];
+            self.queue_node(node, scene, queue, proj, view);
+        }
     }
 }
 
@@ -990,6 +1304,39 @@ impl Node {
     }
 }
 
+impl Drop for Model {
+    fn drop(&mut self) {
+        let mut commands = self.unloader.command_buffer();
+
+        for buffer in &self.gpu_buffers {
+            match *buffer {
+                GPUBuffer::Index(buffer) => commands.unload_index_buffer(buffer),
+                GPUBuffer::Vertex(buffer) => commands.unload_vertex_buffer(buffer),
+                GPUBuffer::None => (),
+            }
+        }
+
+        for &texture in &self.gpu_textures {
+            commands.unload_texture(texture);
+        }
+
+        for &pipeline in self.pipelines.values() {
+            commands.unload_pipeline(pipeline);
+        }
+
+        if let Some(indirect) = &self.indirect {
+            commands.unload_storage_buffer(indirect.matrices);
+
+            for batch in &indirect.batches {
+                commands.unload_indirect_buffer(batch.commands);
+            }
+        }
+
+        // nothing to unload if the renderer has already been dropped
+        let _ = self.unloader.send(commands);
+    }
+}
+
 /// A loaded buffer, either an index buffer, a vertex buffer or neither for specifying
 /// a CPU only buffer
 #[derive(Debug, Copy, Clone)]
@@ -1019,6 +1366,105 @@ impl GPUBuffer {
     }
 }
 
+/// The extension needed by indirect shaders to read the base instance
+const DRAW_PARAMETERS: &str = "GL_ARB_shader_draw_parameters";
+
+/// Identifies a pipeline by its generated shader, state and vertex formats
+type PipelineKey = (String, PipelineState, Vec<VertexFormat>);
+
+/// Load a pipeline, or reuse one already in the cache if it would be the same
+fn load_pipeline(
+    renderer: &mut Renderer,
+    cache: &mut HashMap<PipelineKey, PipelineId>,
+    program: Program,
+    state: PipelineState,
+    vertex_formats: &[VertexFormat],
+) -> Result<PipelineId, ModelError> {
+    let key = (program.to_string(), state, vertex_formats.to_vec());
+    if let Some(&pipeline) = cache.get(&key) {
+        return Ok(pipeline);
+    }
+
+    let mut descriptor = PipelineDescriptor::new(program, state);
+    descriptor.vertex_formats = vertex_formats.to_vec();
+    let pipeline = renderer
+        .load_pipeline(descriptor)
+        .map_err(|e| ModelError::Graphics { inner: e })?;
+
+    cache.insert(key, pipeline);
+
+    Ok(pipeline)
+}
+
+/// One indirect draw, of every node drawing one or more primitives with the
+/// same bindings
+#[derive(Debug)]
+struct IndirectDraw {
+    /// The primitive whose textures, uniforms and buffers are bound
+    mesh_id: usize,
+    primitive: usize,
+
+    /// Where the draw's commands start in the batch's indirect buffer, in
+    /// bytes
+    offset: usize,
+
+    /// The number of commands
+    count: usize,
+}
+
+/// All the draws made with a single pipeline, their commands are stored in
+/// one indirect buffer
+#[derive(Debug)]
+struct IndirectBatch {
+    /// A pipeline reading the model matrix from a storage buffer rather than
+    /// a uniform
+    pipeline: PipelineId,
+
+    commands: IndirectBufferId,
+    draws: Vec<IndirectDraw>,
+}
+
+/// The draws made with a single pipeline while they are being loaded, each
+/// draw has its own commands until they are packed into one indirect buffer
+struct PendingBatch {
+    pipeline: PipelineId,
+
+    /// Whether the pipeline blends, so is drawn after the opaque pipelines
+    blend: bool,
+
+    draws: Vec<(IndirectDraw, Vec<u8>)>,
+}
+
+impl PendingBatch {
+    /// Upload the commands of every draw into one indirect buffer
+    fn load(self, renderer: &mut Renderer) -> IndirectBatch {
+        let mut commands = vec![];
+        let draws = self
+            .draws
+            .into_iter()
+            .map(|(mut draw, draw_commands)| {
+                draw.offset = commands.len();
+                commands.extend(draw_commands);
+                draw
+            })
+            .collect();
+
+        IndirectBatch {
+            pipeline: self.pipeline,
+            commands: renderer.load_indirect_buffer(&commands),
+            draws,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IndirectDraws {
+    /// The model matrices of every node, indexed by each draw's base instance
+    matrices: StorageBufferId,
+
+    batches: Vec<IndirectBatch>,
+}
+
 #[derive(Debug)]
 struct GPUPrimitiveIndexInfo {
     buffer: IndexBufferId,
@@ -1032,13 +1478,22 @@ struct GPUPrimitive {
     pipeline: PipelineId,
     vertex_count: usize,
     base_color_texidx: Option<TextureId>,
+    base_color_factor: Option<glm::Vec4>,
     indicies: Option<GPUPrimitiveIndexInfo>,
     draw_mode: DrawingMode,
-    culling: bool,
+
+    /// Whether the primitive is translucent, so is blended with whatever is
+    /// behind it
+    blend: bool,
+
+    state: PipelineState,
 
     vertex_buffers: Vec<VertexBufferId>,
     vertex_strides: Vec<i32>,
     vertex_offsets: Vec<usize>,
+
+    /// How each vertex array is read, from its accessor's component type
+    vertex_formats: Vec<VertexFormat>,
 }
 
 impl GPUPrimitive {
@@ -1046,8 +1501,9 @@ impl GPUPrimitive {
         prim: &gltf::Primitive,
         model: &Model,
         renderer: &mut Renderer,
+        cache: &mut HashMap<PipelineKey, PipelineId>,
     ) -> Result<Self, ModelError> {
-        let pipeline = Self::create_shader(prim, model)?;
+        let pipeline = Self::create_shader(prim, model, false)?;
 
         let mat = if let Some(mat) = prim.material {
             Some(&model.gltf.materials[mat])
@@ -1069,9 +1525,38 @@ impl GPUPrimitive {
             None
         };
 
-        let pipeline = renderer
-            .load_pipeline(pipeline)
-            .map_err(|e| ModelError::Graphics { inner: e.into() })?;
+        let culling = !mat.map(|a| a.double_sided).unwrap_or(false);
+        let blend = mat
+            .map(|a| matches!(a.alpha_mode, gltf::MaterialAlphaMode::Blend))
+            .unwrap_or(false);
+
+        // translucent primitives still need to be hidden by opaque ones, but
+        // should not hide anything drawn after them
+        let state = PipelineState {
+            culling: if culling {
+                CullingMode::Back
+            } else {
+                CullingMode::None
+            },
+            depth: if blend {
+                DepthTesting::Enabled {
+                    read_only: true,
+                    func: DepthTestingFunction::Less,
+                }
+            } else {
+                DepthTesting::Default
+            },
+            blend: if blend {
+                BlendMode::Alpha
+            } else {
+                BlendMode::None
+            },
+            ..Default::default()
+        };
+
+        let vertex_formats = Self::get_vertex_formats(prim, model);
+
+        let pipeline = load_pipeline(renderer, cache, pipeline, state, &vertex_formats)?;
 
         let draw_mode = match prim.mode {
             gltf::PrimitiveMode::LineLoop => DrawingMode::LineLoop,
@@ -1124,11 +1609,47 @@ impl GPUPrimitive {
             vertex_buffers,
             vertex_offsets,
             vertex_strides,
+            vertex_formats,
             base_color_texidx: base_color,
-            culling: !mat.map(|a| a.double_sided).unwrap_or(false),
+            base_color_factor: mat.and_then(Attribute::base_color_factor),
+            blend,
+            state,
         })
     }
 
+    /// Whether the primitive binds the same buffers, textures and uniforms
+    /// and draws the same way as another, so that both can be drawn by one
+    /// indirect draw
+    fn same_bindings(&self, other: &GPUPrimitive) -> bool {
+        let indices = |prim: &GPUPrimitive| {
+            prim.indicies
+                .as_ref()
+                .map(|indices| (indices.buffer, indices.item_type))
+        };
+
+        self.draw_mode == other.draw_mode
+            && indices(self) == indices(other)
+            && self.base_color_texidx == other.base_color_texidx
+            && self.base_color_factor == other.base_color_factor
+            && self.vertex_buffers == other.vertex_buffers
+            && self.vertex_offsets == other.vertex_offsets
+            && self.vertex_strides == other.vertex_strides
+    }
+
+    /// get the format of each vertex array, in the same order as the buffers
+    fn get_vertex_formats(prim: &gltf::Primitive, model: &Model) -> Vec<VertexFormat> {
+        prim.attributes
+            .iter()
+            .map(|(_, &attr)| {
+                let accessor = &model.gltf.accessors[attr as usize];
+                VertexFormat::new(
+                    accessor.component_type.vertex_component(),
+                    accessor.normalised,
+                )
+            })
+            .collect()
+    }
+
     /// get the vertex buffers, strides and offsets (in that order) for a primitive
     fn get_vertex_array_data(
         prim: &gltf::Primitive,
@@ -1165,51 +1686,60 @@ impl GPUPrimitive {
         Ok((buffers, offsets, strides, vertex_count))
     }
 
-    fn render(
-        &self,
-        renderer: &mut Renderer,
-        view: &glm::Mat4,
-        proj: &glm::Mat4,
-        model: &glm::Mat4,
-    ) -> Result<()> {
-        if self.culling {
-            renderer.backface_culling(CullingMode::Back);
+    fn draw_item(&self, view: &glm::Mat4, proj: &glm::Mat4, model: &glm::Mat4) -> DrawItem<'_> {
+        let call = if let Some(indices) = &self.indicies {
+            DrawCall::Indexed {
+                mode: self.draw_mode,
+                indices: indices.buffer,
+                index_type: indices.item_type,
+                index_offset: indices.offset,
+                count: indices.count,
+            }
         } else {
-            renderer.backface_culling(CullingMode::None)
-        }
-        renderer.depth_testing(renderer::DepthTesting::Default);
+            DrawCall::Arrays {
+                mode: self.draw_mode,
+                start: 0,
+                count: self.vertex_count as _,
+            }
+        };
+
+        // the camera looks down -z, so the depth of the primitive's origin
+        // is its negated z in view space
+        let origin = view * model * glm::vec4(0.0, 0.0, 0.0, 1.0);
 
-        let mut pipeline = renderer.bind_pipeline(self.pipeline);
-        pipeline.bind_matrix("view", *view)?;
-        pipeline.bind_matrix("projection", *proj)?;
-        pipeline.bind_matrix("model", *model)?;
+        let mut item = DrawItem::new(self.pipeline, call);
+        item.translucent = self.blend;
+        item.depth = -origin.z;
+        item.matrices = vec![
+            ("view".into(), *view),
+            ("projection".into(), *proj),
+            ("model".into(), *model),
+        ];
 
         if let Some(tex) = self.base_color_texidx {
-            pipeline.bind_texture("base_color", tex)?;
+            item.textures.push(("base_color".into(), tex));
         }
 
-        pipeline.bind_vertex_arrays(
-            &self.vertex_buffers,
-            &self.vertex_offsets,
-            &self.vertex_strides,
-        );
-
-        if let Some(indices) = &self.indicies {
-            pipeline.draw_indicies(
-                self.draw_mode,
-                indices.buffer,
-                indices.item_type,
-                indices.offset,
-                indices.count,
-            );
-        } else {
-            pipeline.draw(self.draw_mode, 0, self.vertex_count as _);
+        if let Some(color) = self.base_color_factor {
+            item.uniforms
+                .push(("base_color_factor".into(), color.into()));
         }
 
-        Ok(())
+        item.vertex_buffers = &self.vertex_buffers;
+        item.vertex_offsets = &self.vertex_offsets;
+        item.vertex_strides = &self.vertex_strides;
+
+        item
     }
 
-    fn create_shader(prim: &gltf::Primitive, model: &Model) -> Result<Program, ModelError> {
+    /// Create the shader for a primitive, indirect shaders read the model
+    /// matrix from a storage buffer indexed by the base instance rather than
+    /// a uniform
+    fn create_shader(
+        prim: &gltf::Primitive,
+        model: &Model,
+        indirect: bool,
+    ) -> Result<Program, ModelError> {
         let mut components: Vec<Attribute> = prim
             .attributes
             .iter()
@@ -1226,8 +1756,16 @@ impl GPUPrimitive {
 
         let mut shader = Program::new(|ctx| {
             ctx.vertex(|ctx| {
+                let model_matrix = if indirect {
+                    let matrices = ctx.storage_buffer("model_matrices", &Type::Mat4);
+                    let draw = ctx.get_builtin(BuiltinVariable::BaseInstance);
+                    Expression::index(matrices, draw)
+                } else {
+                    ctx.uniform("model", Type::Mat4)
+                };
+
                 for comp in &components {
-                    comp.vertex(ctx, model);
+                    comp.vertex(ctx, model, &model_matrix);
                 }
             });
 
@@ -1267,7 +1805,7 @@ enum Attribute {
     VertexColor { accessor: usize, idx: usize },
     Joints { accessor: usize, idx: usize },
     Weights { accessor: usize, idx: usize },
-    BaseColor { color: [f32; 4] },
+    BaseColor,
 }
 
 impl Attribute {
@@ -1302,28 +1840,37 @@ impl Attribute {
     fn material(mat: &gltf::Material) -> Vec<Self> {
         let mut ret = vec![];
 
-        if let Some(pbr) = &mat.pbr_metallic_roughness {
-            if pbr.base_color_factor != [1.0; 4] {
-                ret.push(Attribute::BaseColor {
-                    color: pbr.base_color_factor,
-                })
-            }
+        if Self::base_color_factor(mat).is_some() {
+            ret.push(Attribute::BaseColor)
         }
 
         ret
     }
+
+    /// The base color factor of a material, if it is not the default of white
+    fn base_color_factor(mat: &gltf::Material) -> Option<glm::Vec4> {
+        let pbr = mat.pbr_metallic_roughness.as_ref()?;
+
+        if pbr.base_color_factor != [1.0; 4] {
+            Some(pbr.base_color_factor.into())
+        } else {
+            None
+        }
+    }
 }
 
 impl Attribute {
-    fn vertex(&self, ctx: &mut FunctionContext, model: &Model) {
+    fn vertex(&self, ctx: &mut FunctionContext, model: &Model, model_matrix: &Expression) {
         match self {
             Attribute::Position => {
                 let view = ctx.uniform("view", Type::Mat4);
-                let model = ctx.uniform("model", Type::Mat4);
                 let projection = ctx.uniform("projection", Type::Mat4);
 
                 let position = ctx.input("Position_in", Type::Vec3);
-                let value = projection * view * model * Expression::vec(&[position, 1.0.into()]);
+                let value = projection
+                    * view
+                    * model_matrix.clone()
+                    * Expression::vec(&[position, 1.0.into()]);
 
                 ctx.set_builtin(BuiltinVariable::VertexPosition, value)
             }
@@ -1349,14 +1896,16 @@ impl Attribute {
                 ctx.input("Tangent_in", ty);
             }
             Attribute::Joints { accessor, idx } => {
-                let ty = model.gltf.accessors[*accessor].r#type.to_shader_type();
+                let ty = model.gltf.accessors[*accessor]
+                    .r#type
+                    .to_unsigned_shader_type();
                 ctx.input(&format!("Joints{}_in", idx), ty);
             }
             Attribute::Weights { accessor, idx } => {
                 let ty = model.gltf.accessors[*accessor].r#type.to_shader_type();
                 ctx.input(&format!("Weights{}_in", idx), ty);
             }
-            Attribute::BaseColor { .. } => {}
+            Attribute::BaseColor => {}
         }
     }
 
@@ -1380,16 +1929,7 @@ impl Attribute {
 
                 Some(Expression::texture(base_color, uv))
             }
-            Attribute::BaseColor { color } => {
-                let color = [
-                    color[0].into(),
-                    color[1].into(),
-                    color[2].into(),
-                    color[3].into(),
-                ];
-
-                Some(Expression::vec(&color))
-            }
+            Attribute::BaseColor => Some(ctx.uniform("base_color_factor", Type::Vec4)),
             _ => None,
         }
     }
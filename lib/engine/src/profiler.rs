@@ -0,0 +1,260 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::renderer::GpuFrame;
+
+/// The number of frames of timings that are kept
+const HISTORY_LENGTH: usize = 120;
+
+/// The time taken by a single named scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileScope {
+    pub name: String,
+
+    /// The number of scopes this scope is nested inside
+    pub depth: usize,
+
+    /// When the scope started, relative to the start of its frame
+    pub start: Duration,
+
+    pub duration: Duration,
+}
+
+/// The cpu and gpu timings of a single frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameProfile {
+    pub frame: u64,
+
+    /// When the frame started, relative to when the profiler was created
+    pub start: Duration,
+
+    pub duration: Duration,
+
+    pub cpu: Vec<ProfileScope>,
+
+    /// None until the gpu has finished the frame and its timings are read back
+    pub gpu: Option<Vec<ProfileScope>>,
+}
+
+/// Collects cpu scope timings for each frame, along with the gpu timings read
+/// back from the renderer, keeping the last few frames of results
+#[derive(Debug)]
+pub struct Profiler {
+    /// The time that the start of each frame is measured from
+    epoch: Instant,
+
+    /// The number of the frame currently being recorded
+    frame: u64,
+
+    frame_start: Instant,
+
+    /// Scopes recorded in the current frame
+    scopes: Vec<ProfileScope>,
+
+    /// The indices into `scopes` of the scopes that have not been ended, with
+    /// the time they started
+    open: Vec<(usize, Instant)>,
+
+    /// Recent frames, oldest first
+    history: VecDeque<FrameProfile>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            epoch: now,
+            frame: 0,
+            frame_start: now,
+            scopes: vec![],
+            open: vec![],
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    /// The number of the frame currently being recorded
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Start recording a new frame
+    pub fn begin_frame(&mut self) {
+        debug_assert!(self.open.is_empty());
+
+        self.frame_start = Instant::now();
+        self.scopes.clear();
+        self.open.clear();
+    }
+
+    /// Start timing a named scope, scopes can be nested and must be ended
+    /// with [`Self::end_scope`] before the end of the frame
+    pub fn begin_scope(&mut self, name: &str) {
+        let now = Instant::now();
+
+        self.open.push((self.scopes.len(), now));
+        self.scopes.push(ProfileScope {
+            name: name.to_string(),
+            depth: self.open.len() - 1,
+            start: now - self.frame_start,
+            duration: Duration::default(),
+        });
+    }
+
+    /// End the most recently started scope
+    pub fn end_scope(&mut self) {
+        debug_assert!(!self.open.is_empty());
+
+        if let Some((index, start)) = self.open.pop() {
+            self.scopes[index].duration = start.elapsed();
+        }
+    }
+
+    /// Finish recording the current frame, the gpu timings are the frames
+    /// that the renderer has read back since the last frame
+    pub fn end_frame(&mut self, gpu: Vec<GpuFrame>) {
+        while !self.open.is_empty() {
+            self.end_scope();
+        }
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(FrameProfile {
+            frame: self.frame,
+            start: self.frame_start - self.epoch,
+            duration: self.frame_start.elapsed(),
+            cpu: std::mem::take(&mut self.scopes),
+            gpu: None,
+        });
+
+        for gpu in gpu {
+            let frame = self.history.iter_mut().find(|f| f.frame == gpu.frame);
+            if let Some(frame) = frame {
+                frame.gpu = Some(gpu.scopes);
+            }
+        }
+
+        self.frame += 1;
+    }
+
+    /// All the frames kept, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &FrameProfile> {
+        self.history.iter()
+    }
+
+    /// The most recent frame that has both its cpu and gpu timings
+    pub fn latest(&self) -> Option<&FrameProfile> {
+        self.history.iter().rev().find(|frame| frame.gpu.is_some())
+    }
+
+    /// All the frames kept as a trace that can be loaded in chrome://tracing,
+    /// the gpu scopes of each frame are aligned to the start of the frame as
+    /// gpu and cpu timestamps cannot be compared
+    pub fn chrome_trace(&self) -> Value {
+        const CPU_THREAD: u32 = 1;
+        const GPU_THREAD: u32 = 2;
+
+        let micros = |time: Duration| time.as_secs_f64() * 1_000_000.0;
+        let event = |name: &str, thread, start, duration| {
+            json!({
+                "name": name,
+                "ph": "X",
+                "pid": 1,
+                "tid": thread,
+                "ts": micros(start),
+                "dur": micros(duration),
+            })
+        };
+
+        let mut events = vec![
+            json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": CPU_THREAD, "args": { "name": "CPU" } }),
+            json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": GPU_THREAD, "args": { "name": "GPU" } }),
+        ];
+
+        for frame in &self.history {
+            let name = format!("frame {}", frame.frame);
+            events.push(event(&name, CPU_THREAD, frame.start, frame.duration));
+
+            let gpu = frame.gpu.iter().flatten().map(|scope| (GPU_THREAD, scope));
+            let cpu = frame.cpu.iter().map(|scope| (CPU_THREAD, scope));
+
+            for (thread, scope) in cpu.chain(gpu) {
+                let start = frame.start + scope.start;
+                events.push(event(&scope.name, thread, start, scope.duration));
+            }
+        }
+
+        json!({ "traceEvents": events })
+    }
+
+    /// Write [`Self::chrome_trace`] to a file
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(file, &self.chrome_trace())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use crate::renderer::GpuFrame;
+
+    #[test]
+    fn test_frame_history() {
+        let mut profiler = Profiler::new();
+
+        profiler.begin_frame();
+        profiler.begin_scope("update");
+        profiler.begin_scope("physics");
+        profiler.end_scope();
+        profiler.end_scope();
+        profiler.begin_scope("render");
+        profiler.end_frame(vec![]);
+
+        // the gpu has not finished the first frame yet
+        assert!(profiler.latest().is_none());
+
+        let frame = profiler.history().next().unwrap();
+        let scopes: Vec<_> = frame
+            .cpu
+            .iter()
+            .map(|scope| (scope.name.as_str(), scope.depth))
+            .collect();
+        assert_eq!(scopes, [("update", 0), ("physics", 1), ("render", 0)]);
+
+        profiler.begin_frame();
+        profiler.end_frame(vec![GpuFrame {
+            frame: 0,
+            scopes: vec![],
+        }]);
+
+        assert_eq!(profiler.latest().map(|frame| frame.frame), Some(0));
+
+        let trace = profiler.chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let names: Vec<_> = events.iter().filter_map(|e| e["name"].as_str()).collect();
+
+        // 2 thread names, 2 frames and 3 scopes
+        assert_eq!(events.len(), 7);
+        assert!(names.contains(&"frame 1"));
+        assert!(names.contains(&"physics"));
+    }
+}
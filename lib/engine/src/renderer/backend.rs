@@ -2,54 +2,202 @@ use anyhow::Result;
 use nalgebra_glm as glm;
 
 use super::{
-    shader::Program, CullingMode, DepthTesting, DrawingMode, IndexBufferId, IndexType, PipelineId,
-    TextureId, VertexBufferId,
+    BlendMode, CullingMode, DepthTesting, DrawArraysIndirectCommand, DrawElementsIndirectCommand,
+    DrawingMode, IndexBufferId, IndexType, IndirectBufferId, PipelineDescriptor, PipelineId,
+    PixelFormat, PixelRegion, RenderTargetId, RenderTargetOptions, RendererCapabilities,
+    StencilTesting, StorageBufferId, TextureId, UniformBufferId, UniformValue, VertexBufferId,
 };
-use crate::texture::Texture;
+use crate::texture::{Texture, TextureRegion};
 
-/// The methods required for each renderer backend to implement
+/// The methods required for each renderer backend to implement.  Resource ids
+/// are allocated by the frontend, so that they can be reserved before the
+/// resource is loaded, e.g. while recording a command buffer on another thread.
 pub trait RendererBackend {
-    /// Clear the screen to the specified color
+    /// Clear the screen to the specified color, also clears the depth and
+    /// stencil buffers
     fn clear(&mut self, r: f32, g: f32, b: f32);
 
+    /// Clear the stencil buffer to a value
+    fn clear_stencil(&mut self, value: u8);
+
     /// Set the viewport size
     fn viewport(&mut self, width: u32, height: u32);
 
+    /// The size of the default framebuffer's viewport, the last viewport set
+    /// while no render target was bound
+    fn screen_viewport(&self) -> (u32, u32);
+
     /// Enable or disable backface culling
     fn backface_culling(&mut self, enable: CullingMode);
 
     fn depth_testing(&mut self, mode: DepthTesting);
 
+    /// Set the stencil test and how the stencil buffer is written
+    fn stencil_testing(&mut self, mode: StencilTesting);
+
+    /// Set how fragments are blended with the framebuffer
+    fn blending(&mut self, mode: BlendMode);
+
+    /// External code has changed the graphics api's state, any cached state
+    /// should be set again before it is relied on
+    fn invalidate_state_cache(&mut self);
+
+    /// The features and limits of the backend, texture anisotropy levels are
+    /// clamped to its maximum anisotropy when loaded
+    fn capabilities(&self) -> RendererCapabilities;
+
+    /// The total number of state changes skipped because the state was
+    /// already set, 0 for backends that do not cache their state
+    fn skipped_state_changes(&self) -> u64;
+
+    /// Record when the gpu reaches this point into a timestamp query.  Queries
+    /// are created the first time they are used and are reused once their
+    /// result has been read
+    fn timestamp(&mut self, query: u32);
+
+    /// The time written to a timestamp query in nanoseconds, None if the gpu
+    /// has not reached the timestamp yet
+    fn timestamp_result(&mut self, query: u32) -> Option<u64>;
+
     /// Load a new texture
-    fn load_texture(&mut self, texture: Texture) -> TextureId;
+    fn load_texture(&mut self, id: TextureId, texture: Texture);
 
     /// Unload a texture
     fn unload_texture(&mut self, texture: TextureId);
 
+    /// Replace a region of a texture's pixel data, see [`TextureRegion::check`]
+    fn update_texture(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Replace a region of a texture's pixel data for streaming upload, the
+    /// upload can happen asynchronously
+    fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()>;
+
     /// Load data as a vertex buffer
-    fn load_vertex_buffer(&mut self, data: &[u8]) -> VertexBufferId;
+    fn load_vertex_buffer(&mut self, id: VertexBufferId, data: &[u8]);
 
     /// Load data as a vertex buffer for streaming upload
-    fn load_vertex_buffer_stream(&mut self, data: &[u8]) -> VertexBufferId;
+    fn load_vertex_buffer_stream(&mut self, id: VertexBufferId, data: &[u8]);
+
+    /// Create a persistently mapped vertex buffer of a fixed size, that is
+    /// written to as a ring using [`Self::write_vertex_ring_buffer`]
+    fn load_vertex_ring_buffer(&mut self, id: VertexBufferId, size: usize);
 
     /// Unload a vertex buffer
     fn unload_vertex_buffer(&mut self, buffer: VertexBufferId);
 
+    /// Replace part of a vertex buffer's data, starting at a byte offset
+    fn update_vertex_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Replace all of a vertex buffer's data with new storage, which can have
+    /// a different size, without waiting for draws using the old data
+    fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<()>;
+
+    /// Write data into the next free space of a vertex ring buffer, returns
+    /// the byte offset it was written at
+    fn write_vertex_ring_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<usize>;
+
     /// Load data as an index buffer
-    fn load_index_buffer(&mut self, data: &[u8]) -> IndexBufferId;
+    fn load_index_buffer(&mut self, id: IndexBufferId, data: &[u8]);
 
     /// Load data as an index buffer for streaming upload
-    fn load_index_buffer_stream(&mut self, data: &[u8]) -> IndexBufferId;
+    fn load_index_buffer_stream(&mut self, id: IndexBufferId, data: &[u8]);
+
+    /// Create a persistently mapped index buffer of a fixed size, that is
+    /// written to as a ring using [`Self::write_index_ring_buffer`]
+    fn load_index_ring_buffer(&mut self, id: IndexBufferId, size: usize);
 
     /// Unload an index buffer
     fn unload_index_buffer(&mut self, buffer: IndexBufferId);
 
-    /// Load a new pipeline
-    fn load_pipeline(&mut self, pipeline: Program) -> Result<PipelineId>;
+    /// Replace part of an index buffer's data, starting at a byte offset
+    fn update_index_buffer(
+        &mut self,
+        buffer: IndexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Replace all of an index buffer's data with new storage, which can have
+    /// a different size, without waiting for draws using the old data
+    fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<()>;
+
+    /// Write data into the next free space of an index ring buffer, returns
+    /// the byte offset it was written at
+    fn write_index_ring_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<usize>;
+
+    /// Load data as a uniform buffer
+    fn load_uniform_buffer(&mut self, id: UniformBufferId, data: &[u8]);
+
+    /// Unload a uniform buffer
+    fn unload_uniform_buffer(&mut self, buffer: UniformBufferId);
+
+    /// Load data as a storage buffer
+    fn load_storage_buffer(&mut self, id: StorageBufferId, data: &[u8]);
+
+    /// Unload a storage buffer
+    fn unload_storage_buffer(&mut self, buffer: StorageBufferId);
+
+    /// Replace part of a storage buffer's data, starting at a byte offset
+    fn update_storage_buffer(
+        &mut self,
+        buffer: StorageBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Read all of a storage buffer's data, after every earlier dispatch and
+    /// draw has finished writing to it
+    fn read_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<Vec<u8>>;
+
+    /// Load indirect draw commands, so that they can be drawn without being
+    /// uploaded again
+    fn load_indirect_buffer(&mut self, id: IndirectBufferId, data: &[u8]);
+
+    /// Unload an indirect buffer
+    fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId);
+
+    /// Load a new pipeline, its state is set each time it is bound
+    fn load_pipeline(&mut self, id: PipelineId, pipeline: PipelineDescriptor) -> Result<()>;
 
     /// Unloads a pipeline
     fn unload_pipeline(&mut self, pipeline: PipelineId);
 
+    /// Create a render target, its attachments are loaded as textures using
+    /// the ids stored in the render target id
+    fn load_render_target(
+        &mut self,
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    ) -> Result<()>;
+
+    /// Unload a render target and the textures of its attachments
+    fn unload_render_target(&mut self, target: RenderTargetId);
+
+    /// Set the target of all future clear and draw calls, None for the
+    /// default framebuffer.  Sets the viewport to cover the whole target, when
+    /// binding the default framebuffer the last viewport set for it is restored
+    fn bind_render_target(&mut self, target: Option<RenderTargetId>);
+
+    /// Read a region of the color buffer of the current render target, after
+    /// every earlier draw has finished.  Rows are tightly packed, starting at
+    /// the bottom row of the region.
+    fn read_pixels(&mut self, region: PixelRegion, format: PixelFormat) -> Result<Vec<u8>>;
+
     /// Bind a pipeline so that vertex buffers and uniforms can be bound to it
     fn bind_pipeline(&mut self, pipeline: PipelineId);
 
@@ -72,20 +220,57 @@ pub trait RendererBackend {
         texture: TextureId,
     ) -> Result<()>;
 
+    /// Bind a value to a uniform, the value's type must match the uniform's
+    fn pipeline_bind_uniform(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        value: &UniformValue,
+    ) -> Result<()>;
+
+    /// Bind a uniform buffer to a uniform block of a pipeline
+    fn pipeline_bind_uniform_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: UniformBufferId,
+    ) -> Result<()>;
+
+    /// Bind a storage buffer to a storage buffer of a pipeline, the binding
+    /// is shared with other pipelines so must be made each time the pipeline
+    /// is bound
+    fn pipeline_bind_storage_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: StorageBufferId,
+    ) -> Result<()>;
+
     /// Bind vertex arrays with a given offset and stride to a bound pipeline
-    /// offset and stride are both measured in bytes.
+    /// offset and stride are both measured in bytes.  Arrays with a divisor
+    /// of 0 advance once per vertex, otherwise once every divisor instances,
+    /// an empty slice of divisors means every divisor is 0.
     fn pipeline_bind_vertex_arrays(
         &mut self,
         pipeline: PipelineId,
         buffers: &[VertexBufferId],
         offsets: &[usize],
         strides: &[i32],
+        divisors: &[u32],
     );
 
-    /// draw verticies using a pipeline
-    fn draw(&mut self, pipeline: PipelineId, mode: DrawingMode, start: u64, count: u64);
+    /// draw instances of verticies using a pipeline
+    fn draw(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    );
 
-    /// draw indexed verticies
+    /// draw instances of indexed verticies
+    #[allow(clippy::too_many_arguments)]
     fn draw_indicies(
         &mut self,
         pipeline: PipelineId,
@@ -94,5 +279,53 @@ pub trait RendererBackend {
         index_type: IndexType,
         index_offset: usize,
         count: usize,
+        instances: usize,
+    );
+
+    /// make several draws using a pipeline in one call
+    fn draw_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: &[DrawArraysIndirectCommand],
     );
+
+    /// make several indexed draws from one index buffer in one call
+    fn multi_draw_elements_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    );
+
+    /// make several draws using a pipeline in one call, reading `count`
+    /// commands from an indirect buffer starting at a byte offset
+    fn draw_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()>;
+
+    /// make several indexed draws from one index buffer in one call, reading
+    /// `count` commands from an indirect buffer starting at a byte offset
+    #[allow(clippy::too_many_arguments)]
+    fn multi_draw_elements_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()>;
+
+    /// run a pipeline's compute shader over a number of workgroups, storage
+    /// buffer writes must be visible to everything done afterwards
+    fn dispatch(&mut self, pipeline: PipelineId, x: u32, y: u32, z: u32);
 }
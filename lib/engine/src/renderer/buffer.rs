@@ -0,0 +1,132 @@
+use thiserror::Error;
+
+/// Errors from writing to a loaded vertex or index buffer
+#[derive(Debug, Error)]
+pub enum BufferError {
+    #[error("Buffer is not currently loaded, cannot write to it")]
+    Unloaded,
+
+    #[error("Cannot write {len} bytes at offset {offset} into a buffer of {size} bytes")]
+    OutOfBounds {
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+
+    #[error("Buffer was not loaded as a ring buffer")]
+    NotRingBuffer,
+
+    #[error("Ring buffers have a fixed size, they cannot be orphaned")]
+    OrphanRingBuffer,
+
+    #[error("Ring buffers can only be written to the next free space, as draws could still be reading any other part")]
+    UpdateRingBuffer,
+
+    #[error("Cannot write {len} bytes into a ring buffer of {size} bytes")]
+    RingBufferTooSmall { len: usize, size: usize },
+
+    #[error(
+        "Cannot read {count} indirect commands at offset {offset} from a buffer of {size} bytes"
+    )]
+    IndirectOutOfBounds {
+        offset: usize,
+        count: usize,
+        size: usize,
+    },
+
+    #[error("Indirect commands must be read from an offset that is a multiple of 4, not {offset}")]
+    UnalignedIndirectOffset { offset: usize },
+}
+
+/// Check that a write of `len` bytes at `offset` fits inside a buffer
+pub(crate) fn check_buffer_write(
+    offset: usize,
+    len: usize,
+    size: usize,
+) -> Result<(), BufferError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(BufferError::OutOfBounds { offset, len, size }),
+    }
+}
+
+/// Check that `count` indirect commands of `command_size` bytes can be read
+/// from a buffer starting at `offset`
+pub(crate) fn check_indirect_read(
+    offset: usize,
+    count: usize,
+    command_size: usize,
+    size: usize,
+) -> Result<(), BufferError> {
+    // the commands are read as 4 byte integers
+    if offset & 3 != 0 {
+        return Err(BufferError::UnalignedIndirectOffset { offset });
+    }
+
+    match count
+        .checked_mul(command_size)
+        .and_then(|len| len.checked_add(offset))
+    {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(BufferError::IndirectOutOfBounds {
+            offset,
+            count,
+            size,
+        }),
+    }
+}
+
+/// The number of sections a ring buffer is split into, each section is
+/// fenced separately so writing to one only has to wait for the draws that
+/// read from it
+pub(crate) const RING_SECTIONS: usize = 3;
+
+/// The alignment of every allocation in a ring buffer, large enough for any
+/// vertex attribute or index type
+const RING_ALIGNMENT: usize = 16;
+
+/// Allocates space in a ring buffer, the same for every backend so that the
+/// offsets returned do not depend on the backend used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RingBuffer {
+    /// Total size of the buffer in bytes
+    pub size: usize,
+
+    /// Where the next allocation will start if it fits
+    head: usize,
+}
+
+impl RingBuffer {
+    pub fn new(size: usize) -> Self {
+        Self { size, head: 0 }
+    }
+
+    /// Find the offset to write `len` bytes at, wrapping back to the start of
+    /// the buffer if they do not fit before the end
+    pub fn allocate(&mut self, len: usize) -> Result<usize, BufferError> {
+        if len > self.size {
+            return Err(BufferError::RingBufferTooSmall {
+                len,
+                size: self.size,
+            });
+        }
+
+        let mut offset = self.head.next_multiple_of(RING_ALIGNMENT);
+        if offset + len > self.size {
+            offset = 0;
+        }
+
+        self.head = offset + len;
+        Ok(offset)
+    }
+
+    /// The section a byte offset is part of
+    pub fn section(&self, offset: usize) -> usize {
+        (offset * RING_SECTIONS / self.size.max(1)).min(RING_SECTIONS - 1)
+    }
+
+    /// Where the last allocation ended
+    pub fn head(&self) -> usize {
+        self.head
+    }
+}
@@ -0,0 +1,87 @@
+use thiserror::Error;
+
+use crate::texture::{Texture, TextureStorageType};
+
+/// Errors from using a feature that the renderer backend does not support
+#[derive(Debug, Error)]
+pub enum CapabilityError {
+    #[error("Texture of {width}x{height} pixels is larger than the maximum size of {max}")]
+    TextureTooLarge { width: u32, height: u32, max: u32 },
+
+    #[error("Compressed texture format {storage:?} is not supported by {renderer}")]
+    UnsupportedFormat {
+        storage: TextureStorageType,
+        renderer: String,
+    },
+}
+
+/// The features and limits of a renderer backend, queried once when the
+/// backend is created
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RendererCapabilities {
+    /// The graphics api version (major, minor)
+    pub version: (u32, u32),
+
+    /// The company responsible for the driver
+    pub vendor: String,
+
+    /// The name of the device being rendered with
+    pub renderer: String,
+
+    /// The maximum width or height of a texture in pixels
+    pub max_texture_size: u32,
+
+    /// The maximum anisotropy supported, 1 if anisotropic filtering is not
+    /// supported
+    pub max_anisotropy: u32,
+
+    /// The number of textures that can be bound at once across all shader
+    /// stages
+    pub texture_units: u32,
+
+    /// The names of all the supported extensions, e.g. `GL_NV_command_list`
+    pub extensions: Vec<String>,
+
+    /// The compressed texture formats that can be loaded
+    pub compressed_formats: Vec<TextureStorageType>,
+}
+
+impl RendererCapabilities {
+    /// Is the graphics api version at least (major, minor)
+    pub fn supports_version(&self, major: u32, minor: u32) -> bool {
+        self.version >= (major, minor)
+    }
+
+    /// Is an extension supported, using its full name, e.g. `GL_NV_command_list`
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == name)
+    }
+
+    /// Can textures with a storage type be loaded, uncompressed formats are
+    /// always supported
+    pub fn supports_storage(&self, storage: TextureStorageType) -> bool {
+        !storage.is_compressed() || self.compressed_formats.contains(&storage)
+    }
+
+    /// Check that a texture can be loaded by the backend
+    pub fn check_texture(&self, texture: &Texture) -> Result<(), CapabilityError> {
+        let (width, height) = (texture.width(), texture.height());
+        if width > self.max_texture_size || height > self.max_texture_size {
+            return Err(CapabilityError::TextureTooLarge {
+                width,
+                height,
+                max: self.max_texture_size,
+            });
+        }
+
+        let storage = texture.config().storage;
+        if !self.supports_storage(storage) {
+            return Err(CapabilityError::UnsupportedFormat {
+                storage,
+                renderer: self.renderer.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
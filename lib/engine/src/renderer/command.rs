@@ -0,0 +1,1340 @@
+use anyhow::Result;
+use nalgebra_glm as glm;
+use std::sync::mpsc::{SendError, Sender};
+
+use super::{
+    backend::RendererBackend, BlendMode, CullingMode, DepthTesting, DrawArraysIndirectCommand,
+    DrawElementsIndirectCommand, DrawingMode, IndexBufferId, IndexType, IndirectBufferId,
+    PipelineDescriptor, PipelineId, RenderTargetId, RenderTargetOptions, RendererStats,
+    ResourceIds, ResourceKind, StencilTesting, StorageBufferId, TextureId, UniformBufferId,
+    UniformValue, VertexBufferId,
+};
+use crate::texture::{Texture, TextureRegion};
+
+/// A single recorded renderer operation, owns all the data it requires so
+/// that it can be sent between threads
+#[derive(Debug)]
+enum Command {
+    Clear {
+        r: f32,
+        g: f32,
+        b: f32,
+    },
+    ClearStencil(u8),
+    Viewport {
+        width: u32,
+        height: u32,
+    },
+    BackfaceCulling(CullingMode),
+    DepthTesting(DepthTesting),
+    StencilTesting(StencilTesting),
+    Blending(BlendMode),
+    LoadTexture {
+        id: TextureId,
+        texture: Texture,
+    },
+    UnloadTexture(TextureId),
+    ReloadTexture {
+        id: TextureId,
+        texture: Texture,
+    },
+    UpdateTexture {
+        id: TextureId,
+        region: TextureRegion,
+        data: Vec<u8>,
+        stream: bool,
+    },
+    LoadVertexBuffer {
+        id: VertexBufferId,
+        data: Vec<u8>,
+        stream: bool,
+    },
+    LoadVertexRingBuffer {
+        id: VertexBufferId,
+        size: usize,
+    },
+    UnloadVertexBuffer(VertexBufferId),
+    UpdateVertexBuffer {
+        id: VertexBufferId,
+        offset: usize,
+        data: Vec<u8>,
+    },
+    OrphanVertexBuffer {
+        id: VertexBufferId,
+        data: Vec<u8>,
+    },
+    LoadIndexBuffer {
+        id: IndexBufferId,
+        data: Vec<u8>,
+        stream: bool,
+    },
+    LoadIndexRingBuffer {
+        id: IndexBufferId,
+        size: usize,
+    },
+    UnloadIndexBuffer(IndexBufferId),
+    UpdateIndexBuffer {
+        id: IndexBufferId,
+        offset: usize,
+        data: Vec<u8>,
+    },
+    OrphanIndexBuffer {
+        id: IndexBufferId,
+        data: Vec<u8>,
+    },
+    LoadUniformBuffer {
+        id: UniformBufferId,
+        data: Vec<u8>,
+    },
+    UnloadUniformBuffer(UniformBufferId),
+    LoadStorageBuffer {
+        id: StorageBufferId,
+        data: Vec<u8>,
+    },
+    UnloadStorageBuffer(StorageBufferId),
+    LoadIndirectBuffer {
+        id: IndirectBufferId,
+        data: Vec<u8>,
+    },
+    UnloadIndirectBuffer(IndirectBufferId),
+    UpdateStorageBuffer {
+        id: StorageBufferId,
+        offset: usize,
+        data: Vec<u8>,
+    },
+    LoadPipeline {
+        id: PipelineId,
+        pipeline: PipelineDescriptor,
+    },
+    UnloadPipeline(PipelineId),
+    LoadRenderTarget {
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    },
+    UnloadRenderTarget(RenderTargetId),
+    BindRenderTarget(Option<RenderTargetId>),
+    BindPipeline(PipelineId),
+    UnbindPipeline(PipelineId),
+    BindMatrix {
+        pipeline: PipelineId,
+        name: String,
+        matrix: glm::Mat4,
+    },
+    BindTexture {
+        pipeline: PipelineId,
+        name: String,
+        texture: TextureId,
+    },
+    BindUniform {
+        pipeline: PipelineId,
+        name: String,
+        value: UniformValue,
+    },
+    BindUniformBuffer {
+        pipeline: PipelineId,
+        name: String,
+        buffer: UniformBufferId,
+    },
+    BindStorageBuffer {
+        pipeline: PipelineId,
+        name: String,
+        buffer: StorageBufferId,
+    },
+    BindVertexArrays {
+        pipeline: PipelineId,
+        buffers: Vec<VertexBufferId>,
+        offsets: Vec<usize>,
+        strides: Vec<i32>,
+        divisors: Vec<u32>,
+    },
+    Draw {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    },
+    DrawIndicies {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    },
+    DrawIndirect {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: Vec<DrawArraysIndirectCommand>,
+    },
+    MultiDrawElementsIndirect {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: Vec<DrawElementsIndirectCommand>,
+    },
+    DrawIndirectBuffer {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    },
+    MultiDrawElementsIndirectBuffer {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    },
+    Dispatch {
+        pipeline: PipelineId,
+        x: u32,
+        y: u32,
+        z: u32,
+    },
+}
+
+/// A list of renderer operations that can be recorded on any thread, then
+/// submitted to the renderer on the thread that owns the graphics context.
+/// Resources loaded by the command buffer have their ids reserved while
+/// recording, so they can be used by later commands before being submitted.
+#[derive(Debug)]
+pub struct CommandBuffer {
+    ids: ResourceIds,
+    commands: Vec<Command>,
+}
+
+impl CommandBuffer {
+    pub(crate) fn new(ids: ResourceIds) -> Self {
+        Self {
+            ids,
+            commands: vec![],
+        }
+    }
+
+    /// Is there anything recorded in the command buffer
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// The number of commands recorded
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Reserve an id for a texture that will be loaded later
+    pub fn reserve_texture_id(&self) -> TextureId {
+        TextureId(self.ids.next(ResourceKind::Texture))
+    }
+
+    /// Reserve an id for a vertex buffer that will be loaded later
+    pub fn reserve_vertex_buffer_id(&self) -> VertexBufferId {
+        VertexBufferId(self.ids.next(ResourceKind::VertexBuffer))
+    }
+
+    /// Reserve an id for an index buffer that will be loaded later
+    pub fn reserve_index_buffer_id(&self) -> IndexBufferId {
+        IndexBufferId(self.ids.next(ResourceKind::IndexBuffer))
+    }
+
+    /// Reserve an id for a uniform buffer that will be loaded later
+    pub fn reserve_uniform_buffer_id(&self) -> UniformBufferId {
+        UniformBufferId(self.ids.next(ResourceKind::UniformBuffer))
+    }
+
+    /// Reserve an id for a storage buffer that will be loaded later
+    pub fn reserve_storage_buffer_id(&self) -> StorageBufferId {
+        StorageBufferId(self.ids.next(ResourceKind::StorageBuffer))
+    }
+
+    /// Reserve an id for an indirect buffer that will be loaded later
+    pub fn reserve_indirect_buffer_id(&self) -> IndirectBufferId {
+        IndirectBufferId(self.ids.next(ResourceKind::IndirectBuffer))
+    }
+
+    /// Reserve an id for a pipeline that will be loaded later
+    pub fn reserve_pipeline_id(&self) -> PipelineId {
+        PipelineId(self.ids.next(ResourceKind::Pipeline))
+    }
+
+    /// Reserve the ids for a render target that will be loaded later
+    pub fn reserve_render_target_id(&self, options: &RenderTargetOptions) -> RenderTargetId {
+        RenderTargetId::new(&self.ids, options)
+    }
+
+    /// Clear the screen to the specified color
+    pub fn clear(&mut self, r: f32, g: f32, b: f32) {
+        self.commands.push(Command::Clear { r, g, b });
+    }
+
+    /// Clear only the stencil buffer to the specified value
+    pub fn clear_stencil(&mut self, value: u8) {
+        self.commands.push(Command::ClearStencil(value));
+    }
+
+    /// Set the viewport size
+    pub fn viewport(&mut self, width: u32, height: u32) {
+        self.commands.push(Command::Viewport { width, height });
+    }
+
+    /// Enable or disable backface culling
+    pub fn backface_culling(&mut self, enable: CullingMode) {
+        self.commands.push(Command::BackfaceCulling(enable));
+    }
+
+    pub fn depth_testing(&mut self, mode: DepthTesting) {
+        self.commands.push(Command::DepthTesting(mode));
+    }
+
+    /// Set how fragments are tested against and written to the stencil buffer
+    pub fn stencil_testing(&mut self, mode: StencilTesting) {
+        self.commands.push(Command::StencilTesting(mode));
+    }
+
+    /// Set how fragments are blended with the framebuffer
+    pub fn blending(&mut self, mode: BlendMode) {
+        self.commands.push(Command::Blending(mode));
+    }
+
+    /// Load a new texture
+    pub fn load_texture(&mut self, texture: Texture) -> TextureId {
+        let id = self.reserve_texture_id();
+        self.load_texture_reserved(id, texture);
+        id
+    }
+
+    /// Load a new texture using a previously reserved id
+    pub fn load_texture_reserved(&mut self, id: TextureId, texture: Texture) {
+        self.commands.push(Command::LoadTexture { id, texture });
+    }
+
+    /// Unload a texture
+    pub fn unload_texture(&mut self, texture: TextureId) {
+        self.commands.push(Command::UnloadTexture(texture));
+    }
+
+    /// Replace a loaded texture with a new one without changing its id
+    pub fn reload_texture(&mut self, id: TextureId, texture: Texture) {
+        self.commands.push(Command::ReloadTexture { id, texture });
+    }
+
+    /// Replace a region of a texture's pixel data
+    pub fn update_texture(&mut self, texture: TextureId, region: TextureRegion, data: Vec<u8>) {
+        self.commands.push(Command::UpdateTexture {
+            id: texture,
+            region,
+            data,
+            stream: false,
+        });
+    }
+
+    /// Replace a region of a texture's pixel data for streaming upload
+    pub fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: Vec<u8>,
+    ) {
+        self.commands.push(Command::UpdateTexture {
+            id: texture,
+            region,
+            data,
+            stream: true,
+        });
+    }
+
+    /// Load data as a vertex buffer
+    pub fn load_vertex_buffer(&mut self, data: Vec<u8>) -> VertexBufferId {
+        let id = self.reserve_vertex_buffer_id();
+        self.load_vertex_buffer_reserved(id, data);
+        id
+    }
+
+    /// Load data as a vertex buffer using a previously reserved id
+    pub fn load_vertex_buffer_reserved(&mut self, id: VertexBufferId, data: Vec<u8>) {
+        self.commands.push(Command::LoadVertexBuffer {
+            id,
+            data,
+            stream: false,
+        });
+    }
+
+    /// Load data as a vertex buffer for streaming upload
+    pub fn load_vertex_buffer_stream(&mut self, data: Vec<u8>) -> VertexBufferId {
+        let id = self.reserve_vertex_buffer_id();
+        self.commands.push(Command::LoadVertexBuffer {
+            id,
+            data,
+            stream: true,
+        });
+        id
+    }
+
+    /// Create a vertex ring buffer of a fixed size in bytes, writing to it
+    /// returns an offset so can only be done through the renderer
+    pub fn load_vertex_ring_buffer(&mut self, size: usize) -> VertexBufferId {
+        let id = self.reserve_vertex_buffer_id();
+        self.commands
+            .push(Command::LoadVertexRingBuffer { id, size });
+        id
+    }
+
+    /// Unload a vertex buffer
+    pub fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) {
+        self.commands.push(Command::UnloadVertexBuffer(buffer));
+    }
+
+    /// Replace part of a vertex buffer's data, starting at a byte offset
+    pub fn update_vertex_buffer(&mut self, buffer: VertexBufferId, offset: usize, data: Vec<u8>) {
+        self.commands.push(Command::UpdateVertexBuffer {
+            id: buffer,
+            offset,
+            data,
+        });
+    }
+
+    /// Replace all of a vertex buffer's data with new storage
+    pub fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: Vec<u8>) {
+        self.commands
+            .push(Command::OrphanVertexBuffer { id: buffer, data });
+    }
+
+    /// Load data as an index buffer
+    pub fn load_index_buffer(&mut self, data: Vec<u8>) -> IndexBufferId {
+        let id = self.reserve_index_buffer_id();
+        self.load_index_buffer_reserved(id, data);
+        id
+    }
+
+    /// Load data as an index buffer using a previously reserved id
+    pub fn load_index_buffer_reserved(&mut self, id: IndexBufferId, data: Vec<u8>) {
+        self.commands.push(Command::LoadIndexBuffer {
+            id,
+            data,
+            stream: false,
+        });
+    }
+
+    /// Load data as an index buffer for streaming upload
+    pub fn load_index_buffer_stream(&mut self, data: Vec<u8>) -> IndexBufferId {
+        let id = self.reserve_index_buffer_id();
+        self.commands.push(Command::LoadIndexBuffer {
+            id,
+            data,
+            stream: true,
+        });
+        id
+    }
+
+    /// Create an index ring buffer of a fixed size in bytes, writing to it
+    /// returns an offset so can only be done through the renderer
+    pub fn load_index_ring_buffer(&mut self, size: usize) -> IndexBufferId {
+        let id = self.reserve_index_buffer_id();
+        self.commands
+            .push(Command::LoadIndexRingBuffer { id, size });
+        id
+    }
+
+    /// Unload an index buffer
+    pub fn unload_index_buffer(&mut self, buffer: IndexBufferId) {
+        self.commands.push(Command::UnloadIndexBuffer(buffer));
+    }
+
+    /// Replace part of an index buffer's data, starting at a byte offset
+    pub fn update_index_buffer(&mut self, buffer: IndexBufferId, offset: usize, data: Vec<u8>) {
+        self.commands.push(Command::UpdateIndexBuffer {
+            id: buffer,
+            offset,
+            data,
+        });
+    }
+
+    /// Replace all of an index buffer's data with new storage
+    pub fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: Vec<u8>) {
+        self.commands
+            .push(Command::OrphanIndexBuffer { id: buffer, data });
+    }
+
+    /// Load data as a uniform buffer
+    pub fn load_uniform_buffer(&mut self, data: Vec<u8>) -> UniformBufferId {
+        let id = self.reserve_uniform_buffer_id();
+        self.load_uniform_buffer_reserved(id, data);
+        id
+    }
+
+    /// Load data as a uniform buffer using a previously reserved id
+    pub fn load_uniform_buffer_reserved(&mut self, id: UniformBufferId, data: Vec<u8>) {
+        self.commands.push(Command::LoadUniformBuffer { id, data });
+    }
+
+    /// Unload a uniform buffer
+    pub fn unload_uniform_buffer(&mut self, buffer: UniformBufferId) {
+        self.commands.push(Command::UnloadUniformBuffer(buffer));
+    }
+
+    /// Load data as a storage buffer, reading it back can only be done
+    /// through the renderer
+    pub fn load_storage_buffer(&mut self, data: Vec<u8>) -> StorageBufferId {
+        let id = self.reserve_storage_buffer_id();
+        self.load_storage_buffer_reserved(id, data);
+        id
+    }
+
+    /// Load data as a storage buffer using a previously reserved id
+    pub fn load_storage_buffer_reserved(&mut self, id: StorageBufferId, data: Vec<u8>) {
+        self.commands.push(Command::LoadStorageBuffer { id, data });
+    }
+
+    /// Unload a storage buffer
+    pub fn unload_storage_buffer(&mut self, buffer: StorageBufferId) {
+        self.commands.push(Command::UnloadStorageBuffer(buffer));
+    }
+
+    /// Replace part of a storage buffer's data, starting at a byte offset
+    pub fn update_storage_buffer(&mut self, buffer: StorageBufferId, offset: usize, data: Vec<u8>) {
+        self.commands.push(Command::UpdateStorageBuffer {
+            id: buffer,
+            offset,
+            data,
+        });
+    }
+
+    /// Load indirect draw commands, see [`Renderer::load_indirect_buffer`]
+    ///
+    /// [`Renderer::load_indirect_buffer`]: super::Renderer::load_indirect_buffer
+    pub fn load_indirect_buffer(&mut self, data: Vec<u8>) -> IndirectBufferId {
+        let id = self.reserve_indirect_buffer_id();
+        self.load_indirect_buffer_reserved(id, data);
+        id
+    }
+
+    /// Load indirect draw commands using a previously reserved id
+    pub fn load_indirect_buffer_reserved(&mut self, id: IndirectBufferId, data: Vec<u8>) {
+        self.commands.push(Command::LoadIndirectBuffer { id, data });
+    }
+
+    /// Unload an indirect buffer
+    pub fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId) {
+        self.commands.push(Command::UnloadIndirectBuffer(buffer));
+    }
+
+    /// Load a new pipeline, shader compilation errors are returned when the
+    /// command buffer is submitted
+    pub fn load_pipeline(&mut self, pipeline: impl Into<PipelineDescriptor>) -> PipelineId {
+        let id = self.reserve_pipeline_id();
+        self.load_pipeline_reserved(id, pipeline);
+        id
+    }
+
+    /// Load a new pipeline using a previously reserved id
+    pub fn load_pipeline_reserved(
+        &mut self,
+        id: PipelineId,
+        pipeline: impl Into<PipelineDescriptor>,
+    ) {
+        let pipeline = pipeline.into();
+        self.commands.push(Command::LoadPipeline { id, pipeline });
+    }
+
+    /// Unload a pipeline
+    pub fn unload_pipeline(&mut self, pipeline: PipelineId) {
+        self.commands.push(Command::UnloadPipeline(pipeline));
+    }
+
+    /// Create a new render target
+    pub fn load_render_target(&mut self, options: RenderTargetOptions) -> RenderTargetId {
+        let target = self.reserve_render_target_id(&options);
+        self.load_render_target_reserved(target, options);
+        target
+    }
+
+    /// Create a new render target using previously reserved ids, the options
+    /// should be the same as those used to reserve the ids
+    pub fn load_render_target_reserved(
+        &mut self,
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    ) {
+        self.commands
+            .push(Command::LoadRenderTarget { target, options });
+    }
+
+    /// Unload a render target
+    pub fn unload_render_target(&mut self, target: RenderTargetId) {
+        self.commands.push(Command::UnloadRenderTarget(target));
+    }
+
+    /// Set where all future clear and draw calls output to, None is the main
+    /// window
+    pub fn bind_render_target(&mut self, target: Option<RenderTargetId>) {
+        self.commands.push(Command::BindRenderTarget(target));
+    }
+
+    /// Bind a pipeline so it can be used for drawing
+    pub fn bind_pipeline(&mut self, pipeline: PipelineId) -> BoundCommandPipeline<'_> {
+        BoundCommandPipeline::new(self, pipeline)
+    }
+
+    /// Run all the commands in the buffer.  If any command fails then the
+    /// remaining commands are not run and the error is returned.  Ids are
+    /// checked when each command runs, as they could be loaded or unloaded
+    /// by earlier commands.
+    pub(super) fn execute(
+        mut self,
+        backend: &mut dyn RendererBackend,
+        stats: &mut RendererStats,
+    ) -> Result<()> {
+        let mut bound = None;
+        let mut commands = std::mem::take(&mut self.commands).into_iter();
+
+        while let Some(command) = commands.next() {
+            if let Err(e) = command.execute(backend, &self.ids, stats, &mut bound) {
+                // make sure that the backend isn't left with a bound pipeline
+                if let Some(pipeline) = bound {
+                    backend.unbind_pipeline(pipeline);
+                }
+
+                // the ids of loads that were not run are freed on drop
+                self.commands = commands.collect();
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CommandBuffer {
+    /// Free the ids reserved by loads that were never run, so that they are
+    /// not reported as leaks
+    fn drop(&mut self) {
+        for command in &self.commands {
+            match *command {
+                Command::LoadTexture { id, .. } => self.ids.unreserve(id),
+                Command::LoadVertexBuffer { id, .. } | Command::LoadVertexRingBuffer { id, .. } => {
+                    self.ids.unreserve(id)
+                }
+                Command::LoadIndexBuffer { id, .. } | Command::LoadIndexRingBuffer { id, .. } => {
+                    self.ids.unreserve(id)
+                }
+                Command::LoadUniformBuffer { id, .. } => self.ids.unreserve(id),
+                Command::LoadStorageBuffer { id, .. } => self.ids.unreserve(id),
+                Command::LoadIndirectBuffer { id, .. } => self.ids.unreserve(id),
+                Command::LoadPipeline { id, .. } => self.ids.unreserve(id),
+                Command::LoadRenderTarget { target, .. } => {
+                    self.ids.unreserve_render_target(target)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Command {
+    fn execute(
+        self,
+        backend: &mut dyn RendererBackend,
+        ids: &ResourceIds,
+        stats: &mut RendererStats,
+        bound: &mut Option<PipelineId>,
+    ) -> Result<()> {
+        match self {
+            Command::Clear { r, g, b } => backend.clear(r, g, b),
+            Command::ClearStencil(value) => backend.clear_stencil(value),
+            Command::Viewport { width, height } => backend.viewport(width, height),
+            Command::BackfaceCulling(mode) => backend.backface_culling(mode),
+            Command::DepthTesting(mode) => backend.depth_testing(mode),
+            Command::StencilTesting(mode) => backend.stencil_testing(mode),
+            Command::Blending(mode) => backend.blending(mode),
+            Command::LoadTexture { id, texture } => {
+                ids.load(id)?;
+                stats.upload(texture.data_size());
+                backend.load_texture(id, texture)
+            }
+            Command::UnloadTexture(id) => {
+                ids.release(id)?;
+                backend.unload_texture(id)
+            }
+            Command::ReloadTexture { id, texture } => {
+                ids.check_replaceable(id)?;
+                stats.upload(texture.data_size());
+                backend.unload_texture(id);
+                backend.load_texture(id, texture)
+            }
+            Command::UpdateTexture {
+                id,
+                region,
+                data,
+                stream,
+            } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                if stream {
+                    backend.update_texture_stream(id, region, &data)?
+                } else {
+                    backend.update_texture(id, region, &data)?
+                }
+            }
+            Command::LoadVertexBuffer { id, data, stream } => {
+                ids.load(id)?;
+                stats.upload(data.len());
+                if stream {
+                    backend.load_vertex_buffer_stream(id, &data)
+                } else {
+                    backend.load_vertex_buffer(id, &data)
+                }
+            }
+            Command::LoadVertexRingBuffer { id, size } => {
+                ids.load(id)?;
+                backend.load_vertex_ring_buffer(id, size)
+            }
+            Command::UnloadVertexBuffer(id) => {
+                ids.release(id)?;
+                backend.unload_vertex_buffer(id)
+            }
+            Command::UpdateVertexBuffer { id, offset, data } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                backend.update_vertex_buffer(id, offset, &data)?
+            }
+            Command::OrphanVertexBuffer { id, data } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                backend.orphan_vertex_buffer(id, &data)?
+            }
+            Command::LoadIndexBuffer { id, data, stream } => {
+                ids.load(id)?;
+                stats.upload(data.len());
+                if stream {
+                    backend.load_index_buffer_stream(id, &data)
+                } else {
+                    backend.load_index_buffer(id, &data)
+                }
+            }
+            Command::LoadIndexRingBuffer { id, size } => {
+                ids.load(id)?;
+                backend.load_index_ring_buffer(id, size)
+            }
+            Command::UnloadIndexBuffer(id) => {
+                ids.release(id)?;
+                backend.unload_index_buffer(id)
+            }
+            Command::UpdateIndexBuffer { id, offset, data } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                backend.update_index_buffer(id, offset, &data)?
+            }
+            Command::OrphanIndexBuffer { id, data } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                backend.orphan_index_buffer(id, &data)?
+            }
+            Command::LoadUniformBuffer { id, data } => {
+                ids.load(id)?;
+                stats.upload(data.len());
+                backend.load_uniform_buffer(id, &data)
+            }
+            Command::UnloadUniformBuffer(id) => {
+                ids.release(id)?;
+                backend.unload_uniform_buffer(id)
+            }
+            Command::LoadStorageBuffer { id, data } => {
+                ids.load(id)?;
+                stats.upload(data.len());
+                backend.load_storage_buffer(id, &data)
+            }
+            Command::UnloadStorageBuffer(id) => {
+                ids.release(id)?;
+                backend.unload_storage_buffer(id)
+            }
+            Command::UpdateStorageBuffer { id, offset, data } => {
+                ids.check(id)?;
+                stats.upload(data.len());
+                backend.update_storage_buffer(id, offset, &data)?
+            }
+            Command::LoadIndirectBuffer { id, data } => {
+                ids.load(id)?;
+                stats.upload(data.len());
+                backend.load_indirect_buffer(id, &data)
+            }
+            Command::UnloadIndirectBuffer(id) => {
+                ids.release(id)?;
+                backend.unload_indirect_buffer(id)
+            }
+            Command::LoadPipeline { id, pipeline } => {
+                ids.load(id)?;
+                if let Err(e) = backend.load_pipeline(id, pipeline) {
+                    ids.release(id)?;
+                    return Err(e);
+                }
+            }
+            Command::UnloadPipeline(id) => {
+                ids.release(id)?;
+                backend.unload_pipeline(id)
+            }
+            Command::LoadRenderTarget { target, options } => {
+                ids.load_render_target(target)?;
+                if let Err(e) = backend.load_render_target(target, options) {
+                    ids.release_render_target(target)?;
+                    return Err(e);
+                }
+            }
+            Command::UnloadRenderTarget(target) => {
+                ids.release_render_target(target)?;
+                backend.unload_render_target(target)
+            }
+            Command::BindRenderTarget(target) => {
+                if let Some(target) = target {
+                    ids.check(target)?;
+                }
+                backend.bind_render_target(target)
+            }
+            Command::BindPipeline(id) => {
+                ids.check(id)?;
+                stats.pipeline_binds += 1;
+                backend.bind_pipeline(id);
+                *bound = Some(id);
+            }
+            Command::UnbindPipeline(id) => {
+                backend.unbind_pipeline(id);
+                *bound = None;
+            }
+            Command::BindMatrix {
+                pipeline,
+                name,
+                matrix,
+            } => backend.pipeline_bind_matrix(pipeline, &name, matrix)?,
+            Command::BindTexture {
+                pipeline,
+                name,
+                texture,
+            } => {
+                ids.check(texture)?;
+                stats.texture_binds += 1;
+                backend.pipeline_bind_texture(pipeline, &name, texture)?
+            }
+            Command::BindUniform {
+                pipeline,
+                name,
+                value,
+            } => backend.pipeline_bind_uniform(pipeline, &name, &value)?,
+            Command::BindUniformBuffer {
+                pipeline,
+                name,
+                buffer,
+            } => {
+                ids.check(buffer)?;
+                backend.pipeline_bind_uniform_buffer(pipeline, &name, buffer)?
+            }
+            Command::BindStorageBuffer {
+                pipeline,
+                name,
+                buffer,
+            } => {
+                ids.check(buffer)?;
+                backend.pipeline_bind_storage_buffer(pipeline, &name, buffer)?
+            }
+            Command::BindVertexArrays {
+                pipeline,
+                buffers,
+                offsets,
+                strides,
+                divisors,
+            } => {
+                for &buffer in &buffers {
+                    ids.check(buffer)?;
+                }
+                backend
+                    .pipeline_bind_vertex_arrays(pipeline, &buffers, &offsets, &strides, &divisors)
+            }
+            Command::Draw {
+                pipeline,
+                mode,
+                start,
+                count,
+                instances,
+            } => {
+                stats.draw(mode, count, instances);
+                backend.draw(pipeline, mode, start, count, instances)
+            }
+            Command::DrawIndicies {
+                pipeline,
+                mode,
+                indices,
+                index_type,
+                index_offset,
+                count,
+                instances,
+            } => {
+                ids.check(indices)?;
+                stats.draw(mode, count as u64, instances as u64);
+                backend.draw_indicies(
+                    pipeline,
+                    mode,
+                    indices,
+                    index_type,
+                    index_offset,
+                    count,
+                    instances,
+                )
+            }
+            Command::DrawIndirect {
+                pipeline,
+                mode,
+                draws,
+            } => {
+                stats.draw_indirect(
+                    mode,
+                    draws
+                        .iter()
+                        .map(|draw| (draw.count as u64, draw.instances as u64)),
+                );
+                backend.draw_indirect(pipeline, mode, &draws)
+            }
+            Command::MultiDrawElementsIndirect {
+                pipeline,
+                mode,
+                indices,
+                index_type,
+                draws,
+            } => {
+                ids.check(indices)?;
+                stats.draw_indirect(
+                    mode,
+                    draws
+                        .iter()
+                        .map(|draw| (draw.count as u64, draw.instances as u64)),
+                );
+                backend.multi_draw_elements_indirect(pipeline, mode, indices, index_type, &draws)
+            }
+            Command::DrawIndirectBuffer {
+                pipeline,
+                mode,
+                buffer,
+                offset,
+                count,
+            } => {
+                ids.check(buffer)?;
+                stats.draw_indirect(mode, std::iter::empty());
+                backend.draw_indirect_buffer(pipeline, mode, buffer, offset, count)?
+            }
+            Command::MultiDrawElementsIndirectBuffer {
+                pipeline,
+                mode,
+                indices,
+                index_type,
+                buffer,
+                offset,
+                count,
+            } => {
+                ids.check(indices)?;
+                ids.check(buffer)?;
+                stats.draw_indirect(mode, std::iter::empty());
+                backend.multi_draw_elements_indirect_buffer(
+                    pipeline, mode, indices, index_type, buffer, offset, count,
+                )?
+            }
+            Command::Dispatch { pipeline, x, y, z } => {
+                stats.dispatches += 1;
+                backend.dispatch(pipeline, x, y, z)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A pipeline bound while recording a command buffer, the pipeline is unbound
+/// when this is dropped
+pub struct BoundCommandPipeline<'a> {
+    buffer: &'a mut CommandBuffer,
+    pipeline: PipelineId,
+}
+
+impl<'a> BoundCommandPipeline<'a> {
+    fn new(buffer: &'a mut CommandBuffer, pipeline: PipelineId) -> Self {
+        buffer.commands.push(Command::BindPipeline(pipeline));
+        Self { buffer, pipeline }
+    }
+
+    pub fn bind_matrix(&mut self, name: &str, matrix: glm::Mat4) {
+        self.buffer.commands.push(Command::BindMatrix {
+            pipeline: self.pipeline,
+            name: name.to_string(),
+            matrix,
+        });
+    }
+
+    pub fn bind_texture(&mut self, name: &str, texture: TextureId) {
+        self.buffer.commands.push(Command::BindTexture {
+            pipeline: self.pipeline,
+            name: name.to_string(),
+            texture,
+        });
+    }
+
+    /// Bind a value to a uniform, type errors are returned when the command
+    /// buffer is submitted
+    pub fn bind_uniform(&mut self, name: &str, value: impl Into<UniformValue>) {
+        self.buffer.commands.push(Command::BindUniform {
+            pipeline: self.pipeline,
+            name: name.to_string(),
+            value: value.into(),
+        });
+    }
+
+    /// Bind a uniform buffer to the uniform block with the given name
+    pub fn bind_uniform_buffer(&mut self, name: &str, buffer: UniformBufferId) {
+        self.buffer.commands.push(Command::BindUniformBuffer {
+            pipeline: self.pipeline,
+            name: name.to_string(),
+            buffer,
+        });
+    }
+
+    /// Bind a storage buffer to the storage buffer with the given name
+    pub fn bind_storage_buffer(&mut self, name: &str, buffer: StorageBufferId) {
+        self.buffer.commands.push(Command::BindStorageBuffer {
+            pipeline: self.pipeline,
+            name: name.to_string(),
+            buffer,
+        });
+    }
+
+    pub fn bind_vertex_arrays(
+        &mut self,
+        buffers: &[VertexBufferId],
+        offsets: &[usize],
+        strides: &[i32],
+    ) {
+        self.bind_vertex_arrays_instanced(buffers, offsets, strides, &[]);
+    }
+
+    /// Bind vertex arrays that can advance per instance instead of per vertex,
+    /// see [`BoundPipeline::bind_vertex_arrays_instanced`]
+    ///
+    /// [`BoundPipeline::bind_vertex_arrays_instanced`]: super::BoundPipeline::bind_vertex_arrays_instanced
+    pub fn bind_vertex_arrays_instanced(
+        &mut self,
+        buffers: &[VertexBufferId],
+        offsets: &[usize],
+        strides: &[i32],
+        divisors: &[u32],
+    ) {
+        self.buffer.commands.push(Command::BindVertexArrays {
+            pipeline: self.pipeline,
+            buffers: buffers.to_vec(),
+            offsets: offsets.to_vec(),
+            strides: strides.to_vec(),
+            divisors: divisors.to_vec(),
+        });
+    }
+
+    pub fn draw(&mut self, mode: DrawingMode, start: u64, count: u64) {
+        self.draw_instanced(mode, start, count, 1);
+    }
+
+    /// draw the same verticies several times
+    pub fn draw_instanced(&mut self, mode: DrawingMode, start: u64, count: u64, instances: u64) {
+        self.buffer.commands.push(Command::Draw {
+            pipeline: self.pipeline,
+            mode,
+            start,
+            count,
+            instances,
+        });
+    }
+
+    /// draw indexed verticies using a pipeline
+    /// draws count verticies
+    pub fn draw_indicies(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+    ) {
+        self.draw_indicies_instanced(mode, indices, index_type, index_offset, count, 1);
+    }
+
+    /// draw indexed verticies several times
+    pub fn draw_indicies_instanced(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        self.buffer.commands.push(Command::DrawIndicies {
+            pipeline: self.pipeline,
+            mode,
+            indices,
+            index_type,
+            index_offset,
+            count,
+            instances,
+        });
+    }
+
+    /// make several draws in one call, see [`BoundPipeline::draw_indirect`]
+    ///
+    /// [`BoundPipeline::draw_indirect`]: super::BoundPipeline::draw_indirect
+    pub fn draw_indirect(&mut self, mode: DrawingMode, draws: &[DrawArraysIndirectCommand]) {
+        self.buffer.commands.push(Command::DrawIndirect {
+            pipeline: self.pipeline,
+            mode,
+            draws: draws.to_vec(),
+        });
+    }
+
+    /// make several indexed draws in one call
+    pub fn multi_draw_elements_indirect(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    ) {
+        self.buffer
+            .commands
+            .push(Command::MultiDrawElementsIndirect {
+                pipeline: self.pipeline,
+                mode,
+                indices,
+                index_type,
+                draws: draws.to_vec(),
+            });
+    }
+
+    /// make several draws in one call, reading their commands from an
+    /// indirect buffer, see [`BoundPipeline::draw_indirect_buffer`]
+    ///
+    /// [`BoundPipeline::draw_indirect_buffer`]: super::BoundPipeline::draw_indirect_buffer
+    pub fn draw_indirect_buffer(
+        &mut self,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) {
+        self.buffer.commands.push(Command::DrawIndirectBuffer {
+            pipeline: self.pipeline,
+            mode,
+            buffer,
+            offset,
+            count,
+        });
+    }
+
+    /// make several indexed draws in one call, reading their commands from
+    /// an indirect buffer
+    pub fn multi_draw_elements_indirect_buffer(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) {
+        self.buffer
+            .commands
+            .push(Command::MultiDrawElementsIndirectBuffer {
+                pipeline: self.pipeline,
+                mode,
+                indices,
+                index_type,
+                buffer,
+                offset,
+                count,
+            });
+    }
+
+    /// run the pipeline's compute shader, see [`BoundPipeline::dispatch`]
+    ///
+    /// [`BoundPipeline::dispatch`]: super::BoundPipeline::dispatch
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        self.buffer.commands.push(Command::Dispatch {
+            pipeline: self.pipeline,
+            x,
+            y,
+            z,
+        });
+    }
+}
+
+impl<'a> Drop for BoundCommandPipeline<'a> {
+    fn drop(&mut self) {
+        self.buffer
+            .commands
+            .push(Command::UnbindPipeline(self.pipeline));
+    }
+}
+
+/// Handle used to send command buffers to the renderer from other threads.
+/// Buffers sent are queued until the renderer's owning thread submits them.
+#[derive(Debug, Clone)]
+pub struct CommandSender {
+    ids: ResourceIds,
+    sender: Sender<CommandBuffer>,
+}
+
+impl CommandSender {
+    pub(crate) fn new(ids: ResourceIds, sender: Sender<CommandBuffer>) -> Self {
+        Self { ids, sender }
+    }
+
+    /// Create a new empty command buffer
+    pub fn command_buffer(&self) -> CommandBuffer {
+        CommandBuffer::new(self.ids.clone())
+    }
+
+    /// Queue a command buffer to be submitted to the renderer, fails if the
+    /// renderer has been dropped
+    pub fn send(&self, buffer: CommandBuffer) -> Result<(), SendError<CommandBuffer>> {
+        self.sender.send(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nalgebra_glm as glm;
+    use std::thread;
+
+    use crate::renderer::{
+        recording::{Call, RecordingRenderer},
+        shader::{BuiltinVariable, Program, Type},
+        DrawingMode, Renderer,
+    };
+
+    #[test]
+    fn test_cross_thread_submit() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let sender = renderer.sender();
+        let (pipeline, buffer) = thread::spawn(move || {
+            let mut program = Program::new(|ctx| {
+                ctx.vertex(|ctx| {
+                    let transform = ctx.uniform("transform", Type::Mat4);
+                    let position = ctx.input("position", Type::Vec4);
+                    ctx.set_builtin(BuiltinVariable::VertexPosition, transform * position);
+                });
+            });
+            program.ok().unwrap();
+
+            let mut commands = sender.command_buffer();
+            let pipeline = commands.load_pipeline(program);
+            let buffer = commands.load_vertex_buffer(vec![0; 48]);
+
+            {
+                let mut bound = commands.bind_pipeline(pipeline);
+                bound.bind_matrix("transform", glm::Mat4::identity());
+                bound.bind_vertex_arrays(&[buffer], &[0], &[16]);
+                bound.draw(DrawingMode::Triangles, 0, 3);
+            }
+
+            sender.send(commands).unwrap();
+            (pipeline, buffer)
+        })
+        .join()
+        .unwrap();
+
+        // nothing is run until the queue is submitted
+        assert!(log.calls().is_empty());
+
+        // ids reserved on the other thread do not overlap with ones allocated
+        // directly by the renderer
+        let other = renderer.load_vertex_buffer(&[0; 4]);
+        assert_ne!(other, buffer);
+
+        renderer.submit_queued()?;
+
+        let calls = log.calls();
+        assert!(matches!(calls[1], Call::LoadPipeline { pipeline: id, .. } if id == pipeline));
+        assert!(matches!(
+            calls[2],
+            Call::LoadVertexBuffer { buffer: id, .. } if id == buffer
+        ));
+        assert!(matches!(calls[7], Call::UnbindPipeline { pipeline: id } if id == pipeline));
+        assert_eq!(log.draw_count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_submit() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec4);
+                ctx.set_builtin(BuiltinVariable::VertexPosition, position);
+            });
+        });
+        program.ok()?;
+        let pipeline = renderer.load_pipeline(program)?;
+
+        let mut commands = renderer.command_buffer();
+        let texture = commands.reserve_texture_id();
+        commands
+            .bind_pipeline(pipeline)
+            .bind_texture("tex", texture);
+        commands.clear(0.0, 0.0, 0.0);
+
+        // binding the unloaded texture fails, so the clear is never run, but
+        // the pipeline is still unbound
+        assert!(renderer.submit(commands).is_err());
+        assert!(matches!(
+            log.calls().last(),
+            Some(Call::UnbindPipeline { pipeline: id }) if *id == pipeline
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_ids() -> Result<()> {
+        let mut renderer = Renderer::new(Box::new(RecordingRenderer::new()));
+
+        // a reserved id cannot be used until it is loaded
+        let reserved = renderer.reserve_vertex_buffer_id();
+        assert!(renderer.update_vertex_buffer(reserved, 0, &[1]).is_err());
+        assert!(renderer.unload_vertex_buffer(reserved).is_err());
+
+        let mut commands = renderer.command_buffer();
+        commands.load_vertex_buffer_reserved(reserved, vec![0; 4]);
+        renderer.submit(commands)?;
+        renderer.update_vertex_buffer(reserved, 0, &[1])?;
+
+        // loads after a failed command are not run, so their ids are freed
+        let mut commands = renderer.command_buffer();
+        commands.unload_vertex_buffer(reserved);
+        commands.unload_vertex_buffer(reserved);
+        let buffer = commands.load_vertex_buffer(vec![0; 4]);
+        assert!(renderer.submit(commands).is_err());
+        assert!(renderer.update_vertex_buffer(buffer, 0, &[1]).is_err());
+
+        // as are the ids of loads in command buffers that are never submitted
+        let mut commands = renderer.command_buffer();
+        let indices = commands.load_index_buffer(vec![0; 4]);
+        drop(commands);
+        assert!(renderer.update_index_buffer(indices, 0, &[1]).is_err());
+
+        assert!(renderer.leak_report().is_empty());
+
+        Ok(())
+    }
+}
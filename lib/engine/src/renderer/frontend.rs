@@ -1,45 +1,152 @@
 use anyhow::Result;
 use nalgebra_glm as glm;
+use std::{
+    convert::TryInto,
+    sync::mpsc::{self, Receiver, Sender},
+};
 
-use super::{backend::RendererBackend, shader::Program};
-use crate::texture::Texture;
+use super::{
+    backend::RendererBackend, shader::Program, CommandBuffer, CommandSender, GpuFrame, GpuTimers,
+    Handle, LeakReport, Owned, PixelFormat, PixelRegion, Pixels, RendererCapabilities,
+    RendererStats, Resource, ResourceIds, ResourceKind, UniformValue, VertexFormat,
+};
+use crate::texture::{
+    MagFilter, MinFilter, Texture, TextureOptions, TextureRegion, TextureSourceFormat,
+    TextureSourceType, TextureStorageType, WrappingMode,
+};
 
-/// type inside all *Id tuple structs
-pub type IdType = u64;
+// Each id contains a generational handle, so using an id after it has been
+// unloaded is reported as an error instead of reaching the backend
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextureId(pub(crate) Handle);
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct VertexBufferId(pub(crate) Handle);
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct IndexBufferId(pub(crate) Handle);
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PipelineId(pub(crate) Handle);
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
-pub struct TextureId(pub(crate) IdType);
+pub struct UniformBufferId(pub(crate) Handle);
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
-pub struct VertexBufferId(pub(crate) IdType);
+pub struct StorageBufferId(pub(crate) Handle);
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
-pub struct IndexBufferId(pub(crate) IdType);
+pub struct IndirectBufferId(pub(crate) Handle);
 
+/// A render target, along with the textures used as its attachments
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
-pub struct PipelineId(pub(crate) IdType);
+pub struct RenderTargetId {
+    pub(crate) id: Handle,
+    color: Option<TextureId>,
+    depth: Option<TextureId>,
+}
+
+impl RenderTargetId {
+    /// Reserve the ids for a render target and each of its attachments
+    pub(crate) fn new(ids: &ResourceIds, options: &RenderTargetOptions) -> Self {
+        Self {
+            id: ids.next(ResourceKind::RenderTarget),
+            color: options
+                .color
+                .map(|_| TextureId(ids.next(ResourceKind::Texture))),
+            depth: if options.depth {
+                Some(TextureId(ids.next(ResourceKind::Texture)))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// The texture that is drawn into as the color buffer, can be bound to
+    /// pipelines once the render target is no longer bound
+    pub fn color(&self) -> Option<TextureId> {
+        self.color
+    }
+
+    /// The texture that is used as the depth buffer, sampling it returns the
+    /// depth in the red component
+    pub fn depth(&self) -> Option<TextureId> {
+        self.depth
+    }
+}
 
 pub struct Renderer {
     backend: Box<dyn RendererBackend>,
+
+    /// Id allocator, shared with all command buffers created by this renderer
+    ids: ResourceIds,
+
+    /// Command buffers sent from other threads, waiting to be submitted
+    queue: Receiver<CommandBuffer>,
+
+    /// Sending half of the queue, cloned into every command sender
+    sender: Sender<CommandBuffer>,
+
+    /// Timestamp queries of the gpu profiling scopes
+    timers: GpuTimers,
+
+    /// Counts of the work submitted since the stats were last reset
+    stats: RendererStats,
+
+    /// The backend's total number of skipped state changes when the stats
+    /// were last reset
+    skipped_at_reset: u64,
+
+    /// The backend's features and limits, they do not change once it is
+    /// created
+    capabilities: RendererCapabilities,
 }
 
 impl Renderer {
     pub fn new(backend: Box<dyn RendererBackend>) -> Self {
-        Self { backend }
+        let (sender, queue) = mpsc::channel();
+        let capabilities = backend.capabilities();
+
+        Self {
+            backend,
+            capabilities,
+            ids: ResourceIds::default(),
+            queue,
+            sender,
+            timers: GpuTimers::default(),
+            stats: RendererStats::default(),
+            skipped_at_reset: 0,
+        }
     }
 
-    /// Clear the screen to the specified color
+    /// Clear the screen to the specified color, the depth buffer is cleared
+    /// to the far plane and the stencil buffer to 0
     #[inline(always)]
     pub fn clear(&mut self, r: f32, g: f32, b: f32) {
         self.backend.clear(r, g, b)
     }
 
+    /// Clear only the stencil buffer to the specified value, every bit is
+    /// changed whatever the current stencil write mask is
+    #[inline(always)]
+    pub fn clear_stencil(&mut self, value: u8) {
+        self.backend.clear_stencil(value)
+    }
+
     /// Set the viewport size
     #[inline(always)]
     pub fn viewport(&mut self, width: u32, height: u32) {
         self.backend.viewport(width, height)
     }
 
+    /// The size of the viewport used when drawing to the window, which is the
+    /// last viewport set while no render target was bound
+    #[inline(always)]
+    pub fn screen_viewport(&self) -> (u32, u32) {
+        self.backend.screen_viewport()
+    }
+
     /// Enable or disable backface culling
     #[inline(always)]
     pub fn backface_culling(&mut self, enable: CullingMode) {
@@ -51,70 +158,527 @@ impl Renderer {
         self.backend.depth_testing(mode)
     }
 
+    /// Set how fragments are tested against and written to the stencil buffer
+    #[inline(always)]
+    pub fn stencil_testing(&mut self, mode: StencilTesting) {
+        self.backend.stencil_testing(mode)
+    }
+
+    /// Set how fragments are combined with the color already drawn
+    #[inline(always)]
+    pub fn blending(&mut self, mode: BlendMode) {
+        self.backend.blending(mode)
+    }
+
+    /// Code outside of the renderer has changed the graphics api's state, e.g.
+    /// raw gl calls, so the backend's cached state has to be set again
+    #[inline(always)]
+    pub fn invalidate_state_cache(&mut self) {
+        self.backend.invalidate_state_cache()
+    }
+
+    /// The features and limits of the backend, used to choose between code
+    /// paths and check resources before loading them
+    #[inline(always)]
+    pub fn capabilities(&self) -> &RendererCapabilities {
+        &self.capabilities
+    }
+
+    /// The maximum anisotropy supported by the backend, 1 if anisotropic
+    /// filtering is not supported
+    #[inline(always)]
+    pub fn max_anisotropy(&self) -> u32 {
+        self.capabilities.max_anisotropy
+    }
+
+    /// Start timing a named scope of gpu work, scopes can be nested and must
+    /// be ended with [`Self::end_scope`]
+    pub fn begin_scope(&mut self, name: &str) {
+        let query = self.timers.begin_scope(name);
+        self.backend.timestamp(query);
+    }
+
+    /// End the most recently started gpu scope
+    pub fn end_scope(&mut self) {
+        if let Some(query) = self.timers.end_scope() {
+            self.backend.timestamp(query);
+        }
+    }
+
+    /// Finish recording the gpu scopes of a frame, returns the timings of any
+    /// earlier frames that the gpu has finished.  Never waits for the gpu, so
+    /// timings are usually returned a few frames after they were recorded
+    pub fn end_profile_frame(&mut self, frame: u64) -> Vec<GpuFrame> {
+        self.timers.end_frame(frame, &mut *self.backend)
+    }
+
+    /// The work submitted since the stats were last reset
+    pub fn stats(&self) -> RendererStats {
+        RendererStats {
+            skipped_state_changes: self.backend.skipped_state_changes() - self.skipped_at_reset,
+            ..self.stats
+        }
+    }
+
+    /// Start counting the work submitted from zero, returns the stats from
+    /// before they were reset
+    pub fn reset_stats(&mut self) -> RendererStats {
+        let stats = self.stats();
+        self.stats = RendererStats::default();
+        self.skipped_at_reset = self.backend.skipped_state_changes();
+        stats
+    }
+
     /// Load a new texture
     #[inline(always)]
     pub fn load_texture(&mut self, texture: Texture) -> TextureId {
-        self.backend.load_texture(texture)
+        let id = self.reserve_texture_id();
+        self.stats.upload(texture.data_size());
+        self.backend.load_texture(id, texture);
+        self.loaded(id)
+    }
+
+    /// Unload a texture, the id cannot be used again.  The textures of a
+    /// render target's attachments are unloaded along with the render target.
+    #[inline(always)]
+    pub fn unload_texture(&mut self, texture: TextureId) -> Result<()> {
+        self.ids.release(texture)?;
+        self.backend.unload_texture(texture);
+        Ok(())
     }
 
-    /// Unload a texture
+    /// Replace a loaded texture with a new one, which can have a different
+    /// size or format, without changing its id.  The textures of a render
+    /// target's attachments cannot be replaced.
     #[inline(always)]
-    pub fn unload_texture(&mut self, texture: TextureId) {
-        self.backend.unload_texture(texture)
+    pub fn reload_texture(&mut self, id: TextureId, texture: Texture) -> Result<()> {
+        self.ids.check_replaceable(id)?;
+        self.stats.upload(texture.data_size());
+        self.backend.unload_texture(id);
+        self.backend.load_texture(id, texture);
+        Ok(())
+    }
+
+    /// Replace a region of a texture's pixel data.  The data must be tightly
+    /// packed rows using the source format and type the texture was loaded
+    /// with, see [`TextureRegion::check`].
+    #[inline(always)]
+    pub fn update_texture(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        self.ids.check(texture)?;
+        self.stats.upload(data.len());
+        self.backend.update_texture(texture, region, data)
+    }
+
+    /// Replace a region of a texture's pixel data for streaming upload, e.g.
+    /// video frames, the upload can happen asynchronously.  See
+    /// [`Self::update_texture`].
+    #[inline(always)]
+    pub fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        self.ids.check(texture)?;
+        self.stats.upload(data.len());
+        self.backend.update_texture_stream(texture, region, data)
     }
 
     /// Load data as a vertex buffer
     #[inline(always)]
     pub fn load_vertex_buffer(&mut self, data: &[u8]) -> VertexBufferId {
-        self.backend.load_vertex_buffer(data)
+        let id = self.reserve_vertex_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_vertex_buffer(id, data);
+        self.loaded(id)
     }
 
     /// Load data as a vertex buffer for streaming upload
     #[inline(always)]
     pub fn load_vertex_buffer_stream(&mut self, data: &[u8]) -> VertexBufferId {
-        self.backend.load_vertex_buffer_stream(data)
+        let id = self.reserve_vertex_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_vertex_buffer_stream(id, data);
+        self.loaded(id)
     }
 
-    /// Unload a vertex buffer
+    /// Create a vertex buffer of a fixed size in bytes, for geometry that is
+    /// rebuilt every frame.  Data written to it stays valid until the buffer
+    /// wraps back around to it, so draws using it should be made before
+    /// another `size` bytes are written.
     #[inline(always)]
-    pub fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) {
-        self.backend.unload_vertex_buffer(buffer)
+    pub fn load_vertex_ring_buffer(&mut self, size: usize) -> VertexBufferId {
+        let id = self.reserve_vertex_buffer_id();
+        self.backend.load_vertex_ring_buffer(id, size);
+        self.loaded(id)
+    }
+
+    /// Unload a vertex buffer, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) -> Result<()> {
+        self.ids.release(buffer)?;
+        self.backend.unload_vertex_buffer(buffer);
+        Ok(())
+    }
+
+    /// Replace part of a vertex buffer's data, starting at a byte offset, the
+    /// data must fit inside the buffer.  Ring buffers can only be written
+    /// using [`Self::write_vertex_ring_buffer`].
+    #[inline(always)]
+    pub fn update_vertex_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.update_vertex_buffer(buffer, offset, data)
+    }
+
+    /// Replace all of a vertex buffer's data, the buffer can change size and
+    /// previous draws using the old data do not have to finish first
+    #[inline(always)]
+    pub fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<()> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.orphan_vertex_buffer(buffer, data)
+    }
+
+    /// Write data into a vertex ring buffer, returns the byte offset to bind
+    /// the buffer with to use the data
+    #[inline(always)]
+    pub fn write_vertex_ring_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        data: &[u8],
+    ) -> Result<usize> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.write_vertex_ring_buffer(buffer, data)
     }
 
     /// Load data as an index buffer
     #[inline(always)]
     pub fn load_index_buffer(&mut self, data: &[u8]) -> IndexBufferId {
-        self.backend.load_index_buffer(data)
+        let id = self.reserve_index_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_index_buffer(id, data);
+        self.loaded(id)
     }
 
     /// Load data as an index buffer for streaming upload
     #[inline(always)]
     pub fn load_index_buffer_stream(&mut self, data: &[u8]) -> IndexBufferId {
-        self.backend.load_index_buffer_stream(data)
+        let id = self.reserve_index_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_index_buffer_stream(id, data);
+        self.loaded(id)
+    }
+
+    /// Create an index buffer of a fixed size in bytes, see
+    /// [`Self::load_vertex_ring_buffer`]
+    #[inline(always)]
+    pub fn load_index_ring_buffer(&mut self, size: usize) -> IndexBufferId {
+        let id = self.reserve_index_buffer_id();
+        self.backend.load_index_ring_buffer(id, size);
+        self.loaded(id)
+    }
+
+    /// Unload an index buffer, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_index_buffer(&mut self, buffer: IndexBufferId) -> Result<()> {
+        self.ids.release(buffer)?;
+        self.backend.unload_index_buffer(buffer);
+        Ok(())
     }
 
-    /// Unload an index buffer
+    /// Replace part of an index buffer's data, starting at a byte offset, the
+    /// data must fit inside the buffer.  Ring buffers can only be written
+    /// using [`Self::write_index_ring_buffer`].
     #[inline(always)]
-    pub fn unload_index_buffer(&mut self, buffer: IndexBufferId) {
-        self.backend.unload_index_buffer(buffer)
+    pub fn update_index_buffer(
+        &mut self,
+        buffer: IndexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.update_index_buffer(buffer, offset, data)
     }
 
-    /// Load a new pipeline, including shader compilation
+    /// Replace all of an index buffer's data, see
+    /// [`Self::orphan_vertex_buffer`]
     #[inline(always)]
-    pub fn load_pipeline(&mut self, pipeline: Program) -> Result<PipelineId> {
-        self.backend.load_pipeline(pipeline)
+    pub fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<()> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.orphan_index_buffer(buffer, data)
     }
 
-    /// Unload a pipeline
+    /// Write data into an index ring buffer, returns the byte offset to draw
+    /// the indices from
     #[inline(always)]
-    pub fn unload_pipeline(&mut self, pipeline: PipelineId) {
-        self.backend.unload_pipeline(pipeline)
+    pub fn write_index_ring_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<usize> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.write_index_ring_buffer(buffer, data)
+    }
+
+    /// Load data as a uniform buffer, the data should be laid out using the
+    /// std140 rules, see [`UniformValue::std140`]
+    #[inline(always)]
+    pub fn load_uniform_buffer(&mut self, data: &[u8]) -> UniformBufferId {
+        let id = self.reserve_uniform_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_uniform_buffer(id, data);
+        self.loaded(id)
+    }
+
+    /// Unload a uniform buffer, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_uniform_buffer(&mut self, buffer: UniformBufferId) -> Result<()> {
+        self.ids.release(buffer)?;
+        self.backend.unload_uniform_buffer(buffer);
+        Ok(())
+    }
+
+    /// Load data as a storage buffer, which shaders can read and write, see
+    /// [`ProgramContext::storage_buffer`]
+    ///
+    /// [`ProgramContext::storage_buffer`]: super::shader::ProgramContext::storage_buffer
+    #[inline(always)]
+    pub fn load_storage_buffer(&mut self, data: &[u8]) -> StorageBufferId {
+        let id = self.reserve_storage_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_storage_buffer(id, data);
+        self.loaded(id)
+    }
+
+    /// Unload a storage buffer, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<()> {
+        self.ids.release(buffer)?;
+        self.backend.unload_storage_buffer(buffer);
+        Ok(())
+    }
+
+    /// Replace part of a storage buffer's data, starting at a byte offset, the
+    /// data must fit inside the buffer
+    #[inline(always)]
+    pub fn update_storage_buffer(
+        &mut self,
+        buffer: StorageBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.ids.check(buffer)?;
+        self.stats.upload(data.len());
+        self.backend.update_storage_buffer(buffer, offset, data)
+    }
+
+    /// Read back all of a storage buffer's data, waits for every dispatch and
+    /// draw writing to the buffer to finish
+    #[inline(always)]
+    pub fn read_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<Vec<u8>> {
+        self.ids.check(buffer)?;
+        self.backend.read_storage_buffer(buffer)
+    }
+
+    /// Load indirect draw commands, laid out as by
+    /// [`DrawArraysIndirectCommand::to_bytes`] and
+    /// [`DrawElementsIndirectCommand::to_bytes`], so that they are uploaded
+    /// once rather than every time they are drawn, see
+    /// [`BoundPipeline::draw_indirect_buffer`]
+    #[inline(always)]
+    pub fn load_indirect_buffer(&mut self, data: &[u8]) -> IndirectBufferId {
+        let id = self.reserve_indirect_buffer_id();
+        self.stats.upload(data.len());
+        self.backend.load_indirect_buffer(id, data);
+        self.loaded(id)
+    }
+
+    /// Unload an indirect buffer, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId) -> Result<()> {
+        self.ids.release(buffer)?;
+        self.backend.unload_indirect_buffer(buffer);
+        Ok(())
+    }
+
+    /// Load a new pipeline, including shader compilation.  Either a program,
+    /// or a [`PipelineDescriptor`] with the state to draw using.
+    #[inline(always)]
+    pub fn load_pipeline(&mut self, pipeline: impl Into<PipelineDescriptor>) -> Result<PipelineId> {
+        let id = self.reserve_pipeline_id();
+        if let Err(e) = self.backend.load_pipeline(id, pipeline.into()) {
+            self.ids.unreserve(id);
+            return Err(e);
+        }
+        Ok(self.loaded(id))
+    }
+
+    /// Unload a pipeline, the id cannot be used again
+    #[inline(always)]
+    pub fn unload_pipeline(&mut self, pipeline: PipelineId) -> Result<()> {
+        self.ids.release(pipeline)?;
+        self.backend.unload_pipeline(pipeline);
+        Ok(())
+    }
+
+    /// Create a new render target, its attachments are textures so can be
+    /// sampled after drawing into them
+    #[inline(always)]
+    pub fn load_render_target(&mut self, options: RenderTargetOptions) -> Result<RenderTargetId> {
+        let id = self.reserve_render_target_id(&options);
+        if let Err(e) = self.backend.load_render_target(id, options) {
+            self.ids.unreserve_render_target(id);
+            return Err(e);
+        }
+        self.ids.load_render_target(id)?;
+        Ok(id)
+    }
+
+    /// Unload a render target, including the textures of its attachments
+    #[inline(always)]
+    pub fn unload_render_target(&mut self, target: RenderTargetId) -> Result<()> {
+        self.ids.release_render_target(target)?;
+        self.backend.unload_render_target(target);
+        Ok(())
+    }
+
+    /// Set where all future clear and draw calls output to, None is the main
+    /// window.  The viewport is set to cover the whole of the new target.
+    #[inline(always)]
+    pub fn bind_render_target(&mut self, target: Option<RenderTargetId>) -> Result<()> {
+        if let Some(target) = target {
+            self.ids.check(target)?;
+        }
+        self.backend.bind_render_target(target);
+        Ok(())
+    }
+
+    /// Read a region of the current render target's color buffer back to the
+    /// cpu, waits for every earlier draw to finish
+    #[inline(always)]
+    pub fn read_pixels(&mut self, region: PixelRegion, format: PixelFormat) -> Result<Pixels> {
+        let data = self.backend.read_pixels(region, format)?;
+        Ok(Pixels::from_rows(region, format, &data))
     }
 
     /// Bind a pipeline so it can be used for drawing
     #[inline(always)]
-    pub fn bind_pipeline(&mut self, pipeline: PipelineId) -> BoundPipeline {
-        BoundPipeline::new(self, pipeline)
+    pub fn bind_pipeline(&mut self, pipeline: PipelineId) -> Result<BoundPipeline<'_>> {
+        self.ids.check(pipeline)?;
+        Ok(BoundPipeline::new(self, pipeline))
+    }
+
+    /// Mark an id reserved by one of the load functions as loaded
+    fn loaded<T: Resource>(&self, id: T) -> T {
+        // the id was reserved by the caller, so cannot have been loaded or
+        // released yet
+        self.ids.load(id).unwrap();
+        id
+    }
+
+    /// Take ownership of a loaded resource, so that it is unloaded when the
+    /// returned owner is dropped, see [`Owned`]
+    pub fn owned<T: Resource>(&self, id: T) -> Owned<T> {
+        Owned::new(id, self.sender())
+    }
+
+    /// List all the resources that are currently loaded, this is printed when
+    /// the renderer is dropped in debug builds if anything was not unloaded
+    pub fn leak_report(&self) -> LeakReport {
+        self.ids.leak_report()
+    }
+
+    /// Reserve an id for a texture that will be loaded later
+    pub fn reserve_texture_id(&self) -> TextureId {
+        TextureId(self.ids.next(ResourceKind::Texture))
+    }
+
+    /// Reserve an id for a vertex buffer that will be loaded later
+    pub fn reserve_vertex_buffer_id(&self) -> VertexBufferId {
+        VertexBufferId(self.ids.next(ResourceKind::VertexBuffer))
+    }
+
+    /// Reserve an id for an index buffer that will be loaded later
+    pub fn reserve_index_buffer_id(&self) -> IndexBufferId {
+        IndexBufferId(self.ids.next(ResourceKind::IndexBuffer))
+    }
+
+    /// Reserve an id for a uniform buffer that will be loaded later
+    pub fn reserve_uniform_buffer_id(&self) -> UniformBufferId {
+        UniformBufferId(self.ids.next(ResourceKind::UniformBuffer))
+    }
+
+    /// Reserve an id for a storage buffer that will be loaded later
+    pub fn reserve_storage_buffer_id(&self) -> StorageBufferId {
+        StorageBufferId(self.ids.next(ResourceKind::StorageBuffer))
+    }
+
+    /// Reserve an id for an indirect buffer that will be loaded later
+    pub fn reserve_indirect_buffer_id(&self) -> IndirectBufferId {
+        IndirectBufferId(self.ids.next(ResourceKind::IndirectBuffer))
+    }
+
+    /// Reserve an id for a pipeline that will be loaded later
+    pub fn reserve_pipeline_id(&self) -> PipelineId {
+        PipelineId(self.ids.next(ResourceKind::Pipeline))
+    }
+
+    /// Reserve the ids for a render target that will be loaded later
+    pub fn reserve_render_target_id(&self, options: &RenderTargetOptions) -> RenderTargetId {
+        RenderTargetId::new(&self.ids, options)
+    }
+
+    /// Create a new empty command buffer
+    pub fn command_buffer(&self) -> CommandBuffer {
+        CommandBuffer::new(self.ids.clone())
+    }
+
+    /// Get a handle that can be used to send command buffers to this renderer
+    /// from any thread
+    pub fn sender(&self) -> CommandSender {
+        CommandSender::new(self.ids.clone(), self.sender.clone())
+    }
+
+    /// Run all the commands in a command buffer
+    pub fn submit(&mut self, buffer: CommandBuffer) -> Result<()> {
+        buffer.execute(&mut *self.backend, &mut self.stats)
+    }
+
+    /// Submit all the command buffers sent to this renderer, in the order they
+    /// were sent.  Stops at the first buffer that fails.
+    pub fn submit_queued(&mut self) -> Result<()> {
+        while let Ok(buffer) = self.queue.try_recv() {
+            self.submit(buffer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // resources owned by anything dropped before the renderer have their
+        // unloads queued, so they are not leaks
+        let _ = self.submit_queued();
+
+        #[cfg(debug_assertions)]
+        {
+            let report = self.leak_report();
+            if !report.is_empty() {
+                eprintln!("{}", report);
+            }
+        }
     }
 }
 
@@ -125,6 +689,7 @@ pub struct BoundPipeline<'a> {
 
 impl<'a> BoundPipeline<'a> {
     pub fn new(renderer: &'a mut Renderer, pipeline: PipelineId) -> Self {
+        renderer.stats.pipeline_binds += 1;
         renderer.backend.bind_pipeline(pipeline);
         Self { renderer, pipeline }
     }
@@ -136,26 +701,83 @@ impl<'a> BoundPipeline<'a> {
     }
 
     pub fn bind_texture(&mut self, name: &str, texture: TextureId) -> Result<()> {
+        self.renderer.ids.check(texture)?;
+        self.renderer.stats.texture_binds += 1;
         self.renderer
             .backend
             .pipeline_bind_texture(self.pipeline, name, texture)
     }
 
+    /// Bind a value to a uniform, returns an error if the pipeline does not
+    /// have a uniform with the name and type of the value
+    pub fn bind_uniform(&mut self, name: &str, value: impl Into<UniformValue>) -> Result<()> {
+        self.renderer
+            .backend
+            .pipeline_bind_uniform(self.pipeline, name, &value.into())
+    }
+
+    /// Bind a uniform buffer to the uniform block with the given name
+    pub fn bind_uniform_buffer(&mut self, name: &str, buffer: UniformBufferId) -> Result<()> {
+        self.renderer.ids.check(buffer)?;
+        self.renderer
+            .backend
+            .pipeline_bind_uniform_buffer(self.pipeline, name, buffer)
+    }
+
+    /// Bind a storage buffer to the storage buffer with the given name
+    pub fn bind_storage_buffer(&mut self, name: &str, buffer: StorageBufferId) -> Result<()> {
+        self.renderer.ids.check(buffer)?;
+        self.renderer
+            .backend
+            .pipeline_bind_storage_buffer(self.pipeline, name, buffer)
+    }
+
     pub fn bind_vertex_arrays(
         &mut self,
         buffers: &[VertexBufferId],
         offsets: &[usize],
         strides: &[i32],
-    ) {
-        self.renderer
-            .backend
-            .pipeline_bind_vertex_arrays(self.pipeline, buffers, offsets, strides);
+    ) -> Result<()> {
+        self.bind_vertex_arrays_instanced(buffers, offsets, strides, &[])
+    }
+
+    /// Bind vertex arrays that can advance per instance instead of per vertex,
+    /// an array with divisor n advances once every n instances, a divisor of
+    /// 0 advances once per vertex.  No divisors is the same as all of them 0.
+    pub fn bind_vertex_arrays_instanced(
+        &mut self,
+        buffers: &[VertexBufferId],
+        offsets: &[usize],
+        strides: &[i32],
+        divisors: &[u32],
+    ) -> Result<()> {
+        for &buffer in buffers {
+            self.renderer.ids.check(buffer)?;
+        }
+
+        self.renderer.backend.pipeline_bind_vertex_arrays(
+            self.pipeline,
+            buffers,
+            offsets,
+            strides,
+            divisors,
+        );
+        Ok(())
     }
 
     pub fn draw(&mut self, mode: DrawingMode, start: u64, count: u64) {
+        self.draw_instanced(mode, start, count, 1);
+    }
+
+    /// draw the same verticies several times, the shader can read which
+    /// instance is being drawn from [`BuiltinVariable::InstanceId`]
+    ///
+    /// [`BuiltinVariable::InstanceId`]: super::shader::BuiltinVariable::InstanceId
+    pub fn draw_instanced(&mut self, mode: DrawingMode, start: u64, count: u64, instances: u64) {
+        self.renderer.stats.draw(mode, count, instances);
         self.renderer
             .backend
-            .draw(self.pipeline, mode, start, count);
+            .draw(self.pipeline, mode, start, count, instances);
     }
 
     /// draw indexed verticies using a pipeline
@@ -167,7 +789,24 @@ impl<'a> BoundPipeline<'a> {
         index_type: IndexType,
         index_offset: usize,
         count: usize,
-    ) {
+    ) -> Result<()> {
+        self.draw_indicies_instanced(mode, indices, index_type, index_offset, count, 1)
+    }
+
+    /// draw indexed verticies several times, see [`BoundPipeline::draw_instanced`]
+    pub fn draw_indicies_instanced(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    ) -> Result<()> {
+        self.renderer.ids.check(indices)?;
+        self.renderer
+            .stats
+            .draw(mode, count as u64, instances as u64);
         self.renderer.backend.draw_indicies(
             self.pipeline,
             mode,
@@ -175,7 +814,100 @@ impl<'a> BoundPipeline<'a> {
             index_type,
             index_offset,
             count,
+            instances,
+        );
+        Ok(())
+    }
+
+    /// Make several draws with a single call, each with its own range of
+    /// verticies, instances and base instance
+    pub fn draw_indirect(&mut self, mode: DrawingMode, draws: &[DrawArraysIndirectCommand]) {
+        self.renderer.stats.draw_indirect(
+            mode,
+            draws
+                .iter()
+                .map(|draw| (draw.count as u64, draw.instances as u64)),
         );
+        self.renderer
+            .backend
+            .draw_indirect(self.pipeline, mode, draws);
+    }
+
+    /// Make several indexed draws from the same index buffer with a single
+    /// call, see [`BoundPipeline::draw_indirect`]
+    pub fn multi_draw_elements_indirect(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    ) -> Result<()> {
+        self.renderer.ids.check(indices)?;
+        self.renderer.stats.draw_indirect(
+            mode,
+            draws
+                .iter()
+                .map(|draw| (draw.count as u64, draw.instances as u64)),
+        );
+        self.renderer.backend.multi_draw_elements_indirect(
+            self.pipeline,
+            mode,
+            indices,
+            index_type,
+            draws,
+        );
+        Ok(())
+    }
+
+    /// Make `count` draws with a single call, reading their commands from an
+    /// indirect buffer starting at a byte offset, which must be a multiple of
+    /// 4.  The commands are only stored on the gpu, so only the draw call is
+    /// counted in the renderer stats, not its vertices or triangles.
+    pub fn draw_indirect_buffer(
+        &mut self,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        self.renderer.ids.check(buffer)?;
+        self.renderer.stats.draw_indirect(mode, std::iter::empty());
+        self.renderer
+            .backend
+            .draw_indirect_buffer(self.pipeline, mode, buffer, offset, count)
+    }
+
+    /// Make `count` indexed draws from the same index buffer with a single
+    /// call, see [`BoundPipeline::draw_indirect_buffer`]
+    pub fn multi_draw_elements_indirect_buffer(
+        &mut self,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        self.renderer.ids.check(indices)?;
+        self.renderer.ids.check(buffer)?;
+        self.renderer.stats.draw_indirect(mode, std::iter::empty());
+        self.renderer.backend.multi_draw_elements_indirect_buffer(
+            self.pipeline,
+            mode,
+            indices,
+            index_type,
+            buffer,
+            offset,
+            count,
+        )
+    }
+
+    /// Run the pipeline's compute shader with the given number of workgroups
+    /// along each axis.  Writes to storage buffers are visible to all
+    /// dispatches, draws and reads made afterwards.
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        self.renderer.stats.dispatches += 1;
+        self.renderer.backend.dispatch(self.pipeline, x, y, z);
     }
 }
 
@@ -203,6 +935,112 @@ pub enum IndexType {
     U32,
 }
 
+impl IndexType {
+    /// The size of a single index in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+}
+
+/// One draw made by [`BoundPipeline::draw_indirect`], laid out the same as
+/// OpenGl's indirect command so that a slice of them can be uploaded directly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct DrawArraysIndirectCommand {
+    pub count: u32,
+    pub instances: u32,
+    pub first: u32,
+
+    /// Added to the element index of per instance vertex arrays, but not to
+    /// the instance id read by the shader
+    pub base_instance: u32,
+}
+
+impl DrawArraysIndirectCommand {
+    /// The size of the command in an indirect buffer
+    pub const SIZE: usize = 16;
+
+    /// The command laid out as it is read from an indirect buffer
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let fields = [self.count, self.instances, self.first, self.base_instance];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(&fields) {
+            chunk.copy_from_slice(&field.to_ne_bytes());
+        }
+
+        bytes
+    }
+
+    /// Read a command written by [`Self::to_bytes`]
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let field = |i: usize| u32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+
+        Self {
+            count: field(0),
+            instances: field(1),
+            first: field(2),
+            base_instance: field(3),
+        }
+    }
+}
+
+/// One draw made by [`BoundPipeline::multi_draw_elements_indirect`], laid
+/// out the same as OpenGl's indirect command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct DrawElementsIndirectCommand {
+    pub count: u32,
+    pub instances: u32,
+
+    /// The first index to read, measured in indices rather than bytes
+    pub first_index: u32,
+
+    /// Added to each index read from the index buffer
+    pub base_vertex: i32,
+
+    /// Added to the element index of per instance vertex arrays
+    pub base_instance: u32,
+}
+
+impl DrawElementsIndirectCommand {
+    /// The size of the command in an indirect buffer
+    pub const SIZE: usize = 20;
+
+    /// The command laid out as it is read from an indirect buffer
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let fields = [
+            self.count,
+            self.instances,
+            self.first_index,
+            self.base_vertex as u32,
+            self.base_instance,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(&fields) {
+            chunk.copy_from_slice(&field.to_ne_bytes());
+        }
+
+        bytes
+    }
+
+    /// Read a command written by [`Self::to_bytes`]
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let field = |i: usize| u32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+
+        Self {
+            count: field(0),
+            instances: field(1),
+            first_index: field(2),
+            base_vertex: field(3) as i32,
+            base_instance: field(4),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CullingMode {
     None,
@@ -239,3 +1077,326 @@ impl DepthTesting {
         func: DepthTestingFunction::Less,
     };
 }
+
+/// Stencil testing compares `reference & mask` against the value in the
+/// stencil buffer `& mask` using the function, e.g. `Less` passes if the
+/// reference is less than the stored value.  Depending on the outcome of the
+/// stencil and depth tests one of the operations is applied to the stored value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StencilTesting {
+    /// Every fragment passes and the stencil buffer is not written to
+    None,
+
+    Enabled {
+        func: DepthTestingFunction,
+        reference: u8,
+        mask: u8,
+
+        /// Only the bits set in the write mask are changed by the operations
+        write_mask: u8,
+
+        /// Applied when the stencil test fails
+        fail: StencilOperation,
+
+        /// Applied when the stencil test passes but the depth test fails
+        depth_fail: StencilOperation,
+
+        /// Applied when both the stencil and depth tests pass, or the stencil
+        /// test passes and depth testing is disabled
+        pass: StencilOperation,
+    },
+}
+
+impl StencilTesting {
+    /// Write the reference value everywhere that is drawn to, e.g. to mark
+    /// the area of a mirror or a selected object
+    pub const fn write(reference: u8) -> Self {
+        StencilTesting::Enabled {
+            func: DepthTestingFunction::Always,
+            reference,
+            mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOperation::Keep,
+            depth_fail: StencilOperation::Keep,
+            pass: StencilOperation::Replace,
+        }
+    }
+
+    /// Only draw where the stencil buffer compares to the reference value
+    /// using the function, without changing the stencil buffer
+    pub const fn test(func: DepthTestingFunction, reference: u8) -> Self {
+        StencilTesting::Enabled {
+            func,
+            reference,
+            mask: 0xff,
+            write_mask: 0,
+            fail: StencilOperation::Keep,
+            depth_fail: StencilOperation::Keep,
+            pass: StencilOperation::Keep,
+        }
+    }
+}
+
+/// What happens to a value in the stencil buffer after a stencil test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StencilOperation {
+    /// The value is unchanged
+    Keep,
+
+    /// The value is set to 0
+    Zero,
+
+    /// The value is set to the reference value
+    Replace,
+
+    /// The value is increased by 1, unless it is already 255
+    Increment,
+
+    /// The value is increased by 1, wrapping to 0
+    IncrementWrap,
+
+    /// The value is decreased by 1, unless it is already 0
+    Decrement,
+
+    /// The value is decreased by 1, wrapping to 255
+    DecrementWrap,
+
+    /// The bits of the value are inverted
+    Invert,
+}
+
+/// How the color output by a fragment shader (the source) is combined with the
+/// color already in the framebuffer (the destination)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// The source replaces the destination
+    None,
+
+    /// The color and alpha components are blended separately
+    Enabled {
+        color: BlendFunction,
+        alpha: BlendFunction,
+    },
+}
+
+/// Calculates `equation(source * source_factor, destination * destination_factor)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlendFunction {
+    pub equation: BlendEquation,
+    pub source: BlendFactor,
+    pub destination: BlendFactor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendEquation {
+    /// source + destination
+    Add,
+
+    /// source - destination
+    Subtract,
+
+    /// destination - source
+    ReverseSubtract,
+
+    /// The minimum of source and destination, the factors are ignored
+    Min,
+
+    /// The maximum of source and destination, the factors are ignored
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SourceColor,
+    OneMinusSourceColor,
+    DestinationColor,
+    OneMinusDestinationColor,
+    SourceAlpha,
+    OneMinusSourceAlpha,
+    DestinationAlpha,
+    OneMinusDestinationAlpha,
+}
+
+impl BlendFunction {
+    /// Add the source and destination, multiplied by the given factors
+    pub const fn add(source: BlendFactor, destination: BlendFactor) -> Self {
+        Self {
+            equation: BlendEquation::Add,
+            source,
+            destination,
+        }
+    }
+}
+
+#[allow(non_upper_case_globals)]
+impl BlendMode {
+    /// Standard transparency, the source color is not premultiplied by alpha
+    pub const Alpha: BlendMode = BlendMode::Enabled {
+        color: BlendFunction::add(BlendFactor::SourceAlpha, BlendFactor::OneMinusSourceAlpha),
+        alpha: BlendFunction::add(BlendFactor::One, BlendFactor::OneMinusSourceAlpha),
+    };
+
+    /// Transparency where the source color is already multiplied by alpha
+    pub const Premultiplied: BlendMode = BlendMode::Enabled {
+        color: BlendFunction::add(BlendFactor::One, BlendFactor::OneMinusSourceAlpha),
+        alpha: BlendFunction::add(BlendFactor::One, BlendFactor::OneMinusSourceAlpha),
+    };
+
+    /// The source is added to the destination, e.g. for lights or particles
+    pub const Additive: BlendMode = BlendMode::Enabled {
+        color: BlendFunction::add(BlendFactor::One, BlendFactor::One),
+        alpha: BlendFunction::add(BlendFactor::One, BlendFactor::One),
+    };
+
+    /// The destination color is multiplied by the source color, its alpha is
+    /// left unchanged
+    pub const Multiply: BlendMode = BlendMode::Enabled {
+        color: BlendFunction::add(BlendFactor::DestinationColor, BlendFactor::Zero),
+        alpha: BlendFunction::add(BlendFactor::Zero, BlendFactor::One),
+    };
+}
+
+/// The fixed function state used when drawing with a pipeline, it is set each
+/// time the pipeline is bound so cannot leak in from whatever was drawn before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineState {
+    pub culling: CullingMode,
+    pub depth: DepthTesting,
+    pub blend: BlendMode,
+    pub stencil: StencilTesting,
+}
+
+impl Default for PipelineState {
+    /// The state that backends start with, no culling, blending or stencil
+    /// testing and the default depth test
+    fn default() -> Self {
+        Self {
+            culling: CullingMode::None,
+            depth: DepthTesting::Default,
+            blend: BlendMode::None,
+            stencil: StencilTesting::None,
+        }
+    }
+}
+
+/// Everything needed to load a pipeline
+#[derive(Debug)]
+pub struct PipelineDescriptor {
+    pub program: Program,
+
+    /// The state set when the pipeline is bound, if None the pipeline uses
+    /// whatever state was last set, e.g. with [`Renderer::backface_culling`]
+    pub state: Option<PipelineState>,
+
+    /// How each vertex shader input is read from its buffer, in input order,
+    /// see [`VertexFormat`]
+    pub vertex_formats: Vec<VertexFormat>,
+}
+
+impl PipelineDescriptor {
+    /// A pipeline that sets its state when it is bound
+    pub fn new(program: Program, state: PipelineState) -> Self {
+        Self {
+            program,
+            state: Some(state),
+            vertex_formats: vec![],
+        }
+    }
+}
+
+impl From<Program> for PipelineDescriptor {
+    fn from(program: Program) -> Self {
+        Self {
+            program,
+            state: None,
+            vertex_formats: vec![],
+        }
+    }
+}
+
+/// The settings used when creating a render target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderTargetOptions {
+    /// The pixel width of all the attachments
+    pub width: u32,
+
+    /// The pixel height of all the attachments
+    pub height: u32,
+
+    /// The format of the color attachment, None if there should not be one,
+    /// e.g. for a shadow map
+    pub color: Option<TextureStorageType>,
+
+    /// Whether to create a depth attachment
+    pub depth: bool,
+
+    /// Whether the depth attachment also contains an 8 bit stencil buffer,
+    /// requires a depth attachment
+    pub stencil: bool,
+
+    /// How the 's' uv coordinate wraps when sampling the attachments
+    pub wrap_s: WrappingMode,
+
+    /// How the 't' uv coordinate wraps when sampling the attachments
+    pub wrap_t: WrappingMode,
+
+    /// Minification filtering setting, attachments do not have mipmaps so
+    /// this should not be a mipmap filter
+    pub min_filter: MinFilter,
+
+    /// Magnification filtering setting
+    pub mag_filter: MagFilter,
+}
+
+impl RenderTargetOptions {
+    /// Settings for an RGBA color and depth target of the given size, without
+    /// a stencil buffer, that is linearly filtered and clamped when sampled
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            color: Some(TextureStorageType::RGBA),
+            depth: true,
+            stencil: false,
+            wrap_s: WrappingMode::ClampToEdge,
+            wrap_t: WrappingMode::ClampToEdge,
+            min_filter: MinFilter::Linear,
+            mag_filter: MagFilter::Linear,
+        }
+    }
+
+    /// The texture settings of the color attachment, its pixel data is
+    /// updated using RGBA bytes
+    pub fn color_options(&self) -> TextureOptions {
+        TextureOptions {
+            wrap_s: self.wrap_s,
+            wrap_t: self.wrap_t,
+            min_filter: self.min_filter,
+            mag_filter: self.mag_filter,
+            width: self.width,
+            height: self.height,
+            source_format: TextureSourceFormat::RGBA,
+            source_type: TextureSourceType::U8,
+            storage: self.color.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// The texture settings of the depth attachment, its pixel data is
+    /// updated using a float per pixel, or a u32 containing 24 bits of depth
+    /// and 8 bits of stencil if there is a stencil buffer
+    pub fn depth_options(&self) -> TextureOptions {
+        TextureOptions {
+            source_format: TextureSourceFormat::R,
+            source_type: if self.stencil {
+                TextureSourceType::U32
+            } else {
+                TextureSourceType::F32
+            },
+            storage: TextureStorageType::R,
+            ..self.color_options()
+        }
+    }
+}
@@ -3,23 +3,31 @@ use gl::types::*;
 use std::{
     collections::HashMap,
     ffi::{CStr, CString, NulError},
-    mem::size_of,
+    mem, ptr,
 };
 use thiserror::Error;
 
 use crate::{
     renderer::DepthTestingFunction,
     texture::{
-        MagFilter, MinFilter, Texture, TextureSourceFormat, TextureSourceType, TextureStorageType,
-        WrappingMode,
+        MagFilter, MinFilter, Texture, TextureKind, TextureOptions, TextureRegion,
+        TextureSourceFormat, TextureSourceType, TextureStorageType, WrappingMode,
     },
 };
 
 use super::{
     backend::RendererBackend,
+    buffer::{check_buffer_write, check_indirect_read, RingBuffer, RING_SECTIONS},
+    readback::{check_read_region, ReadPixelsError},
     shader::{Program, Type},
-    CullingMode, DepthTesting, DrawingMode, IdType, IndexBufferId, IndexType, PipelineId,
-    TextureId, VertexBufferId,
+    uniform::{check_sampler, check_storage_buffer, check_uniform, check_uniform_block},
+    vertex::vertex_formats,
+    BlendEquation, BlendFactor, BlendMode, BufferError, CullingMode, DepthTesting,
+    DrawArraysIndirectCommand, DrawElementsIndirectCommand, DrawingMode, Handle, IndexBufferId,
+    IndexType, IndirectBufferId, PipelineDescriptor, PipelineId, PipelineState, PixelFormat,
+    PixelRegion, RenderTargetId, RenderTargetOptions, RendererCapabilities, StencilOperation,
+    StencilTesting, StorageBufferId, TextureId, UniformBufferId, UniformValue, VertexBufferId,
+    VertexComponentType,
 };
 
 /// Possible errors encounted in OpenGl
@@ -40,14 +48,38 @@ enum GlError {
     #[error("Unable to find a free active texture unit")]
     TextureUnitsFull,
 
+    #[error("Unable to find a free uniform buffer binding point")]
+    UniformBufferBindingsFull,
+
     #[error("Cannot bind texture to unbound pipeline")]
     PipelineNotBound,
 
     #[error("Texture is not currently loaded, cannot bind it to a pipeline")]
     TextureUnloaded,
 
+    #[error("Uniform buffer is not currently loaded, cannot bind it to a pipeline")]
+    UniformBufferUnloaded,
+
+    #[error("Storage buffer is not currently loaded, cannot bind it to a pipeline")]
+    StorageBufferUnloaded,
+
     #[error("Unable to load opaque type as vertex array")]
     OpaqueVerticies,
+
+    #[error("Uniform {name} is not used by the pipeline's shaders so was optimized out, it cannot be bound")]
+    UniformOptimizedOut { name: String },
+
+    #[error("Render target must have at least one attachment")]
+    NoAttachments,
+
+    #[error("Render target stencil buffer requires a depth attachment")]
+    StencilWithoutDepth,
+
+    #[error("Render target color attachment cannot use compressed storage {storage:?}")]
+    CompressedAttachment { storage: TextureStorageType },
+
+    #[error("Render target framebuffer is incomplete, status {status:#x}")]
+    IncompleteFramebuffer { status: GLenum },
 }
 
 /// OpenGl renderer implementation
@@ -55,18 +87,28 @@ pub struct GlRenderer {
     /// The current opengl context used for all rendering operations
     gl: gl::Gl,
 
-    /// The current id counter, constantly increasing counter, allocating any
-    /// resource adds one to this counter
-    id: IdType,
-
     /// All the currently loaded textures
-    textures: HashMap<IdType, GlTexture>,
+    textures: HashMap<Handle, GlTexture>,
 
     /// All the currently loaded buffers stored on the gpu
-    buffers: HashMap<IdType, Buffer>,
+    buffers: HashMap<Handle, Buffer>,
 
     /// All the shader pipelines currently avaliable
-    pipelines: HashMap<IdType, GlPipeline>,
+    pipelines: HashMap<Handle, GlPipeline>,
+
+    /// All the framebuffers used by render targets, their attachments are
+    /// stored with the other textures
+    render_targets: HashMap<Handle, GlFramebuffer>,
+
+    /// The render target currently being drawn to, None for the window
+    render_target: Option<RenderTargetId>,
+
+    /// The viewport size of the default framebuffer, restored when it is
+    /// bound again after drawing to a render target
+    screen_viewport: (u32, u32),
+
+    /// The viewport size currently set, for whichever framebuffer is bound
+    viewport: (u32, u32),
 
     /// A vector of all the texture units, if true then in use, if false then
     /// not in use.  Unit 0 is always set as in use as it is used as the binding
@@ -74,8 +116,16 @@ pub struct GlRenderer {
     texture_units: Vec<bool>,
 
     /// A map connecting the active pipeliness and the indicies into the texture_units
-    /// vec that the pipeline is currently using
-    active_textures: HashMap<PipelineId, Vec<usize>>,
+    /// vec that the pipeline is currently using, along with the location of
+    /// the sampler uniform each unit is bound to
+    active_textures: HashMap<PipelineId, Vec<(GLint, usize)>>,
+
+    /// All the uniform buffer binding points, if true then in use
+    uniform_buffer_bindings: Vec<bool>,
+
+    /// The uniform block index and binding point of each uniform buffer bound
+    /// to an active pipeline
+    active_uniform_buffers: HashMap<PipelineId, Vec<(GLuint, usize)>>,
 
     /// Whether backface culling is enabled for all future draw calls
     backface_culling_enabled: bool,
@@ -85,6 +135,27 @@ pub struct GlRenderer {
     depth_testing_enabled: bool,
     depth_write_enabled: bool,
     depth_function: GLuint,
+
+    /// The stencil testing settings for all future draw calls
+    stencil_mode: StencilTesting,
+
+    /// The features and limits of the driver, queried when the renderer is
+    /// created
+    capabilities: RendererCapabilities,
+
+    /// The blending applied to all future draw calls
+    blend_mode: BlendMode,
+
+    /// The number of state changes skipped as the cached state already matched
+    skipped_state_changes: u64,
+
+    /// The timestamp query objects, indexed by the query number used by the
+    /// frontend, 0 if the query has not been created yet
+    queries: Vec<GLuint>,
+
+    /// The buffer indirect draw commands are uploaded to before drawing, 0
+    /// if it has not been created yet
+    indirect_buffer: GLuint,
 }
 
 impl GlRenderer {
@@ -102,13 +173,27 @@ impl GlRenderer {
         // initial culling mode is back faces culled
         unsafe { gl.CullFace(gl::BACK) }
 
-        // get maximum number of active texture units
-        let mut texture_units = 0;
+        // filter across the edges of cubemap faces
+        unsafe { gl.Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS) }
+
+        let capabilities = query_capabilities(&gl);
+
+        let mut texture_units = vec![false; capabilities.texture_units as _];
+
+        // get maximum number of uniform buffer binding points
+        let mut uniform_buffer_bindings = 0;
         unsafe {
-            gl.GetIntegerv(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS, &mut texture_units);
+            gl.GetIntegerv(
+                gl::MAX_UNIFORM_BUFFER_BINDINGS,
+                &mut uniform_buffer_bindings,
+            );
         }
 
-        let mut texture_units = vec![false; texture_units as _];
+        // the initial viewport covers the whole window
+        let mut viewport = [0; 4];
+        unsafe {
+            gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
 
         // texture unit 0 is used for image loading so don't allow pipelines to
         // use it ever
@@ -116,40 +201,169 @@ impl GlRenderer {
 
         GlRenderer {
             gl,
-            id: 0,
             backface_culling_enabled: false,
             backface_culling_mode: gl::BACK,
             textures: HashMap::new(),
             buffers: HashMap::new(),
             pipelines: HashMap::new(),
+            render_targets: HashMap::new(),
+            render_target: None,
+            screen_viewport: (viewport[2] as _, viewport[3] as _),
+            viewport: (viewport[2] as _, viewport[3] as _),
             active_textures: HashMap::new(),
             texture_units,
+            uniform_buffer_bindings: vec![false; uniform_buffer_bindings as _],
+            active_uniform_buffers: HashMap::new(),
             depth_function: gl::LESS,
+            blend_mode: BlendMode::None,
+            stencil_mode: StencilTesting::None,
+            capabilities,
             depth_testing_enabled: true,
             depth_write_enabled: true,
+            skipped_state_changes: 0,
+            queries: vec![],
+            indirect_buffer: 0,
+        }
+    }
+
+    /// Upload indirect draw commands and bind them for the next draw.  The
+    /// buffer's storage is replaced each time, so the upload does not wait
+    /// for earlier draws to finish reading the previous commands
+    fn upload_indirect<T>(&mut self, draws: &[T]) {
+        unsafe {
+            if self.indirect_buffer == 0 {
+                self.gl.CreateBuffers(1, &mut self.indirect_buffer);
+            }
+
+            self.gl.NamedBufferData(
+                self.indirect_buffer,
+                mem::size_of_val(draws) as _,
+                draws.as_ptr() as _,
+                gl::STREAM_DRAW,
+            );
+            self.gl
+                .BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.indirect_buffer);
+        }
+    }
+
+    /// Set the stencil testing mode, without checking the cached mode
+    fn set_stencil_testing(&mut self, mode: StencilTesting) {
+        match mode {
+            // the write mask is reset so that clearing changes every bit
+            StencilTesting::None => unsafe {
+                self.gl.Disable(gl::STENCIL_TEST);
+                self.gl.StencilMask(0xff);
+            },
+            StencilTesting::Enabled {
+                func,
+                reference,
+                mask,
+                write_mask,
+                fail,
+                depth_fail,
+                pass,
+            } => unsafe {
+                self.gl.Enable(gl::STENCIL_TEST);
+                self.gl
+                    .StencilFunc(comparison_gl(func), reference as _, mask as _);
+                self.gl.StencilMask(write_mask as _);
+                self.gl.StencilOp(
+                    stencil_operation_gl(fail),
+                    stencil_operation_gl(depth_fail),
+                    stencil_operation_gl(pass),
+                );
+            },
+        }
+
+        self.stencil_mode = mode;
+    }
+
+    /// Get a pipeline that is currently bound, so that resources can be bound
+    /// to it
+    fn bound_pipeline(&self, pipeline: PipelineId) -> Result<&GlPipeline, GlError> {
+        self.pipelines
+            .get(&pipeline.0)
+            .filter(|pipeline| pipeline.is_bound)
+            .ok_or(GlError::PipelineNotBound)
+    }
+
+    /// The stencil write mask set by the cached stencil testing mode
+    fn stencil_write_mask(&self) -> GLuint {
+        match self.stencil_mode {
+            StencilTesting::None => 0xff,
+            StencilTesting::Enabled { write_mask, .. } => write_mask as _,
+        }
+    }
+
+    /// Set the blend mode, without checking the cached mode
+    fn set_blending(&mut self, mode: BlendMode) {
+        match mode {
+            BlendMode::None => unsafe { self.gl.Disable(gl::BLEND) },
+            BlendMode::Enabled { color, alpha } => unsafe {
+                self.gl.Enable(gl::BLEND);
+                self.gl.BlendEquationSeparate(
+                    blend_equation_gl(color.equation),
+                    blend_equation_gl(alpha.equation),
+                );
+                self.gl.BlendFuncSeparate(
+                    blend_factor_gl(color.source),
+                    blend_factor_gl(color.destination),
+                    blend_factor_gl(alpha.source),
+                    blend_factor_gl(alpha.destination),
+                );
+            },
         }
+
+        self.blend_mode = mode;
     }
 }
 
 impl RendererBackend for GlRenderer {
     fn clear(&mut self, r: f32, g: f32, b: f32) {
-        // assumes that the framebuffer has no alpha and that depth should
-        // also be cleared
+        // assumes that the framebuffer has no alpha and that depth and
+        // stencil should also be cleared.  Clears are limited by the write
+        // masks, so they are opened for the clear then set back to the cached
+        // state
         unsafe {
             self.gl.ClearColor(r, g, b, 1.0);
-            self.gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            self.gl.ClearStencil(0);
+            self.gl.StencilMask(0xff);
+            self.gl.DepthMask(gl::TRUE);
+            self.gl
+                .Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+            self.gl.StencilMask(self.stencil_write_mask());
+            self.gl.DepthMask(self.depth_write_enabled as _);
+        }
+    }
+
+    fn clear_stencil(&mut self, value: u8) {
+        unsafe {
+            self.gl.ClearStencil(value as _);
+            self.gl.StencilMask(0xff);
+            self.gl.Clear(gl::STENCIL_BUFFER_BIT);
+            self.gl.StencilMask(self.stencil_write_mask());
         }
     }
 
     fn viewport(&mut self, width: u32, height: u32) {
+        if self.render_target.is_none() {
+            self.screen_viewport = (width, height);
+        }
+        self.viewport = (width, height);
+
         // top left (0, 0) view port always
         unsafe {
             self.gl.Viewport(0, 0, width as _, height as _);
         }
     }
 
+    fn screen_viewport(&self) -> (u32, u32) {
+        self.screen_viewport
+    }
+
     fn backface_culling(&mut self, enable: CullingMode) {
         // cache whether culling is enabled or not to reduce draw calls
+        let before = (self.backface_culling_enabled, self.backface_culling_mode);
 
         if enable != CullingMode::None && !self.backface_culling_enabled {
             unsafe { self.gl.Enable(gl::CULL_FACE) }
@@ -175,12 +389,23 @@ impl RendererBackend for GlRenderer {
             }
             _ => (),
         }
+
+        if before == (self.backface_culling_enabled, self.backface_culling_mode) {
+            self.skipped_state_changes += 1;
+        }
     }
 
     fn depth_testing(&mut self, mode: DepthTesting) {
+        let before = (
+            self.depth_testing_enabled,
+            self.depth_write_enabled,
+            self.depth_function,
+        );
+
         match mode {
             DepthTesting::None => {
                 if self.depth_testing_enabled {
+                    self.depth_testing_enabled = false;
                     unsafe { self.gl.Disable(gl::DEPTH_TEST) }
                 }
             }
@@ -195,35 +420,155 @@ impl RendererBackend for GlRenderer {
                     self.depth_write_enabled = false;
                 } else if !read_only && !self.depth_write_enabled {
                     unsafe { self.gl.DepthMask(gl::TRUE) }
-                    self.depth_write_enabled = false;
+                    self.depth_write_enabled = true;
                 }
 
-                let func = match func {
-                    DepthTestingFunction::Always => gl::ALWAYS,
-                    DepthTestingFunction::Never => gl::NEVER,
-                    DepthTestingFunction::Less => gl::LESS,
-                    DepthTestingFunction::Equal => gl::EQUAL,
-                    DepthTestingFunction::LessEqual => gl::LEQUAL,
-                    DepthTestingFunction::Greater => gl::GREATER,
-                    DepthTestingFunction::NotEqual => gl::NOTEQUAL,
-                    DepthTestingFunction::GreaterEqual => gl::GEQUAL,
-                };
+                let func = comparison_gl(func);
 
                 if self.depth_function != func {
                     unsafe { self.gl.DepthFunc(func) }
+                    self.depth_function = func;
                 }
             }
         }
+
+        let after = (
+            self.depth_testing_enabled,
+            self.depth_write_enabled,
+            self.depth_function,
+        );
+        if before == after {
+            self.skipped_state_changes += 1;
+        }
     }
 
-    fn load_texture(&mut self, texture: Texture) -> TextureId {
-        let id = self.id;
-        self.id += 1;
+    fn stencil_testing(&mut self, mode: StencilTesting) {
+        // cache the stencil mode to reduce draw calls
+        if mode == self.stencil_mode {
+            self.skipped_state_changes += 1;
+            return;
+        }
 
-        self.textures
-            .insert(id, GlTexture::new(&self.gl, &texture, 0));
+        self.set_stencil_testing(mode);
+    }
+
+    fn blending(&mut self, mode: BlendMode) {
+        // cache the blend mode to reduce draw calls
+        if mode == self.blend_mode {
+            self.skipped_state_changes += 1;
+            return;
+        }
+
+        self.set_blending(mode);
+    }
+
+    fn invalidate_state_cache(&mut self) {
+        // write the cached state back to gl, so that it is correct again
+        unsafe {
+            if self.backface_culling_enabled {
+                self.gl.Enable(gl::CULL_FACE);
+            } else {
+                self.gl.Disable(gl::CULL_FACE);
+            }
+            self.gl.CullFace(self.backface_culling_mode);
+
+            if self.depth_testing_enabled {
+                self.gl.Enable(gl::DEPTH_TEST);
+            } else {
+                self.gl.Disable(gl::DEPTH_TEST);
+            }
+            self.gl.DepthMask(self.depth_write_enabled as _);
+            self.gl.DepthFunc(self.depth_function);
+
+            // the renderer never uses scissor testing or polygon modes other
+            // than fill, but they are commonly changed, e.g. by imgui
+            self.gl.Disable(gl::SCISSOR_TEST);
+            self.gl.PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+
+            let framebuffer = self
+                .render_target
+                .and_then(|target| self.render_targets.get(&target.id))
+                .map_or(0, |framebuffer| framebuffer.fbo);
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            self.gl
+                .Viewport(0, 0, self.viewport.0 as _, self.viewport.1 as _);
+        }
+
+        // the program and vertex array are set when a pipeline is bound, so
+        // only need restoring if one is bound now
+        let gl = &self.gl;
+        match self
+            .pipelines
+            .values_mut()
+            .find(|pipeline| pipeline.is_bound)
+        {
+            Some(pipeline) => pipeline.bind(gl),
+            None => unsafe {
+                gl.UseProgram(0);
+                gl.BindVertexArray(0);
+            },
+        }
+
+        self.set_stencil_testing(self.stencil_mode);
+        self.set_blending(self.blend_mode);
+    }
+
+    fn capabilities(&self) -> RendererCapabilities {
+        self.capabilities.clone()
+    }
+
+    fn skipped_state_changes(&self) -> u64 {
+        self.skipped_state_changes
+    }
+
+    fn timestamp(&mut self, query: u32) {
+        let query = query as usize;
+        if query >= self.queries.len() {
+            self.queries.resize(query + 1, 0);
+        }
+
+        if self.queries[query] == 0 {
+            unsafe {
+                self.gl.GenQueries(1, &mut self.queries[query]);
+            }
+        }
+
+        unsafe {
+            self.gl.QueryCounter(self.queries[query], gl::TIMESTAMP);
+        }
+    }
+
+    fn timestamp_result(&mut self, query: u32) -> Option<u64> {
+        let query = *self.queries.get(query as usize)?;
+        if query == 0 {
+            return None;
+        }
+
+        // check first so that reading the result never stalls
+        let mut available = 0;
+        unsafe {
+            self.gl
+                .GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+
+        if available == 0 {
+            return None;
+        }
+
+        let mut time = 0;
+        unsafe {
+            self.gl
+                .GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut time);
+        }
 
-        TextureId(id)
+        Some(time)
+    }
+
+    fn load_texture(&mut self, id: TextureId, texture: Texture) {
+        self.textures.insert(
+            id.0,
+            GlTexture::new(&self.gl, &texture, 0, self.capabilities.max_anisotropy),
+        );
     }
 
     fn unload_texture(&mut self, texture: TextureId) {
@@ -233,32 +578,61 @@ impl RendererBackend for GlRenderer {
         debug_assert!(!removed.is_none());
     }
 
-    fn load_vertex_buffer(&mut self, data: &[u8]) -> VertexBufferId {
-        let id = self.id;
-        self.id += 1;
+    fn update_texture(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        let texture = self
+            .textures
+            .get_mut(&texture.0)
+            .ok_or(GlError::TextureUnloaded)?;
 
-        let buf = Buffer::new(&self.gl, gl::ARRAY_BUFFER);
+        region.check(&texture.options, texture.levels, data)?;
+        texture.update(region, data, false);
+
+        Ok(())
+    }
+
+    fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        let texture = self
+            .textures
+            .get_mut(&texture.0)
+            .ok_or(GlError::TextureUnloaded)?;
+
+        region.check(&texture.options, texture.levels, data)?;
+        texture.update(region, data, true);
+
+        Ok(())
+    }
+
+    fn load_vertex_buffer(&mut self, id: VertexBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::ARRAY_BUFFER);
         buf.bind();
         buf.static_draw_data(data);
         buf.unbind();
 
-        self.buffers.insert(id, buf);
-
-        VertexBufferId(id)
+        self.buffers.insert(id.0, buf);
     }
 
-    fn load_vertex_buffer_stream(&mut self, data: &[u8]) -> VertexBufferId {
-        let id = self.id;
-        self.id += 1;
-
-        let buf = Buffer::new(&self.gl, gl::ARRAY_BUFFER);
+    fn load_vertex_buffer_stream(&mut self, id: VertexBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::ARRAY_BUFFER);
         buf.bind();
         buf.static_draw_data_stream(data);
         buf.unbind();
 
-        self.buffers.insert(id, buf);
+        self.buffers.insert(id.0, buf);
+    }
 
-        VertexBufferId(id)
+    fn load_vertex_ring_buffer(&mut self, id: VertexBufferId, size: usize) {
+        let buf = Buffer::ring(&self.gl, gl::ARRAY_BUFFER, size);
+        self.buffers.insert(id.0, buf);
     }
 
     fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) {
@@ -268,32 +642,61 @@ impl RendererBackend for GlRenderer {
         debug_assert!(!removed.is_none());
     }
 
-    fn load_index_buffer(&mut self, data: &[u8]) -> IndexBufferId {
-        let id = self.id;
-        self.id += 1;
+    fn update_vertex_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+        buffer.update(offset, data)?;
 
-        let buf = Buffer::new(&self.gl, gl::ELEMENT_ARRAY_BUFFER);
+        Ok(())
+    }
+
+    fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+        buffer.orphan(data)?;
+
+        Ok(())
+    }
+
+    fn write_vertex_ring_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<usize> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+
+        Ok(buffer.write_ring(data)?)
+    }
+
+    fn load_index_buffer(&mut self, id: IndexBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::ELEMENT_ARRAY_BUFFER);
         buf.bind();
         buf.static_draw_data(data);
         buf.unbind();
 
-        self.buffers.insert(id, buf);
-
-        IndexBufferId(id)
+        self.buffers.insert(id.0, buf);
     }
 
-    fn load_index_buffer_stream(&mut self, data: &[u8]) -> IndexBufferId {
-        let id = self.id;
-        self.id += 1;
-
-        let buf = Buffer::new(&self.gl, gl::ELEMENT_ARRAY_BUFFER);
+    fn load_index_buffer_stream(&mut self, id: IndexBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::ELEMENT_ARRAY_BUFFER);
         buf.bind();
         buf.static_draw_data_stream(data);
         buf.unbind();
 
-        self.buffers.insert(id, buf);
+        self.buffers.insert(id.0, buf);
+    }
 
-        IndexBufferId(id)
+    fn load_index_ring_buffer(&mut self, id: IndexBufferId, size: usize) {
+        let buf = Buffer::ring(&self.gl, gl::ELEMENT_ARRAY_BUFFER, size);
+        self.buffers.insert(id.0, buf);
     }
 
     fn unload_index_buffer(&mut self, buffer: IndexBufferId) {
@@ -303,14 +706,114 @@ impl RendererBackend for GlRenderer {
         debug_assert!(!removed.is_none());
     }
 
-    fn load_pipeline(&mut self, pipeline: Program) -> Result<PipelineId> {
-        let id = self.id;
-        self.id += 1;
+    fn update_index_buffer(
+        &mut self,
+        buffer: IndexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+        buffer.update(offset, data)?;
 
-        self.pipelines
-            .insert(id, GlPipeline::new(pipeline, self.gl.clone())?);
+        Ok(())
+    }
+
+    fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+        buffer.orphan(data)?;
+
+        Ok(())
+    }
+
+    fn write_index_ring_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<usize> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+
+        Ok(buffer.write_ring(data)?)
+    }
+
+    fn load_uniform_buffer(&mut self, id: UniformBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::UNIFORM_BUFFER);
+        buf.bind();
+        buf.static_draw_data(data);
+        buf.unbind();
+
+        self.buffers.insert(id.0, buf);
+    }
+
+    fn unload_uniform_buffer(&mut self, buffer: UniformBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+
+        // if removing a uniform buffer it must have already existed
+        debug_assert!(removed.is_some());
+    }
+
+    fn load_storage_buffer(&mut self, id: StorageBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::SHADER_STORAGE_BUFFER);
+        buf.bind();
+        buf.static_draw_data(data);
+        buf.unbind();
 
-        Ok(PipelineId(id))
+        self.buffers.insert(id.0, buf);
+    }
+
+    fn unload_storage_buffer(&mut self, buffer: StorageBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+
+        // if removing a storage buffer it must have already existed
+        debug_assert!(removed.is_some());
+    }
+
+    fn update_storage_buffer(
+        &mut self,
+        buffer: StorageBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(&buffer.0)
+            .ok_or(BufferError::Unloaded)?;
+        buffer.update(offset, data)?;
+
+        Ok(())
+    }
+
+    fn read_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<Vec<u8>> {
+        let buffer = self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+
+        Ok(buffer.read())
+    }
+
+    fn load_indirect_buffer(&mut self, id: IndirectBufferId, data: &[u8]) {
+        let mut buf = Buffer::new(&self.gl, gl::DRAW_INDIRECT_BUFFER);
+        buf.bind();
+        buf.static_draw_data(data);
+        buf.unbind();
+
+        self.buffers.insert(id.0, buf);
+    }
+
+    fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+
+        // if removing an indirect buffer it must have already existed
+        debug_assert!(removed.is_some());
+    }
+
+    fn load_pipeline(&mut self, id: PipelineId, pipeline: PipelineDescriptor) -> Result<()> {
+        let gl_pipeline = GlPipeline::new(pipeline, self.gl.clone())?;
+        self.pipelines.insert(id.0, gl_pipeline);
+
+        Ok(())
     }
 
     fn unload_pipeline(&mut self, pipeline: PipelineId) {
@@ -320,26 +823,167 @@ impl RendererBackend for GlRenderer {
         debug_assert!(!removed.is_none());
     }
 
+    fn load_render_target(
+        &mut self,
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    ) -> Result<()> {
+        if options.color.is_none() && !options.depth {
+            return Err(GlError::NoAttachments.into());
+        }
+        if options.stencil && !options.depth {
+            return Err(GlError::StencilWithoutDepth.into());
+        }
+        if let Some(storage) = options.color.filter(TextureStorageType::is_compressed) {
+            return Err(GlError::CompressedAttachment { storage }.into());
+        }
+
+        let color = options.color.map(|storage| {
+            GlTexture::attachment(
+                &self.gl,
+                options.color_options(),
+                internal_format_gl(storage),
+            )
+        });
+
+        let depth = if options.stencil {
+            Some(GlTexture::attachment(
+                &self.gl,
+                options.depth_options(),
+                gl::DEPTH24_STENCIL8 as _,
+            ))
+        } else if options.depth {
+            Some(GlTexture::attachment(
+                &self.gl,
+                options.depth_options(),
+                gl::DEPTH_COMPONENT24 as _,
+            ))
+        } else {
+            None
+        };
+
+        let framebuffer = GlFramebuffer::new(&self.gl, &options, color.as_ref(), depth.as_ref())?;
+        self.render_targets.insert(target.id, framebuffer);
+
+        if let (Some(id), Some(color)) = (target.color(), color) {
+            self.textures.insert(id.0, color);
+        }
+        if let (Some(id), Some(depth)) = (target.depth(), depth) {
+            self.textures.insert(id.0, depth);
+        }
+
+        Ok(())
+    }
+
+    fn unload_render_target(&mut self, target: RenderTargetId) {
+        if self.render_target == Some(target) {
+            self.bind_render_target(None);
+        }
+
+        let removed = self.render_targets.remove(&target.id);
+
+        // if removing a render target it must have already existed
+        debug_assert!(removed.is_some());
+
+        for texture in target.color().iter().chain(&target.depth()) {
+            self.textures.remove(&texture.0);
+        }
+    }
+
+    fn bind_render_target(&mut self, target: Option<RenderTargetId>) {
+        let (framebuffer, (width, height)) = match target {
+            Some(target) => {
+                if let Some(framebuffer) = self.render_targets.get(&target.id) {
+                    (framebuffer.fbo, (framebuffer.width, framebuffer.height))
+                } else {
+                    debug_assert!(false, "Cannot bind non-existant render target");
+                    return;
+                }
+            }
+            None => (0, self.screen_viewport),
+        };
+
+        self.render_target = target;
+        self.viewport = (width, height);
+
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            self.gl.Viewport(0, 0, width as _, height as _);
+        }
+    }
+
+    fn read_pixels(&mut self, region: PixelRegion, format: PixelFormat) -> Result<Vec<u8>> {
+        let (width, height) = match self.render_target {
+            Some(target) if target.color().is_none() => {
+                return Err(ReadPixelsError::NoColorAttachment.into());
+            }
+            Some(target) => {
+                let framebuffer = &self.render_targets[&target.id];
+                (framebuffer.width, framebuffer.height)
+            }
+            None => self.screen_viewport,
+        };
+        check_read_region(region, width, height)?;
+
+        let ty = match format {
+            PixelFormat::Rgba8 => gl::UNSIGNED_BYTE,
+            PixelFormat::Rgba32F => gl::FLOAT,
+        };
+
+        let mut data = vec![0; region.width as usize * region.height as usize * format.size()];
+        unsafe {
+            // rows of rgba pixels are always a multiple of 4 bytes long
+            self.gl.ReadPixels(
+                region.x as _,
+                region.y as _,
+                region.width as _,
+                region.height as _,
+                gl::RGBA,
+                ty,
+                data.as_mut_ptr() as *mut _,
+            );
+        }
+
+        Ok(data)
+    }
+
     fn bind_pipeline(&mut self, pipeline: PipelineId) {
         self.active_textures.insert(pipeline, vec![]);
+        self.active_uniform_buffers.insert(pipeline, vec![]);
 
-        if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
+        let state = if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
             debug_assert!(!pipeline.is_bound);
 
             pipeline.bind(&self.gl);
+            pipeline.state
         } else {
             debug_assert!(false, "Cannot bind non-existant pipeline");
+            None
+        };
+
+        // the caches skip any state that is already set
+        if let Some(state) = state {
+            self.backface_culling(state.culling);
+            self.depth_testing(state.depth);
+            self.blending(state.blend);
+            self.stencil_testing(state.stencil);
         }
     }
 
     fn unbind_pipeline(&mut self, pipeline: PipelineId) {
-        for &texture_unit in &self.active_textures[&pipeline] {
+        for &(_, texture_unit) in &self.active_textures[&pipeline] {
             self.texture_units[texture_unit] = false;
         }
 
         // doesn't matter if this succeeds, failure just means no textures were used
         self.active_textures.remove(&pipeline);
 
+        if let Some(bindings) = self.active_uniform_buffers.remove(&pipeline) {
+            for (_, binding) in bindings {
+                self.uniform_buffer_bindings[binding] = false;
+            }
+        }
+
         if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
             debug_assert!(pipeline.is_bound);
 
@@ -355,24 +999,7 @@ impl RendererBackend for GlRenderer {
         name: &str,
         matrix: nalgebra_glm::Mat4,
     ) -> Result<()> {
-        let name = CString::new(name)?;
-
-        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
-            debug_assert!(pipeline.is_bound);
-
-            // todo: cache get uniform location?
-            unsafe {
-                let loc = self
-                    .gl
-                    .GetUniformLocation(pipeline.program_id, name.as_ptr());
-                self.gl
-                    .UniformMatrix4fv(loc, 1, gl::FALSE, matrix.as_slice().as_ptr());
-            }
-        } else {
-            debug_assert!(false, "Cannot bind matrix to non-existant pipeline");
-        }
-
-        Ok(())
+        self.pipeline_bind_uniform(pipeline, name, &UniformValue::Mat4(matrix))
     }
 
     fn pipeline_bind_texture(
@@ -381,23 +1008,45 @@ impl RendererBackend for GlRenderer {
         name: &str,
         texture: TextureId,
     ) -> Result<()> {
-        // get the uniform's name
-        let name = CString::new(name)?;
-
-        // find the first avaliable texture unit
-        let (texture_unit, _) = self
-            .texture_units
-            .iter()
-            .enumerate()
-            .find(|&(_, &in_use)| !in_use)
-            .ok_or(GlError::TextureUnitsFull)?;
+        let kind = self
+            .textures
+            .get(&texture.0)
+            .ok_or(GlError::TextureUnloaded)?
+            .options
+            .kind;
+
+        // find the uniform before allocating a texture unit, so that none
+        // are used up if the uniform does not exist
+        let location = {
+            let pipeline = self.bound_pipeline(pipeline)?;
+            let uniform = check_sampler(&pipeline.pipeline, name, kind)?;
+            pipeline.uniform_location(uniform)?
+        };
 
-        // tell the renderer that a texture unit is in use
-        self.texture_units[texture_unit] = true;
-        self.active_textures
+        let active = self
+            .active_textures
             .get_mut(&pipeline)
-            .ok_or(GlError::PipelineNotBound)?
-            .push(texture_unit);
+            .ok_or(GlError::PipelineNotBound)?;
+
+        // binding another texture to the same uniform reuses its unit, so
+        // drawing many times with one bind does not run out of units
+        let texture_unit = match active.iter().find(|&&(bound, _)| bound == location) {
+            Some(&(_, texture_unit)) => texture_unit,
+            None => {
+                // find the first avaliable texture unit
+                let (texture_unit, _) = self
+                    .texture_units
+                    .iter()
+                    .enumerate()
+                    .find(|&(_, &in_use)| !in_use)
+                    .ok_or(GlError::TextureUnitsFull)?;
+
+                // tell the renderer that a texture unit is in use
+                self.texture_units[texture_unit] = true;
+                active.push((location, texture_unit));
+                texture_unit
+            }
+        };
 
         // tell the renderer which pipeline owns a particular texture unit
         self.textures
@@ -406,17 +1055,114 @@ impl RendererBackend for GlRenderer {
             .set_bound(texture_unit as _);
 
         // tell the shader about the texture unit
-        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
-            debug_assert!(pipeline.is_bound);
+        unsafe {
+            self.gl.Uniform1i(location, texture_unit as _);
+        }
 
-            unsafe {
-                let loc = self
-                    .gl
-                    .GetUniformLocation(pipeline.program_id, name.as_ptr());
-                self.gl.Uniform1i(loc, texture_unit as _);
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        value: &UniformValue,
+    ) -> Result<()> {
+        let pipeline = self.bound_pipeline(pipeline)?;
+        let uniform = check_uniform(&pipeline.pipeline, name, value)?;
+        let loc = pipeline.uniform_location(uniform)?;
+        let count = value.len() as _;
+
+        unsafe {
+            let floats = value.floats();
+            let floats = floats.as_ptr();
+            match value.element_type() {
+                Type::Integer => self.gl.Uniform1iv(loc, count, value.ints().as_ptr()),
+                Type::Floating => self.gl.Uniform1fv(loc, count, floats),
+                Type::Vector(2) => self.gl.Uniform2fv(loc, count, floats),
+                Type::Vector(3) => self.gl.Uniform3fv(loc, count, floats),
+                Type::Vector(4) => self.gl.Uniform4fv(loc, count, floats),
+                Type::Matrix(2, 2) => self.gl.UniformMatrix2fv(loc, count, gl::FALSE, floats),
+                Type::Matrix(3, 3) => self.gl.UniformMatrix3fv(loc, count, gl::FALSE, floats),
+                Type::Matrix(4, 4) => self.gl.UniformMatrix4fv(loc, count, gl::FALSE, floats),
+                ty => unreachable!("Uniform value with type {}", ty),
             }
-        } else {
-            debug_assert!(false, "Cannot bind texture to pipeline that dosen't exist");
+        }
+
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: UniformBufferId,
+    ) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get(&buffer.0)
+            .ok_or(GlError::UniformBufferUnloaded)?;
+
+        let (program_id, index) = {
+            let gl_pipeline = self.bound_pipeline(pipeline)?;
+            let block = check_uniform_block(&gl_pipeline.pipeline, name, buffer.size)?;
+            (
+                gl_pipeline.program_id,
+                gl_pipeline.uniform_block_index(block)?,
+            )
+        };
+
+        let active = self
+            .active_uniform_buffers
+            .get_mut(&pipeline)
+            .ok_or(GlError::PipelineNotBound)?;
+
+        // binding another buffer to the same block reuses its binding point,
+        // the same as texture units
+        let binding = match active.iter().find(|&&(block, _)| block == index) {
+            Some(&(_, binding)) => binding,
+            None => {
+                // find the first avaliable binding point
+                let (binding, _) = self
+                    .uniform_buffer_bindings
+                    .iter()
+                    .enumerate()
+                    .find(|&(_, &in_use)| !in_use)
+                    .ok_or(GlError::UniformBufferBindingsFull)?;
+
+                self.uniform_buffer_bindings[binding] = true;
+                active.push((index, binding));
+                binding
+            }
+        };
+
+        unsafe {
+            self.gl.UniformBlockBinding(program_id, index, binding as _);
+            self.gl
+                .BindBufferBase(gl::UNIFORM_BUFFER, binding as _, buffer.id());
+        }
+
+        Ok(())
+    }
+
+    fn pipeline_bind_storage_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: StorageBufferId,
+    ) -> Result<()> {
+        let buffer = self
+            .buffers
+            .get(&buffer.0)
+            .ok_or(GlError::StorageBufferUnloaded)?;
+
+        // the glsl sets each storage buffer's binding to its index, so no
+        // binding points need allocating
+        let binding = check_storage_buffer(&self.bound_pipeline(pipeline)?.pipeline, name)?;
+
+        unsafe {
+            self.gl
+                .BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding as _, buffer.id());
         }
 
         Ok(())
@@ -428,6 +1174,7 @@ impl RendererBackend for GlRenderer {
         buffers: &[VertexBufferId],
         offsets: &[usize],
         strides: &[i32],
+        divisors: &[u32],
     ) {
         let buffers: Vec<_> = buffers
             .iter()
@@ -452,97 +1199,288 @@ impl RendererBackend for GlRenderer {
         // all slices must be the same length
         debug_assert!(buffers.len() == offsets.len());
         debug_assert!(buffers.len() == strides.len());
+        debug_assert!(divisors.is_empty() || buffers.len() == divisors.len());
+
+        unsafe {
+            self.gl.VertexArrayVertexBuffers(
+                pipeline.vao,
+                0,
+                buffers.len() as _,
+                buffers.as_ptr(),
+                offsets.as_ptr() as _,
+                strides.as_ptr() as _,
+            );
+
+            // divisors are always set, so ones from an earlier bind are reset
+            for i in 0..buffers.len() {
+                let divisor = divisors.get(i).copied().unwrap_or(0);
+                self.gl
+                    .VertexArrayBindingDivisor(pipeline.vao, i as _, divisor);
+            }
+        }
+    }
+
+    fn draw(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    ) {
+        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+        } else {
+            debug_assert!(false, "Cannot draw using pipeline that does not exist");
+        }
+
+        let mode = drawing_mode(mode);
+
+        unsafe {
+            self.gl
+                .DrawArraysInstanced(mode, start as _, count as _, instances as _);
+        }
+    }
+
+    fn draw_indicies(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+        } else {
+            debug_assert!(false, "Cannot draw using pipeline that does not exist");
+        }
+
+        let mode = drawing_mode(mode);
+
+        let index_type = gl_index_type(index_type);
+
+        self.buffers[&indices.0].bind();
+
+        unsafe {
+            self.gl.DrawElementsInstanced(
+                mode,
+                count as _,
+                index_type,
+                index_offset as _,
+                instances as _,
+            );
+        }
+    }
+
+    fn draw_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: &[DrawArraysIndirectCommand],
+    ) {
+        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+        } else {
+            debug_assert!(false, "Cannot draw using pipeline that does not exist");
+        }
+
+        self.upload_indirect(draws);
+
+        unsafe {
+            self.gl
+                .MultiDrawArraysIndirect(drawing_mode(mode), ptr::null(), draws.len() as _, 0);
+        }
+    }
+
+    fn multi_draw_elements_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    ) {
+        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+        } else {
+            debug_assert!(false, "Cannot draw using pipeline that does not exist");
+        }
+
+        self.buffers[&indices.0].bind();
+        self.upload_indirect(draws);
 
         unsafe {
-            self.gl.VertexArrayVertexBuffers(
-                pipeline.vao,
+            self.gl.MultiDrawElementsIndirect(
+                drawing_mode(mode),
+                gl_index_type(index_type),
+                ptr::null(),
+                draws.len() as _,
                 0,
-                buffers.len() as _,
-                buffers.as_ptr(),
-                offsets.as_ptr() as _,
-                strides.as_ptr() as _,
             );
         }
     }
 
-    fn draw(&mut self, pipeline: PipelineId, mode: DrawingMode, start: u64, count: u64) {
+    fn draw_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
         if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
             debug_assert!(pipeline.is_bound);
         } else {
             debug_assert!(false, "Cannot draw using pipeline that does not exist");
         }
 
-        let mode = match mode {
-            DrawingMode::Points => gl::POINTS,
-            DrawingMode::Lines => gl::LINES,
-            DrawingMode::LineLoop => gl::LINE_LOOP,
-            DrawingMode::LineStrip => gl::LINE_STRIP,
-            DrawingMode::Triangles => gl::TRIANGLES,
-            DrawingMode::TriangleStrip => gl::TRIANGLE_STRIP,
-            DrawingMode::TriangleFan => gl::TRIANGLE_FAN,
-        };
+        let buffer = self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+        check_indirect_read(offset, count, DrawArraysIndirectCommand::SIZE, buffer.size)?;
+        buffer.bind();
 
         unsafe {
-            self.gl.DrawArrays(mode, start as _, count as _);
+            self.gl
+                .MultiDrawArraysIndirect(drawing_mode(mode), offset as _, count as _, 0);
         }
+
+        Ok(())
     }
 
-    fn draw_indicies(
+    fn multi_draw_elements_indirect_buffer(
         &mut self,
         pipeline: PipelineId,
         mode: DrawingMode,
         indices: IndexBufferId,
         index_type: IndexType,
-        index_offset: usize,
+        buffer: IndirectBufferId,
+        offset: usize,
         count: usize,
-    ) {
+    ) -> Result<()> {
         if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
             debug_assert!(pipeline.is_bound);
         } else {
             debug_assert!(false, "Cannot draw using pipeline that does not exist");
         }
 
-        let mode = match mode {
-            DrawingMode::Points => gl::POINTS,
-            DrawingMode::Lines => gl::LINES,
-            DrawingMode::LineLoop => gl::LINE_LOOP,
-            DrawingMode::LineStrip => gl::LINE_STRIP,
-            DrawingMode::Triangles => gl::TRIANGLES,
-            DrawingMode::TriangleStrip => gl::TRIANGLE_STRIP,
-            DrawingMode::TriangleFan => gl::TRIANGLE_FAN,
-        };
-
-        let index_type = match index_type {
-            IndexType::U8 => gl::UNSIGNED_BYTE,
-            IndexType::U16 => gl::UNSIGNED_SHORT,
-            IndexType::U32 => gl::UNSIGNED_INT,
-        };
+        let buffer = self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+        check_indirect_read(
+            offset,
+            count,
+            DrawElementsIndirectCommand::SIZE,
+            buffer.size,
+        )?;
 
         self.buffers[&indices.0].bind();
+        buffer.bind();
 
         unsafe {
-            self.gl
-                .DrawElements(mode, count as _, index_type, index_offset as _);
+            self.gl.MultiDrawElementsIndirect(
+                drawing_mode(mode),
+                gl_index_type(index_type),
+                offset as _,
+                count as _,
+                0,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self, pipeline: PipelineId, x: u32, y: u32, z: u32) {
+        if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+            debug_assert!(
+                pipeline.pipeline.compute_main().is_some(),
+                "Cannot dispatch pipeline without a compute shader"
+            );
+        }
+
+        // the barrier makes the storage buffer writes visible to anything
+        // that could read them afterwards, not just other dispatches
+        unsafe {
+            self.gl.DispatchCompute(x, y, z);
+            self.gl.MemoryBarrier(
+                gl::SHADER_STORAGE_BARRIER_BIT
+                    | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT
+                    | gl::ELEMENT_ARRAY_BARRIER_BIT
+                    | gl::COMMAND_BARRIER_BIT
+                    | gl::BUFFER_UPDATE_BARRIER_BIT,
+            );
+        }
+    }
+}
+
+impl Drop for GlRenderer {
+    /// deletes the timestamp queries and indirect command buffer, resources
+    /// loaded by the frontend free themselves when their maps are dropped
+    fn drop(&mut self) {
+        unsafe {
+            for query in self.queries.iter().filter(|&&query| query != 0) {
+                self.gl.DeleteQueries(1, query);
+            }
+
+            if self.indirect_buffer != 0 {
+                self.gl.DeleteBuffers(1, &self.indirect_buffer);
+            }
         }
     }
 }
 
+/// The OpenGl primitive type for a drawing mode
+fn drawing_mode(mode: DrawingMode) -> GLenum {
+    match mode {
+        DrawingMode::Points => gl::POINTS,
+        DrawingMode::Lines => gl::LINES,
+        DrawingMode::LineLoop => gl::LINE_LOOP,
+        DrawingMode::LineStrip => gl::LINE_STRIP,
+        DrawingMode::Triangles => gl::TRIANGLES,
+        DrawingMode::TriangleStrip => gl::TRIANGLE_STRIP,
+        DrawingMode::TriangleFan => gl::TRIANGLE_FAN,
+    }
+}
+
+fn gl_index_type(index_type: IndexType) -> GLenum {
+    match index_type {
+        IndexType::U8 => gl::UNSIGNED_BYTE,
+        IndexType::U16 => gl::UNSIGNED_SHORT,
+        IndexType::U32 => gl::UNSIGNED_INT,
+    }
+}
+
 struct GlPipeline {
     gl: gl::Gl,
     program_id: GLuint,
     vao: GLuint,
     pipeline: Program,
 
+    /// The location of each of the program's uniforms, by uniform index.
+    /// Uniforms in a block, or that are optimized out, have location -1
+    uniform_locations: Vec<GLint>,
+
+    /// The index of each of the program's uniform blocks, by block index
+    uniform_block_indices: Vec<GLuint>,
+
+    /// The state set when the pipeline is bound
+    state: Option<PipelineState>,
+
     is_bound: bool,
 }
 
 impl GlPipeline {
-    fn new(mut pipeline: Program, gl: gl::Gl) -> Result<Self> {
+    fn new(descriptor: PipelineDescriptor, gl: gl::Gl) -> Result<Self> {
+        let mut pipeline = descriptor.program;
+        let state = descriptor.state;
+
         let shaders = pipeline.to_glsl()?;
+        let formats = vertex_formats(&pipeline, &descriptor.vertex_formats)?;
 
         let shaders = vec![
             (shaders.vert, gl::VERTEX_SHADER),
             (shaders.frag, gl::FRAGMENT_SHADER),
+            (shaders.compute, gl::COMPUTE_SHADER),
         ];
 
         // convert shader source code into gl shader ids
@@ -562,6 +1500,26 @@ impl GlPipeline {
 
         let program_id = program_from_shaders(&gl, &shaders)?;
 
+        // resolve all the uniform locations once, so binding a uniform does not
+        // need to query the driver
+        let uniform_locations = pipeline
+            .uniforms()
+            .iter()
+            .map(|uniform| {
+                let name = CString::new(&uniform.name[..])?;
+                Ok(unsafe { gl.GetUniformLocation(program_id, name.as_ptr()) })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let uniform_block_indices = pipeline
+            .uniform_blocks()
+            .iter()
+            .map(|block| {
+                let name = CString::new(&block.name[..])?;
+                Ok(unsafe { gl.GetUniformBlockIndex(program_id, name.as_ptr()) })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
         // Create a vao to store the vertex attribute types using OpenGL DSA functions
         // If not using DSA then this would depend on vertex buffers being bound,
         // so would need to be re-done every time the vertex buffers bound are
@@ -576,27 +1534,62 @@ impl GlPipeline {
             for (i, attribute) in vert.inputs().iter().enumerate() {
                 let name = CString::new(&attribute.name[..])?;
 
-                let count = match attribute.ty {
-                    Type::Vector(n) => n,
-                    Type::Matrix(n, m) => n * m,
-                    Type::Floating => 1,
-                    Type::Sampler2D | Type::Unknown => return Err(GlError::OpaqueVerticies.into()),
+                // matrices use a location for each column, as attributes
+                // have at most 4 components
+                let (count, columns) = match attribute.ty {
+                    Type::Vector(n) | Type::IntVector(n) | Type::UIntVector(n) => (n, 1),
+                    Type::Matrix(rows, cols) => (rows, cols),
+                    Type::Floating | Type::Integer | Type::UnsignedInteger => (1, 1),
+                    Type::Sampler2D
+                    | Type::SamplerCube
+                    | Type::Sampler2DArray
+                    | Type::Sampler3D
+                    | Type::Array(..)
+                    | Type::Buffer(..)
+                    | Type::Unknown => return Err(GlError::OpaqueVerticies.into()),
+                };
+
+                let format = formats[i];
+                let component = match format.component {
+                    VertexComponentType::I8 => gl::BYTE,
+                    VertexComponentType::U8 => gl::UNSIGNED_BYTE,
+                    VertexComponentType::I16 => gl::SHORT,
+                    VertexComponentType::U16 => gl::UNSIGNED_SHORT,
+                    VertexComponentType::I32 => gl::INT,
+                    VertexComponentType::U32 => gl::UNSIGNED_INT,
+                    VertexComponentType::F32 => gl::FLOAT,
                 };
 
                 unsafe {
                     let location = gl.GetAttribLocation(program_id, name.as_ptr());
 
                     if location >= 0 {
-                        gl.EnableVertexArrayAttrib(vao, location as _);
-                        gl.VertexArrayAttribFormat(
-                            vao,
-                            location as _,
-                            count as _,
-                            gl::FLOAT,
-                            false as _,
-                            0,
-                        );
-                        gl.VertexArrayAttribBinding(vao, location as _, i as _);
+                        // integer inputs must not be converted to floats
+                        if attribute.ty.is_integer() {
+                            gl.EnableVertexArrayAttrib(vao, location as _);
+                            gl.VertexArrayAttribIFormat(
+                                vao,
+                                location as _,
+                                count as _,
+                                component,
+                                0,
+                            );
+                            gl.VertexArrayAttribBinding(vao, location as _, i as _);
+                        } else {
+                            for column in 0..columns {
+                                let location = (location as usize + column) as _;
+                                gl.EnableVertexArrayAttrib(vao, location);
+                                gl.VertexArrayAttribFormat(
+                                    vao,
+                                    location,
+                                    count as _,
+                                    component,
+                                    format.normalized as _,
+                                    (column * count * format.component.size()) as _,
+                                );
+                                gl.VertexArrayAttribBinding(vao, location, i as _);
+                            }
+                        }
                     }
                 }
             }
@@ -607,10 +1600,33 @@ impl GlPipeline {
             vao,
             gl,
             pipeline,
+            uniform_locations,
+            uniform_block_indices,
+            state,
             is_bound: false,
         })
     }
 
+    /// Get the location of a uniform, by its index in the program
+    fn uniform_location(&self, uniform: usize) -> Result<GLint, GlError> {
+        match self.uniform_locations[uniform] {
+            -1 => Err(GlError::UniformOptimizedOut {
+                name: self.pipeline.uniforms()[uniform].name.clone(),
+            }),
+            location => Ok(location),
+        }
+    }
+
+    /// Get the index of a uniform block, by its index in the program
+    fn uniform_block_index(&self, block: usize) -> Result<GLuint, GlError> {
+        match self.uniform_block_indices[block] {
+            gl::INVALID_INDEX => Err(GlError::UniformOptimizedOut {
+                name: self.pipeline.uniform_blocks()[block].name.clone(),
+            }),
+            index => Ok(index),
+        }
+    }
+
     fn bind(&mut self, gl: &gl::Gl) {
         unsafe {
             gl.UseProgram(self.program_id);
@@ -638,55 +1654,337 @@ pub struct GlTexture {
     gl: gl::Gl,
     id: GLuint,
     active_index: GLuint,
+
+    /// The settings the texture was loaded with, the kind is used to check it
+    /// is bound to the right type of sampler
+    options: TextureOptions,
+
+    /// The target used when binding the texture, depends on its kind
+    target: GLenum,
+
+    /// The number of mipmap levels that have storage allocated
+    levels: u32,
+
+    /// Regenerate the mipmaps when the base level is updated
+    generates_mipmaps: bool,
+
+    /// The format and type of the pixel data uploaded to the texture
+    format: GLenum,
+    ty: GLenum,
+
+    /// Pixel unpack buffer used for streaming updates, 0 until the first one
+    pixel_buffer: GLuint,
 }
 
 impl GlTexture {
-    pub fn new(gl: &gl::Gl, tex: &Texture, index: GLuint) -> Self {
+    pub fn new(gl: &gl::Gl, tex: &Texture, index: GLuint, max_anisotropy: u32) -> Self {
         let config = tex.config();
+        let target = texture_target_gl(config.kind);
+
+        let format = format_gl(config.source_format);
+        let ty = texture_type_gl(config.source_type);
+
+        // only the base level is uploaded if the mipmaps are generated
+        let levels = if tex.generates_mipmaps() {
+            1
+        } else {
+            tex.mip_levels()
+        };
 
         let mut texture = 0;
         unsafe {
             gl.ActiveTexture(gl::TEXTURE0 + index);
             gl.GenTextures(1, &mut texture);
+            gl.BindTexture(target, texture);
+
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_S, wrap_gl(config.wrap_s));
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_T, wrap_gl(config.wrap_t));
+            gl.TexParameteri(target, gl::TEXTURE_WRAP_R, wrap_gl(config.wrap_r));
+            gl.TexParameteri(
+                target,
+                gl::TEXTURE_MIN_FILTER,
+                min_filter_gl(config.min_filter),
+            );
+            gl.TexParameteri(
+                target,
+                gl::TEXTURE_MAG_FILTER,
+                mag_filter_gl(config.mag_filter),
+            );
+
+            // anisotropy is clamped to the maximum the driver supports
+            let anisotropy = config.anisotropy.min(max_anisotropy);
+            if anisotropy > 1 {
+                gl.TexParameterf(target, gl::TEXTURE_MAX_ANISOTROPY_EXT, anisotropy as _);
+            }
+
+            let internal_format = internal_format_gl(config.storage);
+
+            // compressed blocks are uploaded as is, the size of the data is
+            // needed instead of its format
+            let compressed = tex.is_compressed();
+            let image_2d = |target, level: u32, data: *const u8, size: usize| {
+                let (width, height) = tex.level_size(level);
+                if compressed {
+                    gl.CompressedTexImage2D(
+                        target,
+                        level as _,
+                        internal_format as _,
+                        width as _,
+                        height as _,
+                        0,
+                        size as _,
+                        data as _,
+                    )
+                } else {
+                    gl.TexImage2D(
+                        target,
+                        level as _,
+                        internal_format,
+                        width as _,
+                        height as _,
+                        0,
+                        format,
+                        ty,
+                        data as _,
+                    )
+                }
+            };
+            let image_3d = |level: u32, data: *const u8, size: usize| {
+                let (width, height) = tex.level_size(level);
+                let depth = tex.level_layers(level);
+                if compressed {
+                    gl.CompressedTexImage3D(
+                        target,
+                        level as _,
+                        internal_format as _,
+                        width as _,
+                        height as _,
+                        depth as _,
+                        0,
+                        size as _,
+                        data as _,
+                    )
+                } else {
+                    gl.TexImage3D(
+                        target,
+                        level as _,
+                        internal_format,
+                        width as _,
+                        height as _,
+                        depth as _,
+                        0,
+                        format,
+                        ty,
+                        data as _,
+                    )
+                }
+            };
+
+            for level in 0..levels {
+                let data = tex.level_ptr(level) as *const u8;
+                let layer_size = tex.level_layer_size(level);
+
+                match config.kind {
+                    TextureKind::Texture2D => image_2d(target, level, data, layer_size),
+                    TextureKind::Cubemap => {
+                        // each face is uploaded separately, they are stored one
+                        // after the other in the same order as the face targets
+                        for face in 0..6 {
+                            image_2d(
+                                gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum,
+                                level,
+                                data.add(face * layer_size),
+                                layer_size,
+                            );
+                        }
+                    }
+                    TextureKind::Array2D | TextureKind::Texture3D => {
+                        image_3d(level, data, layer_size * tex.level_layers(level) as usize)
+                    }
+                }
+            }
+
+            if tex.generates_mipmaps() {
+                gl.GenerateMipmap(target);
+            } else {
+                // the texture is incomplete unless all levels up to the max
+                // level are present
+                gl.TexParameteri(target, gl::TEXTURE_MAX_LEVEL, levels as GLint - 1);
+            }
+        }
+
+        Self {
+            gl: gl.clone(),
+            id: texture,
+            active_index: 0,
+            options: config,
+            target,
+            levels: if tex.generates_mipmaps() {
+                config.full_mip_levels()
+            } else {
+                levels
+            },
+            generates_mipmaps: tex.generates_mipmaps(),
+            format,
+            ty,
+            pixel_buffer: 0,
+        }
+    }
+
+    /// Create an empty texture to use as a render target attachment, see
+    /// [`RenderTargetOptions::color_options`] and
+    /// [`RenderTargetOptions::depth_options`]
+    fn attachment(gl: &gl::Gl, options: TextureOptions, internal_format: GLint) -> Self {
+        // depth attachments use a single component of source data
+        let (format, ty) = match (options.source_format, options.source_type) {
+            (TextureSourceFormat::R, TextureSourceType::U32) => {
+                (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8)
+            }
+            (TextureSourceFormat::R, ty) => (gl::DEPTH_COMPONENT, texture_type_gl(ty)),
+            (format, ty) => (format_gl(format), texture_type_gl(ty)),
+        };
+
+        let mut texture = 0;
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0);
+            gl.GenTextures(1, &mut texture);
             gl.BindTexture(gl::TEXTURE_2D, texture);
 
-            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap_gl(config.wrap_s));
-            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_gl(config.wrap_t));
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap_gl(options.wrap_s));
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_gl(options.wrap_t));
             gl.TexParameteri(
                 gl::TEXTURE_2D,
                 gl::TEXTURE_MIN_FILTER,
-                min_filter_gl(config.min_filter),
+                min_filter_gl(options.min_filter),
             );
             gl.TexParameteri(
                 gl::TEXTURE_2D,
                 gl::TEXTURE_MAG_FILTER,
-                mag_filter_gl(config.mag_filter),
+                mag_filter_gl(options.mag_filter),
             );
 
             gl.TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                internal_format_gl(config.storage),
-                tex.width() as _,
-                tex.height() as _,
+                internal_format,
+                options.width as _,
+                options.height as _,
                 0,
-                format_gl(config.source_format),
-                texture_type_gl(config.source_type),
-                tex.img_ptr() as _,
+                format,
+                ty,
+                std::ptr::null(),
             );
-
-            gl.GenerateMipmap(gl::TEXTURE_2D);
         }
 
         Self {
             gl: gl.clone(),
             id: texture,
             active_index: 0,
+            options,
+            target: gl::TEXTURE_2D,
+            levels: 1,
+            generates_mipmaps: false,
+            format,
+            ty,
+            pixel_buffer: 0,
+        }
+    }
+
+    /// Replace a region of the texture's pixel data, the region must already
+    /// have been checked.  Streamed data is copied into a pixel buffer first,
+    /// so the driver can upload it to the texture asynchronously.
+    fn update(&mut self, region: TextureRegion, data: &[u8], stream: bool) {
+        let gl = &self.gl;
+
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0);
+            gl.BindTexture(self.target, self.id);
+
+            // when a pixel buffer is bound the pointers are offsets into it
+            let mut pixels = data.as_ptr();
+            if stream {
+                if self.pixel_buffer == 0 {
+                    gl.GenBuffers(1, &mut self.pixel_buffer);
+                }
+                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, self.pixel_buffer);
+
+                // orphan the previous data, so the copy does not have to
+                // wait for the last upload to finish
+                gl.BufferData(
+                    gl::PIXEL_UNPACK_BUFFER,
+                    data.len() as GLsizeiptr,
+                    std::ptr::null(),
+                    gl::STREAM_DRAW,
+                );
+                gl.BufferSubData(
+                    gl::PIXEL_UNPACK_BUFFER,
+                    0,
+                    data.len() as GLsizeiptr,
+                    data.as_ptr() as _,
+                );
+                pixels = std::ptr::null();
+            }
+
+            // the rows of the data are tightly packed
+            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+
+            let level = region.level as GLint;
+            match self.options.kind {
+                TextureKind::Texture2D => gl.TexSubImage2D(
+                    self.target,
+                    level,
+                    region.x as _,
+                    region.y as _,
+                    region.width as _,
+                    region.height as _,
+                    self.format,
+                    self.ty,
+                    pixels as _,
+                ),
+                TextureKind::Cubemap => {
+                    let face_size = data.len() / region.layers.max(1) as usize;
+                    for face in 0..region.layers {
+                        gl.TexSubImage2D(
+                            gl::TEXTURE_CUBE_MAP_POSITIVE_X + region.layer + face,
+                            level,
+                            region.x as _,
+                            region.y as _,
+                            region.width as _,
+                            region.height as _,
+                            self.format,
+                            self.ty,
+                            pixels.wrapping_add(face as usize * face_size) as _,
+                        );
+                    }
+                }
+                TextureKind::Array2D | TextureKind::Texture3D => gl.TexSubImage3D(
+                    self.target,
+                    level,
+                    region.x as _,
+                    region.y as _,
+                    region.layer as _,
+                    region.width as _,
+                    region.height as _,
+                    region.layers as _,
+                    self.format,
+                    self.ty,
+                    pixels as _,
+                ),
+            }
+
+            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            if stream {
+                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            }
+
+            if self.generates_mipmaps && region.level == 0 {
+                gl.GenerateMipmap(self.target);
+            }
         }
     }
 
     /// Bind this texture to the current shader program.
-    pub fn bind(&self, index: GLuint) -> BoundGlTexture {
+    pub fn bind(&self, index: GLuint) -> BoundGlTexture<'_> {
         BoundGlTexture::new(&self, index)
     }
 
@@ -694,14 +1992,14 @@ impl GlTexture {
         self.active_index = index;
         unsafe {
             self.gl.ActiveTexture(gl::TEXTURE0 + index);
-            self.gl.BindTexture(gl::TEXTURE_2D, self.id);
+            self.gl.BindTexture(self.target, self.id);
         }
     }
 
     pub fn set_unbound(&mut self) {
         unsafe {
             self.gl.ActiveTexture(gl::TEXTURE0 + self.active_index);
-            self.gl.BindTexture(gl::TEXTURE_2D, 0);
+            self.gl.BindTexture(self.target, 0);
         }
         self.active_index = 0;
     }
@@ -712,10 +2010,65 @@ impl Drop for GlTexture {
     fn drop(&mut self) {
         unsafe {
             self.gl.DeleteTextures(1, &self.id);
+
+            if self.pixel_buffer != 0 {
+                self.gl.DeleteBuffers(1, &self.pixel_buffer);
+            }
         }
     }
 }
 
+fn comparison_gl(func: DepthTestingFunction) -> GLenum {
+    match func {
+        DepthTestingFunction::Always => gl::ALWAYS,
+        DepthTestingFunction::Never => gl::NEVER,
+        DepthTestingFunction::Less => gl::LESS,
+        DepthTestingFunction::Equal => gl::EQUAL,
+        DepthTestingFunction::LessEqual => gl::LEQUAL,
+        DepthTestingFunction::Greater => gl::GREATER,
+        DepthTestingFunction::NotEqual => gl::NOTEQUAL,
+        DepthTestingFunction::GreaterEqual => gl::GEQUAL,
+    }
+}
+
+fn stencil_operation_gl(operation: StencilOperation) -> GLenum {
+    match operation {
+        StencilOperation::Keep => gl::KEEP,
+        StencilOperation::Zero => gl::ZERO,
+        StencilOperation::Replace => gl::REPLACE,
+        StencilOperation::Increment => gl::INCR,
+        StencilOperation::IncrementWrap => gl::INCR_WRAP,
+        StencilOperation::Decrement => gl::DECR,
+        StencilOperation::DecrementWrap => gl::DECR_WRAP,
+        StencilOperation::Invert => gl::INVERT,
+    }
+}
+
+fn blend_equation_gl(equation: BlendEquation) -> GLenum {
+    match equation {
+        BlendEquation::Add => gl::FUNC_ADD,
+        BlendEquation::Subtract => gl::FUNC_SUBTRACT,
+        BlendEquation::ReverseSubtract => gl::FUNC_REVERSE_SUBTRACT,
+        BlendEquation::Min => gl::MIN,
+        BlendEquation::Max => gl::MAX,
+    }
+}
+
+fn blend_factor_gl(factor: BlendFactor) -> GLenum {
+    match factor {
+        BlendFactor::Zero => gl::ZERO,
+        BlendFactor::One => gl::ONE,
+        BlendFactor::SourceColor => gl::SRC_COLOR,
+        BlendFactor::OneMinusSourceColor => gl::ONE_MINUS_SRC_COLOR,
+        BlendFactor::DestinationColor => gl::DST_COLOR,
+        BlendFactor::OneMinusDestinationColor => gl::ONE_MINUS_DST_COLOR,
+        BlendFactor::SourceAlpha => gl::SRC_ALPHA,
+        BlendFactor::OneMinusSourceAlpha => gl::ONE_MINUS_SRC_ALPHA,
+        BlendFactor::DestinationAlpha => gl::DST_ALPHA,
+        BlendFactor::OneMinusDestinationAlpha => gl::ONE_MINUS_DST_ALPHA,
+    }
+}
+
 fn wrap_gl(wrap: WrappingMode) -> GLint {
     match wrap {
         WrappingMode::Repeat => gl::REPEAT as _,
@@ -742,6 +2095,15 @@ fn mag_filter_gl(mag: MagFilter) -> GLint {
     }
 }
 
+fn texture_target_gl(kind: TextureKind) -> GLenum {
+    match kind {
+        TextureKind::Texture2D => gl::TEXTURE_2D,
+        TextureKind::Cubemap => gl::TEXTURE_CUBE_MAP,
+        TextureKind::Array2D => gl::TEXTURE_2D_ARRAY,
+        TextureKind::Texture3D => gl::TEXTURE_3D,
+    }
+}
+
 fn internal_format_gl(source: TextureStorageType) -> GLint {
     match source {
         TextureStorageType::R => gl::RED as _,
@@ -750,6 +2112,30 @@ fn internal_format_gl(source: TextureStorageType) -> GLint {
         TextureStorageType::SRGB => gl::SRGB as _,
         TextureStorageType::RGBA => gl::RGBA as _,
         TextureStorageType::SRGBA => gl::SRGB_ALPHA as _,
+        TextureStorageType::R16F => gl::R16F as _,
+        TextureStorageType::RG16F => gl::RG16F as _,
+        TextureStorageType::RGB16F => gl::RGB16F as _,
+        TextureStorageType::RGBA16F => gl::RGBA16F as _,
+        TextureStorageType::R32F => gl::R32F as _,
+        TextureStorageType::RG32F => gl::RG32F as _,
+        TextureStorageType::RGB32F => gl::RGB32F as _,
+        TextureStorageType::RGBA32F => gl::RGBA32F as _,
+        TextureStorageType::BC1RGB => gl::COMPRESSED_RGB_S3TC_DXT1_EXT as _,
+        TextureStorageType::BC1SRGB => gl::COMPRESSED_SRGB_S3TC_DXT1_EXT as _,
+        TextureStorageType::BC1RGBA => gl::COMPRESSED_RGBA_S3TC_DXT1_EXT as _,
+        TextureStorageType::BC1SRGBA => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT as _,
+        TextureStorageType::BC2RGBA => gl::COMPRESSED_RGBA_S3TC_DXT3_EXT as _,
+        TextureStorageType::BC2SRGBA => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT as _,
+        TextureStorageType::BC3RGBA => gl::COMPRESSED_RGBA_S3TC_DXT5_EXT as _,
+        TextureStorageType::BC3SRGBA => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT as _,
+        TextureStorageType::BC4R => gl::COMPRESSED_RED_RGTC1 as _,
+        TextureStorageType::BC4SignedR => gl::COMPRESSED_SIGNED_RED_RGTC1 as _,
+        TextureStorageType::BC5RG => gl::COMPRESSED_RG_RGTC2 as _,
+        TextureStorageType::BC5SignedRG => gl::COMPRESSED_SIGNED_RG_RGTC2 as _,
+        TextureStorageType::BC6HUnsignedFloat => gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT as _,
+        TextureStorageType::BC6HSignedFloat => gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT as _,
+        TextureStorageType::BC7RGBA => gl::COMPRESSED_RGBA_BPTC_UNORM as _,
+        TextureStorageType::BC7SRGBA => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM as _,
     }
 }
 
@@ -772,6 +2158,7 @@ fn texture_type_gl(ty: TextureSourceType) -> GLenum {
         TextureSourceType::I16 => gl::SHORT,
         TextureSourceType::U32 => gl::UNSIGNED_INT,
         TextureSourceType::I32 => gl::INT,
+        TextureSourceType::F16 => gl::HALF_FLOAT,
         TextureSourceType::F32 => gl::FLOAT,
     }
 }
@@ -806,11 +2193,94 @@ impl<'a> Drop for BoundGlTexture<'a> {
     }
 }
 
+/// An OpenGl framebuffer object used by a render target
+#[derive(Debug)]
+struct GlFramebuffer {
+    gl: gl::Gl,
+    fbo: GLuint,
+    width: u32,
+    height: u32,
+}
+
+impl GlFramebuffer {
+    fn new(
+        gl: &gl::Gl,
+        options: &RenderTargetOptions,
+        color: Option<&GlTexture>,
+        depth: Option<&GlTexture>,
+    ) -> Result<Self, GlError> {
+        let mut fbo = 0;
+        unsafe {
+            gl.CreateFramebuffers(1, &mut fbo);
+        }
+
+        // create the struct first so the framebuffer is deleted on error
+        let framebuffer = GlFramebuffer {
+            gl: gl.clone(),
+            fbo,
+            width: options.width,
+            height: options.height,
+        };
+
+        unsafe {
+            if let Some(color) = color {
+                gl.NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, color.id, 0);
+            } else {
+                // depth only framebuffers have nothing to read from or draw to
+                gl.NamedFramebufferDrawBuffer(fbo, gl::NONE);
+                gl.NamedFramebufferReadBuffer(fbo, gl::NONE);
+            }
+
+            if let Some(depth) = depth {
+                let attachment = if options.stencil {
+                    gl::DEPTH_STENCIL_ATTACHMENT
+                } else {
+                    gl::DEPTH_ATTACHMENT
+                };
+                gl.NamedFramebufferTexture(fbo, attachment, depth.id, 0);
+            }
+        }
+
+        let status = unsafe { gl.CheckNamedFramebufferStatus(fbo, gl::FRAMEBUFFER) };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(GlError::IncompleteFramebuffer { status });
+        }
+
+        Ok(framebuffer)
+    }
+}
+
+impl Drop for GlFramebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteFramebuffers(1, &self.fbo);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Buffer {
     gl: gl::Gl,
     vbo: GLuint,
     pub buffer_type: GLenum,
+
+    /// The size of the buffer's data in bytes
+    size: usize,
+
+    /// The usage hint the data was loaded with, reused when orphaning
+    usage: GLenum,
+
+    /// The mapping of a ring buffer, None for any other buffer
+    ring: Option<MappedRing>,
+}
+
+/// A ring buffer's persistent mapping, along with the fences for each of its
+/// sections, so that a section is not overwritten while being read by a draw
+#[derive(Debug)]
+struct MappedRing {
+    ring: RingBuffer,
+    ptr: *mut u8,
+    fences: [GLsync; RING_SECTIONS],
 }
 
 impl Buffer {
@@ -824,9 +2294,34 @@ impl Buffer {
             gl: gl.clone(),
             vbo,
             buffer_type,
+            size: 0,
+            usage: gl::STATIC_DRAW,
+            ring: None,
         }
     }
 
+    /// Create a fixed size buffer that stays mapped for writing as a ring
+    fn ring(gl: &gl::Gl, buffer_type: GLenum, size: usize) -> Buffer {
+        let mut buf = Buffer::new(gl, buffer_type);
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+
+        buf.bind();
+        let mapping = unsafe {
+            gl.BufferStorage(buffer_type, size as GLsizeiptr, ptr::null(), flags);
+            gl.MapBufferRange(buffer_type, 0, size as GLsizeiptr, flags)
+        };
+        buf.unbind();
+
+        buf.size = size;
+        buf.ring = Some(MappedRing {
+            ring: RingBuffer::new(size),
+            ptr: mapping as *mut u8,
+            fences: [ptr::null(); RING_SECTIONS],
+        });
+
+        buf
+    }
+
     fn bind(&self) {
         unsafe {
             self.gl.BindBuffer(self.buffer_type, self.vbo);
@@ -839,41 +2334,239 @@ impl Buffer {
         }
     }
 
-    fn static_draw_data<T>(&self, data: &[T]) {
+    fn static_draw_data<T>(&mut self, data: &[T]) {
+        self.size = mem::size_of_val(data);
+        self.usage = gl::STATIC_DRAW;
         unsafe {
             self.gl.BufferData(
                 self.buffer_type,
-                (data.len() * size_of::<T>()) as GLsizeiptr,
+                mem::size_of_val(data) as GLsizeiptr,
                 data.as_ptr() as *const GLvoid,
                 gl::STATIC_DRAW,
             );
         }
     }
 
-    fn static_draw_data_stream<T>(&self, data: &[T]) {
+    fn static_draw_data_stream<T>(&mut self, data: &[T]) {
+        self.size = mem::size_of_val(data);
+        self.usage = gl::STREAM_DRAW;
         unsafe {
             self.gl.BufferData(
                 self.buffer_type,
-                (data.len() * size_of::<T>()) as GLsizeiptr,
+                mem::size_of_val(data) as GLsizeiptr,
                 data.as_ptr() as *const GLvoid,
                 gl::STREAM_DRAW,
             );
         }
     }
 
+    /// Replace part of the buffer's data
+    fn update(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferError> {
+        // draws could still be reading any part of a ring buffer other than
+        // the space allocated by write_ring
+        if self.ring.is_some() {
+            return Err(BufferError::UpdateRingBuffer);
+        }
+
+        check_buffer_write(offset, data.len(), self.size)?;
+
+        unsafe {
+            self.gl.NamedBufferSubData(
+                self.vbo,
+                offset as GLintptr,
+                data.len() as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replace all of the buffer's data, respecifying the whole buffer lets the
+    /// driver allocate new storage instead of waiting for draws using the old
+    fn orphan(&mut self, data: &[u8]) -> Result<(), BufferError> {
+        if self.ring.is_some() {
+            return Err(BufferError::OrphanRingBuffer);
+        }
+
+        self.size = data.len();
+        unsafe {
+            self.gl.NamedBufferData(
+                self.vbo,
+                data.len() as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                self.usage,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write data to the next free space in a ring buffer, returns the offset
+    /// it was written at
+    fn write_ring(&mut self, data: &[u8]) -> Result<usize, BufferError> {
+        let gl = &self.gl;
+        let mapped = self.ring.as_mut().ok_or(BufferError::NotRingBuffer)?;
+
+        let previous = mapped.ring.head();
+        let last = mapped.ring.section(previous.saturating_sub(1));
+        let offset = mapped.ring.allocate(data.len())?;
+        let first = mapped.ring.section(offset);
+        let end = mapped.ring.section(offset + data.len().max(1) - 1);
+
+        unsafe {
+            // every draw made so far could read from the sections left behind
+            let left = if offset < previous {
+                last..RING_SECTIONS
+            } else {
+                last..first
+            };
+            for section in left {
+                if !mapped.fences[section].is_null() {
+                    gl.DeleteSync(mapped.fences[section]);
+                }
+                mapped.fences[section] = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            }
+
+            // wait for the draws reading the sections about to be written to
+            for section in first..=end {
+                let fence = mem::replace(&mut mapped.fences[section], ptr::null());
+                if !fence.is_null() {
+                    gl.ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+                    gl.DeleteSync(fence);
+                }
+            }
+
+            ptr::copy_nonoverlapping(data.as_ptr(), mapped.ptr.add(offset), data.len());
+        }
+
+        Ok(offset)
+    }
+
     fn id(&self) -> GLuint {
         self.vbo
     }
+
+    /// Read back all of the buffer's data, waits for the gpu to finish writing
+    fn read(&self) -> Vec<u8> {
+        let mut data = vec![0; self.size];
+        unsafe {
+            self.gl.GetNamedBufferSubData(
+                self.vbo,
+                0,
+                self.size as GLsizeiptr,
+                data.as_mut_ptr() as *mut GLvoid,
+            );
+        }
+
+        data
+    }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
         unsafe {
+            // deleting a buffer also unmaps it
+            if let Some(mapped) = &self.ring {
+                for &fence in mapped.fences.iter().filter(|fence| !fence.is_null()) {
+                    self.gl.DeleteSync(fence);
+                }
+            }
+
             self.gl.DeleteBuffers(1, &self.vbo);
         }
     }
 }
 
+/// Query the features and limits of the driver
+fn query_capabilities(gl: &gl::Gl) -> RendererCapabilities {
+    let get_string = |name| unsafe {
+        let string = gl.GetString(name);
+        if string.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(string as *const _)
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+
+    let get_integer = |name| {
+        let mut value = 0;
+        unsafe { gl.GetIntegerv(name, &mut value) };
+        value as u32
+    };
+
+    let extensions: Vec<_> = (0..get_integer(gl::NUM_EXTENSIONS))
+        .map(|i| unsafe {
+            let name = gl.GetStringi(gl::EXTENSIONS, i);
+            CStr::from_ptr(name as *const _)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    let has_extension = |name| extensions.iter().any(|ext| ext == name);
+
+    let version = (
+        get_integer(gl::MAJOR_VERSION),
+        get_integer(gl::MINOR_VERSION),
+    );
+
+    // querying the maximum anisotropy is an error if it is not supported
+    let mut max_anisotropy = 1.0;
+    if version >= (4, 6)
+        || has_extension("GL_EXT_texture_filter_anisotropic")
+        || has_extension("GL_ARB_texture_filter_anisotropic")
+    {
+        unsafe { gl.GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY_EXT, &mut max_anisotropy) };
+    }
+
+    // rgtc is core in every version the renderer supports, the others depend
+    // on the version or extensions
+    let mut compressed_formats = vec![
+        TextureStorageType::BC4R,
+        TextureStorageType::BC4SignedR,
+        TextureStorageType::BC5RG,
+        TextureStorageType::BC5SignedRG,
+    ];
+    if has_extension("GL_EXT_texture_compression_s3tc") {
+        compressed_formats.extend(&[
+            TextureStorageType::BC1RGB,
+            TextureStorageType::BC1RGBA,
+            TextureStorageType::BC2RGBA,
+            TextureStorageType::BC3RGBA,
+        ]);
+
+        if has_extension("GL_EXT_texture_sRGB") {
+            compressed_formats.extend(&[
+                TextureStorageType::BC1SRGB,
+                TextureStorageType::BC1SRGBA,
+                TextureStorageType::BC2SRGBA,
+                TextureStorageType::BC3SRGBA,
+            ]);
+        }
+    }
+    if version >= (4, 2) || has_extension("GL_ARB_texture_compression_bptc") {
+        compressed_formats.extend(&[
+            TextureStorageType::BC6HUnsignedFloat,
+            TextureStorageType::BC6HSignedFloat,
+            TextureStorageType::BC7RGBA,
+            TextureStorageType::BC7SRGBA,
+        ]);
+    }
+
+    RendererCapabilities {
+        version,
+        vendor: get_string(gl::VENDOR),
+        renderer: get_string(gl::RENDERER),
+        max_texture_size: get_integer(gl::MAX_TEXTURE_SIZE),
+        max_anisotropy: max_anisotropy as _,
+        texture_units: get_integer(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS),
+        extensions,
+        compressed_formats,
+    }
+}
+
 /// attach console print debugging to the provided OpenGL Context
 fn enable_gl_debugging(gl: &gl::Gl) {
     let mut flags = 0;
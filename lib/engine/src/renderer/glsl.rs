@@ -1,5 +1,5 @@
 use super::shader::{
-    Block, BuiltinFunction, Function, Program, Statement, Type, Variable,
+    Block, BuiltinFunction, BuiltinVariable, Function, Program, Statement, Type, Variable,
     VariableAllocationContext, VariableId,
 };
 use thiserror::Error;
@@ -8,11 +8,41 @@ use thiserror::Error;
 enum GlslError {
     #[error("Unable to represent the type {ty} in glsl")]
     UnreprsentableType { ty: Type },
+
+    #[error("Variable {name} has type {ty}, arrays can only be used as uniforms")]
+    ArrayNotUniform { name: String, ty: Type },
+
+    #[error("Uniform block member {name} has opaque type {ty}")]
+    OpaqueBlockMember { name: String, ty: Type },
+
+    #[error("Builtin variable {variable} is read only")]
+    ReadOnlyBuiltin { variable: BuiltinVariable },
+
+    #[error("Builtin variable {variable} can only be used in a vertex shader")]
+    VertexOnlyBuiltin { variable: BuiltinVariable },
+
+    #[error("Builtin variable {variable} can only be used in a compute shader")]
+    ComputeOnlyBuiltin { variable: BuiltinVariable },
+
+    #[error("A program with a compute shader cannot also have vertex or fragment shaders")]
+    ComputeWithGraphics,
+
+    #[error("Compute shaders cannot have inputs or outputs, use storage buffers instead")]
+    ComputeInterface,
 }
 
 pub(super) struct GlslCode {
     pub(super) vert: Option<String>,
     pub(super) frag: Option<String>,
+    pub(super) compute: Option<String>,
+}
+
+/// The stage a shader function is compiled as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Vertex,
+    Fragment,
+    Compute,
 }
 
 impl Program {
@@ -22,21 +52,83 @@ impl Program {
         Ok(GlslCode {
             vert: self.vert_shader()?,
             frag: self.frag_shader()?,
+            compute: self.compute_shader()?,
         })
     }
 
     fn glsl_verification(&self) -> Result<(), GlslError> {
+        if let Some(compute) = self.compute_main() {
+            if self.vertex_main().is_some() || self.frag_main().is_some() {
+                return Err(GlslError::ComputeWithGraphics);
+            }
+
+            if !compute.inputs().is_empty() || !compute.outputs().is_empty() {
+                return Err(GlslError::ComputeInterface);
+            }
+        }
+
         for uniform in self.uniforms() {
             if !uniform.ty.is_representable() {
                 return Err(GlslError::UnreprsentableType { ty: uniform.ty });
             }
         }
 
+        for block in self.uniform_blocks() {
+            for &member in &block.members {
+                let member = &self.uniforms()[member];
+                if member.ty.is_sampler() {
+                    return Err(GlslError::OpaqueBlockMember {
+                        name: member.name.clone(),
+                        ty: member.ty,
+                    });
+                }
+            }
+        }
+
         for func in self.functions() {
             for var in func.all_vars() {
                 if !var.ty.is_representable() {
                     return Err(GlslError::UnreprsentableType { ty: var.ty });
                 }
+
+                if let Type::Array(..) = var.ty {
+                    return Err(GlslError::ArrayNotUniform {
+                        name: var.name.clone(),
+                        ty: var.ty,
+                    });
+                }
+            }
+
+            let is_vertex = matches!(self.vertex_main(), Some(vert) if std::ptr::eq(vert, func));
+            let is_compute =
+                matches!(self.compute_main(), Some(compute) if std::ptr::eq(compute, func));
+
+            for block in func.blocks() {
+                for statement in block.statements() {
+                    match *statement {
+                        Statement::SetBuiltinVariable {
+                            variable:
+                                variable @ (BuiltinVariable::InstanceId | BuiltinVariable::BaseInstance),
+                            ..
+                        } => return Err(GlslError::ReadOnlyBuiltin { variable }),
+                        Statement::GetBuiltinVariable {
+                            variable:
+                                variable @ (BuiltinVariable::InstanceId | BuiltinVariable::BaseInstance),
+                            ..
+                        } if !is_vertex => return Err(GlslError::VertexOnlyBuiltin { variable }),
+                        Statement::SetBuiltinVariable { variable, .. }
+                            if variable.is_invocation() =>
+                        {
+                            return Err(GlslError::ReadOnlyBuiltin { variable })
+                        }
+                        Statement::GetBuiltinVariable { variable, .. }
+                            if variable.is_invocation() && !is_compute =>
+                        {
+                            return Err(GlslError::ComputeOnlyBuiltin { variable })
+                        }
+                        _ => (),
+                    }
+                }
             }
         }
 
@@ -50,7 +142,7 @@ impl Program {
             return Ok(None);
         };
 
-        self.write_shader(shader)
+        self.write_shader(shader, Stage::Vertex)
     }
 
     fn frag_shader(&self) -> Result<Option<String>, GlslError> {
@@ -60,7 +152,17 @@ impl Program {
             return Ok(None);
         };
 
-        self.write_shader(shader)
+        self.write_shader(shader, Stage::Fragment)
+    }
+
+    fn compute_shader(&self) -> Result<Option<String>, GlslError> {
+        let shader = if let Some(compute) = self.compute_main() {
+            compute
+        } else {
+            return Ok(None);
+        };
+
+        self.write_shader(shader, Stage::Compute)
     }
 
     fn used_uniforms(&self, func: &Function) -> Vec<&Variable> {
@@ -72,31 +174,124 @@ impl Program {
         uniforms
     }
 
+    /// Get the uniforms used by a block that are not members of a uniform
+    /// block, each uniform is only included once
     fn block_uniforms<'a>(&'a self, uniforms: &mut Vec<&'a Variable>, block: &Block) {
         for statement in block.statements() {
-            match statement {
-                Statement::CallBuiltin { arguments, .. } => {
+            let arguments = match statement {
+                Statement::CallBuiltin { arguments, .. } => &arguments[..],
+                Statement::SetBuiltinVariable { value, .. } => std::slice::from_ref(value),
+                _ => continue,
+            };
+
+            for arg in arguments {
+                if arg.allocation_kind() != VariableAllocationContext::Uniform
+                    || self.uniform_block_of(arg.id()).is_some()
+                {
+                    continue;
+                }
+
+                let uniform = &self.uniforms()[arg.id()];
+                if !uniforms.iter().any(|&used| std::ptr::eq(used, uniform)) {
+                    uniforms.push(uniform);
+                }
+            }
+        }
+    }
+
+    /// Get the indices of the storage buffers used by a function, each buffer
+    /// is only included once
+    fn used_storage_buffers(&self, func: &Function) -> Vec<usize> {
+        let mut buffers = vec![];
+        for block in func.blocks() {
+            for statement in block.statements() {
+                if let Statement::CallBuiltin { arguments, .. } = statement {
                     for arg in arguments {
-                        if arg.allocation_kind() == VariableAllocationContext::Uniform {
-                            uniforms.push(&self.uniforms()[arg.id()])
+                        if arg.allocation_kind() == VariableAllocationContext::Storage
+                            && !buffers.contains(&arg.id())
+                        {
+                            buffers.push(arg.id());
                         }
                     }
                 }
-                _ => (),
             }
         }
+
+        buffers.sort_unstable();
+        buffers
     }
 
-    fn write_shader(&self, shader: &Function) -> Result<Option<String>, GlslError> {
-        let mut source = String::from("#version 330 core\n");
+    fn write_shader(&self, shader: &Function, stage: Stage) -> Result<Option<String>, GlslError> {
+        // compute shaders and storage buffers need OpenGl 4.3
+        let mut source = if self.compute_main().is_some() || !self.storage_buffers().is_empty() {
+            String::from("#version 430 core\n")
+        } else {
+            String::from("#version 330 core\n")
+        };
+
+        // the base instance is not core until OpenGl 4.6
+        let base_instance = shader.blocks().iter().any(|block| {
+            block.statements().iter().any(|statement| {
+                matches!(
+                    statement,
+                    Statement::GetBuiltinVariable {
+                        variable: BuiltinVariable::BaseInstance,
+                        ..
+                    }
+                )
+            })
+        });
+        if base_instance {
+            source.push_str("#extension GL_ARB_shader_draw_parameters : require\n");
+        }
+
+        if let Some([x, y, z]) = self.local_size().filter(|_| stage == Stage::Compute) {
+            source.push_str(&format!(
+                "layout(local_size_x = {}, local_size_y = {}, local_size_z = {}) in;\n",
+                x, y, z
+            ));
+        }
 
         global_output(
             &mut source,
             "uniform",
+            "uniform",
             self.used_uniforms(shader).into_iter(),
         )?;
-        global_output(&mut source, "in", shader.inputs().into_iter())?;
-        global_output(&mut source, "out", shader.outputs().into_iter())?;
+
+        // every block is declared in every shader, so they have the same
+        // layout in all stages
+        for block in self.uniform_blocks() {
+            source.push_str(&format!("layout(std140) uniform {} {{\n", block.name));
+            for &member in &block.members {
+                source.push_str(&format!("    {};\n", declaration(&self.uniforms()[member])));
+            }
+            source.push_str("};\n");
+        }
+
+        // the binding of each buffer is its index in the program, so buffers
+        // can be bound without querying the program
+        for index in self.used_storage_buffers(shader) {
+            let buffer = &self.storage_buffers()[index];
+            if let Type::Buffer(element) = buffer.ty {
+                source.push_str(&format!(
+                    "layout(std430, binding = {}) buffer {}_buffer {{\n    {} {}[];\n}};\n",
+                    index,
+                    buffer.name,
+                    element.to_glsl(),
+                    buffer.name
+                ));
+            }
+        }
+
+        // integers can't be interpolated so must be flat between stages
+        let (integer_inputs, integer_outputs) = if stage == Stage::Vertex {
+            ("in", "flat out")
+        } else {
+            ("flat in", "out")
+        };
+        global_output(&mut source, "in", integer_inputs, shader.inputs().iter())?;
+        global_output(&mut source, "out", integer_outputs, shader.outputs().iter())?;
 
         source.push_str("void main() {\n");
         write_func(&mut source, self, shader);
@@ -109,10 +304,28 @@ impl Program {
 impl Type {
     fn is_representable(&self) -> bool {
         match self {
-            Type::Vector(n) => *n <= 4,
+            Type::Vector(n) | Type::IntVector(n) | Type::UIntVector(n) => *n <= 4,
             Type::Matrix(m, n) => *m <= 4 && *n <= 4,
             Type::Floating => true,
-            Type::Sampler2D => true,
+            Type::Integer | Type::UnsignedInteger => true,
+            Type::Sampler2D | Type::SamplerCube | Type::Sampler2DArray | Type::Sampler3D => true,
+            Type::Array(element, len) => {
+                *len > 0
+                    && matches!(
+                        element,
+                        Type::Vector(_)
+                            | Type::IntVector(_)
+                            | Type::UIntVector(_)
+                            | Type::Matrix(..)
+                            | Type::Floating
+                            | Type::Integer
+                            | Type::UnsignedInteger
+                    )
+                    && element.is_representable()
+            }
+
+            // storage buffers are declared separately from other variables
+            Type::Buffer(_) => false,
             Type::Unknown => false,
         }
     }
@@ -120,6 +333,8 @@ impl Type {
     fn to_glsl(&self) -> String {
         match self {
             Type::Vector(n) => format!("vec{}", *n),
+            Type::IntVector(n) => format!("ivec{}", *n),
+            Type::UIntVector(n) => format!("uvec{}", *n),
             Type::Matrix(rows, cols) => {
                 if rows == cols {
                     format!("mat{}", rows)
@@ -128,24 +343,48 @@ impl Type {
                 }
             }
             Type::Floating => "float".to_string(),
+            Type::Integer => "int".to_string(),
+            Type::UnsignedInteger => "uint".to_string(),
             Type::Sampler2D => "sampler2D".to_string(),
+            Type::SamplerCube => "samplerCube".to_string(),
+            Type::Sampler2DArray => "sampler2DArray".to_string(),
+            Type::Sampler3D => "sampler3D".to_string(),
+            Type::Array(element, len) => format!("{}[{}]", element.to_glsl(), len),
+            Type::Buffer(element) => format!("{}[]", element.to_glsl()),
             Type::Unknown => "".to_string(), // should not occur
         }
     }
 }
 
+/// Declare a list of global variables, `integer_kind` is used instead of `kind`
+/// for integer variables
 fn global_output<'a>(
     out: &mut String,
     kind: &str,
+    integer_kind: &str,
     vars: impl Iterator<Item = &'a Variable>,
 ) -> Result<(), GlslError> {
     for var in vars {
-        out.push_str(&format!("{} {} {};\n", kind, var.ty.to_glsl(), var.name));
+        let kind = if var.ty.is_integer() {
+            integer_kind
+        } else {
+            kind
+        };
+
+        out.push_str(&format!("{} {};\n", kind, declaration(var)));
     }
 
     Ok(())
 }
 
+/// The type and name of a variable, as used when declaring it
+fn declaration(var: &Variable) -> String {
+    match var.ty {
+        Type::Array(element, len) => format!("{} {}[{}]", element.to_glsl(), var.name, len),
+        ty => format!("{} {}", ty.to_glsl(), var.name),
+    }
+}
+
 fn write_func(shader: &mut String, prog: &Program, func: &Function) {
     for block in func.blocks() {
         for statement in block.statements() {
@@ -163,9 +402,15 @@ fn write_func(shader: &mut String, prog: &Program, func: &Function) {
                     shader.push_str(&format!("{:.20}", value));
                     shader.push_str(";\n");
                 }
+                Statement::MakeInt { value, variable } => {
+                    write_variable_new(shader, prog, func, *variable);
+                    shader.push_str(" = ");
+                    shader.push_str(&value.to_string());
+                    shader.push_str(";\n");
+                }
                 Statement::SetBuiltinVariable { variable, value } => {
                     shader.push_str("    ");
-                    shader.push_str(&variable.to_string());
+                    shader.push_str(&builtin_glsl(*variable));
                     shader.push_str(" = ");
                     write_variable_get(shader, prog, func, *value);
                     shader.push_str(";\n");
@@ -173,7 +418,7 @@ fn write_func(shader: &mut String, prog: &Program, func: &Function) {
                 Statement::GetBuiltinVariable { variable, result } => {
                     write_variable_new(shader, prog, func, *result);
                     shader.push_str(" = ");
-                    shader.push_str(&variable.to_string());
+                    shader.push_str(&builtin_glsl(*variable));
                     shader.push_str(";\n");
                 }
             }
@@ -181,6 +426,16 @@ fn write_func(shader: &mut String, prog: &Program, func: &Function) {
     }
 }
 
+/// The glsl expression for a builtin variable, invocation ids are unsigned in
+/// glsl so are converted to match their integer type
+fn builtin_glsl(variable: BuiltinVariable) -> String {
+    if variable.is_invocation() {
+        format!("int({})", variable)
+    } else {
+        variable.to_string()
+    }
+}
+
 fn write_variable_new(shader: &mut String, prog: &Program, func: &Function, variable: VariableId) {
     shader.push_str("    ");
 
@@ -239,12 +494,26 @@ fn write_builtin_call(
             write_variable_get(shader, prog, func, arguments[1]);
             shader.push_str(");\n");
         }
+        BuiltinFunction::Index => {
+            write_variable_get(shader, prog, func, arguments[0]);
+            shader.push('[');
+            write_variable_get(shader, prog, func, arguments[1]);
+            shader.push_str("];\n");
+        }
         BuiltinFunction::SetGlobal => {
             write_variable_get(shader, prog, func, arguments[0]);
             shader.push_str(" = ");
             write_variable_get(shader, prog, func, arguments[1]);
             shader.push_str(";\n");
         }
+        BuiltinFunction::Store => {
+            write_variable_get(shader, prog, func, arguments[0]);
+            shader.push('[');
+            write_variable_get(shader, prog, func, arguments[1]);
+            shader.push_str("] = ");
+            write_variable_get(shader, prog, func, arguments[2]);
+            shader.push_str(";\n");
+        }
         BuiltinFunction::MakeVec => {
             if let Some(result) = result {
                 let result = prog.get_variable(func, *result);
@@ -0,0 +1,499 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use super::{
+    CommandBuffer, CommandSender, IndexBufferId, IndirectBufferId, PipelineId, RenderTargetId,
+    StorageBufferId, TextureId, UniformBufferId, VertexBufferId,
+};
+
+/// The different types of resource that can be loaded into a renderer
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Texture,
+    VertexBuffer,
+    IndexBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    IndirectBuffer,
+    Pipeline,
+    RenderTarget,
+}
+
+impl fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResourceKind::Texture => "texture",
+            ResourceKind::VertexBuffer => "vertex buffer",
+            ResourceKind::IndexBuffer => "index buffer",
+            ResourceKind::UniformBuffer => "uniform buffer",
+            ResourceKind::StorageBuffer => "storage buffer",
+            ResourceKind::IndirectBuffer => "indirect buffer",
+            ResourceKind::Pipeline => "pipeline",
+            ResourceKind::RenderTarget => "render target",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The value inside all *Id structs.  Indices are reused once a resource is
+/// unloaded, the generation is increased each time so that a handle to the
+/// old resource can be told apart from one to the new resource.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl fmt::Display for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+/// Errors from using a handle that does not refer to a loaded resource
+#[derive(Debug, Error)]
+pub enum HandleError {
+    #[error("The {kind} {handle} has already been unloaded")]
+    Stale { kind: ResourceKind, handle: Handle },
+
+    #[error("The {kind} {handle} was reserved but has not been loaded")]
+    NotLoaded { kind: ResourceKind, handle: Handle },
+
+    #[error("The {kind} {handle} has already been loaded")]
+    AlreadyLoaded { kind: ResourceKind, handle: Handle },
+
+    #[error("The texture {handle} is an attachment of the render target {target}, so can only be replaced or unloaded along with it")]
+    Attachment { handle: Handle, target: Handle },
+}
+
+/// A resource id that can be unloaded
+pub trait Resource: Copy {
+    /// The type of resource the id refers to
+    const KIND: ResourceKind;
+
+    fn handle(&self) -> Handle;
+
+    /// Record unloading the resource into a command buffer
+    fn unload(self, commands: &mut CommandBuffer);
+}
+
+macro_rules! resource {
+    ($id:ident, $kind:ident, $unload:ident) => {
+        impl Resource for $id {
+            const KIND: ResourceKind = ResourceKind::$kind;
+
+            fn handle(&self) -> Handle {
+                self.0
+            }
+
+            fn unload(self, commands: &mut CommandBuffer) {
+                commands.$unload(self);
+            }
+        }
+    };
+}
+
+resource!(TextureId, Texture, unload_texture);
+resource!(VertexBufferId, VertexBuffer, unload_vertex_buffer);
+resource!(IndexBufferId, IndexBuffer, unload_index_buffer);
+resource!(UniformBufferId, UniformBuffer, unload_uniform_buffer);
+resource!(StorageBufferId, StorageBuffer, unload_storage_buffer);
+resource!(IndirectBufferId, IndirectBuffer, unload_indirect_buffer);
+resource!(PipelineId, Pipeline, unload_pipeline);
+
+impl Resource for RenderTargetId {
+    const KIND: ResourceKind = ResourceKind::RenderTarget;
+
+    fn handle(&self) -> Handle {
+        self.id
+    }
+
+    fn unload(self, commands: &mut CommandBuffer) {
+        commands.unload_render_target(self);
+    }
+}
+
+/// A single slot of the allocator, kind is None if the slot is free.  A slot
+/// is reserved when its id is handed out, but is only loaded once the backend
+/// has loaded the resource.
+#[derive(Debug, Default)]
+struct Slot {
+    generation: u32,
+    kind: Option<ResourceKind>,
+    loaded: bool,
+
+    /// The render target that a texture is an attachment of
+    owner: Option<Handle>,
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    slots: Vec<Slot>,
+
+    /// Indices of all the free slots, reused before making new ones
+    free: Vec<u32>,
+}
+
+impl Slots {
+    /// Get the slot of a handle, if it has not been freed and is the right
+    /// kind
+    fn slot<T: Resource>(&mut self, id: T) -> Result<&mut Slot, HandleError> {
+        let handle = id.handle();
+
+        match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation && slot.kind == Some(T::KIND) => {
+                Ok(slot)
+            }
+            _ => Err(HandleError::Stale {
+                kind: T::KIND,
+                handle,
+            }),
+        }
+    }
+
+    /// Check that a handle refers to a loaded slot of the right kind
+    fn check<T: Resource>(&mut self, id: T) -> Result<(), HandleError> {
+        if self.slot(id)?.loaded {
+            Ok(())
+        } else {
+            Err(HandleError::NotLoaded {
+                kind: T::KIND,
+                handle: id.handle(),
+            })
+        }
+    }
+
+    /// Check that a handle refers to a reserved slot that is not yet loaded
+    fn check_reserved<T: Resource>(&mut self, id: T) -> Result<(), HandleError> {
+        if self.slot(id)?.loaded {
+            Err(HandleError::AlreadyLoaded {
+                kind: T::KIND,
+                handle: id.handle(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that a handle refers to a loaded slot that is not owned by a
+    /// render target
+    fn check_unowned<T: Resource>(&mut self, id: T) -> Result<(), HandleError> {
+        self.check(id)?;
+
+        match self.slot(id)?.owner {
+            Some(target) => Err(HandleError::Attachment {
+                handle: id.handle(),
+                target,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Mark a checked handle's slot as loaded
+    fn load(&mut self, handle: Handle) {
+        self.slots[handle.index as usize].loaded = true;
+    }
+
+    /// Free a checked handle's slot so that it can be reused
+    fn release(&mut self, handle: Handle) {
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.kind = None;
+        slot.loaded = false;
+        slot.owner = None;
+        self.free.push(handle.index);
+    }
+}
+
+/// Allocator for resource ids, shared between a renderer and all of the
+/// command buffers created from it, so ids can be reserved on any thread.
+/// Keeps track of which ids are in use, so that using an id after it was
+/// unloaded is an error rather than a panic in the backend.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResourceIds {
+    slots: Arc<Mutex<Slots>>,
+}
+
+impl ResourceIds {
+    /// Reserve a handle for a resource
+    pub(crate) fn next(&self, kind: ResourceKind) -> Handle {
+        let mut slots = self.slots.lock().unwrap();
+
+        let index = match slots.free.pop() {
+            Some(index) => index,
+            None => {
+                slots.slots.push(Slot::default());
+                slots.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut slots.slots[index as usize];
+        slot.kind = Some(kind);
+
+        Handle {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    /// Mark a reserved handle as loaded, so that it can be used
+    pub(crate) fn load<T: Resource>(&self, id: T) -> Result<(), HandleError> {
+        let mut slots = self.slots.lock().unwrap();
+
+        slots.check_reserved(id)?;
+        slots.load(id.handle());
+
+        Ok(())
+    }
+
+    /// Mark a render target's reserved handle as loaded along with the
+    /// handles of its attachments
+    pub(crate) fn load_render_target(&self, target: RenderTargetId) -> Result<(), HandleError> {
+        let mut slots = self.slots.lock().unwrap();
+        let attachments: Vec<_> = target.color().into_iter().chain(target.depth()).collect();
+
+        slots.check_reserved(target)?;
+        for &texture in &attachments {
+            slots.check_reserved(texture)?;
+        }
+
+        slots.load(target.handle());
+        for texture in attachments {
+            slots.load(texture.handle());
+            slots.slots[texture.handle().index as usize].owner = Some(target.handle());
+        }
+
+        Ok(())
+    }
+
+    /// Check that a handle has been loaded and not released
+    pub(crate) fn check<T: Resource>(&self, id: T) -> Result<(), HandleError> {
+        self.slots.lock().unwrap().check(id)
+    }
+
+    /// Check that a handle can be replaced by a new resource, the textures of
+    /// a render target's attachments cannot be as the render target uses them
+    pub(crate) fn check_replaceable<T: Resource>(&self, id: T) -> Result<(), HandleError> {
+        self.slots.lock().unwrap().check_unowned(id)
+    }
+
+    /// Free a handle so its index can be reused, any copies of the handle
+    /// become stale.  The textures of a render target's attachments are only
+    /// freed along with the render target.
+    pub(crate) fn release<T: Resource>(&self, id: T) -> Result<(), HandleError> {
+        let mut slots = self.slots.lock().unwrap();
+
+        slots.check_unowned(id)?;
+        slots.release(id.handle());
+
+        Ok(())
+    }
+
+    /// Free a handle that was reserved but never loaded, e.g. because loading
+    /// it failed.  Does nothing if the handle is stale or has been loaded.
+    pub(crate) fn unreserve<T: Resource>(&self, id: T) {
+        let mut slots = self.slots.lock().unwrap();
+
+        if slots.check_reserved(id).is_ok() {
+            slots.release(id.handle());
+        }
+    }
+
+    /// Free the handles of a render target and its attachments that were
+    /// reserved but never loaded
+    pub(crate) fn unreserve_render_target(&self, target: RenderTargetId) {
+        self.unreserve(target);
+        for texture in target.color().into_iter().chain(target.depth()) {
+            self.unreserve(texture);
+        }
+    }
+
+    /// Free a render target's handle along with the handles of its
+    /// attachments.  Nothing is released unless all of them are live.
+    pub(crate) fn release_render_target(&self, target: RenderTargetId) -> Result<(), HandleError> {
+        let mut slots = self.slots.lock().unwrap();
+        let attachments: Vec<_> = target.color().into_iter().chain(target.depth()).collect();
+
+        slots.check(target)?;
+        for &texture in &attachments {
+            slots.check(texture)?;
+        }
+
+        slots.release(target.handle());
+        for texture in attachments {
+            slots.release(texture.handle());
+        }
+
+        Ok(())
+    }
+
+    /// All the handles that are currently reserved
+    fn live(&self) -> Vec<(ResourceKind, Handle)> {
+        let slots = self.slots.lock().unwrap();
+
+        slots
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let handle = Handle {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                slot.kind.map(|kind| (kind, handle))
+            })
+            .collect()
+    }
+
+    /// Report all the resources that have not been unloaded
+    pub(crate) fn leak_report(&self) -> LeakReport {
+        LeakReport {
+            resources: self.live(),
+        }
+    }
+}
+
+/// The resources that were still loaded when the report was made, along with
+/// ids that were reserved but never loaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LeakReport {
+    pub resources: Vec<(ResourceKind, Handle)>,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// The number of resources of a kind that were leaked
+    pub fn count(&self, kind: ResourceKind) -> usize {
+        self.resources.iter().filter(|(k, _)| *k == kind).count()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} renderer resources were not unloaded",
+            self.resources.len()
+        )?;
+
+        for (kind, handle) in &self.resources {
+            write!(f, "\n    {} {}", kind, handle)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Owns a loaded resource, unloading it when dropped.  The unload is queued
+/// the same as a command buffer sent from another thread, so happens the next
+/// time the renderer submits its queued command buffers.  An owned resource
+/// should not also be unloaded directly.
+#[derive(Debug)]
+pub struct Owned<T: Resource> {
+    id: T,
+
+    /// None once the resource has been released from being owned
+    sender: Option<CommandSender>,
+}
+
+impl<T: Resource> Owned<T> {
+    pub(crate) fn new(id: T, sender: CommandSender) -> Self {
+        Self {
+            id,
+            sender: Some(sender),
+        }
+    }
+
+    /// The id of the owned resource
+    pub fn id(&self) -> T {
+        self.id
+    }
+
+    /// Stop owning the resource without unloading it
+    pub fn into_inner(mut self) -> T {
+        self.sender = None;
+        self.id
+    }
+}
+
+impl<T: Resource> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let mut commands = sender.command_buffer();
+            self.id.unload(&mut commands);
+
+            // if the renderer has already been dropped, so has the resource
+            let _ = sender.send(commands);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::ResourceKind;
+    use crate::{
+        renderer::{recording::RecordingRenderer, RenderTargetOptions, Renderer},
+        texture::{Texture, TextureOptions},
+    };
+
+    #[test]
+    fn test_stale_handles() -> Result<()> {
+        let mut renderer = Renderer::new(Box::new(RecordingRenderer::new()));
+
+        let first = renderer.load_vertex_buffer(&[0; 4]);
+        renderer.unload_vertex_buffer(first)?;
+        assert!(renderer.unload_vertex_buffer(first).is_err());
+
+        // the slot is reused, but the old id still cannot be used
+        let second = renderer.load_vertex_buffer(&[0; 4]);
+        assert_eq!(first.0.index, second.0.index);
+        assert!(renderer.update_vertex_buffer(first, 0, &[1]).is_err());
+        renderer.update_vertex_buffer(second, 0, &[1])?;
+
+        // command buffers check ids when they are submitted
+        let mut commands = renderer.command_buffer();
+        commands.unload_vertex_buffer(first);
+        assert!(renderer.submit(commands).is_err());
+
+        // owned resources are unloaded once queued commands are submitted
+        let owned = renderer.load_index_buffer(&[0; 4]);
+        let owned = renderer.owned(owned);
+        let report = renderer.leak_report();
+        assert_eq!(report.count(ResourceKind::VertexBuffer), 1);
+        assert_eq!(report.count(ResourceKind::IndexBuffer), 1);
+
+        drop(owned);
+        renderer.unload_vertex_buffer(second)?;
+        assert_eq!(renderer.leak_report().resources.len(), 1);
+        renderer.submit_queued()?;
+        assert!(renderer.leak_report().is_empty());
+
+        // a render target's attachments can only be unloaded along with it
+        let target = renderer.load_render_target(RenderTargetOptions::new(1, 1))?;
+        let color = target.color().unwrap();
+        let options = TextureOptions {
+            width: 1,
+            height: 1,
+            ..Default::default()
+        };
+        assert!(renderer.unload_texture(color).is_err());
+        assert!(renderer
+            .reload_texture(color, Texture::from_raw_config(&[0; 4], options)?)
+            .is_err());
+
+        renderer.unload_render_target(target)?;
+        assert!(renderer.leak_report().is_empty());
+        assert!(renderer.unload_texture(color).is_err());
+
+        Ok(())
+    }
+}
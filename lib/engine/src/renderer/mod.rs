@@ -1,7 +1,29 @@
 pub mod backend;
 pub mod gl;
 pub mod glsl;
+pub mod recording;
 pub mod shader;
+pub mod software;
 
+mod buffer;
+mod capabilities;
+mod command;
 mod frontend;
+mod handle;
+mod queue;
+mod readback;
+mod stats;
+mod timer;
+mod uniform;
+mod vertex;
+pub use buffer::*;
+pub use capabilities::*;
+pub use command::*;
 pub use frontend::*;
+pub use handle::*;
+pub use queue::*;
+pub use readback::*;
+pub use stats::*;
+pub use timer::*;
+pub use uniform::*;
+pub use vertex::*;
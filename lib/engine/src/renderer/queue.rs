@@ -0,0 +1,189 @@
+use anyhow::Result;
+use nalgebra_glm as glm;
+use std::{borrow::Cow, cmp::Ordering};
+
+use super::{
+    DrawingMode, IndexBufferId, IndexType, PipelineId, Renderer, TextureId, UniformValue,
+    VertexBufferId,
+};
+
+/// The draw call made for a draw item
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCall {
+    Arrays {
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+    },
+    Indexed {
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+    },
+}
+
+/// A draw call waiting in a draw queue, along with everything bound to the
+/// pipeline before it is made.  Uniform names are usually string literals and
+/// vertex arrays are borrowed from whatever is being drawn, so that queueing
+/// an item does not need to copy them.
+#[derive(Debug, Clone)]
+pub struct DrawItem<'a> {
+    /// Items in lower passes are drawn first, regardless of anything else
+    pub pass: u8,
+
+    pub pipeline: PipelineId,
+
+    /// Whether the item is blended with whatever is behind it, translucent
+    /// items are drawn after opaque items in the same pass
+    pub translucent: bool,
+
+    /// Distance from the camera, opaque items are drawn front to back and
+    /// translucent items back to front
+    pub depth: f32,
+
+    pub matrices: Vec<(Cow<'static, str>, glm::Mat4)>,
+    pub textures: Vec<(Cow<'static, str>, TextureId)>,
+    pub uniforms: Vec<(Cow<'static, str>, UniformValue)>,
+
+    pub vertex_buffers: &'a [VertexBufferId],
+    pub vertex_offsets: &'a [usize],
+    pub vertex_strides: &'a [i32],
+
+    pub call: DrawCall,
+}
+
+impl<'a> DrawItem<'a> {
+    /// An opaque item in pass 0 that does not bind anything
+    pub fn new(pipeline: PipelineId, call: DrawCall) -> Self {
+        Self {
+            pass: 0,
+            pipeline,
+            translucent: false,
+            depth: 0.0,
+            matrices: vec![],
+            textures: vec![],
+            uniforms: vec![],
+            vertex_buffers: &[],
+            vertex_offsets: &[],
+            vertex_strides: &[],
+            call,
+        }
+    }
+
+    /// The order items are drawn in.  Opaque items are grouped by pipeline
+    /// and then by texture set so that as few binds as possible are made,
+    /// translucent items have to be drawn in depth order for blending to be
+    /// correct, so are only grouped if they are at the same depth.
+    fn draw_order(&self, other: &Self) -> Ordering {
+        // negative depths are behind the camera, clamping them means the
+        // bit pattern of the depth increases with the depth
+        let depth = self.depth.max(0.0).to_bits();
+        let other_depth = other.depth.max(0.0).to_bits();
+
+        let state = || {
+            self.pipeline.cmp(&other.pipeline).then_with(|| {
+                let textures = self.textures.iter().map(|(_, texture)| texture);
+                let other_textures = other.textures.iter().map(|(_, texture)| texture);
+                textures.cmp(other_textures)
+            })
+        };
+
+        self.pass
+            .cmp(&other.pass)
+            .then(self.translucent.cmp(&other.translucent))
+            .then_with(|| {
+                if self.translucent {
+                    other_depth.cmp(&depth).then_with(state)
+                } else {
+                    state().then(depth.cmp(&other_depth))
+                }
+            })
+    }
+}
+
+/// Collects draw items so that they can be sorted before they are drawn
+#[derive(Debug, Default)]
+pub struct DrawQueue<'a> {
+    items: Vec<DrawItem<'a>>,
+}
+
+impl<'a> DrawQueue<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, item: DrawItem<'a>) {
+        self.items.push(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Sort the queued items and draw them, leaving the queue empty.  Items
+    /// using the same pipeline one after another share a single bind, and
+    /// textures already bound by the previous item are not bound again.
+    pub fn submit(&mut self, renderer: &mut Renderer) -> Result<()> {
+        // stable, so items that compare equal are drawn in the order queued
+        self.items.sort_by(DrawItem::draw_order);
+
+        let mut items = self.items.drain(..).peekable();
+        while let Some(first) = items.next() {
+            let mut pipeline = renderer.bind_pipeline(first.pipeline)?;
+            let mut bound_textures: Vec<(Cow<'static, str>, TextureId)> = vec![];
+
+            let mut item = Some(first);
+            while let Some(current) = item {
+                for (name, matrix) in &current.matrices {
+                    pipeline.bind_matrix(name, *matrix)?;
+                }
+
+                for (name, texture) in &current.textures {
+                    if !bound_textures
+                        .iter()
+                        .any(|bound| bound.0 == *name && bound.1 == *texture)
+                    {
+                        pipeline.bind_texture(name, *texture)?;
+                        bound_textures.retain(|(bound, _)| bound != name);
+                        bound_textures.push((name.clone(), *texture));
+                    }
+                }
+
+                for (name, value) in &current.uniforms {
+                    pipeline.bind_uniform(name, value.clone())?;
+                }
+
+                pipeline.bind_vertex_arrays(
+                    current.vertex_buffers,
+                    current.vertex_offsets,
+                    current.vertex_strides,
+                )?;
+
+                match current.call {
+                    DrawCall::Arrays { mode, start, count } => pipeline.draw(mode, start, count),
+                    DrawCall::Indexed {
+                        mode,
+                        indices,
+                        index_type,
+                        index_offset,
+                        count,
+                    } => pipeline.draw_indicies(mode, indices, index_type, index_offset, count)?,
+                }
+
+                item = items.next_if(|next| next.pipeline == current.pipeline);
+            }
+        }
+
+        Ok(())
+    }
+}
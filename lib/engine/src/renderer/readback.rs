@@ -0,0 +1,156 @@
+use std::path::Path;
+
+use anyhow::Result;
+use image::{ImageBuffer, ImageFormat, Rgba, RgbaImage};
+use thiserror::Error;
+
+/// Errors from reading pixels back from a framebuffer
+#[derive(Debug, Error)]
+pub enum ReadPixelsError {
+    #[error("Cannot read {region:?} from a framebuffer of {width}x{height} pixels")]
+    OutOfBounds {
+        region: PixelRegion,
+        width: u32,
+        height: u32,
+    },
+
+    #[error("The current render target has no color attachment to read from")]
+    NoColorAttachment,
+}
+
+/// A rectangle of pixels in a framebuffer, (0, 0) is the bottom left pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PixelRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRegion {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// The format that pixels are converted to when they are read back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// 8 bits per component, clamped to [0, 1]
+    Rgba8,
+
+    /// 32 bit float per component, not clamped
+    Rgba32F,
+}
+
+impl PixelFormat {
+    /// The size of a single pixel in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Rgba32F => 16,
+        }
+    }
+}
+
+/// Pixels read back from a framebuffer, stored with the top row first like
+/// any other image
+#[derive(Debug, Clone)]
+pub enum Pixels {
+    Rgba8(RgbaImage),
+    Rgba32F(ImageBuffer<Rgba<f32>, Vec<f32>>),
+}
+
+impl Pixels {
+    /// Create an image from tightly packed rows of pixels, with the bottom
+    /// row first, as they are given by the renderer backends
+    pub(crate) fn from_rows(region: PixelRegion, format: PixelFormat, data: &[u8]) -> Self {
+        debug_assert_eq!(
+            data.len(),
+            region.width as usize * region.height as usize * format.size()
+        );
+
+        let row_size = region.width as usize * format.size();
+        let rows = data.chunks_exact(row_size.max(1)).rev();
+
+        match format {
+            PixelFormat::Rgba8 => {
+                let data = rows.flatten().copied().collect();
+                Pixels::Rgba8(RgbaImage::from_raw(region.width, region.height, data).unwrap())
+            }
+            PixelFormat::Rgba32F => {
+                let data = rows
+                    .flat_map(|row| row.chunks_exact(4))
+                    .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                Pixels::Rgba32F(ImageBuffer::from_raw(region.width, region.height, data).unwrap())
+            }
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            Pixels::Rgba8(image) => image.width(),
+            Pixels::Rgba32F(image) => image.width(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Pixels::Rgba8(image) => image.height(),
+            Pixels::Rgba32F(image) => image.height(),
+        }
+    }
+
+    /// Convert the pixels to 8 bits per component, float pixels are clamped
+    /// to [0, 1]
+    pub fn to_rgba8(&self) -> RgbaImage {
+        match self {
+            Pixels::Rgba8(image) => image.clone(),
+            Pixels::Rgba32F(image) => RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                let Rgba(color) = *image.get_pixel(x, y);
+                Rgba([
+                    to_unorm8(color[0]),
+                    to_unorm8(color[1]),
+                    to_unorm8(color[2]),
+                    to_unorm8(color[3]),
+                ])
+            }),
+        }
+    }
+
+    /// Write the pixels to a png file
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.to_rgba8().save_with_format(path, ImageFormat::Png)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Check that a region is inside a framebuffer
+pub(crate) fn check_read_region(
+    region: PixelRegion,
+    width: u32,
+    height: u32,
+) -> Result<(), ReadPixelsError> {
+    let inside = |start: u32, size: u32, max: u32| matches!(start.checked_add(size), Some(end) if end <= max);
+
+    if inside(region.x, region.width, width) && inside(region.y, region.height, height) {
+        Ok(())
+    } else {
+        Err(ReadPixelsError::OutOfBounds {
+            region,
+            width,
+            height,
+        })
+    }
+}
@@ -0,0 +1,1810 @@
+use anyhow::Result;
+use nalgebra_glm as glm;
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+use thiserror::Error;
+
+use crate::texture::{Texture, TextureOptions, TextureRegion, TextureStorageType};
+
+use super::{
+    backend::RendererBackend,
+    buffer::{check_buffer_write, check_indirect_read, RingBuffer},
+    shader::Program,
+    uniform::{check_sampler, check_storage_buffer, check_uniform, check_uniform_block},
+    vertex::vertex_formats,
+    BlendMode, BufferError, CullingMode, DepthTesting, DrawArraysIndirectCommand,
+    DrawElementsIndirectCommand, DrawingMode, Handle, IndexBufferId, IndexType, IndirectBufferId,
+    PipelineDescriptor, PipelineId, PipelineState, PixelFormat, PixelRegion, RenderTargetId,
+    RenderTargetOptions, RendererCapabilities, StencilTesting, StorageBufferId, TextureId,
+    UniformBufferId, UniformValue, VertexBufferId,
+};
+
+/// Errors returned by the recording backend, these mirror the errors that the
+/// OpenGl backend would return for the same calls
+#[derive(Debug, Error)]
+enum RecordingError {
+    #[error("Cannot bind to unbound pipeline")]
+    PipelineNotBound,
+
+    #[error("Texture is not currently loaded, cannot bind it to a pipeline")]
+    TextureUnloaded,
+
+    #[error("Uniform buffer is not currently loaded, cannot bind it to a pipeline")]
+    UniformBufferUnloaded,
+
+    #[error("Storage buffer is not currently loaded, cannot bind it to a pipeline")]
+    StorageBufferUnloaded,
+
+    #[error("Render target must have at least one attachment")]
+    NoAttachments,
+
+    #[error("Render target stencil buffer requires a depth attachment")]
+    StencilWithoutDepth,
+
+    #[error("Render target color attachment cannot use compressed storage {storage:?}")]
+    CompressedAttachment { storage: TextureStorageType },
+}
+
+/// A single call made to the recording backend
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Clear {
+        r: f32,
+        g: f32,
+        b: f32,
+    },
+    ClearStencil(u8),
+    Viewport {
+        width: u32,
+        height: u32,
+    },
+    BackfaceCulling(CullingMode),
+    DepthTesting(DepthTesting),
+    StencilTesting(StencilTesting),
+    Blending(BlendMode),
+    InvalidateStateCache,
+    Timestamp {
+        query: u32,
+    },
+    LoadTexture {
+        texture: TextureId,
+        options: TextureOptions,
+    },
+    UnloadTexture {
+        texture: TextureId,
+    },
+    UpdateTexture {
+        texture: TextureId,
+        region: TextureRegion,
+        len: usize,
+        stream: bool,
+    },
+    LoadVertexBuffer {
+        buffer: VertexBufferId,
+        len: usize,
+        stream: bool,
+    },
+    LoadVertexRingBuffer {
+        buffer: VertexBufferId,
+        size: usize,
+    },
+    UnloadVertexBuffer {
+        buffer: VertexBufferId,
+    },
+    UpdateVertexBuffer {
+        buffer: VertexBufferId,
+        offset: usize,
+        len: usize,
+    },
+    OrphanVertexBuffer {
+        buffer: VertexBufferId,
+        len: usize,
+    },
+    WriteVertexRingBuffer {
+        buffer: VertexBufferId,
+        offset: usize,
+        len: usize,
+    },
+    LoadIndexBuffer {
+        buffer: IndexBufferId,
+        len: usize,
+        stream: bool,
+    },
+    LoadIndexRingBuffer {
+        buffer: IndexBufferId,
+        size: usize,
+    },
+    UnloadIndexBuffer {
+        buffer: IndexBufferId,
+    },
+    UpdateIndexBuffer {
+        buffer: IndexBufferId,
+        offset: usize,
+        len: usize,
+    },
+    OrphanIndexBuffer {
+        buffer: IndexBufferId,
+        len: usize,
+    },
+    WriteIndexRingBuffer {
+        buffer: IndexBufferId,
+        offset: usize,
+        len: usize,
+    },
+    LoadUniformBuffer {
+        buffer: UniformBufferId,
+        len: usize,
+    },
+    UnloadUniformBuffer {
+        buffer: UniformBufferId,
+    },
+    LoadStorageBuffer {
+        buffer: StorageBufferId,
+        len: usize,
+    },
+    UnloadStorageBuffer {
+        buffer: StorageBufferId,
+    },
+    UpdateStorageBuffer {
+        buffer: StorageBufferId,
+        offset: usize,
+        len: usize,
+    },
+    ReadStorageBuffer {
+        buffer: StorageBufferId,
+    },
+
+    /// The commands are stored so that they can be checked, as they are not
+    /// passed to the draw calls reading them
+    LoadIndirectBuffer {
+        buffer: IndirectBufferId,
+        data: Vec<u8>,
+    },
+    UnloadIndirectBuffer {
+        buffer: IndirectBufferId,
+    },
+
+    /// The generated glsl is stored so that shader generation can be checked
+    /// without needing a driver to compile it
+    LoadPipeline {
+        pipeline: PipelineId,
+        vert: Option<String>,
+        frag: Option<String>,
+        compute: Option<String>,
+        state: Option<PipelineState>,
+    },
+    UnloadPipeline {
+        pipeline: PipelineId,
+    },
+    LoadRenderTarget {
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    },
+    UnloadRenderTarget {
+        target: RenderTargetId,
+    },
+    BindRenderTarget {
+        target: Option<RenderTargetId>,
+    },
+    ReadPixels {
+        region: PixelRegion,
+        format: PixelFormat,
+    },
+    BindPipeline {
+        pipeline: PipelineId,
+    },
+    UnbindPipeline {
+        pipeline: PipelineId,
+    },
+    BindMatrix {
+        pipeline: PipelineId,
+        name: String,
+        matrix: glm::Mat4,
+    },
+    BindTexture {
+        pipeline: PipelineId,
+        name: String,
+        texture: TextureId,
+    },
+    BindUniform {
+        pipeline: PipelineId,
+        name: String,
+        value: UniformValue,
+    },
+    BindUniformBuffer {
+        pipeline: PipelineId,
+        name: String,
+        buffer: UniformBufferId,
+    },
+    BindStorageBuffer {
+        pipeline: PipelineId,
+        name: String,
+        buffer: StorageBufferId,
+    },
+    BindVertexArrays {
+        pipeline: PipelineId,
+        buffers: Vec<VertexBufferId>,
+        offsets: Vec<usize>,
+        strides: Vec<i32>,
+        divisors: Vec<u32>,
+    },
+    Draw {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    },
+    DrawIndicies {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    },
+    DrawIndirect {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: Vec<DrawArraysIndirectCommand>,
+    },
+    MultiDrawElementsIndirect {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: Vec<DrawElementsIndirectCommand>,
+    },
+    DrawIndirectBuffer {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    },
+    MultiDrawElementsIndirectBuffer {
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    },
+    Dispatch {
+        pipeline: PipelineId,
+        x: u32,
+        y: u32,
+        z: u32,
+    },
+}
+
+impl Call {
+    /// Is this call one that draws verticies
+    pub fn is_draw(&self) -> bool {
+        matches!(
+            self,
+            Call::Draw { .. }
+                | Call::DrawIndicies { .. }
+                | Call::DrawIndirect { .. }
+                | Call::MultiDrawElementsIndirect { .. }
+                | Call::DrawIndirectBuffer { .. }
+                | Call::MultiDrawElementsIndirectBuffer { .. }
+        )
+    }
+}
+
+/// Shared handle to the calls made to a recording backend.  The backend is
+/// moved into the renderer, so this is how the calls are read back afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct CallLog {
+    calls: Rc<RefCell<Vec<Call>>>,
+}
+
+impl CallLog {
+    /// Get all the calls made since the log was last cleared
+    pub fn calls(&self) -> Ref<'_, [Call]> {
+        Ref::map(self.calls.borrow(), |calls| &calls[..])
+    }
+
+    /// Remove all calls from the log, returning them
+    pub fn take(&self) -> Vec<Call> {
+        std::mem::take(&mut *self.calls.borrow_mut())
+    }
+
+    /// Remove all calls from the log
+    pub fn clear(&self) {
+        self.calls.borrow_mut().clear();
+    }
+
+    /// The number of draw calls made
+    pub fn draw_count(&self) -> usize {
+        self.calls
+            .borrow()
+            .iter()
+            .filter(|call| call.is_draw())
+            .count()
+    }
+
+    fn push(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+/// Renderer backend that makes no graphics api calls, instead it records every
+/// call made to it so they can be inspected, e.g. in tests on machines without
+/// a GPU.
+#[derive(Debug, Default)]
+pub struct RecordingRenderer {
+    /// Where all the calls are stored
+    log: CallLog,
+
+    /// The settings and number of mipmap levels of all the currently loaded
+    /// textures
+    textures: HashMap<Handle, (TextureOptions, u32)>,
+
+    /// The size in bytes of all the currently loaded vertex, index and storage
+    /// buffers
+    buffers: HashMap<Handle, usize>,
+
+    /// The render target currently being drawn to, None for the window
+    render_target: Option<RenderTargetId>,
+
+    /// The last viewport set while drawing to the window
+    screen_viewport: (u32, u32),
+
+    /// Allocation state of the buffers loaded as ring buffers
+    rings: HashMap<Handle, RingBuffer>,
+
+    /// The size in bytes of all the currently loaded uniform buffers
+    uniform_buffers: HashMap<Handle, usize>,
+
+    /// All the currently loaded pipelines
+    pipelines: HashMap<Handle, RecordingPipeline>,
+
+    /// The values written to timestamp queries, the number of draw calls made
+    /// before the timestamp so scope durations count the draws inside them
+    timestamps: HashMap<u32, u64>,
+}
+
+/// A loaded pipeline, the program is kept so that uniforms can be type checked
+#[derive(Debug)]
+struct RecordingPipeline {
+    program: Program,
+    is_bound: bool,
+}
+
+impl RecordingRenderer {
+    /// Create a new backend with an empty call log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a handle to the calls made to this backend
+    pub fn log(&self) -> CallLog {
+        self.log.clone()
+    }
+
+    fn is_bound(&self, pipeline: PipelineId) -> bool {
+        self.pipelines
+            .get(&pipeline.0)
+            .map(|pipeline| pipeline.is_bound)
+            .unwrap_or(false)
+    }
+
+    /// Get the program of a pipeline, if it is bound
+    fn bound_program(&self, pipeline: PipelineId) -> Result<&Program> {
+        match self.pipelines.get(&pipeline.0) {
+            Some(pipeline) if pipeline.is_bound => Ok(&pipeline.program),
+            _ => Err(RecordingError::PipelineNotBound.into()),
+        }
+    }
+
+    /// Check a write to part of a vertex, index or storage buffer
+    fn check_buffer(&self, buffer: Handle, offset: usize, len: usize) -> Result<()> {
+        if self.rings.contains_key(&buffer) {
+            return Err(BufferError::UpdateRingBuffer.into());
+        }
+
+        let size = self.buffers.get(&buffer).ok_or(BufferError::Unloaded)?;
+        check_buffer_write(offset, len, *size)?;
+
+        Ok(())
+    }
+
+    /// Check that a buffer can be orphaned and record its new size
+    fn orphan(&mut self, buffer: Handle, len: usize) -> Result<()> {
+        if self.rings.contains_key(&buffer) {
+            return Err(BufferError::OrphanRingBuffer.into());
+        }
+
+        let size = self.buffers.get_mut(&buffer).ok_or(BufferError::Unloaded)?;
+        *size = len;
+
+        Ok(())
+    }
+
+    /// Allocate space in a ring buffer, the same as the other backends would
+    fn allocate(&mut self, buffer: Handle, len: usize) -> Result<usize> {
+        let ring = self
+            .rings
+            .get_mut(&buffer)
+            .ok_or(BufferError::NotRingBuffer)?;
+
+        Ok(ring.allocate(len)?)
+    }
+
+    /// Check and record a texture update
+    fn update(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+        stream: bool,
+    ) -> Result<()> {
+        let (options, levels) = self
+            .textures
+            .get(&texture.0)
+            .ok_or(RecordingError::TextureUnloaded)?;
+        region.check(options, *levels, data)?;
+
+        self.log.push(Call::UpdateTexture {
+            texture,
+            region,
+            len: data.len(),
+            stream,
+        });
+
+        Ok(())
+    }
+}
+
+impl RendererBackend for RecordingRenderer {
+    fn clear(&mut self, r: f32, g: f32, b: f32) {
+        self.log.push(Call::Clear { r, g, b });
+    }
+
+    fn clear_stencil(&mut self, value: u8) {
+        self.log.push(Call::ClearStencil(value));
+    }
+
+    fn viewport(&mut self, width: u32, height: u32) {
+        if self.render_target.is_none() {
+            self.screen_viewport = (width, height);
+        }
+
+        self.log.push(Call::Viewport { width, height });
+    }
+
+    fn screen_viewport(&self) -> (u32, u32) {
+        self.screen_viewport
+    }
+
+    fn backface_culling(&mut self, enable: CullingMode) {
+        self.log.push(Call::BackfaceCulling(enable));
+    }
+
+    fn depth_testing(&mut self, mode: DepthTesting) {
+        self.log.push(Call::DepthTesting(mode));
+    }
+
+    fn stencil_testing(&mut self, mode: StencilTesting) {
+        self.log.push(Call::StencilTesting(mode));
+    }
+
+    fn blending(&mut self, mode: BlendMode) {
+        self.log.push(Call::Blending(mode));
+    }
+
+    /// Typical values for a desktop gpu, with the extensions the gl bindings
+    /// are generated with
+    fn capabilities(&self) -> RendererCapabilities {
+        use TextureStorageType::*;
+
+        RendererCapabilities {
+            version: (4, 5),
+            vendor: "engine".to_string(),
+            renderer: "Recording renderer".to_string(),
+            max_texture_size: 16384,
+            max_anisotropy: 16,
+            texture_units: 32,
+            extensions: vec![
+                "GL_NV_command_list".to_string(),
+                "GL_ARB_shader_draw_parameters".to_string(),
+                "GL_EXT_texture_filter_anisotropic".to_string(),
+                "GL_EXT_texture_compression_s3tc".to_string(),
+                "GL_EXT_texture_sRGB".to_string(),
+            ],
+            compressed_formats: vec![
+                BC1RGB,
+                BC1SRGB,
+                BC1RGBA,
+                BC1SRGBA,
+                BC2RGBA,
+                BC2SRGBA,
+                BC3RGBA,
+                BC3SRGBA,
+                BC4R,
+                BC4SignedR,
+                BC5RG,
+                BC5SignedRG,
+                BC6HUnsignedFloat,
+                BC6HSignedFloat,
+                BC7RGBA,
+                BC7SRGBA,
+            ],
+        }
+    }
+
+    fn invalidate_state_cache(&mut self) {
+        self.log.push(Call::InvalidateStateCache);
+    }
+
+    fn skipped_state_changes(&self) -> u64 {
+        // every call is recorded, none are skipped
+        0
+    }
+
+    fn timestamp(&mut self, query: u32) {
+        self.timestamps.insert(query, self.log.draw_count() as u64);
+        self.log.push(Call::Timestamp { query });
+    }
+
+    fn timestamp_result(&mut self, query: u32) -> Option<u64> {
+        self.timestamps.get(&query).copied()
+    }
+
+    fn load_texture(&mut self, id: TextureId, texture: Texture) {
+        let levels = if texture.generates_mipmaps() {
+            texture.config().full_mip_levels()
+        } else {
+            texture.mip_levels()
+        };
+        self.textures.insert(id.0, (texture.config(), levels));
+
+        self.log.push(Call::LoadTexture {
+            texture: id,
+            options: texture.config(),
+        });
+    }
+
+    fn unload_texture(&mut self, texture: TextureId) {
+        let removed = self.textures.remove(&texture.0);
+        debug_assert!(removed.is_some());
+
+        self.log.push(Call::UnloadTexture { texture });
+    }
+
+    fn update_texture(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        self.update(texture, region, data, false)
+    }
+
+    fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        self.update(texture, region, data, true)
+    }
+
+    fn load_vertex_buffer(&mut self, id: VertexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadVertexBuffer {
+            buffer: id,
+            len: data.len(),
+            stream: false,
+        });
+    }
+
+    fn load_vertex_buffer_stream(&mut self, id: VertexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadVertexBuffer {
+            buffer: id,
+            len: data.len(),
+            stream: true,
+        });
+    }
+
+    fn load_vertex_ring_buffer(&mut self, id: VertexBufferId, size: usize) {
+        self.buffers.insert(id.0, size);
+        self.rings.insert(id.0, RingBuffer::new(size));
+
+        self.log
+            .push(Call::LoadVertexRingBuffer { buffer: id, size });
+    }
+
+    fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+        self.rings.remove(&buffer.0);
+
+        self.log.push(Call::UnloadVertexBuffer { buffer });
+    }
+
+    fn update_vertex_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.check_buffer(buffer.0, offset, data.len())?;
+
+        self.log.push(Call::UpdateVertexBuffer {
+            buffer,
+            offset,
+            len: data.len(),
+        });
+
+        Ok(())
+    }
+
+    fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<()> {
+        self.orphan(buffer.0, data.len())?;
+
+        self.log.push(Call::OrphanVertexBuffer {
+            buffer,
+            len: data.len(),
+        });
+
+        Ok(())
+    }
+
+    fn write_vertex_ring_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<usize> {
+        let offset = self.allocate(buffer.0, data.len())?;
+
+        self.log.push(Call::WriteVertexRingBuffer {
+            buffer,
+            offset,
+            len: data.len(),
+        });
+
+        Ok(offset)
+    }
+
+    fn load_index_buffer(&mut self, id: IndexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadIndexBuffer {
+            buffer: id,
+            len: data.len(),
+            stream: false,
+        });
+    }
+
+    fn load_index_buffer_stream(&mut self, id: IndexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadIndexBuffer {
+            buffer: id,
+            len: data.len(),
+            stream: true,
+        });
+    }
+
+    fn load_index_ring_buffer(&mut self, id: IndexBufferId, size: usize) {
+        self.buffers.insert(id.0, size);
+        self.rings.insert(id.0, RingBuffer::new(size));
+
+        self.log
+            .push(Call::LoadIndexRingBuffer { buffer: id, size });
+    }
+
+    fn unload_index_buffer(&mut self, buffer: IndexBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+        self.rings.remove(&buffer.0);
+
+        self.log.push(Call::UnloadIndexBuffer { buffer });
+    }
+
+    fn update_index_buffer(
+        &mut self,
+        buffer: IndexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.check_buffer(buffer.0, offset, data.len())?;
+
+        self.log.push(Call::UpdateIndexBuffer {
+            buffer,
+            offset,
+            len: data.len(),
+        });
+
+        Ok(())
+    }
+
+    fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<()> {
+        self.orphan(buffer.0, data.len())?;
+
+        self.log.push(Call::OrphanIndexBuffer {
+            buffer,
+            len: data.len(),
+        });
+
+        Ok(())
+    }
+
+    fn write_index_ring_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<usize> {
+        let offset = self.allocate(buffer.0, data.len())?;
+
+        self.log.push(Call::WriteIndexRingBuffer {
+            buffer,
+            offset,
+            len: data.len(),
+        });
+
+        Ok(offset)
+    }
+
+    fn load_uniform_buffer(&mut self, id: UniformBufferId, data: &[u8]) {
+        self.uniform_buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadUniformBuffer {
+            buffer: id,
+            len: data.len(),
+        });
+    }
+
+    fn unload_uniform_buffer(&mut self, buffer: UniformBufferId) {
+        let removed = self.uniform_buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+
+        self.log.push(Call::UnloadUniformBuffer { buffer });
+    }
+
+    fn load_storage_buffer(&mut self, id: StorageBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadStorageBuffer {
+            buffer: id,
+            len: data.len(),
+        });
+    }
+
+    fn unload_storage_buffer(&mut self, buffer: StorageBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+
+        self.log.push(Call::UnloadStorageBuffer { buffer });
+    }
+
+    fn update_storage_buffer(
+        &mut self,
+        buffer: StorageBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.check_buffer(buffer.0, offset, data.len())?;
+
+        self.log.push(Call::UpdateStorageBuffer {
+            buffer,
+            offset,
+            len: data.len(),
+        });
+
+        Ok(())
+    }
+
+    /// Buffer contents are not recorded, so this always reads zeros
+    fn read_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<Vec<u8>> {
+        let size = *self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+
+        self.log.push(Call::ReadStorageBuffer { buffer });
+
+        Ok(vec![0; size])
+    }
+
+    fn load_indirect_buffer(&mut self, id: IndirectBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.len());
+
+        self.log.push(Call::LoadIndirectBuffer {
+            buffer: id,
+            data: data.to_vec(),
+        });
+    }
+
+    fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+
+        self.log.push(Call::UnloadIndirectBuffer { buffer });
+    }
+
+    fn load_pipeline(&mut self, id: PipelineId, pipeline: PipelineDescriptor) -> Result<()> {
+        let mut program = pipeline.program;
+
+        // generate the glsl so that the same errors are reported as would be
+        // when using the OpenGl backend
+        let glsl = program.to_glsl()?;
+        vertex_formats(&program, &pipeline.vertex_formats)?;
+
+        self.log.push(Call::LoadPipeline {
+            pipeline: id,
+            vert: glsl.vert,
+            frag: glsl.frag,
+            compute: glsl.compute,
+            state: pipeline.state,
+        });
+
+        self.pipelines.insert(
+            id.0,
+            RecordingPipeline {
+                program,
+                is_bound: false,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn unload_pipeline(&mut self, pipeline: PipelineId) {
+        let removed = self.pipelines.remove(&pipeline.0);
+        debug_assert!(removed.is_some());
+
+        self.log.push(Call::UnloadPipeline { pipeline });
+    }
+
+    fn load_render_target(
+        &mut self,
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    ) -> Result<()> {
+        if options.color.is_none() && !options.depth {
+            return Err(RecordingError::NoAttachments.into());
+        }
+        if options.stencil && !options.depth {
+            return Err(RecordingError::StencilWithoutDepth.into());
+        }
+        if let Some(storage) = options.color.filter(TextureStorageType::is_compressed) {
+            return Err(RecordingError::CompressedAttachment { storage }.into());
+        }
+
+        // the attachments can be bound to pipelines the same as any other texture
+        if let Some(texture) = target.color() {
+            self.textures
+                .insert(texture.0, (options.color_options(), 1));
+        }
+        if let Some(texture) = target.depth() {
+            self.textures
+                .insert(texture.0, (options.depth_options(), 1));
+        }
+
+        self.log.push(Call::LoadRenderTarget { target, options });
+
+        Ok(())
+    }
+
+    fn unload_render_target(&mut self, target: RenderTargetId) {
+        for texture in target.color().iter().chain(&target.depth()) {
+            let removed = self.textures.remove(&texture.0);
+            debug_assert!(removed.is_some());
+        }
+
+        self.log.push(Call::UnloadRenderTarget { target });
+    }
+
+    fn bind_render_target(&mut self, target: Option<RenderTargetId>) {
+        self.render_target = target;
+        self.log.push(Call::BindRenderTarget { target });
+    }
+
+    /// The framebuffer sizes are not tracked, so any region can be read, the
+    /// pixels are always zero
+    fn read_pixels(&mut self, region: PixelRegion, format: PixelFormat) -> Result<Vec<u8>> {
+        self.log.push(Call::ReadPixels { region, format });
+
+        Ok(vec![
+            0;
+            region.width as usize
+                * region.height as usize
+                * format.size()
+        ])
+    }
+
+    fn bind_pipeline(&mut self, pipeline: PipelineId) {
+        if let Some(recorded) = self.pipelines.get_mut(&pipeline.0) {
+            debug_assert!(!recorded.is_bound);
+            recorded.is_bound = true;
+        } else {
+            debug_assert!(false, "Cannot bind non-existant pipeline");
+        }
+
+        self.log.push(Call::BindPipeline { pipeline });
+    }
+
+    fn unbind_pipeline(&mut self, pipeline: PipelineId) {
+        if let Some(recorded) = self.pipelines.get_mut(&pipeline.0) {
+            debug_assert!(recorded.is_bound);
+            recorded.is_bound = false;
+        } else {
+            debug_assert!(false, "Cannot unbind non-existant pipeline");
+        }
+
+        self.log.push(Call::UnbindPipeline { pipeline });
+    }
+
+    fn pipeline_bind_matrix(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        matrix: glm::Mat4,
+    ) -> Result<()> {
+        check_uniform(
+            self.bound_program(pipeline)?,
+            name,
+            &UniformValue::Mat4(matrix),
+        )?;
+
+        self.log.push(Call::BindMatrix {
+            pipeline,
+            name: name.to_string(),
+            matrix,
+        });
+
+        Ok(())
+    }
+
+    fn pipeline_bind_texture(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        texture: TextureId,
+    ) -> Result<()> {
+        let kind = self
+            .textures
+            .get(&texture.0)
+            .ok_or(RecordingError::TextureUnloaded)?
+            .0
+            .kind;
+
+        check_sampler(self.bound_program(pipeline)?, name, kind)?;
+
+        self.log.push(Call::BindTexture {
+            pipeline,
+            name: name.to_string(),
+            texture,
+        });
+
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        value: &UniformValue,
+    ) -> Result<()> {
+        check_uniform(self.bound_program(pipeline)?, name, value)?;
+
+        self.log.push(Call::BindUniform {
+            pipeline,
+            name: name.to_string(),
+            value: value.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: UniformBufferId,
+    ) -> Result<()> {
+        let size = *self
+            .uniform_buffers
+            .get(&buffer.0)
+            .ok_or(RecordingError::UniformBufferUnloaded)?;
+
+        check_uniform_block(self.bound_program(pipeline)?, name, size)?;
+
+        self.log.push(Call::BindUniformBuffer {
+            pipeline,
+            name: name.to_string(),
+            buffer,
+        });
+
+        Ok(())
+    }
+
+    fn pipeline_bind_storage_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: StorageBufferId,
+    ) -> Result<()> {
+        if !self.buffers.contains_key(&buffer.0) {
+            return Err(RecordingError::StorageBufferUnloaded.into());
+        }
+
+        check_storage_buffer(self.bound_program(pipeline)?, name)?;
+
+        self.log.push(Call::BindStorageBuffer {
+            pipeline,
+            name: name.to_string(),
+            buffer,
+        });
+
+        Ok(())
+    }
+
+    fn pipeline_bind_vertex_arrays(
+        &mut self,
+        pipeline: PipelineId,
+        buffers: &[VertexBufferId],
+        offsets: &[usize],
+        strides: &[i32],
+        divisors: &[u32],
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+        debug_assert!(buffers.iter().all(|b| self.buffers.contains_key(&b.0)));
+
+        // all slices must be the same length
+        debug_assert!(buffers.len() == offsets.len());
+        debug_assert!(buffers.len() == strides.len());
+        debug_assert!(divisors.is_empty() || buffers.len() == divisors.len());
+
+        // no divisors are recorded as all of them being 0
+        let divisors = if divisors.is_empty() {
+            vec![0; buffers.len()]
+        } else {
+            divisors.to_vec()
+        };
+
+        self.log.push(Call::BindVertexArrays {
+            pipeline,
+            buffers: buffers.to_vec(),
+            offsets: offsets.to_vec(),
+            strides: strides.to_vec(),
+            divisors,
+        });
+    }
+
+    fn draw(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+
+        self.log.push(Call::Draw {
+            pipeline,
+            mode,
+            start,
+            count,
+            instances,
+        });
+    }
+
+    fn draw_indicies(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+        debug_assert!(self.buffers.contains_key(&indices.0));
+
+        self.log.push(Call::DrawIndicies {
+            pipeline,
+            mode,
+            indices,
+            index_type,
+            index_offset,
+            count,
+            instances,
+        });
+    }
+
+    fn draw_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: &[DrawArraysIndirectCommand],
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+
+        self.log.push(Call::DrawIndirect {
+            pipeline,
+            mode,
+            draws: draws.to_vec(),
+        });
+    }
+
+    fn multi_draw_elements_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+        debug_assert!(self.buffers.contains_key(&indices.0));
+
+        self.log.push(Call::MultiDrawElementsIndirect {
+            pipeline,
+            mode,
+            indices,
+            index_type,
+            draws: draws.to_vec(),
+        });
+    }
+
+    fn draw_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        debug_assert!(self.is_bound(pipeline));
+
+        let size = *self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+        check_indirect_read(offset, count, DrawArraysIndirectCommand::SIZE, size)?;
+
+        self.log.push(Call::DrawIndirectBuffer {
+            pipeline,
+            mode,
+            buffer,
+            offset,
+            count,
+        });
+
+        Ok(())
+    }
+
+    fn multi_draw_elements_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        debug_assert!(self.is_bound(pipeline));
+        debug_assert!(self.buffers.contains_key(&indices.0));
+
+        let size = *self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+        check_indirect_read(offset, count, DrawElementsIndirectCommand::SIZE, size)?;
+
+        self.log.push(Call::MultiDrawElementsIndirectBuffer {
+            pipeline,
+            mode,
+            indices,
+            index_type,
+            buffer,
+            offset,
+            count,
+        });
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self, pipeline: PipelineId, x: u32, y: u32, z: u32) {
+        debug_assert!(self.is_bound(pipeline));
+
+        self.log.push(Call::Dispatch { pipeline, x, y, z });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nalgebra_glm as glm;
+
+    use super::{Call, RecordingRenderer};
+    use crate::{
+        gltf,
+        model::Model,
+        renderer::{
+            backend::RendererBackend,
+            shader::{BuiltinVariable, Expression, Program, Type},
+            DrawArraysIndirectCommand, DrawCall, DrawItem, DrawQueue, DrawingMode, IndexType,
+            PipelineId, Renderer, RendererStats, ResourceIds, ResourceKind,
+        },
+        resources::Resources,
+    };
+
+    #[test]
+    fn test_bound_pipeline() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let projection = ctx.uniform("projection", Type::Mat4);
+                let position = ctx.input("position", Type::Vec3);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    projection * Expression::vec(&[position, 1.0.into()]),
+                );
+            });
+        });
+        program.ok()?;
+
+        let pipeline = renderer.load_pipeline(program)?;
+        let buffer = renderer.load_vertex_buffer(&[0; 36]);
+        log.clear();
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_matrix("projection", glm::Mat4::identity())?;
+            bound.bind_vertex_arrays(&[buffer], &[0], &[0])?;
+            bound.draw(DrawingMode::Triangles, 0, 3);
+        }
+
+        assert_eq!(
+            &*log.calls(),
+            &[
+                Call::BindPipeline { pipeline },
+                Call::BindMatrix {
+                    pipeline,
+                    name: "projection".to_string(),
+                    matrix: glm::Mat4::identity(),
+                },
+                Call::BindVertexArrays {
+                    pipeline,
+                    buffers: vec![buffer],
+                    offsets: vec![0],
+                    strides: vec![0],
+                    divisors: vec![0],
+                },
+                Call::Draw {
+                    pipeline,
+                    mode: DrawingMode::Triangles,
+                    start: 0,
+                    count: 3,
+                    instances: 1,
+                },
+                Call::UnbindPipeline { pipeline },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uniform_errors() -> Result<()> {
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let scale = ctx.uniform("scale", Type::Floating);
+                ctx.uniform("unused", Type::Floating);
+                let position = ctx.input("position", Type::Vec3);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position * scale, 1.0.into()]),
+                );
+            });
+        });
+        program.ok()?;
+
+        let ids = ResourceIds::default();
+        let pipeline = PipelineId(ids.next(ResourceKind::Pipeline));
+        let unknown = PipelineId(ids.next(ResourceKind::Pipeline));
+
+        let mut backend = RecordingRenderer::new();
+        backend.load_pipeline(pipeline, program.into())?;
+
+        // pipelines must be loaded and bound
+        assert!(backend
+            .pipeline_bind_uniform(unknown, "scale", &1.0.into())
+            .is_err());
+        assert!(backend
+            .pipeline_bind_uniform(pipeline, "scale", &1.0.into())
+            .is_err());
+
+        // the same as OpenGl, uniforms that are never read are optimized out
+        backend.bind_pipeline(pipeline);
+        backend.pipeline_bind_uniform(pipeline, "scale", &1.0.into())?;
+        assert!(backend
+            .pipeline_bind_uniform(pipeline, "unused", &1.0.into())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut program = Program::new(|ctx| {
+            let values = ctx.storage_buffer("values", &Type::Vec4);
+
+            ctx.compute([64, 1, 1], |ctx| {
+                let id = ctx.get_builtin(BuiltinVariable::GlobalInvocationX);
+                let value = Expression::index(values.clone(), id.clone());
+                ctx.store(values, id, value * 2.0.into());
+            });
+        });
+        program.ok()?;
+
+        let pipeline = renderer.load_pipeline(program)?;
+        let glsl = match &log.calls()[0] {
+            Call::LoadPipeline { compute, .. } => compute.clone().unwrap(),
+            call => panic!("expected pipeline load, got {:?}", call),
+        };
+        assert!(glsl.starts_with("#version 430 core"));
+        assert!(glsl.contains("local_size_x = 64"));
+        assert!(glsl.contains("layout(std430, binding = 0) buffer values_buffer"));
+
+        let buffer = renderer.load_storage_buffer(&[0; 64]);
+        log.clear();
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_storage_buffer("values", buffer)?;
+            bound.dispatch(4, 1, 1);
+        }
+
+        assert_eq!(
+            &log.calls()[1..3],
+            &[
+                Call::BindStorageBuffer {
+                    pipeline,
+                    name: "values".to_string(),
+                    buffer,
+                },
+                Call::Dispatch {
+                    pipeline,
+                    x: 4,
+                    y: 1,
+                    z: 1,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpu_scopes() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec4);
+                ctx.set_builtin(BuiltinVariable::VertexPosition, position);
+            });
+        });
+        program.ok()?;
+
+        let pipeline = renderer.load_pipeline(program)?;
+        let buffer = renderer.load_vertex_buffer(&[0; 48]);
+
+        let frame = |renderer: &mut Renderer, index| -> Result<_> {
+            log.clear();
+            renderer.begin_scope("frame");
+            renderer.begin_scope("draws");
+            {
+                let mut bound = renderer.bind_pipeline(pipeline)?;
+                bound.bind_vertex_arrays(&[buffer], &[0], &[0])?;
+                bound.draw(DrawingMode::Triangles, 0, 3);
+                bound.draw(DrawingMode::Triangles, 0, 3);
+            }
+            renderer.end_scope();
+            renderer.begin_scope("empty");
+            renderer.end_scope();
+            renderer.end_scope();
+
+            let queries: Vec<_> = log
+                .calls()
+                .iter()
+                .filter_map(|call| match call {
+                    Call::Timestamp { query } => Some(*query),
+                    _ => None,
+                })
+                .collect();
+
+            Ok((renderer.end_profile_frame(index), queries))
+        };
+
+        let (frames, first_queries) = frame(&mut renderer, 0)?;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame, 0);
+
+        // the recording backend's timestamps count draw calls
+        let scopes: Vec<_> = frames[0]
+            .scopes
+            .iter()
+            .map(|scope| {
+                let start = scope.start.as_nanos();
+                let duration = scope.duration.as_nanos();
+                (scope.name.as_str(), scope.depth, start, duration)
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            [("frame", 0, 0, 2), ("draws", 1, 0, 2), ("empty", 1, 2, 0)]
+        );
+
+        // the queries of the first frame are reused once read back
+        let (frames, mut queries) = frame(&mut renderer, 1)?;
+        assert_eq!(frames[0].frame, 1);
+        queries.sort_unstable();
+        assert_eq!(queries, (0..6).collect::<Vec<_>>());
+        assert_eq!(first_queries.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_renderer_stats() -> Result<()> {
+        let mut renderer = Renderer::new(Box::new(RecordingRenderer::new()));
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec4);
+                ctx.set_builtin(BuiltinVariable::VertexPosition, position);
+            });
+        });
+        program.ok()?;
+
+        let pipeline = renderer.load_pipeline(program)?;
+        let buffer = renderer.load_vertex_buffer(&[0; 96]);
+        let indices = renderer.load_index_buffer(&[0; 12]);
+        renderer.update_vertex_buffer(buffer, 0, &[0; 16])?;
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_vertex_arrays(&[buffer], &[0], &[0])?;
+            bound.draw_instanced(DrawingMode::Triangles, 0, 6, 2);
+            bound.draw_indicies(DrawingMode::TriangleStrip, indices, IndexType::U16, 0, 6)?;
+        }
+
+        // command buffers are counted when they are submitted
+        let mut commands = renderer.command_buffer();
+        {
+            let mut bound = commands.bind_pipeline(pipeline);
+            bound.draw(DrawingMode::Lines, 0, 4);
+        }
+        renderer.submit(commands)?;
+
+        let stats = renderer.reset_stats();
+        assert_eq!(stats.draw_calls, 3);
+        assert_eq!(stats.vertices, 12 + 6 + 4);
+        assert_eq!(stats.triangles, 4 + 4);
+        assert_eq!(stats.pipeline_binds, 2);
+        assert_eq!(stats.bytes_uploaded, 96 + 12 + 16);
+
+        assert_eq!(renderer.stats(), RendererStats::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_draw_queue() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let program = || -> Result<_> {
+            let mut program = Program::new(|ctx| {
+                ctx.vertex(|ctx| {
+                    let position = ctx.input("position", Type::Vec4);
+                    ctx.set_builtin(BuiltinVariable::VertexPosition, position);
+                });
+            });
+            program.ok()?;
+            Ok(program)
+        };
+
+        let first = renderer.load_pipeline(program()?)?;
+        let second = renderer.load_pipeline(program()?)?;
+        let buffer = renderer.load_vertex_buffer(&[0; 48]);
+
+        // the start of each draw identifies the item
+        let items = [
+            (first, false, 3.0),
+            (second, false, 2.0),
+            (first, false, 1.0),
+            (second, false, 4.0),
+            (first, true, 1.0),
+            (first, true, 5.0),
+        ];
+
+        let (buffers, offsets, strides) = ([buffer], [0], [0]);
+        let mut queue = DrawQueue::new();
+        for (start, &(pipeline, translucent, depth)) in items.iter().enumerate() {
+            let call = DrawCall::Arrays {
+                mode: DrawingMode::Triangles,
+                start: start as _,
+                count: 3,
+            };
+
+            let mut item = DrawItem::new(pipeline, call);
+            item.translucent = translucent;
+            item.depth = depth;
+            item.vertex_buffers = &buffers;
+            item.vertex_offsets = &offsets;
+            item.vertex_strides = &strides;
+            queue.push(item);
+        }
+
+        log.clear();
+        renderer.reset_stats();
+        queue.submit(&mut renderer)?;
+        assert!(queue.is_empty());
+
+        let starts: Vec<_> = log
+            .calls()
+            .iter()
+            .filter_map(|call| match call {
+                Call::Draw { start, .. } => Some(*start),
+                _ => None,
+            })
+            .collect();
+
+        // opaque items are grouped by pipeline and drawn front to back, then
+        // translucent items are drawn back to front
+        assert_eq!(starts, [2, 0, 1, 3, 5, 4]);
+        assert_eq!(renderer.stats().pipeline_binds, 3);
+
+        Ok(())
+    }
+
+    /// A model of a single triangle, drawn by two nodes
+    fn triangle_model(renderer: &mut Renderer) -> Result<Model> {
+        triangle_mesh_model(renderer, r#"[{ "attributes": { "POSITION": 0 } }]"#, "[]")
+    }
+
+    /// A model with one mesh made of the given primitives, all reading the
+    /// same triangle, drawn by two nodes
+    fn triangle_mesh_model(
+        renderer: &mut Renderer,
+        primitives: &str,
+        materials: &str,
+    ) -> Result<Model> {
+        let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes().to_vec())
+            .collect();
+
+        let gltf = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0, 1] }}],
+                "nodes": [{{ "mesh": 0 }}, {{ "mesh": 0, "translation": [2.0, 0.0, 0.0] }}],
+                "meshes": [{{ "primitives": {} }}],
+                "materials": {},
+                "accessors": [{{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0],
+                    "max": [1.0, 1.0, 0.0]
+                }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 36, "target": 34962 }}],
+                "buffers": [{{
+                    "byteLength": 36,
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}]
+            }}"#,
+            primitives,
+            materials,
+            base64::encode(&positions)
+        );
+
+        let gltf: gltf::Model = serde_json::from_str(&gltf)?;
+        Ok(Model::from_gltf(
+            gltf,
+            &Resources::from_path("."),
+            renderer,
+        )?)
+    }
+
+    #[test]
+    fn test_model_render() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let model = triangle_model(&mut renderer)?;
+
+        log.clear();
+        model.render(
+            &mut renderer,
+            &glm::Mat4::identity(),
+            &glm::Mat4::identity(),
+        )?;
+
+        let calls = log.calls();
+        assert_eq!(log.draw_count(), 2);
+
+        let model_matrices: Vec<_> = calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::BindMatrix { name, matrix, .. } if name == "model" => Some(*matrix),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            model_matrices,
+            vec![
+                glm::Mat4::identity(),
+                glm::translation(&glm::vec3(2.0, 0.0, 0.0))
+            ]
+        );
+
+        // dropping the model unloads everything it loaded
+        drop(calls);
+        assert!(!renderer.leak_report().is_empty());
+        drop(model);
+        renderer.submit_queued()?;
+        assert!(renderer.leak_report().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_render_indirect() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut model = triangle_model(&mut renderer)?;
+        let view = glm::Mat4::identity();
+        assert!(model.render_indirect(&mut renderer, &view, &view).is_err());
+
+        log.clear();
+        model.load_indirect(&mut renderer)?;
+
+        let commands = log.calls().iter().find_map(|call| match call {
+            Call::LoadIndirectBuffer { data, .. } => Some(data.clone()),
+            _ => None,
+        });
+        let draw = |base_instance| DrawArraysIndirectCommand {
+            count: 3,
+            instances: 1,
+            first: 0,
+            base_instance,
+        };
+        let expected: Vec<u8> = [draw(0), draw(1)]
+            .iter()
+            .flat_map(|draw| draw.to_bytes())
+            .collect();
+        assert_eq!(commands, Some(expected));
+
+        log.clear();
+        model.render_indirect(&mut renderer, &view, &view)?;
+
+        // both nodes are drawn by one call, reading their matrix from a
+        // storage buffer by base instance rather than a per instance array
+        let calls = log.calls();
+        assert_eq!(log.draw_count(), 1);
+
+        let storage = calls.iter().find_map(|call| match call {
+            Call::BindStorageBuffer { name, .. } => Some(name.clone()),
+            _ => None,
+        });
+        assert_eq!(storage.as_deref(), Some("model_matrices"));
+
+        let divisors = calls.iter().find_map(|call| match call {
+            Call::BindVertexArrays { divisors, .. } => Some(divisors.clone()),
+            _ => None,
+        });
+        assert_eq!(divisors, Some(vec![0]));
+
+        // the commands are uploaded once when loading, not on every draw
+        let draws = calls.iter().find_map(|call| match call {
+            Call::DrawIndirectBuffer { offset, count, .. } => Some((*offset, *count)),
+            _ => None,
+        });
+        assert_eq!(draws, Some((0, 2)));
+        assert!(!calls
+            .iter()
+            .any(|call| matches!(call, Call::LoadIndirectBuffer { .. })));
+
+        // drawing again reuses the same commands
+        drop(calls);
+        model.render_indirect(&mut renderer, &view, &view)?;
+        assert_eq!(log.draw_count(), 2);
+        assert!(!log
+            .calls()
+            .iter()
+            .any(|call| matches!(call, Call::LoadIndirectBuffer { .. })));
+
+        drop(model);
+        renderer.submit_queued()?;
+        assert!(renderer.leak_report().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_shared_pipeline() -> Result<()> {
+        let backend = RecordingRenderer::new();
+        let log = backend.log();
+        let mut renderer = Renderer::new(Box::new(backend));
+
+        let mut model = triangle_mesh_model(
+            &mut renderer,
+            r#"[
+                { "attributes": { "POSITION": 0 }, "material": 0 },
+                { "attributes": { "POSITION": 0 }, "material": 0 }
+            ]"#,
+            r#"[{ "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0] } }]"#,
+        )?;
+
+        log.clear();
+        model.render(
+            &mut renderer,
+            &glm::Mat4::identity(),
+            &glm::Mat4::identity(),
+        )?;
+
+        // both primitives use the same shader and state, so share a pipeline
+        let calls = log.calls();
+        assert_eq!(log.draw_count(), 4);
+
+        let binds = calls
+            .iter()
+            .filter(|call| matches!(call, Call::BindPipeline { .. }))
+            .count();
+        assert_eq!(binds, 1);
+
+        // the indirect pipelines are shared too, and as the primitives have
+        // the same bindings all four draws are made by one call
+        drop(calls);
+        log.clear();
+        model.load_indirect(&mut renderer)?;
+
+        let loads = log
+            .calls()
+            .iter()
+            .filter(|call| matches!(call, Call::LoadPipeline { .. }))
+            .count();
+        assert_eq!(loads, 1);
+
+        let view = glm::Mat4::identity();
+        log.clear();
+        model.render_indirect(&mut renderer, &view, &view)?;
+
+        let calls = log.calls();
+        assert_eq!(log.draw_count(), 1);
+
+        let draws = calls.iter().find_map(|call| match call {
+            Call::DrawIndirectBuffer { count, .. } => Some(*count),
+            _ => None,
+        });
+        assert_eq!(draws, Some(4));
+
+        drop(calls);
+        drop(model);
+        renderer.submit_queued()?;
+        assert!(renderer.leak_report().is_empty());
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     fmt::{self, Display, Formatter, Write},
     ops::{Deref, DerefMut},
 };
@@ -30,6 +31,15 @@ pub enum ShaderCreationError {
 
     #[error("Variable cannot have location applied: {name}")]
     VariableLocation { name: String },
+
+    #[error("Uniform block {name} has no members")]
+    EmptyUniformBlock { name: String },
+
+    #[error("Storage buffer {name} cannot hold elements of type {ty}, elements must be a scalar, vec2, vec4, mat2 or mat4")]
+    StorageElementType { name: String, ty: Type },
+
+    #[error("Compute shader workgroup size {size:?} must be at least 1 in every dimension")]
+    WorkgroupSize { size: [u32; 3] },
 }
 
 #[derive(Debug)]
@@ -52,10 +62,26 @@ pub struct Program {
     functions: Vec<Function>,
     vertex: Option<VertexShader>,
     frag: Option<FragmentShader>,
+    compute: Option<ComputeShader>,
     uniforms: Vec<Variable>,
+    uniform_blocks: Vec<UniformBlock>,
+
+    /// Arrays stored in storage buffers, each has type [`Type::Buffer`]
+    storage_buffers: Vec<Variable>,
     errors: Vec<ShaderCreationError>,
 }
 
+/// A named group of uniforms that are read from a uniform buffer, laid out
+/// using the std140 rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniformBlock {
+    pub name: String,
+
+    /// The indices into the program's uniforms of each member of the block,
+    /// in the order they are stored in the buffer
+    pub members: Vec<usize>,
+}
+
 #[context_globals(program => uniforms)]
 pub struct ProgramContext {
     program: Program,
@@ -73,6 +99,14 @@ struct FragmentShader {
     main: usize,
 }
 
+/// A compute shader's main function and the number of invocations in each of
+/// its workgroups
+#[derive(Debug)]
+struct ComputeShader {
+    main: usize,
+    local_size: [u32; 3],
+}
+
 /// A single function in a shader program, either a shader main function or
 /// a utility function
 #[derive(Debug)]
@@ -113,6 +147,9 @@ pub enum Expression {
     /// Create a constant floating point value
     MakeFloat { value: f32 },
 
+    /// Create a constant integer value
+    MakeInt { value: i32 },
+
     /// Read a variable
     GetVariable { variable: VariableId },
 }
@@ -129,6 +166,10 @@ pub enum Statement {
         value: f32,
         variable: VariableId,
     },
+    MakeInt {
+        value: i32,
+        variable: VariableId,
+    },
     SetBuiltinVariable {
         variable: BuiltinVariable,
         value: VariableId,
@@ -149,12 +190,33 @@ pub enum BuiltinFunction {
     Texture,
     MakeVec,
     SetGlobal,
+
+    /// Get a single element of an array: (array, integer index)
+    Index,
+
+    /// Set a single element of a storage buffer: (buffer, integer index, value)
+    Store,
 }
 
 /// Variables automagically provided by a shader without having to declare them
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BuiltinVariable {
     VertexPosition,
+
+    /// The index of the instance being drawn, read only and only avaliable in
+    /// vertex shaders
+    InstanceId,
+
+    /// The base instance of the draw, which is not included in the instance
+    /// id.  Read only and only avaliable in vertex shaders, indirect draws can
+    /// use it to give each draw its own data.
+    BaseInstance,
+
+    /// The position of the invocation in the whole dispatch along each axis,
+    /// read only and only avaliable in compute shaders
+    GlobalInvocationX,
+    GlobalInvocationY,
+    GlobalInvocationZ,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -169,6 +231,7 @@ pub enum VariableAllocationContext {
     Uniform,
     Input,
     Output,
+    Storage,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -181,10 +244,31 @@ pub struct Variable {
 pub enum Type {
     Vector(usize),
 
+    /// A vector of signed integers, e.g. `ivec4`
+    IntVector(usize),
+
+    /// A vector of unsigned integers, e.g. `uvec4`, such as glTF joint indices
+    UIntVector(usize),
+
     /// matrix rows x columns
     Matrix(usize, usize),
     Floating,
+    Integer,
+
+    /// An unsigned integer, e.g. `uint`, such as a single glTF joint index
+    UnsignedInteger,
     Sampler2D,
+    SamplerCube,
+    Sampler2DArray,
+    Sampler3D,
+
+    /// A fixed length array, e.g. `Type::Array(&Type::Vec4, 8)`, arrays can
+    /// only be used as uniforms and their elements must be numeric
+    Array(&'static Type, usize),
+
+    /// An array stored in a storage buffer, its length is set by the size of
+    /// the buffer bound to it, see [`ProgramContext::storage_buffer`]
+    Buffer(&'static Type),
     Unknown,
 }
 
@@ -197,6 +281,45 @@ impl Type {
     pub const Vec4: Type = Type::Vector(4);
     pub const Vec3: Type = Type::Vector(3);
     pub const Vec2: Type = Type::Vector(2);
+    pub const IVec4: Type = Type::IntVector(4);
+    pub const IVec3: Type = Type::IntVector(3);
+    pub const IVec2: Type = Type::IntVector(2);
+    pub const UVec4: Type = Type::UIntVector(4);
+    pub const UVec3: Type = Type::UIntVector(3);
+    pub const UVec2: Type = Type::UIntVector(2);
+
+    /// Is the type an integer scalar or vector, integers cannot be mixed with
+    /// floating point values and are not interpolated between vertices
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::Integer | Type::UnsignedInteger | Type::IntVector(_) | Type::UIntVector(_)
+        )
+    }
+
+    /// Is the type an unsigned integer scalar or vector, unsigned values are
+    /// stored as u32 rather than i32
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::UnsignedInteger | Type::UIntVector(_))
+    }
+
+    /// Is the type an opaque texture sampler
+    pub fn is_sampler(&self) -> bool {
+        matches!(
+            self,
+            Type::Sampler2D | Type::SamplerCube | Type::Sampler2DArray | Type::Sampler3D
+        )
+    }
+
+    /// The type of the coordinates used to sample a texture using a sampler,
+    /// None if the type is not a sampler
+    pub fn texture_coordinates(&self) -> Option<Type> {
+        match self {
+            Type::Sampler2D => Some(Type::Vec2),
+            Type::SamplerCube | Type::Sampler2DArray | Type::Sampler3D => Some(Type::Vec3),
+            _ => None,
+        }
+    }
 }
 
 // =============== //
@@ -208,7 +331,10 @@ impl Program {
         let program = Program {
             vertex: None,
             frag: None,
+            compute: None,
             uniforms: vec![],
+            uniform_blocks: vec![],
+            storage_buffers: vec![],
             functions: vec![],
             errors: vec![],
         };
@@ -252,6 +378,20 @@ impl Program {
         }
     }
 
+    /// Get the compute shader main function
+    pub fn compute_main(&self) -> Option<&Function> {
+        if let Some(compute) = &self.compute {
+            Some(&self.functions[compute.main])
+        } else {
+            None
+        }
+    }
+
+    /// The number of invocations in each workgroup of the compute shader
+    pub fn local_size(&self) -> Option<[u32; 3]> {
+        self.compute.as_ref().map(|compute| compute.local_size)
+    }
+
     /// Get the vertex shader main function
     pub fn vertex_mut(&mut self) -> Option<&mut Function> {
         if let Some(vert) = &self.vertex {
@@ -270,6 +410,15 @@ impl Program {
         }
     }
 
+    /// Get the compute shader main function
+    pub fn compute_mut(&mut self) -> Option<&mut Function> {
+        if let Some(compute) = &self.compute {
+            Some(&mut self.functions[compute.main])
+        } else {
+            None
+        }
+    }
+
     /// Get a list of all the uniform variables in the program
     pub fn uniforms(&self) -> &[Variable] {
         &self.uniforms
@@ -279,12 +428,45 @@ impl Program {
         &mut self.uniforms
     }
 
+    /// Get a list of all the uniform blocks in the program
+    pub fn uniform_blocks(&self) -> &[UniformBlock] {
+        &self.uniform_blocks
+    }
+
+    /// Get a list of all the storage buffers in the program, in the order they
+    /// were declared
+    pub fn storage_buffers(&self) -> &[Variable] {
+        &self.storage_buffers
+    }
+
+    /// Get the uniform block that a uniform is a member of, if any
+    pub fn uniform_block_of(&self, uniform: usize) -> Option<&UniformBlock> {
+        self.uniform_blocks
+            .iter()
+            .find(|block| block.members.contains(&uniform))
+    }
+
+    /// Whether a uniform contributes to any of the program's outputs, unused
+    /// uniforms are optimized out by the shader compiler so cannot be bound
+    pub fn uniform_used(&self, uniform: usize) -> bool {
+        let variable = VariableId {
+            id: uniform,
+            kind: VariableAllocationContext::Uniform,
+        };
+
+        [self.vertex_main(), self.frag_main(), self.compute_main()]
+            .iter()
+            .flatten()
+            .any(|func| func.live_variables().contains(&variable))
+    }
+
     pub fn get_variable<'a>(&'a self, func: &'a Function, variable: VariableId) -> &'a Variable {
         match variable.kind {
             VariableAllocationContext::Local => &func.vars.locals[variable.id],
             VariableAllocationContext::Uniform => &self.uniforms[variable.id],
             VariableAllocationContext::Input => &func.vars.inputs[variable.id],
             VariableAllocationContext::Output => &func.vars.outputs[variable.id],
+            VariableAllocationContext::Storage => &self.storage_buffers[variable.id],
         }
     }
 }
@@ -309,6 +491,87 @@ impl ProgramContext {
         self.program.frag = Some(shader);
     }
 
+    /// Declare a compute shader, run once for each invocation of a dispatch.
+    /// Invocations are grouped into workgroups of `local_size` invocations,
+    /// dispatches give the number of workgroups along each axis.  A program
+    /// with a compute shader cannot have vertex or fragment shaders.
+    pub fn compute(
+        &mut self,
+        local_size: [u32; 3],
+        constructor: impl FnOnce(&mut FunctionContext),
+    ) {
+        if local_size.contains(&0) {
+            self.creation_error(ShaderCreationError::WorkgroupSize { size: local_size });
+        }
+
+        let shader = ComputeShader::new(self, local_size, constructor);
+        self.program.compute = Some(shader);
+    }
+
+    /// Declare an array stored in a storage buffer, which can be read and
+    /// written by every shader in the program.  Elements are read using
+    /// [`Expression::index`] and written using [`FunctionContext::store`],
+    /// they are tightly packed in the buffer so only types whose std430 layout
+    /// has no padding can be used.
+    pub fn storage_buffer(&mut self, name: &str, element: &'static Type) -> Expression {
+        let packed = matches!(
+            element,
+            Type::Floating
+                | Type::Integer
+                | Type::UnsignedInteger
+                | Type::Vector(2)
+                | Type::Vector(4)
+                | Type::IntVector(2)
+                | Type::IntVector(4)
+                | Type::UIntVector(2)
+                | Type::UIntVector(4)
+                | Type::Matrix(2, 2)
+                | Type::Matrix(4, 4)
+        );
+        if !packed {
+            self.creation_error(ShaderCreationError::StorageElementType {
+                name: name.to_string(),
+                ty: *element,
+            });
+        }
+
+        let id = self.program.storage_buffers.len();
+        self.program.storage_buffers.push(Variable {
+            name: name.to_string(),
+            ty: Type::Buffer(element),
+        });
+
+        Expression::GetVariable {
+            variable: VariableId {
+                id,
+                kind: VariableAllocationContext::Storage,
+            },
+        }
+    }
+
+    /// Declare a uniform block, members are declared in the order they are
+    /// stored in the buffer.  Returns an expression for reading each member.
+    pub fn uniform_block(&mut self, name: &str, members: &[(&str, Type)]) -> Vec<Expression> {
+        if members.is_empty() {
+            self.creation_error(ShaderCreationError::EmptyUniformBlock {
+                name: name.to_string(),
+            });
+        }
+
+        let start = self.program.uniforms.len();
+        let expressions = members
+            .iter()
+            .map(|&(name, ty)| self.uniform(name, ty))
+            .collect();
+
+        self.program.uniform_blocks.push(UniformBlock {
+            name: name.to_string(),
+            members: (start..self.program.uniforms.len()).collect(),
+        });
+
+        expressions
+    }
+
     pub fn emit_error(&mut self, err: anyhow::Error) {
         self.program
             .errors
@@ -359,6 +622,20 @@ impl FragmentShader {
     }
 }
 
+impl ComputeShader {
+    fn new(
+        prog: &mut ProgramContext,
+        local_size: [u32; 3],
+        constructor: impl FnOnce(&mut FunctionContext),
+    ) -> Self {
+        let func = Function::new(prog, constructor);
+        let main = prog.program.functions.len();
+        prog.program.functions.push(func);
+
+        ComputeShader { main, local_size }
+    }
+}
+
 impl Function {
     /// create a function in a shader
     fn new(program: &mut ProgramContext, constructor: impl FnOnce(&mut FunctionContext)) -> Self {
@@ -421,6 +698,9 @@ impl Function {
             VariableAllocationContext::Output => {
                 self.vars.outputs[var.id].name = name;
             }
+            VariableAllocationContext::Storage => {
+                program.storage_buffers[var.id].name = name;
+            }
         }
     }
 
@@ -441,6 +721,20 @@ impl Function {
 
                 variable
             }
+            &Expression::MakeInt { value } => {
+                let variable = self.local_variable("", Type::Integer);
+                self.set_var_name(
+                    program,
+                    &format!("i32_{}", self.vars.locals.len() - 1),
+                    variable,
+                );
+
+                self.blocks[0]
+                    .statements
+                    .push(Statement::MakeInt { value, variable });
+
+                variable
+            }
             &Expression::CallBuiltin {
                 ref arguments,
                 function,
@@ -465,6 +759,39 @@ impl Function {
 }
 
 impl Function {
+    /// Find every variable that the function's outputs, builtin variables or
+    /// storage buffer writes depend on.  Each local is only assigned once,
+    /// before it is read, so a single backwards pass finds all of them
+    fn live_variables(&self) -> HashSet<VariableId> {
+        let mut live = HashSet::new();
+
+        let statements = self
+            .blocks
+            .iter()
+            .rev()
+            .flat_map(|b| b.statements.iter().rev());
+        for statement in statements {
+            match statement {
+                Statement::CallBuiltin {
+                    function: BuiltinFunction::SetGlobal | BuiltinFunction::Store,
+                    arguments,
+                    ..
+                } => live.extend(arguments),
+                Statement::CallBuiltin {
+                    arguments,
+                    result: Some(result),
+                    ..
+                } if live.contains(result) => live.extend(arguments),
+                Statement::SetBuiltinVariable { value, .. } => {
+                    live.insert(*value);
+                }
+                _ => (),
+            }
+        }
+
+        live
+    }
+
     pub fn blocks(&self) -> &[Block] {
         &self.blocks
     }
@@ -516,6 +843,19 @@ impl<'a, 'b> FunctionContext<'a, 'b> {
             })
     }
 
+    pub fn get_builtin(&mut self, builtin: BuiltinVariable) -> Expression {
+        let result = self.function.local_variable("", builtin.get_type());
+
+        self.function.blocks[0]
+            .statements
+            .push(Statement::GetBuiltinVariable {
+                variable: builtin,
+                result,
+            });
+
+        Expression::GetVariable { variable: result }
+    }
+
     pub fn set_output(&mut self, target: Expression, value: Expression) {
         let target = self
             .function
@@ -533,6 +873,26 @@ impl<'a, 'b> FunctionContext<'a, 'b> {
                 result: None,
             })
     }
+
+    /// Write a value to an element of a storage buffer, writing out of the
+    /// bounds of the buffer does nothing
+    pub fn store(&mut self, buffer: Expression, index: Expression, value: Expression) {
+        let arguments = [buffer, index, value]
+            .iter()
+            .map(|expr| {
+                self.function
+                    .expr_to_variable(&mut self.program.program, expr)
+            })
+            .collect();
+
+        self.function.blocks[0]
+            .statements
+            .push(Statement::CallBuiltin {
+                function: BuiltinFunction::Store,
+                arguments,
+                result: None,
+            })
+    }
 }
 
 impl<'a, 'b> Deref for FunctionContext<'a, 'b> {
@@ -562,6 +922,12 @@ impl From<f32> for Expression {
     }
 }
 
+impl From<i32> for Expression {
+    fn from(value: i32) -> Self {
+        Expression::MakeInt { value }
+    }
+}
+
 macro_rules! ExpressionOp {
     ($op:ident, $func:ident) => {
         impl ::std::ops::$op for Expression {
@@ -608,12 +974,35 @@ impl Expression {
             function: BuiltinFunction::MakeVec,
         }
     }
+
+    /// Get an element of an array or storage buffer using an integer index
+    pub fn index(array: Expression, index: Expression) -> Expression {
+        Expression::CallBuiltin {
+            arguments: vec![array, index],
+            function: BuiltinFunction::Index,
+        }
+    }
 }
 
 impl BuiltinVariable {
+    /// Is the variable one of the compute shader invocation ids
+    pub fn is_invocation(&self) -> bool {
+        matches!(
+            self,
+            BuiltinVariable::GlobalInvocationX
+                | BuiltinVariable::GlobalInvocationY
+                | BuiltinVariable::GlobalInvocationZ
+        )
+    }
+
     fn get_type(&self) -> Type {
         match self {
             &BuiltinVariable::VertexPosition => Type::Vec4,
+            &BuiltinVariable::InstanceId
+            | &BuiltinVariable::BaseInstance
+            | &BuiltinVariable::GlobalInvocationX
+            | &BuiltinVariable::GlobalInvocationY
+            | &BuiltinVariable::GlobalInvocationZ => Type::Integer,
         }
     }
 }
@@ -652,6 +1041,7 @@ fn get_variable<'a>(
         VariableAllocationContext::Uniform => &mut prog.program.uniforms[variable.id],
         VariableAllocationContext::Input => &mut vars.inputs[variable.id],
         VariableAllocationContext::Output => &mut vars.outputs[variable.id],
+        VariableAllocationContext::Storage => &mut prog.program.storage_buffers[variable.id],
     }
 }
 
@@ -675,6 +1065,11 @@ impl Block {
                 {
                     get_variable(*variable, prog, vars).ty = Type::Floating;
                 }
+                Statement::MakeInt { variable, .. }
+                    if get_variable(*variable, prog, vars).ty == Type::Unknown =>
+                {
+                    get_variable(*variable, prog, vars).ty = Type::Integer;
+                }
                 Statement::GetBuiltinVariable { variable, result }
                     if get_variable(*result, prog, vars).ty == Type::Unknown =>
                 {
@@ -709,12 +1104,17 @@ impl BuiltinFunction {
 
             BuiltinFunction::Texture => Self::type_check_texture(prog, vars, arguments),
             BuiltinFunction::MakeVec => Self::type_check_make_vec(prog, vars, arguments),
+            BuiltinFunction::Index => Self::type_check_index(prog, vars, arguments),
 
             // These functions do not have an output variable
             BuiltinFunction::SetGlobal => {
                 Self::type_check_setter("set_global", prog, vars, arguments);
                 None
             }
+            BuiltinFunction::Store => {
+                Self::type_check_store(prog, vars, arguments);
+                None
+            }
         }
     }
 
@@ -726,6 +1126,10 @@ impl BuiltinFunction {
     ) -> Option<Type> {
         prog.check_arg_count(fn_name, arguments, 2)?;
 
+        if Self::integer_operands(prog, vars, fn_name, arguments)? {
+            return Some(get_variable(arguments[0], prog, vars).ty);
+        }
+
         let arg1_shape = get_variable(arguments[0], prog, vars).ty;
         let arg1_shape = arg1_shape.get_shape(fn_name, prog)?;
         let arg2_shape = get_variable(arguments[1], prog, vars).ty;
@@ -752,6 +1156,44 @@ impl BuiltinFunction {
         None
     }
 
+    /// Check whether both arguments of a binary operator are integers, integers
+    /// cannot be mixed with floating point values or with integers of another
+    /// type
+    fn integer_operands(
+        prog: &mut ProgramContext,
+        vars: &mut FunctionVars,
+        fn_name: &str,
+        arguments: &[VariableId],
+    ) -> Option<bool> {
+        let arg1 = get_variable(arguments[0], prog, vars).ty;
+        let arg2 = get_variable(arguments[1], prog, vars).ty;
+
+        match (arg1.is_integer(), arg2.is_integer()) {
+            (true, true) if arg1 == arg2 => Some(true),
+            (true, true) => {
+                prog.creation_error(ShaderCreationError::ArgumentType {
+                    func: fn_name.to_string(),
+                    message: format!(
+                        "Unable to {} values of type {} and {}, both integers must have the same type",
+                        fn_name, arg1, arg2
+                    ),
+                });
+                None
+            }
+            (false, false) => Some(false),
+            _ => {
+                prog.creation_error(ShaderCreationError::ArgumentType {
+                    func: fn_name.to_string(),
+                    message: format!(
+                        "Unable to {} values of type {} and {}, integers cannot be mixed with floats",
+                        fn_name, arg1, arg2
+                    ),
+                });
+                None
+            }
+        }
+    }
+
     fn type_check_mul(
         prog: &mut ProgramContext,
         vars: &mut FunctionVars,
@@ -759,6 +1201,10 @@ impl BuiltinFunction {
     ) -> Option<Type> {
         prog.check_arg_count("mul", arguments, 2)?;
 
+        if Self::integer_operands(prog, vars, "mul", arguments)? {
+            return Some(get_variable(arguments[0], prog, vars).ty);
+        }
+
         let arg1_shape = get_variable(arguments[0], prog, vars).ty;
         let arg1_shape = arg1_shape.get_shape("mul", prog)?;
         let arg2_shape = get_variable(arguments[1], prog, vars).ty;
@@ -814,11 +1260,12 @@ impl BuiltinFunction {
         let arg1 = get_variable(arguments[0], prog, vars).ty;
         let arg2 = get_variable(arguments[1], prog, vars).ty;
 
-        if arg1 != Type::Sampler2D && arg2 != Type::Vec2 {
+        if arg1.texture_coordinates() != Some(arg2) {
             prog.creation_error(ShaderCreationError::ArgumentType {
                 func: "texture".to_string(),
                 message: format!(
-                    "The texture function currently only supports (Sampler2D, vec2), got {}, {}",
+                    "The texture function requires a sampler and its coordinates, e.g. \
+                    (sampler2D, vec2) or (samplerCube, vec3), got {}, {}",
                     arg1, arg2
                 ),
             });
@@ -867,6 +1314,52 @@ impl BuiltinFunction {
         }
     }
 
+    fn type_check_index(
+        prog: &mut ProgramContext,
+        vars: &mut FunctionVars,
+        arguments: &[VariableId],
+    ) -> Option<Type> {
+        prog.check_arg_count("index", arguments, 2)?;
+
+        let arg1 = get_variable(arguments[0], prog, vars).ty;
+        let arg2 = get_variable(arguments[1], prog, vars).ty;
+
+        match (arg1, arg2) {
+            (Type::Array(element, _) | Type::Buffer(element), Type::Integer) => Some(*element),
+            _ => {
+                prog.creation_error(ShaderCreationError::ArgumentType {
+                    func: "index".to_string(),
+                    message: format!("Expected (array or buffer, i32), got {}, {}", arg1, arg2),
+                });
+                None
+            }
+        }
+    }
+
+    fn type_check_store(
+        prog: &mut ProgramContext,
+        vars: &mut FunctionVars,
+        arguments: &[VariableId],
+    ) {
+        if prog.check_arg_count("store", arguments, 3).is_none() {
+            return;
+        }
+
+        let buffer = get_variable(arguments[0], prog, vars).ty;
+        let index = get_variable(arguments[1], prog, vars).ty;
+        let value = get_variable(arguments[2], prog, vars).ty;
+
+        if !matches!((buffer, index), (Type::Buffer(&element), Type::Integer) if element == value) {
+            prog.creation_error(ShaderCreationError::ArgumentType {
+                func: "store".to_string(),
+                message: format!(
+                    "Expected (buffer, i32, buffer element), got {}, {}, {}",
+                    buffer, index, value
+                ),
+            })
+        }
+    }
+
     fn type_check_setter(
         fn_name: &str,
         prog: &mut ProgramContext,
@@ -893,13 +1386,20 @@ impl BuiltinFunction {
 }
 
 impl Type {
-    /// returns (rows, cols)
+    /// returns (rows, cols), integer vectors have the same shape as float
+    /// vectors so that they can be converted using make_vec
     fn get_shape(&self, fn_name: &str, prog: &mut ProgramContext) -> Option<(usize, usize)> {
         match self {
-            Type::Vector(cols) => Some((1, *cols)),
+            Type::Vector(cols) | Type::IntVector(cols) | Type::UIntVector(cols) => Some((1, *cols)),
             Type::Matrix(rows, cols) => Some((*rows, *cols)),
-            Type::Floating => Some((1, 1)),
-            ty @ (Type::Sampler2D | Type::Unknown) => {
+            Type::Floating | Type::Integer | Type::UnsignedInteger => Some((1, 1)),
+            ty @ (Type::Sampler2D
+            | Type::SamplerCube
+            | Type::Sampler2DArray
+            | Type::Sampler3D
+            | Type::Array(..)
+            | Type::Buffer(..)
+            | Type::Unknown) => {
                 prog.creation_error(ShaderCreationError::ArgumentType {
                     func: fn_name.to_string(),
                     message: format!("Expected numeric type such as matrix or scalar, got {}", ty),
@@ -931,6 +1431,24 @@ impl Display for Program {
             writeln!(f, "    {};", uniform.to_string("uniform ")?)?;
         }
 
+        for block in &self.uniform_blocks {
+            let members: Vec<_> = block
+                .members
+                .iter()
+                .map(|&member| format!("${}", self.uniforms[member].name))
+                .collect();
+            writeln!(
+                f,
+                "    uniform block {} {{ {} }};",
+                block.name,
+                members.join(", ")
+            )?;
+        }
+
+        for buffer in &self.storage_buffers {
+            writeln!(f, "    {};", buffer.to_string("storage ")?)?;
+        }
+
         if let Some(vertex) = &self.vertex {
             write!(f, "\n    vertex main")?;
             self.functions[vertex.main].fmt(f, self)?;
@@ -941,6 +1459,12 @@ impl Display for Program {
             self.functions[frag.main].fmt(f, self)?;
         }
 
+        if let Some(compute) = &self.compute {
+            let [x, y, z] = compute.local_size;
+            write!(f, "\n    compute main [{}, {}, {}]", x, y, z)?;
+            self.functions[compute.main].fmt(f, self)?;
+        }
+
         writeln!(f, "}}")?;
 
         Ok(())
@@ -961,10 +1485,19 @@ impl Display for Type {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             Type::Floating => write!(f, "f32"),
+            Type::Integer => write!(f, "i32"),
+            Type::UnsignedInteger => write!(f, "u32"),
             Type::Sampler2D => write!(f, "sampler2D"),
+            Type::SamplerCube => write!(f, "samplerCube"),
+            Type::Sampler2DArray => write!(f, "sampler2DArray"),
+            Type::Sampler3D => write!(f, "sampler3D"),
+            Type::Array(element, len) => write!(f, "[{}; {}]", element, len),
+            Type::Buffer(element) => write!(f, "[{}]", element),
             Type::Unknown => write!(f, "null_type"),
 
             Type::Vector(n) => write!(f, "vec{}", n),
+            Type::IntVector(n) => write!(f, "ivec{}", n),
+            Type::UIntVector(n) => write!(f, "uvec{}", n),
             Type::Matrix(n, m) => {
                 if n == m {
                     write!(f, "mat{}", n)
@@ -979,7 +1512,12 @@ impl Display for Type {
 impl Display for BuiltinVariable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &BuiltinVariable::VertexPosition => write!(f, "gl_Position"),
+            BuiltinVariable::VertexPosition => write!(f, "gl_Position"),
+            BuiltinVariable::InstanceId => write!(f, "gl_InstanceID"),
+            BuiltinVariable::BaseInstance => write!(f, "gl_BaseInstanceARB"),
+            BuiltinVariable::GlobalInvocationX => write!(f, "gl_GlobalInvocationID.x"),
+            BuiltinVariable::GlobalInvocationY => write!(f, "gl_GlobalInvocationID.y"),
+            BuiltinVariable::GlobalInvocationZ => write!(f, "gl_GlobalInvocationID.z"),
         }
     }
 }
@@ -1095,6 +1633,10 @@ impl Statement {
                 variable.fmt(f, prog, func)?;
                 write!(f, " = {};", value)?;
             }
+            &Statement::MakeInt { value, variable } => {
+                variable.fmt(f, prog, func)?;
+                write!(f, " = {};", value)?;
+            }
             &Statement::GetBuiltinVariable { variable, result } => {
                 result.fmt(f, prog, func)?;
                 write!(f, " = {};", variable)?;
@@ -1140,6 +1682,9 @@ impl VariableId {
             VariableAllocationContext::Output => {
                 var_display(f, "$", self.id, &func.vars.outputs[self.id])?;
             }
+            VariableAllocationContext::Storage => {
+                var_display(f, "$", self.id, &prog.storage_buffers[self.id])?;
+            }
         }
 
         Ok(())
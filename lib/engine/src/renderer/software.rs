@@ -0,0 +1,4362 @@
+use anyhow::Result;
+use image::{ImageFormat, Rgba, RgbaImage};
+use nalgebra_glm as glm;
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    convert::TryInto,
+    path::Path,
+    rc::Rc,
+    time::Instant,
+};
+use thiserror::Error;
+
+use crate::texture::{
+    MagFilter, MinFilter, Texture, TextureKind, TextureOptions, TextureRegion, TextureSourceFormat,
+    TextureSourceType, TextureStorageType, WrappingMode,
+};
+
+use super::{
+    backend::RendererBackend,
+    buffer::{check_buffer_write, check_indirect_read, RingBuffer},
+    readback::{check_read_region, to_unorm8, ReadPixelsError},
+    shader::{
+        BuiltinFunction, BuiltinVariable, Function, Program, Statement, Type, Variable,
+        VariableAllocationContext, VariableId,
+    },
+    uniform::{
+        check_sampler, check_storage_buffer, check_uniform, check_uniform_block, std140_offsets,
+    },
+    vertex::vertex_formats,
+    BlendEquation, BlendFactor, BlendFunction, BlendMode, BufferError, CullingMode, DepthTesting,
+    DepthTestingFunction, DrawArraysIndirectCommand, DrawElementsIndirectCommand, DrawingMode,
+    Handle, IndexBufferId, IndexType, IndirectBufferId, PipelineDescriptor, PipelineId,
+    PipelineState, PixelFormat, PixelRegion, RenderTargetId, RenderTargetOptions,
+    RendererCapabilities, StencilOperation, StencilTesting, StorageBufferId, TextureId,
+    UniformBufferId, UniformValue, VertexBufferId, VertexComponentType, VertexFormat,
+};
+
+/// Errors returned by the software backend, these mirror the errors that the
+/// OpenGl backend would return for the same calls
+#[derive(Debug, Error)]
+enum SoftwareError {
+    #[error("Cannot bind to unbound pipeline")]
+    PipelineNotBound,
+
+    #[error("Texture is not currently loaded, cannot bind it to a pipeline")]
+    TextureUnloaded,
+
+    #[error("Uniform buffer is not currently loaded, cannot bind it to a pipeline")]
+    UniformBufferUnloaded,
+
+    #[error("Storage buffer is not currently loaded, cannot bind it to a pipeline")]
+    StorageBufferUnloaded,
+
+    #[error("Unable to load opaque type as vertex array")]
+    OpaqueVerticies,
+
+    #[error("Render target must have at least one attachment")]
+    NoAttachments,
+
+    #[error("Render target stencil buffer requires a depth attachment")]
+    StencilWithoutDepth,
+
+    #[error("Render target color attachment cannot use compressed storage {storage:?}")]
+    CompressedAttachment { storage: TextureStorageType },
+}
+
+/// Renderer backend that rasterizes everything on the cpu, into a color and
+/// depth buffer that can be read back as an image.  Shader programs are
+/// interpreted directly from their IR, so no graphics driver is needed.
+pub struct SoftwareRenderer {
+    /// Where all rendering output is written to
+    framebuffer: SoftwareFramebuffer,
+
+    /// The region of the framebuffer drawn into: x, y, width, height
+    viewport: (usize, usize, usize, usize),
+
+    /// All the currently loaded textures
+    textures: HashMap<Handle, SoftwareTexture>,
+
+    /// All the currently loaded vertex, index, uniform and storage buffers
+    buffers: HashMap<Handle, Vec<u8>>,
+
+    /// Allocation state of the buffers loaded as ring buffers
+    rings: HashMap<Handle, RingBuffer>,
+
+    /// All the shader pipelines currently avaliable
+    pipelines: HashMap<Handle, SoftwarePipeline>,
+
+    /// The framebuffers of all the render targets, their contents are copied
+    /// into the attachment textures when the target is unbound
+    render_targets: HashMap<Handle, SoftwareFramebuffer>,
+
+    /// The render target currently being drawn to, None for the main framebuffer
+    render_target: Option<RenderTargetId>,
+
+    /// The viewport of the main framebuffer, restored when it is bound again
+    screen_viewport: (usize, usize, usize, usize),
+
+    /// Which faces are culled for all future draw calls
+    culling: CullingMode,
+
+    /// The depth testing settings for all future draw calls
+    depth: DepthTesting,
+
+    /// The stencil testing settings for all future draw calls
+    stencil: StencilTesting,
+
+    /// The blending applied to all future draw calls
+    blend: BlendMode,
+
+    /// When the backend was created, timestamps are measured from this
+    epoch: Instant,
+
+    /// The values of all the timestamp queries written, as drawing happens
+    /// immediately these are always available
+    timestamps: HashMap<u32, u64>,
+}
+
+impl SoftwareRenderer {
+    /// Create a new software backend that renders into a framebuffer of the
+    /// given size, the viewport initially covers the whole framebuffer
+    pub fn new(width: u32, height: u32) -> Self {
+        SoftwareRenderer {
+            framebuffer: SoftwareFramebuffer::new(
+                width as _,
+                height as _,
+                RenderTargetOptions {
+                    stencil: true,
+                    ..RenderTargetOptions::new(width, height)
+                },
+            ),
+            viewport: (0, 0, width as _, height as _),
+            textures: HashMap::new(),
+            buffers: HashMap::new(),
+            rings: HashMap::new(),
+            pipelines: HashMap::new(),
+            render_targets: HashMap::new(),
+            render_target: None,
+            screen_viewport: (0, 0, width as _, height as _),
+            culling: CullingMode::None,
+            depth: DepthTesting::Default,
+            stencil: StencilTesting::None,
+            blend: BlendMode::None,
+            epoch: Instant::now(),
+            timestamps: HashMap::new(),
+        }
+    }
+
+    /// Get a handle to the framebuffer this backend renders into
+    pub fn framebuffer(&self) -> SoftwareFramebuffer {
+        self.framebuffer.clone()
+    }
+
+    /// The framebuffer that is currently being drawn into
+    fn current_framebuffer(&self) -> &SoftwareFramebuffer {
+        self.render_target
+            .and_then(|target| self.render_targets.get(&target.id))
+            .unwrap_or(&self.framebuffer)
+    }
+
+    /// Copy the contents of a render target into its attachment textures
+    fn resolve_render_target(&mut self, target: RenderTargetId) {
+        let framebuffer = if let Some(framebuffer) = self.render_targets.get(&target.id) {
+            framebuffer.inner.borrow()
+        } else {
+            return;
+        };
+
+        let options = framebuffer.options;
+        let (width, height) = (framebuffer.width, framebuffer.height);
+
+        // rows are stored bottom to top, the same as a texture's rows
+        if let (Some(id), Some(storage)) = (target.color(), options.color) {
+            let texels = framebuffer
+                .color
+                .iter()
+                .map(|&texel| store(storage, texel))
+                .collect();
+
+            let texture =
+                SoftwareTexture::from_texels(options.color_options(), width, height, texels);
+            self.textures.insert(id.0, texture);
+        }
+
+        if let Some(id) = target.depth() {
+            let texels = framebuffer
+                .depth
+                .iter()
+                .map(|&depth| [depth, 0.0, 0.0, 1.0])
+                .collect();
+
+            let texture =
+                SoftwareTexture::from_texels(options.depth_options(), width, height, texels);
+            self.textures.insert(id.0, texture);
+        }
+    }
+
+    /// Replace part of a vertex or index buffer
+    fn update_buffer(&mut self, buffer: Handle, offset: usize, data: &[u8]) -> Result<()> {
+        let buffer = self.buffers.get_mut(&buffer).ok_or(BufferError::Unloaded)?;
+        check_buffer_write(offset, data.len(), buffer.len())?;
+
+        buffer[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Replace all of a vertex or index buffer
+    fn orphan_buffer(&mut self, buffer: Handle, data: &[u8]) -> Result<()> {
+        if self.rings.contains_key(&buffer) {
+            return Err(BufferError::OrphanRingBuffer.into());
+        }
+
+        let buffer = self.buffers.get_mut(&buffer).ok_or(BufferError::Unloaded)?;
+
+        *buffer = data.to_vec();
+        Ok(())
+    }
+
+    /// Write to the next free space in a ring buffer, draws are made
+    /// immediately so there is nothing to wait for
+    fn write_ring_buffer(&mut self, buffer: Handle, data: &[u8]) -> Result<usize> {
+        let ring = self
+            .rings
+            .get_mut(&buffer)
+            .ok_or(BufferError::NotRingBuffer)?;
+        let offset = ring.allocate(data.len())?;
+
+        self.update_buffer(buffer, offset, data)?;
+        Ok(offset)
+    }
+
+    fn is_bound(&self, pipeline: PipelineId) -> bool {
+        self.pipelines
+            .get(&pipeline.0)
+            .map(|pipeline| pipeline.is_bound)
+            .unwrap_or(false)
+    }
+
+    /// Read count indices from an index buffer, starting at a byte offset.
+    /// Indices past the end of the buffer are ignored.
+    /// Read `count` indirect commands of `command_size` bytes from an
+    /// indirect buffer, starting at a byte offset
+    fn read_indirect(
+        &self,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+        command_size: usize,
+    ) -> Result<&[u8]> {
+        let data = self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+        check_indirect_read(offset, count, command_size, data.len())?;
+
+        Ok(&data[offset..offset + count * command_size])
+    }
+
+    fn read_indices(
+        &self,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+    ) -> Option<Vec<usize>> {
+        let buffer = if let Some(buffer) = self.buffers.get(&indices.0) {
+            buffer
+        } else {
+            debug_assert!(false, "Cannot draw using index buffer that does not exist");
+            return None;
+        };
+
+        let size = index_type.size();
+        let indices = (0..count)
+            .filter_map(|i| {
+                let start = index_offset + i * size;
+                let bytes = buffer.get(start..start + size)?;
+
+                Some(match index_type {
+                    IndexType::U8 => bytes[0] as usize,
+                    IndexType::U16 => u16::from_ne_bytes([bytes[0], bytes[1]]) as usize,
+                    IndexType::U32 => {
+                        u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+                    }
+                })
+            })
+            .collect();
+
+        Some(indices)
+    }
+
+    /// Run a draw call using the vertex indices provided, once for each
+    /// instance.  The base instance is added to the element index of per
+    /// instance vertex arrays, but not to the instance id.
+    fn draw_elements(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: &[usize],
+        instances: usize,
+        base_instance: usize,
+    ) {
+        let pipeline = if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+            pipeline
+        } else {
+            debug_assert!(false, "Cannot draw using pipeline that does not exist");
+            return;
+        };
+
+        let vert = if let Some(vert) = pipeline.pipeline.vertex_main() {
+            vert
+        } else {
+            return;
+        };
+
+        let uniforms = pipeline.uniform_values(&self.buffers);
+        let storage = RefCell::new(pipeline.storage_values(&self.buffers));
+
+        let mut state = DrawState {
+            program: &pipeline.pipeline,
+            varyings: &pipeline.varyings,
+            uniforms: &uniforms,
+            textures: &self.textures,
+            storage: &storage,
+            framebuffer: self.current_framebuffer().inner.borrow_mut(),
+            viewport: self.viewport,
+            culling: self.culling,
+            depth: self.depth,
+            stencil: self.stencil,
+            blend: self.blend,
+        };
+
+        for instance in 0..instances {
+            // run the vertex shader once per unique vertex
+            let mut cache = HashMap::new();
+            let mut verticies = vec![];
+            let mut elements = Vec::with_capacity(indices.len());
+            for &index in indices {
+                let element = *cache.entry(index).or_insert_with(|| {
+                    let mut invocation = Invocation::new(
+                        &pipeline.pipeline,
+                        vert,
+                        &uniforms,
+                        &self.textures,
+                        &storage,
+                        1,
+                    );
+                    invocation.instance = instance;
+                    invocation.base_instance = base_instance;
+
+                    for (i, input) in vert.inputs().iter().enumerate() {
+                        let value = match pipeline.vertex_arrays.get(i) {
+                            Some(array) => {
+                                array.fetch(&self.buffers, index, instance, base_instance, input.ty)
+                            }
+                            None => Value::zero(input.ty),
+                        };
+                        invocation.inputs.push(value);
+                    }
+
+                    invocation.run();
+
+                    verticies.push(ShadedVertex {
+                        position: invocation.position[0].vec4(),
+                        varyings: invocation.outputs,
+                    });
+
+                    verticies.len() - 1
+                });
+
+                elements.push(element);
+            }
+
+            let verticies: Vec<_> = elements.iter().map(|&idx| &verticies[idx]).collect();
+            state.assemble(mode, &verticies);
+        }
+
+        drop(state);
+        pipeline.write_storage(&mut self.buffers, storage.into_inner());
+    }
+}
+
+impl RendererBackend for SoftwareRenderer {
+    fn clear(&mut self, r: f32, g: f32, b: f32) {
+        let mut framebuffer = self.current_framebuffer().inner.borrow_mut();
+
+        for color in &mut framebuffer.color {
+            *color = [r, g, b, 1.0];
+        }
+
+        // clears are not limited by the depth or stencil write masks
+        for stencil in &mut framebuffer.stencil {
+            *stencil = 0;
+        }
+
+        for depth in &mut framebuffer.depth {
+            *depth = 1.0;
+        }
+    }
+
+    fn clear_stencil(&mut self, value: u8) {
+        let mut framebuffer = self.current_framebuffer().inner.borrow_mut();
+
+        for stencil in &mut framebuffer.stencil {
+            *stencil = value;
+        }
+    }
+
+    fn viewport(&mut self, width: u32, height: u32) {
+        // top left (0, 0) view port always
+        self.viewport = (0, 0, width as _, height as _);
+
+        if self.render_target.is_none() {
+            self.screen_viewport = self.viewport;
+        }
+    }
+
+    fn screen_viewport(&self) -> (u32, u32) {
+        let (_, _, width, height) = self.screen_viewport;
+        (width as _, height as _)
+    }
+
+    fn backface_culling(&mut self, enable: CullingMode) {
+        self.culling = enable;
+    }
+
+    fn depth_testing(&mut self, mode: DepthTesting) {
+        self.depth = mode;
+    }
+
+    fn stencil_testing(&mut self, mode: StencilTesting) {
+        self.stencil = mode;
+    }
+
+    fn blending(&mut self, mode: BlendMode) {
+        self.blend = mode;
+    }
+
+    /// There are no limits on texture sizes or bindings, anisotropic
+    /// filtering is not implemented and only BC1 to BC5 blocks can be decoded
+    fn capabilities(&self) -> RendererCapabilities {
+        use TextureStorageType::*;
+
+        RendererCapabilities {
+            version: (4, 5),
+            vendor: "engine".to_string(),
+            renderer: "Software renderer".to_string(),
+            max_texture_size: u32::MAX,
+            max_anisotropy: 1,
+            texture_units: u32::MAX,
+            // the base instance is read by the shader interpreter like any
+            // other builtin
+            extensions: vec!["GL_ARB_shader_draw_parameters".to_string()],
+            compressed_formats: vec![
+                BC1RGB,
+                BC1SRGB,
+                BC1RGBA,
+                BC1SRGBA,
+                BC2RGBA,
+                BC2SRGBA,
+                BC3RGBA,
+                BC3SRGBA,
+                BC4R,
+                BC4SignedR,
+                BC5RG,
+                BC5SignedRG,
+            ],
+        }
+    }
+
+    fn invalidate_state_cache(&mut self) {
+        // no state is cached, all of it is owned by this backend
+    }
+
+    fn skipped_state_changes(&self) -> u64 {
+        // state is read directly when drawing, so there is nothing to skip
+        0
+    }
+
+    fn timestamp(&mut self, query: u32) {
+        let time = self.epoch.elapsed().as_nanos() as u64;
+        self.timestamps.insert(query, time);
+    }
+
+    fn timestamp_result(&mut self, query: u32) -> Option<u64> {
+        self.timestamps.get(&query).copied()
+    }
+
+    fn load_texture(&mut self, id: TextureId, texture: Texture) {
+        self.textures.insert(id.0, SoftwareTexture::new(&texture));
+    }
+
+    fn unload_texture(&mut self, texture: TextureId) {
+        let removed = self.textures.remove(&texture.0);
+        debug_assert!(removed.is_some());
+    }
+
+    fn update_texture(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        let texture = self
+            .textures
+            .get_mut(&texture.0)
+            .ok_or(SoftwareError::TextureUnloaded)?;
+
+        region.check(&texture.options, texture.layers[0].len() as u32, data)?;
+        texture.update(region, data);
+
+        Ok(())
+    }
+
+    fn update_texture_stream(
+        &mut self,
+        texture: TextureId,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<()> {
+        self.update_texture(texture, region, data)
+    }
+
+    fn load_vertex_buffer(&mut self, id: VertexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.to_vec());
+    }
+
+    fn load_vertex_buffer_stream(&mut self, id: VertexBufferId, data: &[u8]) {
+        self.load_vertex_buffer(id, data)
+    }
+
+    fn load_vertex_ring_buffer(&mut self, id: VertexBufferId, size: usize) {
+        self.buffers.insert(id.0, vec![0; size]);
+        self.rings.insert(id.0, RingBuffer::new(size));
+    }
+
+    fn unload_vertex_buffer(&mut self, buffer: VertexBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+        self.rings.remove(&buffer.0);
+    }
+
+    fn update_vertex_buffer(
+        &mut self,
+        buffer: VertexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        if self.rings.contains_key(&buffer.0) {
+            return Err(BufferError::UpdateRingBuffer.into());
+        }
+
+        self.update_buffer(buffer.0, offset, data)
+    }
+
+    fn orphan_vertex_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<()> {
+        self.orphan_buffer(buffer.0, data)
+    }
+
+    fn write_vertex_ring_buffer(&mut self, buffer: VertexBufferId, data: &[u8]) -> Result<usize> {
+        self.write_ring_buffer(buffer.0, data)
+    }
+
+    fn load_index_buffer(&mut self, id: IndexBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.to_vec());
+    }
+
+    fn load_index_buffer_stream(&mut self, id: IndexBufferId, data: &[u8]) {
+        self.load_index_buffer(id, data)
+    }
+
+    fn load_index_ring_buffer(&mut self, id: IndexBufferId, size: usize) {
+        self.buffers.insert(id.0, vec![0; size]);
+        self.rings.insert(id.0, RingBuffer::new(size));
+    }
+
+    fn unload_index_buffer(&mut self, buffer: IndexBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+        self.rings.remove(&buffer.0);
+    }
+
+    fn update_index_buffer(
+        &mut self,
+        buffer: IndexBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        if self.rings.contains_key(&buffer.0) {
+            return Err(BufferError::UpdateRingBuffer.into());
+        }
+
+        self.update_buffer(buffer.0, offset, data)
+    }
+
+    fn orphan_index_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<()> {
+        self.orphan_buffer(buffer.0, data)
+    }
+
+    fn write_index_ring_buffer(&mut self, buffer: IndexBufferId, data: &[u8]) -> Result<usize> {
+        self.write_ring_buffer(buffer.0, data)
+    }
+
+    fn load_uniform_buffer(&mut self, id: UniformBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.to_vec());
+    }
+
+    fn unload_uniform_buffer(&mut self, buffer: UniformBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+    }
+
+    fn load_storage_buffer(&mut self, id: StorageBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.to_vec());
+    }
+
+    fn unload_storage_buffer(&mut self, buffer: StorageBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+    }
+
+    fn update_storage_buffer(
+        &mut self,
+        buffer: StorageBufferId,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.update_buffer(buffer.0, offset, data)
+    }
+
+    fn read_storage_buffer(&mut self, buffer: StorageBufferId) -> Result<Vec<u8>> {
+        let data = self.buffers.get(&buffer.0).ok_or(BufferError::Unloaded)?;
+
+        Ok(data.clone())
+    }
+
+    fn load_indirect_buffer(&mut self, id: IndirectBufferId, data: &[u8]) {
+        self.buffers.insert(id.0, data.to_vec());
+    }
+
+    fn unload_indirect_buffer(&mut self, buffer: IndirectBufferId) {
+        let removed = self.buffers.remove(&buffer.0);
+        debug_assert!(removed.is_some());
+    }
+
+    fn load_pipeline(&mut self, id: PipelineId, pipeline: PipelineDescriptor) -> Result<()> {
+        self.pipelines
+            .insert(id.0, SoftwarePipeline::new(pipeline)?);
+
+        Ok(())
+    }
+
+    fn unload_pipeline(&mut self, pipeline: PipelineId) {
+        let removed = self.pipelines.remove(&pipeline.0);
+        debug_assert!(removed.is_some());
+    }
+
+    fn load_render_target(
+        &mut self,
+        target: RenderTargetId,
+        options: RenderTargetOptions,
+    ) -> Result<()> {
+        if options.color.is_none() && !options.depth {
+            return Err(SoftwareError::NoAttachments.into());
+        }
+        if options.stencil && !options.depth {
+            return Err(SoftwareError::StencilWithoutDepth.into());
+        }
+        if let Some(storage) = options.color.filter(TextureStorageType::is_compressed) {
+            return Err(SoftwareError::CompressedAttachment { storage }.into());
+        }
+
+        let framebuffer =
+            SoftwareFramebuffer::new(options.width as _, options.height as _, options);
+        self.render_targets.insert(target.id, framebuffer);
+
+        // create the attachment textures so they can be bound straight away
+        self.resolve_render_target(target);
+
+        Ok(())
+    }
+
+    fn unload_render_target(&mut self, target: RenderTargetId) {
+        if self.render_target == Some(target) {
+            self.render_target = None;
+            self.viewport = self.screen_viewport;
+        }
+
+        let removed = self.render_targets.remove(&target.id);
+        debug_assert!(removed.is_some());
+
+        for texture in target.color().iter().chain(&target.depth()) {
+            self.textures.remove(&texture.0);
+        }
+    }
+
+    fn bind_render_target(&mut self, target: Option<RenderTargetId>) {
+        if let Some(previous) = self.render_target {
+            self.resolve_render_target(previous);
+        }
+
+        self.viewport = match target {
+            Some(target) => {
+                if let Some(framebuffer) = self.render_targets.get(&target.id) {
+                    let framebuffer = framebuffer.inner.borrow();
+                    (0, 0, framebuffer.width, framebuffer.height)
+                } else {
+                    debug_assert!(false, "Cannot bind non-existant render target");
+                    return;
+                }
+            }
+            None => self.screen_viewport,
+        };
+
+        self.render_target = target;
+    }
+
+    fn read_pixels(&mut self, region: PixelRegion, format: PixelFormat) -> Result<Vec<u8>> {
+        if matches!(self.render_target, Some(target) if target.color().is_none()) {
+            return Err(ReadPixelsError::NoColorAttachment.into());
+        }
+
+        let framebuffer = self.current_framebuffer().inner.borrow();
+        check_read_region(region, framebuffer.width as _, framebuffer.height as _)?;
+
+        let mut data =
+            Vec::with_capacity(region.width as usize * region.height as usize * format.size());
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let color = framebuffer.color[y as usize * framebuffer.width + x as usize];
+
+                match format {
+                    PixelFormat::Rgba8 => data.extend(color.iter().map(|&c| to_unorm8(c))),
+                    PixelFormat::Rgba32F => {
+                        data.extend(color.iter().flat_map(|c| c.to_ne_bytes().to_vec()))
+                    }
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn bind_pipeline(&mut self, pipeline: PipelineId) {
+        let state = if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
+            debug_assert!(!pipeline.is_bound);
+            pipeline.is_bound = true;
+            pipeline.state
+        } else {
+            debug_assert!(false, "Cannot bind non-existant pipeline");
+            None
+        };
+
+        if let Some(state) = state {
+            self.culling = state.culling;
+            self.depth = state.depth;
+            self.blend = state.blend;
+            self.stencil = state.stencil;
+        }
+    }
+
+    fn unbind_pipeline(&mut self, pipeline: PipelineId) {
+        if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+            pipeline.is_bound = false;
+        } else {
+            debug_assert!(false, "Cannot unbind non-existant pipeline");
+        }
+    }
+
+    fn pipeline_bind_matrix(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        matrix: glm::Mat4,
+    ) -> Result<()> {
+        self.pipeline_bind_uniform(pipeline, name, &UniformValue::Mat4(matrix))
+    }
+
+    fn pipeline_bind_texture(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        texture: TextureId,
+    ) -> Result<()> {
+        if !self.is_bound(pipeline) {
+            return Err(SoftwareError::PipelineNotBound.into());
+        }
+
+        let kind = self
+            .textures
+            .get(&texture.0)
+            .ok_or(SoftwareError::TextureUnloaded)?
+            .options
+            .kind;
+
+        let pipeline = self.pipelines.get_mut(&pipeline.0).unwrap();
+        check_sampler(&pipeline.pipeline, name, kind)?;
+        pipeline
+            .uniforms
+            .insert(name.to_string(), vec![Value::Sampler(Some(texture.0))]);
+
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        value: &UniformValue,
+    ) -> Result<()> {
+        if !self.is_bound(pipeline) {
+            return Err(SoftwareError::PipelineNotBound.into());
+        }
+
+        let pipeline = self.pipelines.get_mut(&pipeline.0).unwrap();
+        let idx = check_uniform(&pipeline.pipeline, name, value)?;
+
+        // binding fewer array elements than the uniform has leaves the rest
+        // unchanged, the same as OpenGl
+        let len = match pipeline.pipeline.uniforms()[idx].ty {
+            Type::Array(_, len) => len,
+            _ => 1,
+        };
+        let values = pipeline
+            .uniforms
+            .entry(name.to_string())
+            .or_insert_with(|| vec![Value::zero(value.element_type()); len]);
+
+        for (stored, element) in values.iter_mut().zip(value.elements()) {
+            *stored = Value::from_slice(element.element_type(), &element.floats());
+        }
+
+        Ok(())
+    }
+
+    fn pipeline_bind_uniform_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: UniformBufferId,
+    ) -> Result<()> {
+        if !self.is_bound(pipeline) {
+            return Err(SoftwareError::PipelineNotBound.into());
+        }
+
+        let size = self
+            .buffers
+            .get(&buffer.0)
+            .ok_or(SoftwareError::UniformBufferUnloaded)?
+            .len();
+
+        let pipeline = self.pipelines.get_mut(&pipeline.0).unwrap();
+        let block = check_uniform_block(&pipeline.pipeline, name, size)?;
+        pipeline.uniform_buffers.insert(block, buffer.0);
+
+        Ok(())
+    }
+
+    fn pipeline_bind_storage_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        name: &str,
+        buffer: StorageBufferId,
+    ) -> Result<()> {
+        if !self.is_bound(pipeline) {
+            return Err(SoftwareError::PipelineNotBound.into());
+        }
+
+        if !self.buffers.contains_key(&buffer.0) {
+            return Err(SoftwareError::StorageBufferUnloaded.into());
+        }
+
+        let pipeline = self.pipelines.get_mut(&pipeline.0).unwrap();
+        let index = check_storage_buffer(&pipeline.pipeline, name)?;
+        pipeline.storage_buffers.insert(index, buffer.0);
+
+        Ok(())
+    }
+
+    fn pipeline_bind_vertex_arrays(
+        &mut self,
+        pipeline: PipelineId,
+        buffers: &[VertexBufferId],
+        offsets: &[usize],
+        strides: &[i32],
+        divisors: &[u32],
+    ) {
+        debug_assert!(self.is_bound(pipeline));
+        debug_assert!(buffers.iter().all(|b| self.buffers.contains_key(&b.0)));
+
+        // all slices must be the same length
+        debug_assert!(buffers.len() == offsets.len());
+        debug_assert!(buffers.len() == strides.len());
+        debug_assert!(divisors.is_empty() || buffers.len() == divisors.len());
+
+        if let Some(pipeline) = self.pipelines.get_mut(&pipeline.0) {
+            let formats = &pipeline.vertex_formats;
+            pipeline.vertex_arrays = buffers
+                .iter()
+                .zip(offsets)
+                .zip(strides)
+                .enumerate()
+                .map(|(i, ((buffer, &offset), &stride))| VertexArray {
+                    buffer: buffer.0,
+                    offset,
+                    stride: stride.max(0) as _,
+                    divisor: divisors.get(i).copied().unwrap_or(0) as _,
+                    format: formats
+                        .get(i)
+                        .copied()
+                        .unwrap_or_else(|| VertexFormat::new(VertexComponentType::F32, false)),
+                })
+                .collect();
+        }
+    }
+
+    fn draw(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        start: u64,
+        count: u64,
+        instances: u64,
+    ) {
+        let indices: Vec<_> = (start as usize..(start + count) as usize).collect();
+
+        self.draw_elements(pipeline, mode, &indices, instances as _, 0);
+    }
+
+    fn draw_indicies(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        index_offset: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        if let Some(indices) = self.read_indices(indices, index_type, index_offset, count) {
+            self.draw_elements(pipeline, mode, &indices, instances, 0);
+        }
+    }
+
+    fn draw_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        draws: &[DrawArraysIndirectCommand],
+    ) {
+        for draw in draws {
+            let start = draw.first as usize;
+            let indices: Vec<_> = (start..start + draw.count as usize).collect();
+
+            self.draw_elements(
+                pipeline,
+                mode,
+                &indices,
+                draw.instances as _,
+                draw.base_instance as _,
+            );
+        }
+    }
+
+    fn multi_draw_elements_indirect(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        draws: &[DrawElementsIndirectCommand],
+    ) {
+        for draw in draws {
+            let offset = draw.first_index as usize * index_type.size();
+            let elements = self.read_indices(indices, index_type, offset, draw.count as _);
+
+            if let Some(elements) = elements {
+                let elements: Vec<_> = elements
+                    .into_iter()
+                    .map(|index| (index as i64 + draw.base_vertex as i64) as usize)
+                    .collect();
+
+                self.draw_elements(
+                    pipeline,
+                    mode,
+                    &elements,
+                    draw.instances as _,
+                    draw.base_instance as _,
+                );
+            }
+        }
+    }
+
+    /// Invocations are run one at a time in order, x varying fastest, so
+    /// invocations that write to the same element always give the same result
+    fn draw_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        let draws: Vec<_> = self
+            .read_indirect(buffer, offset, count, DrawArraysIndirectCommand::SIZE)?
+            .chunks_exact(DrawArraysIndirectCommand::SIZE)
+            .map(|bytes| DrawArraysIndirectCommand::from_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        self.draw_indirect(pipeline, mode, &draws);
+        Ok(())
+    }
+
+    fn multi_draw_elements_indirect_buffer(
+        &mut self,
+        pipeline: PipelineId,
+        mode: DrawingMode,
+        indices: IndexBufferId,
+        index_type: IndexType,
+        buffer: IndirectBufferId,
+        offset: usize,
+        count: usize,
+    ) -> Result<()> {
+        let draws: Vec<_> = self
+            .read_indirect(buffer, offset, count, DrawElementsIndirectCommand::SIZE)?
+            .chunks_exact(DrawElementsIndirectCommand::SIZE)
+            .map(|bytes| DrawElementsIndirectCommand::from_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        self.multi_draw_elements_indirect(pipeline, mode, indices, index_type, &draws);
+        Ok(())
+    }
+
+    fn dispatch(&mut self, pipeline: PipelineId, x: u32, y: u32, z: u32) {
+        let pipeline = if let Some(pipeline) = self.pipelines.get(&pipeline.0) {
+            debug_assert!(pipeline.is_bound);
+            pipeline
+        } else {
+            debug_assert!(false, "Cannot dispatch using pipeline that does not exist");
+            return;
+        };
+
+        let (compute, local_size) = match (
+            pipeline.pipeline.compute_main(),
+            pipeline.pipeline.local_size(),
+        ) {
+            (Some(compute), Some(local_size)) => (compute, local_size),
+            _ => return,
+        };
+
+        let uniforms = pipeline.uniform_values(&self.buffers);
+        let storage = RefCell::new(pipeline.storage_values(&self.buffers));
+        let size = [
+            (x * local_size[0]) as usize,
+            (y * local_size[1]) as usize,
+            (z * local_size[2]) as usize,
+        ];
+
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let mut invocation = Invocation::new(
+                        &pipeline.pipeline,
+                        compute,
+                        &uniforms,
+                        &self.textures,
+                        &storage,
+                        1,
+                    );
+                    invocation.global_id = [x, y, z];
+                    invocation.run();
+                }
+            }
+        }
+
+        pipeline.write_storage(&mut self.buffers, storage.into_inner());
+    }
+}
+
+/// The color and depth buffers rendered into
+#[derive(Debug)]
+struct Framebuffer {
+    width: usize,
+    height: usize,
+
+    /// Rows are stored bottom to top, the same as OpenGl's window coordinates
+    color: Vec<[f32; 4]>,
+    depth: Vec<f32>,
+
+    /// Empty if the framebuffer has no stencil buffer
+    stencil: Vec<u8>,
+
+    /// The settings used to create the framebuffer, if it is a render target
+    options: RenderTargetOptions,
+}
+
+/// Shared handle to the output of a software backend.  The backend is moved
+/// into the renderer, so this is how the rendered image is read back.
+/// Coordinates used by the handle have (0, 0) as the top left pixel, the same
+/// as the `image` crate.
+#[derive(Debug, Clone)]
+pub struct SoftwareFramebuffer {
+    inner: Rc<RefCell<Framebuffer>>,
+}
+
+impl SoftwareFramebuffer {
+    fn new(width: usize, height: usize, options: RenderTargetOptions) -> Self {
+        SoftwareFramebuffer {
+            inner: Rc::new(RefCell::new(Framebuffer {
+                width,
+                height,
+                color: vec![[0.0, 0.0, 0.0, 1.0]; width * height],
+                depth: vec![1.0; width * height],
+                stencil: if options.stencil {
+                    vec![0; width * height]
+                } else {
+                    vec![]
+                },
+                options,
+            })),
+        }
+    }
+
+    /// The width of the framebuffer in pixels
+    pub fn width(&self) -> u32 {
+        self.inner.borrow().width as _
+    }
+
+    /// The height of the framebuffer in pixels
+    pub fn height(&self) -> u32 {
+        self.inner.borrow().height as _
+    }
+
+    /// Get the color of a single pixel
+    pub fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+        let framebuffer = self.inner.borrow();
+        let row = framebuffer.height - 1 - y as usize;
+
+        framebuffer.color[row * framebuffer.width + x as usize]
+    }
+
+    /// Get the value in the depth buffer for a single pixel
+    pub fn depth(&self, x: u32, y: u32) -> f32 {
+        let framebuffer = self.inner.borrow();
+        let row = framebuffer.height - 1 - y as usize;
+
+        framebuffer.depth[row * framebuffer.width + x as usize]
+    }
+
+    /// Get the value in the stencil buffer for a single pixel, 0 if there is
+    /// no stencil buffer
+    pub fn stencil(&self, x: u32, y: u32) -> u8 {
+        let framebuffer = self.inner.borrow();
+        let row = framebuffer.height - 1 - y as usize;
+
+        framebuffer
+            .stencil
+            .get(row * framebuffer.width + x as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Convert the color buffer into an 8 bit per channel image
+    pub fn image(&self) -> RgbaImage {
+        let framebuffer = self.inner.borrow();
+
+        RgbaImage::from_fn(framebuffer.width as _, framebuffer.height as _, |x, y| {
+            let row = framebuffer.height - 1 - y as usize;
+            let color = framebuffer.color[row * framebuffer.width + x as usize];
+
+            Rgba([
+                to_unorm8(color[0]),
+                to_unorm8(color[1]),
+                to_unorm8(color[2]),
+                to_unorm8(color[3]),
+            ])
+        })
+    }
+
+    /// Write the color buffer to a png file
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.image().save_with_format(path, ImageFormat::Png)?;
+
+        Ok(())
+    }
+}
+
+/// A shader program along with the state bound to it
+struct SoftwarePipeline {
+    pipeline: Program,
+
+    /// For each fragment shader input, the index of the vertex shader output
+    /// with the same name, if there is one
+    varyings: Vec<Option<usize>>,
+
+    /// The values bound to uniforms, by name, with one value per array element
+    uniforms: HashMap<String, Vec<Value>>,
+
+    /// The uniform buffers bound to each uniform block, by block index
+    uniform_buffers: HashMap<usize, Handle>,
+
+    /// The buffers bound to each storage buffer, by storage buffer index
+    storage_buffers: HashMap<usize, Handle>,
+
+    /// The vertex arrays bound, index i is used for vertex shader input i
+    vertex_arrays: Vec<VertexArray>,
+
+    /// How each vertex shader input is read from its vertex array
+    vertex_formats: Vec<VertexFormat>,
+
+    /// The state set when the pipeline is bound
+    state: Option<PipelineState>,
+
+    is_bound: bool,
+}
+
+impl SoftwarePipeline {
+    fn new(descriptor: PipelineDescriptor) -> Result<Self> {
+        let mut pipeline = descriptor.program;
+
+        // generate the glsl so that the same errors are reported as would be
+        // when using the OpenGl backend
+        pipeline.to_glsl()?;
+        let vertex_formats = vertex_formats(&pipeline, &descriptor.vertex_formats)?;
+
+        if let Some(vert) = pipeline.vertex_main() {
+            if vert
+                .inputs()
+                .iter()
+                .any(|input| input.ty.is_sampler() || matches!(input.ty, Type::Array(..)))
+            {
+                return Err(SoftwareError::OpaqueVerticies.into());
+            }
+        }
+
+        let varyings = match (pipeline.vertex_main(), pipeline.frag_main()) {
+            (Some(vert), Some(frag)) => frag
+                .inputs()
+                .iter()
+                .map(|input| {
+                    vert.outputs()
+                        .iter()
+                        .position(|output| output.name == input.name)
+                })
+                .collect(),
+            (_, Some(frag)) => vec![None; frag.inputs().len()],
+            _ => vec![],
+        };
+
+        Ok(SoftwarePipeline {
+            pipeline,
+            varyings,
+            uniforms: HashMap::new(),
+            uniform_buffers: HashMap::new(),
+            storage_buffers: HashMap::new(),
+            vertex_arrays: vec![],
+            vertex_formats,
+            state: descriptor.state,
+            is_bound: false,
+        })
+    }
+
+    /// Get the values for every uniform in the program, with one value per
+    /// array element.  Uniform block members are read from the bound uniform
+    /// buffers, any uniforms that have not been bound are zero.
+    fn uniform_values(&self, buffers: &HashMap<Handle, Vec<u8>>) -> Vec<Vec<Value>> {
+        let mut values: Vec<_> = self
+            .pipeline
+            .uniforms()
+            .iter()
+            .map(|uniform| {
+                let (ty, len) = match uniform.ty {
+                    Type::Array(ty, len) => (*ty, len),
+                    ty => (ty, 1),
+                };
+
+                match self.uniforms.get(&uniform.name) {
+                    Some(value) => value.clone(),
+                    None => vec![Value::zero(ty); len],
+                }
+            })
+            .collect();
+
+        for (&block, buffer) in &self.uniform_buffers {
+            let members = &self.pipeline.uniform_blocks()[block].members;
+            let data = buffers.get(buffer).map(|b| &b[..]).unwrap_or(&[]);
+
+            let types = members.iter().map(|&m| self.pipeline.uniforms()[m].ty);
+            let (offsets, _) = std140_offsets(types);
+
+            for (&member, offset) in members.iter().zip(offsets) {
+                let ty = self.pipeline.uniforms()[member].ty;
+                values[member] = read_std140(ty, data.get(offset..).unwrap_or(&[]));
+            }
+        }
+
+        values
+    }
+
+    /// Copy the data of every storage buffer in the program, by storage
+    /// buffer index, storage buffers that have not been bound are empty
+    fn storage_values(&self, buffers: &HashMap<Handle, Vec<u8>>) -> Vec<Vec<u8>> {
+        (0..self.pipeline.storage_buffers().len())
+            .map(|index| {
+                self.storage_buffers
+                    .get(&index)
+                    .and_then(|buffer| buffers.get(buffer))
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Write the storage buffer data back to the buffers bound to them
+    fn write_storage(&self, buffers: &mut HashMap<Handle, Vec<u8>>, storage: Vec<Vec<u8>>) {
+        for (index, data) in storage.into_iter().enumerate() {
+            if let Some(buffer) = self
+                .storage_buffers
+                .get(&index)
+                .and_then(|buffer| buffers.get_mut(buffer))
+            {
+                *buffer = data;
+            }
+        }
+    }
+}
+
+/// Read a value from a uniform buffer laid out with the std140 rules, with one
+/// value per array element
+fn read_std140(ty: Type, data: &[u8]) -> Vec<Value> {
+    let read = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|b| [b[0], b[1], b[2], b[3]])
+            .unwrap_or([0; 4])
+    };
+
+    match ty {
+        Type::Array(element, len) => {
+            // every element is padded to the size of a vec4
+            let (_, stride) = std140_offsets(std::iter::once(Type::Array(element, 1)));
+            (0..len)
+                .map(|i| read_std140(*element, data.get(i * stride..).unwrap_or(&[]))[0])
+                .collect()
+        }
+        Type::Integer | Type::IntVector(_) => {
+            let data: Vec<_> = (0..component_count(ty))
+                .map(|i| i32::from_ne_bytes(read(i * 4)) as f32)
+                .collect();
+            vec![Value::from_slice(ty, &data)]
+        }
+        Type::UnsignedInteger | Type::UIntVector(_) => {
+            let data: Vec<_> = (0..component_count(ty))
+                .map(|i| u32::from_ne_bytes(read(i * 4)) as f32)
+                .collect();
+            vec![Value::from_slice(ty, &data)]
+        }
+        Type::Matrix(rows, cols) => {
+            // each column is padded to the size of a vec4
+            let data: Vec<_> = (0..cols)
+                .flat_map(|col| (0..rows).map(move |row| col * 16 + row * 4))
+                .map(|offset| f32::from_ne_bytes(read(offset)))
+                .collect();
+            vec![Value::from_slice(ty, &data)]
+        }
+        ty => {
+            let data: Vec<_> = (0..component_count(ty))
+                .map(|i| f32::from_ne_bytes(read(i * 4)))
+                .collect();
+            vec![Value::from_slice(ty, &data)]
+        }
+    }
+}
+
+/// Read a storage buffer element, elements are tightly packed so each
+/// component follows the last
+fn read_storage(ty: Type, bytes: &[u8]) -> Value {
+    let data: Vec<_> = bytes
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if ty.is_unsigned() {
+                u32::from_ne_bytes(b) as f32
+            } else if ty.is_integer() {
+                i32::from_ne_bytes(b) as f32
+            } else {
+                f32::from_ne_bytes(b)
+            }
+        })
+        .collect();
+
+    Value::from_slice(ty, &data)
+}
+
+/// A buffer bound as a vertex shader input, offset and stride are in bytes
+struct VertexArray {
+    buffer: Handle,
+    offset: usize,
+    stride: usize,
+
+    /// The number of instances between each element, 0 if there is one
+    /// element per vertex
+    divisor: usize,
+
+    format: VertexFormat,
+}
+
+impl VertexArray {
+    /// Read a single vertex's attribute from the buffer, converting each
+    /// component from the array's format, the same as the OpenGl backend.  A
+    /// stride of zero is treated as the attributes being tightly packed.
+    /// Reading out of the buffer's bounds gives zero instead of failing.
+    fn fetch(
+        &self,
+        buffers: &HashMap<Handle, Vec<u8>>,
+        index: usize,
+        instance: usize,
+        base_instance: usize,
+        ty: Type,
+    ) -> Value {
+        let count = component_count(ty);
+        let size = self.format.component.size();
+        let stride = if self.stride == 0 {
+            count * size
+        } else {
+            self.stride
+        };
+
+        let index = match self.divisor {
+            0 => index,
+            divisor => base_instance + instance / divisor,
+        };
+
+        let buffer = buffers.get(&self.buffer).map(|b| &b[..]).unwrap_or(&[]);
+        let start = self.offset + index * stride;
+
+        let mut data = [0.0; 16];
+        for (i, component) in data.iter_mut().enumerate().take(count) {
+            let offset = start + i * size;
+            if let Some(bytes) = buffer.get(offset..offset + size) {
+                *component = read_component(self.format, bytes);
+            }
+        }
+
+        Value::Numeric { ty, data }
+    }
+}
+
+/// Convert a single vertex attribute component to a float, integers are
+/// normalized to 0..1, or -1..1 if signed, if the format is normalized
+fn read_component(format: VertexFormat, bytes: &[u8]) -> f32 {
+    let (value, max) = match format.component {
+        VertexComponentType::I8 => (bytes[0] as i8 as f64, i8::MAX as f64),
+        VertexComponentType::U8 => (bytes[0] as f64, u8::MAX as f64),
+        VertexComponentType::I16 => (
+            i16::from_ne_bytes([bytes[0], bytes[1]]) as f64,
+            i16::MAX as f64,
+        ),
+        VertexComponentType::U16 => (
+            u16::from_ne_bytes([bytes[0], bytes[1]]) as f64,
+            u16::MAX as f64,
+        ),
+        VertexComponentType::I32 => (
+            i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            i32::MAX as f64,
+        ),
+        VertexComponentType::U32 => (
+            u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            u32::MAX as f64,
+        ),
+        VertexComponentType::F32 => {
+            return f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+    };
+
+    if format.normalized {
+        (value / max).max(-1.0) as f32
+    } else {
+        value as f32
+    }
+}
+
+/// A value computed while interpreting a shader, numeric values are stored
+/// column major, the same as glm.  Integers are stored as whole floats.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Numeric {
+        ty: Type,
+        data: [f32; 16],
+    },
+    Sampler(Option<Handle>),
+
+    /// An array uniform, by its index in the program's uniforms
+    Array(usize),
+
+    /// A storage buffer, by its index in the program's storage buffers
+    Storage(usize),
+}
+
+impl Value {
+    fn zero(ty: Type) -> Self {
+        match ty {
+            ty if ty.is_sampler() => Value::Sampler(None),
+            ty => Value::Numeric {
+                ty,
+                data: [0.0; 16],
+            },
+        }
+    }
+
+    fn from_slice(ty: Type, values: &[f32]) -> Self {
+        let mut data = [0.0; 16];
+        data[..values.len()].copy_from_slice(values);
+
+        Value::Numeric { ty, data }
+    }
+
+    fn ty(&self) -> Type {
+        match self {
+            Value::Numeric { ty, .. } => *ty,
+            Value::Sampler(_) => Type::Sampler2D,
+            Value::Array(_) | Value::Storage(_) => Type::Unknown,
+        }
+    }
+
+    fn data(&self) -> &[f32] {
+        match self {
+            Value::Numeric { ty, data } => &data[..component_count(*ty)],
+            Value::Sampler(_) | Value::Array(_) | Value::Storage(_) => &[],
+        }
+    }
+
+    /// Get the value as a vec4, padding missing components with (0, 0, 0, 1)
+    fn vec4(&self) -> [f32; 4] {
+        let data = self.data();
+        let mut result = [0.0, 0.0, 0.0, 1.0];
+        for (result, value) in result.iter_mut().zip(data) {
+            *result = *value;
+        }
+
+        result
+    }
+}
+
+/// The number of f32 values required to store a value of a given type
+fn component_count(ty: Type) -> usize {
+    match ty {
+        Type::Vector(n) | Type::IntVector(n) | Type::UIntVector(n) => n,
+        Type::Matrix(rows, cols) => rows * cols,
+        Type::Floating | Type::Integer | Type::UnsignedInteger => 1,
+        Type::Sampler2D
+        | Type::SamplerCube
+        | Type::Sampler2DArray
+        | Type::Sampler3D
+        | Type::Array(..)
+        | Type::Buffer(..)
+        | Type::Unknown => 0,
+    }
+}
+
+/// returns (rows, cols), vectors are treated as row vectors, the same as the
+/// shader type checker
+fn shape(ty: Type) -> (usize, usize) {
+    match ty {
+        Type::Vector(n) | Type::IntVector(n) | Type::UIntVector(n) => (1, n),
+        Type::Matrix(rows, cols) => (rows, cols),
+        _ => (1, 1),
+    }
+}
+
+/// apply an operation to each component of two values, if either is a scalar
+/// then it is used for every component
+fn componentwise(ty: Type, a: &Value, b: &Value, op: impl Fn(f32, f32) -> f32) -> Value {
+    let (a, b) = (a.data(), b.data());
+    let get = |values: &[f32], i: usize| {
+        if values.len() == 1 {
+            values[0]
+        } else {
+            values.get(i).copied().unwrap_or(0.0)
+        }
+    };
+
+    let mut data = [0.0; 16];
+    for (i, value) in data.iter_mut().enumerate().take(component_count(ty)) {
+        *value = op(get(a, i), get(b, i));
+    }
+
+    Value::Numeric { ty, data }
+}
+
+/// multiply two values, with the same semantics as glsl's `*` operator
+fn multiply(ty: Type, a: &Value, b: &Value) -> Value {
+    let (a_shape, b_shape) = (shape(a.ty()), shape(b.ty()));
+
+    let is_scalar = a_shape == (1, 1) || b_shape == (1, 1);
+    let is_vectors = a_shape.0 == 1 && b_shape.0 == 1;
+    if is_scalar || is_vectors {
+        return componentwise(ty, a, b, |a, b| a * b);
+    }
+
+    // vectors on the right hand side of a matrix multiplication are column vectors
+    let b_shape = if let (1, n) = b_shape {
+        (n, 1)
+    } else {
+        b_shape
+    };
+
+    let (a, b) = (a.data(), b.data());
+    let (rows, cols, inner) = (a_shape.0, b_shape.1, a_shape.1);
+
+    let mut data = [0.0; 16];
+    for col in 0..cols {
+        for row in 0..rows {
+            data[col * rows + row] = (0..inner)
+                .map(|k| a[k * rows + row] * b[col * inner + k])
+                .sum();
+        }
+    }
+
+    Value::Numeric { ty, data }
+}
+
+/// A vertex after the vertex shader has been run on it
+#[derive(Clone)]
+struct ShadedVertex {
+    /// clip space position
+    position: [f32; 4],
+
+    /// The values of all the vertex shader's outputs
+    varyings: Vec<Value>,
+}
+
+impl ShadedVertex {
+    /// linearly interpolate between two verticies in clip space
+    fn lerp(a: &ShadedVertex, b: &ShadedVertex, t: f32) -> ShadedVertex {
+        let mut position = [0.0; 4];
+        for (i, position) in position.iter_mut().enumerate() {
+            *position = a.position[i] + (b.position[i] - a.position[i]) * t;
+        }
+
+        let varyings = a
+            .varyings
+            .iter()
+            .zip(&b.varyings)
+            .map(|(a, b)| componentwise(a.ty(), a, b, |a, b| a + (b - a) * t))
+            .collect();
+
+        ShadedVertex { position, varyings }
+    }
+}
+
+/// A vertex transformed into window coordinates
+#[derive(Debug, Clone, Copy)]
+struct WindowVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+
+    /// 1 / clip space w, used for perspective correct interpolation
+    inv_w: f32,
+}
+
+/// The state used while running a single draw call
+struct DrawState<'a> {
+    program: &'a Program,
+    varyings: &'a [Option<usize>],
+    uniforms: &'a [Vec<Value>],
+    textures: &'a HashMap<Handle, SoftwareTexture>,
+    storage: &'a RefCell<Vec<Vec<u8>>>,
+    framebuffer: RefMut<'a, Framebuffer>,
+    viewport: (usize, usize, usize, usize),
+    culling: CullingMode,
+    depth: DepthTesting,
+    stencil: StencilTesting,
+    blend: BlendMode,
+}
+
+impl<'a> DrawState<'a> {
+    /// Split the verticies into primitives depending on the drawing mode
+    fn assemble(&mut self, mode: DrawingMode, verticies: &[&ShadedVertex]) {
+        match mode {
+            DrawingMode::Points => {
+                for vertex in verticies {
+                    self.point(vertex);
+                }
+            }
+            DrawingMode::Lines => {
+                for line in verticies.chunks_exact(2) {
+                    self.line(line[0], line[1]);
+                }
+            }
+            DrawingMode::LineStrip => {
+                for line in verticies.windows(2) {
+                    self.line(line[0], line[1]);
+                }
+            }
+            DrawingMode::LineLoop => {
+                for line in verticies.windows(2) {
+                    self.line(line[0], line[1]);
+                }
+
+                if verticies.len() > 2 {
+                    self.line(verticies[verticies.len() - 1], verticies[0]);
+                }
+            }
+            DrawingMode::Triangles => {
+                for tri in verticies.chunks_exact(3) {
+                    self.triangle(tri[0], tri[1], tri[2]);
+                }
+            }
+            DrawingMode::TriangleStrip => {
+                // every other triangle is flipped to keep the winding order consistent
+                for (i, tri) in verticies.windows(3).enumerate() {
+                    if i % 2 == 0 {
+                        self.triangle(tri[0], tri[1], tri[2]);
+                    } else {
+                        self.triangle(tri[1], tri[0], tri[2]);
+                    }
+                }
+            }
+            DrawingMode::TriangleFan => {
+                for i in 1..verticies.len().saturating_sub(1) {
+                    self.triangle(verticies[0], verticies[i], verticies[i + 1]);
+                }
+            }
+        }
+    }
+
+    /// Convert a clip space vertex into window coordinates
+    fn to_window(&self, vertex: &ShadedVertex) -> WindowVertex {
+        let [x, y, z, w] = vertex.position;
+        let (vx, vy, vw, vh) = self.viewport;
+
+        WindowVertex {
+            x: vx as f32 + (x / w + 1.0) * 0.5 * vw as f32,
+            y: vy as f32 + (y / w + 1.0) * 0.5 * vh as f32,
+            z: (z / w + 1.0) * 0.5,
+            inv_w: 1.0 / w,
+        }
+    }
+
+    /// The range of pixels that can be drawn to: min x, min y, max x, max y (exclusive)
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        let (vx, vy, vw, vh) = self.viewport;
+
+        (
+            vx.min(self.framebuffer.width),
+            vy.min(self.framebuffer.height),
+            (vx + vw).min(self.framebuffer.width),
+            (vy + vh).min(self.framebuffer.height),
+        )
+    }
+
+    fn point(&mut self, vertex: &ShadedVertex) {
+        if !is_inside_near(vertex) {
+            return;
+        }
+
+        let window = self.to_window(vertex);
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+
+        let (x, y) = (window.x.floor(), window.y.floor());
+        if x < min_x as f32 || y < min_y as f32 || x >= max_x as f32 || y >= max_y as f32 {
+            return;
+        }
+
+        let verticies = [vertex, vertex, vertex];
+        self.fragment(x as _, y as _, window.z, verticies, &[[1.0, 0.0, 0.0]]);
+    }
+
+    /// Draw a one pixel wide line, the final pixel is not drawn so that line
+    /// strips do not draw shared verticies twice
+    fn line(&mut self, a: &ShadedVertex, b: &ShadedVertex) {
+        let polygon = clip_near(vec![a.clone(), b.clone()]);
+        if polygon.len() < 2 {
+            return;
+        }
+
+        let (a, b) = (&polygon[0], &polygon[polygon.len() - 1]);
+        let (wa, wb) = (self.to_window(a), self.to_window(b));
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+
+        let steps = (wb.x - wa.x).abs().max((wb.y - wa.y).abs()).ceil() as usize;
+        for step in 0..steps {
+            let t = (step as f32 + 0.5) / steps as f32;
+            let x = wa.x + (wb.x - wa.x) * t;
+            let y = wa.y + (wb.y - wa.y) * t;
+
+            if x < min_x as f32 || y < min_y as f32 || x >= max_x as f32 || y >= max_y as f32 {
+                continue;
+            }
+
+            let z = wa.z + (wb.z - wa.z) * t;
+            let weights = perspective_weights([1.0 - t, t, 0.0], [wa, wb, wb]);
+
+            self.fragment(x as _, y as _, z, [a, b, b], &[weights]);
+        }
+    }
+
+    fn triangle(&mut self, a: &ShadedVertex, b: &ShadedVertex, c: &ShadedVertex) {
+        if self.culling == CullingMode::FrontBack {
+            return;
+        }
+
+        let polygon = clip_near(vec![a.clone(), b.clone(), c.clone()]);
+
+        for i in 1..polygon.len().saturating_sub(1) {
+            self.raster_triangle([&polygon[0], &polygon[i], &polygon[i + 1]]);
+        }
+    }
+
+    fn raster_triangle(&mut self, mut verticies: [&ShadedVertex; 3]) {
+        let mut window = [
+            self.to_window(verticies[0]),
+            self.to_window(verticies[1]),
+            self.to_window(verticies[2]),
+        ];
+
+        let area = edge(window[0], window[1], window[2].x, window[2].y);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+
+        // counter clockwise triangles are front facing, the same as OpenGl's default
+        let is_front = area > 0.0;
+        match self.culling {
+            CullingMode::Front if is_front => return,
+            CullingMode::Back if !is_front => return,
+            _ => (),
+        }
+
+        // make all triangles counter clockwise so only one winding order needs
+        // to be rasterized
+        let area = if is_front {
+            area
+        } else {
+            verticies.swap(1, 2);
+            window.swap(1, 2);
+            -area
+        };
+
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let min_x = (window
+            .iter()
+            .map(|v| v.x)
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(0.0) as usize)
+            .max(min_x);
+        let min_y = (window
+            .iter()
+            .map(|v| v.y)
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(0.0) as usize)
+            .max(min_y);
+        let max_x = (window
+            .iter()
+            .map(|v| v.x)
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .max(0.0) as usize)
+            .min(max_x);
+        let max_y = (window
+            .iter()
+            .map(|v| v.y)
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .max(0.0) as usize)
+            .min(max_y);
+
+        let edges = [(1, 2), (2, 0), (0, 1)];
+        let top_left = edges.map(|(a, b)| is_top_left(window[a], window[b]));
+
+        // screen space barycentric coordinates of a point
+        let barycentric = |x: f32, y: f32| {
+            let mut weights = [0.0; 3];
+            for (weight, &(a, b)) in weights.iter_mut().zip(&edges) {
+                *weight = edge(window[a], window[b], x, y) / area;
+            }
+            weights
+        };
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                let inside = edges.iter().zip(&top_left).all(|(&(a, b), &top_left)| {
+                    let e = edge(window[a], window[b], px, py);
+                    e > 0.0 || (e == 0.0 && top_left)
+                });
+                if !inside {
+                    continue;
+                }
+
+                let weights = barycentric(px, py);
+                let z =
+                    weights[0] * window[0].z + weights[1] * window[1].z + weights[2] * window[2].z;
+
+                // the neighbouring pixels are also evaluated, so that texture
+                // sampling can calculate derivatives, the same as a gpu would
+                // by shading 2x2 quads
+                let lanes = [
+                    perspective_weights(weights, window),
+                    perspective_weights(barycentric(px + 1.0, py), window),
+                    perspective_weights(barycentric(px, py + 1.0), window),
+                ];
+
+                self.fragment(x, y, z, verticies, &lanes);
+            }
+        }
+    }
+
+    /// Stencil and depth test, shade and write a single fragment.  Lane 0 is the fragment
+    /// being drawn, any other lanes are only used for derivatives.
+    fn fragment(
+        &mut self,
+        x: usize,
+        y: usize,
+        z: f32,
+        verticies: [&ShadedVertex; 3],
+        lanes: &[[f32; 3]],
+    ) {
+        // fragments behind the far plane are clipped
+        if !(0.0..=1.0).contains(&z) {
+            return;
+        }
+
+        let framebuffer = &mut *self.framebuffer;
+        let pixel = y * framebuffer.width + x;
+
+        // without a stencil buffer the stencil test always passes
+        let stencil = match (self.stencil, framebuffer.stencil.get_mut(pixel)) {
+            (
+                StencilTesting::Enabled {
+                    func,
+                    reference,
+                    mask,
+                    write_mask,
+                    fail,
+                    depth_fail,
+                    pass,
+                },
+                Some(stored),
+            ) => {
+                if !compare(func, reference & mask, *stored & mask) {
+                    *stored = stencil_operation(fail, *stored, reference, write_mask);
+                    return;
+                }
+
+                Some((stored, reference, write_mask, depth_fail, pass))
+            }
+            _ => None,
+        };
+
+        let write_depth = match self.depth {
+            DepthTesting::None => false,
+            DepthTesting::Enabled { read_only, func } => {
+                let current = framebuffer.depth[pixel];
+                if !compare(func, z, current) {
+                    if let Some((stored, reference, write_mask, depth_fail, _)) = stencil {
+                        *stored = stencil_operation(depth_fail, *stored, reference, write_mask);
+                    }
+                    return;
+                }
+
+                !read_only
+            }
+        };
+
+        if let Some((stored, reference, write_mask, _, pass)) = stencil {
+            *stored = stencil_operation(pass, *stored, reference, write_mask);
+        }
+
+        if write_depth {
+            framebuffer.depth[pixel] = z;
+        }
+
+        let frag = if let Some(frag) = self.program.frag_main() {
+            frag
+        } else {
+            return;
+        };
+
+        let mut invocation = Invocation::new(
+            self.program,
+            frag,
+            self.uniforms,
+            self.textures,
+            self.storage,
+            lanes.len(),
+        );
+
+        for (input, varying) in frag.inputs().iter().zip(self.varyings) {
+            for weights in lanes {
+                let value = match varying {
+                    Some(varying) => {
+                        let mut value = Value::zero(input.ty);
+                        for (vertex, &weight) in verticies.iter().zip(weights) {
+                            let varying = &vertex.varyings[*varying];
+                            value = componentwise(input.ty, &value, varying, |a, b| a + b * weight);
+                        }
+                        value
+                    }
+                    None => Value::zero(input.ty),
+                };
+
+                invocation.inputs.push(value);
+            }
+        }
+
+        invocation.run();
+
+        // the first output is the color written to the framebuffer
+        if !invocation.outputs.is_empty() {
+            let normalized =
+                !matches!(self.framebuffer.options.color, Some(storage) if storage.is_float());
+            let color = &mut self.framebuffer.color[pixel];
+            *color = blend(self.blend, invocation.outputs[0].vec4(), *color, normalized);
+        }
+    }
+}
+
+/// Compare a value against the one stored in the framebuffer
+fn compare<T: PartialOrd>(func: DepthTestingFunction, value: T, stored: T) -> bool {
+    match func {
+        DepthTestingFunction::Always => true,
+        DepthTestingFunction::Never => false,
+        DepthTestingFunction::Less => value < stored,
+        DepthTestingFunction::Equal => value == stored,
+        DepthTestingFunction::LessEqual => value <= stored,
+        DepthTestingFunction::Greater => value > stored,
+        DepthTestingFunction::NotEqual => value != stored,
+        DepthTestingFunction::GreaterEqual => value >= stored,
+    }
+}
+
+/// Apply a stencil operation to a stored value, only changing the bits set in
+/// the write mask
+fn stencil_operation(operation: StencilOperation, stored: u8, reference: u8, write_mask: u8) -> u8 {
+    let value = match operation {
+        StencilOperation::Keep => stored,
+        StencilOperation::Zero => 0,
+        StencilOperation::Replace => reference,
+        StencilOperation::Increment => stored.saturating_add(1),
+        StencilOperation::IncrementWrap => stored.wrapping_add(1),
+        StencilOperation::Decrement => stored.saturating_sub(1),
+        StencilOperation::DecrementWrap => stored.wrapping_sub(1),
+        StencilOperation::Invert => !stored,
+    };
+
+    (stored & !write_mask) | (value & write_mask)
+}
+
+/// Combine a fragment's color with the color already in the framebuffer.  The
+/// same as OpenGl, colors are clamped to [0, 1] when blending is enabled
+/// unless the framebuffer stores floats.
+fn blend(mode: BlendMode, source: [f32; 4], destination: [f32; 4], normalized: bool) -> [f32; 4] {
+    let (color, alpha) = match mode {
+        BlendMode::None => return source,
+        BlendMode::Enabled { color, alpha } => (color, alpha),
+    };
+
+    let clamp = |c: f32| if normalized { c.clamp(0.0, 1.0) } else { c };
+    let source = source.map(clamp);
+
+    let mut result = [0.0; 4];
+    for (i, value) in result.iter_mut().enumerate() {
+        let function: BlendFunction = if i == 3 { alpha } else { color };
+
+        let factor = |factor| match factor {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::SourceColor => source[i],
+            BlendFactor::OneMinusSourceColor => 1.0 - source[i],
+            BlendFactor::DestinationColor => destination[i],
+            BlendFactor::OneMinusDestinationColor => 1.0 - destination[i],
+            BlendFactor::SourceAlpha => source[3],
+            BlendFactor::OneMinusSourceAlpha => 1.0 - source[3],
+            BlendFactor::DestinationAlpha => destination[3],
+            BlendFactor::OneMinusDestinationAlpha => 1.0 - destination[3],
+        };
+
+        let s = source[i] * factor(function.source);
+        let d = destination[i] * factor(function.destination);
+
+        *value = clamp(match function.equation {
+            BlendEquation::Add => s + d,
+            BlendEquation::Subtract => s - d,
+            BlendEquation::ReverseSubtract => d - s,
+            BlendEquation::Min => source[i].min(destination[i]),
+            BlendEquation::Max => source[i].max(destination[i]),
+        });
+    }
+
+    result
+}
+
+/// Twice the signed area of the triangle (a, b, (x, y)), positive if counter clockwise
+fn edge(a: WindowVertex, b: WindowVertex, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+/// Is the edge from a to b a top or left edge of a counter clockwise triangle,
+/// pixels exactly on these edges are drawn, so that pixels on an edge shared by
+/// two triangles are only drawn once
+fn is_top_left(a: WindowVertex, b: WindowVertex) -> bool {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    (dy == 0.0 && dx < 0.0) || dy < 0.0
+}
+
+/// Convert screen space weights into perspective correct weights
+fn perspective_weights(weights: [f32; 3], window: [WindowVertex; 3]) -> [f32; 3] {
+    let mut result = weights;
+    for (weight, vertex) in result.iter_mut().zip(&window) {
+        *weight *= vertex.inv_w;
+    }
+
+    let sum: f32 = result.iter().sum();
+    if sum != 0.0 {
+        for weight in &mut result {
+            *weight /= sum;
+        }
+    }
+
+    result
+}
+
+/// Is the vertex in front of the near plane
+fn is_inside_near(vertex: &ShadedVertex) -> bool {
+    near_distance(vertex) >= 0.0 && vertex.position[3] > f32::EPSILON
+}
+
+fn near_distance(vertex: &ShadedVertex) -> f32 {
+    vertex.position[2] + vertex.position[3]
+}
+
+/// Clip a polygon or line against the near plane, also removes anything with
+/// a non-positive w, as that cannot be projected
+fn clip_near(polygon: Vec<ShadedVertex>) -> Vec<ShadedVertex> {
+    let polygon = clip_plane(polygon, near_distance);
+    clip_plane(polygon, |vertex| vertex.position[3] - f32::EPSILON)
+}
+
+/// Sutherland–Hodgman clipping against a single plane, anything with a
+/// negative distance is removed
+fn clip_plane(
+    polygon: Vec<ShadedVertex>,
+    distance: impl Fn(&ShadedVertex) -> f32,
+) -> Vec<ShadedVertex> {
+    if polygon.iter().all(|vertex| distance(vertex) >= 0.0) {
+        return polygon;
+    }
+
+    // lines are not closed loops, so do not clip the edge from the end to the start
+    let edges = if polygon.len() == 2 { 1 } else { polygon.len() };
+
+    let mut result = vec![];
+    for i in 0..edges {
+        let a = &polygon[i];
+        let b = &polygon[(i + 1) % polygon.len()];
+        let (da, db) = (distance(a), distance(b));
+
+        if da >= 0.0 {
+            result.push(a.clone());
+        }
+
+        if (da >= 0.0) != (db >= 0.0) {
+            result.push(ShadedVertex::lerp(a, b, da / (da - db)));
+        }
+
+        if polygon.len() == 2 && db >= 0.0 {
+            result.push(b.clone());
+        }
+    }
+
+    result
+}
+
+/// A single run of a shader function, running several lanes at once, where
+/// each lane has a different set of inputs
+struct Invocation<'a> {
+    program: &'a Program,
+    function: &'a Function,
+    uniforms: &'a [Vec<Value>],
+    textures: &'a HashMap<Handle, SoftwareTexture>,
+
+    /// The data of each storage buffer, shared by every invocation of a draw
+    /// or dispatch so that writes are seen by later invocations
+    storage: &'a RefCell<Vec<Vec<u8>>>,
+    lanes: usize,
+
+    /// The index of the instance being drawn
+    instance: usize,
+
+    /// The base instance of the draw, not included in the instance
+    base_instance: usize,
+
+    /// The position of a compute shader invocation in the whole dispatch
+    global_id: [usize; 3],
+
+    /// All variable storage is indexed by `variable id * lanes + lane`
+    locals: Vec<Value>,
+    inputs: Vec<Value>,
+    outputs: Vec<Value>,
+    position: Vec<Value>,
+}
+
+impl<'a> Invocation<'a> {
+    fn new(
+        program: &'a Program,
+        function: &'a Function,
+        uniforms: &'a [Vec<Value>],
+        textures: &'a HashMap<Handle, SoftwareTexture>,
+        storage: &'a RefCell<Vec<Vec<u8>>>,
+        lanes: usize,
+    ) -> Self {
+        let repeat = |vars: &[Variable]| -> Vec<Value> {
+            vars.iter()
+                .flat_map(|var| vec![Value::zero(var.ty); lanes])
+                .collect()
+        };
+
+        Invocation {
+            program,
+            function,
+            uniforms,
+            textures,
+            storage,
+            lanes,
+            instance: 0,
+            base_instance: 0,
+            global_id: [0; 3],
+            locals: repeat(function.locals()),
+            inputs: Vec::with_capacity(function.inputs().len() * lanes),
+            outputs: repeat(function.outputs()),
+            position: vec![Value::zero(Type::Vec4); lanes],
+        }
+    }
+
+    fn get(&self, variable: VariableId, lane: usize) -> Value {
+        let idx = variable.id() * self.lanes + lane;
+
+        match variable.allocation_kind() {
+            VariableAllocationContext::Local => self.locals[idx],
+            VariableAllocationContext::Uniform => match self.program.uniforms()[variable.id()].ty {
+                Type::Array(..) => Value::Array(variable.id()),
+                _ => self.uniforms[variable.id()][0],
+            },
+            VariableAllocationContext::Input => self.inputs[idx],
+            VariableAllocationContext::Output => self.outputs[idx],
+            VariableAllocationContext::Storage => Value::Storage(variable.id()),
+        }
+    }
+
+    fn set(&mut self, variable: VariableId, lane: usize, value: Value) {
+        let idx = variable.id() * self.lanes + lane;
+
+        match variable.allocation_kind() {
+            VariableAllocationContext::Local => self.locals[idx] = value,
+            VariableAllocationContext::Input => self.inputs[idx] = value,
+            VariableAllocationContext::Output => self.outputs[idx] = value,
+
+            // uniforms cannot be written to by a shader and storage buffers
+            // are only written one element at a time
+            VariableAllocationContext::Uniform | VariableAllocationContext::Storage => (),
+        }
+    }
+
+    fn run(&mut self) {
+        let function = self.function;
+
+        for block in function.blocks() {
+            for statement in block.statements() {
+                self.statement(statement);
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match *statement {
+            Statement::MakeFloat { value, variable } => {
+                for lane in 0..self.lanes {
+                    self.set(variable, lane, Value::from_slice(Type::Floating, &[value]));
+                }
+            }
+            Statement::MakeInt { value, variable } => {
+                for lane in 0..self.lanes {
+                    self.set(
+                        variable,
+                        lane,
+                        Value::from_slice(Type::Integer, &[value as f32]),
+                    );
+                }
+            }
+            Statement::GetBuiltinVariable { variable, result } => match variable {
+                BuiltinVariable::VertexPosition => {
+                    for lane in 0..self.lanes {
+                        self.set(result, lane, self.position[lane]);
+                    }
+                }
+                BuiltinVariable::InstanceId => {
+                    let instance = Value::from_slice(Type::Integer, &[self.instance as f32]);
+                    for lane in 0..self.lanes {
+                        self.set(result, lane, instance);
+                    }
+                }
+                BuiltinVariable::BaseInstance => {
+                    let base = Value::from_slice(Type::Integer, &[self.base_instance as f32]);
+                    for lane in 0..self.lanes {
+                        self.set(result, lane, base);
+                    }
+                }
+                BuiltinVariable::GlobalInvocationX
+                | BuiltinVariable::GlobalInvocationY
+                | BuiltinVariable::GlobalInvocationZ => {
+                    let axis = match variable {
+                        BuiltinVariable::GlobalInvocationX => 0,
+                        BuiltinVariable::GlobalInvocationY => 1,
+                        _ => 2,
+                    };
+                    let id = Value::from_slice(Type::Integer, &[self.global_id[axis] as f32]);
+                    for lane in 0..self.lanes {
+                        self.set(result, lane, id);
+                    }
+                }
+            },
+            Statement::SetBuiltinVariable { variable, value } => match variable {
+                BuiltinVariable::VertexPosition => {
+                    for lane in 0..self.lanes {
+                        self.position[lane] = self.get(value, lane);
+                    }
+                }
+
+                // read only, rejected when the pipeline is loaded
+                BuiltinVariable::InstanceId
+                | BuiltinVariable::BaseInstance
+                | BuiltinVariable::GlobalInvocationX
+                | BuiltinVariable::GlobalInvocationY
+                | BuiltinVariable::GlobalInvocationZ => (),
+            },
+            Statement::CallBuiltin {
+                function,
+                ref arguments,
+                result,
+            } => self.call(function, arguments, result),
+        }
+    }
+
+    fn call(
+        &mut self,
+        function: BuiltinFunction,
+        arguments: &[VariableId],
+        result: Option<VariableId>,
+    ) {
+        if function == BuiltinFunction::SetGlobal {
+            for lane in 0..self.lanes {
+                self.set(arguments[0], lane, self.get(arguments[1], lane));
+            }
+            return;
+        }
+
+        // other lanes are only used for derivatives, so must not write
+        if function == BuiltinFunction::Store {
+            let args: Vec<_> = arguments.iter().map(|&arg| self.get(arg, 0)).collect();
+            self.store(&args[0], &args[1], &args[2]);
+            return;
+        }
+
+        let result = if let Some(result) = result {
+            result
+        } else {
+            return;
+        };
+
+        let ty = self.program.get_variable(self.function, result).ty;
+
+        if function == BuiltinFunction::Texture {
+            let values = self.texture(arguments);
+            for (lane, value) in values.into_iter().enumerate() {
+                self.set(result, lane, value);
+            }
+            return;
+        }
+
+        for lane in 0..self.lanes {
+            let args: Vec<_> = arguments.iter().map(|&arg| self.get(arg, lane)).collect();
+
+            let value = match function {
+                BuiltinFunction::Add => componentwise(ty, &args[0], &args[1], |a, b| a + b),
+                BuiltinFunction::Sub => componentwise(ty, &args[0], &args[1], |a, b| a - b),
+                BuiltinFunction::Div if ty.is_integer() => {
+                    componentwise(ty, &args[0], &args[1], |a, b| (a / b).trunc())
+                }
+                BuiltinFunction::Div => componentwise(ty, &args[0], &args[1], |a, b| a / b),
+                BuiltinFunction::Mul => multiply(ty, &args[0], &args[1]),
+                BuiltinFunction::MakeVec => {
+                    let data: Vec<_> = args.iter().flat_map(|arg| arg.data().to_vec()).collect();
+                    Value::from_slice(ty, &data[..data.len().min(16)])
+                }
+                BuiltinFunction::Index => self.index(&args[0], &args[1], ty),
+                BuiltinFunction::Texture | BuiltinFunction::SetGlobal | BuiltinFunction::Store => {
+                    unreachable!()
+                }
+            };
+
+            self.set(result, lane, value);
+        }
+    }
+
+    /// Get an element of an array uniform or storage buffer, indexing out of
+    /// bounds gives zero
+    fn index(&self, array: &Value, index: &Value, ty: Type) -> Value {
+        let index = index.data()[0];
+        if index < 0.0 {
+            return Value::zero(ty);
+        }
+
+        let values = match array {
+            Value::Array(uniform) => &self.uniforms[*uniform],
+            Value::Storage(buffer) => {
+                let storage = self.storage.borrow();
+                let size = component_count(ty) * 4;
+                let start = index as usize * size;
+
+                return match storage[*buffer].get(start..start + size) {
+                    Some(bytes) => read_storage(ty, bytes),
+                    None => Value::zero(ty),
+                };
+            }
+            _ => return Value::zero(ty),
+        };
+
+        values
+            .get(index as usize)
+            .copied()
+            .unwrap_or_else(|| Value::zero(ty))
+    }
+
+    /// Write an element of a storage buffer, writing out of bounds does nothing
+    fn store(&self, buffer: &Value, index: &Value, value: &Value) {
+        let buffer = match buffer {
+            Value::Storage(buffer) => *buffer,
+            _ => return,
+        };
+
+        let index = index.data()[0];
+        if index < 0.0 {
+            return;
+        }
+
+        let mut storage = self.storage.borrow_mut();
+        let size = value.data().len() * 4;
+        let start = index as usize * size;
+
+        if let Some(bytes) = storage[buffer].get_mut(start..start + size) {
+            for (bytes, &component) in bytes.chunks_exact_mut(4).zip(value.data()) {
+                let component = if value.ty().is_unsigned() {
+                    (component as u32).to_ne_bytes()
+                } else if value.ty().is_integer() {
+                    (component as i32).to_ne_bytes()
+                } else {
+                    component.to_ne_bytes()
+                };
+                bytes.copy_from_slice(&component);
+            }
+        }
+    }
+
+    /// Sample a texture for every lane, the level of detail is calculated from
+    /// the differences between lanes 0, 1 and 2.  If there are not enough lanes
+    /// then the base level is used.
+    fn texture(&self, arguments: &[VariableId]) -> Vec<Value> {
+        let texture = match self.get(arguments[0], 0) {
+            Value::Sampler(Some(id)) => self.textures.get(&id),
+            _ => None,
+        };
+
+        let coords: Vec<_> = (0..self.lanes)
+            .map(|lane| {
+                let coords = self.get(arguments[1], lane).vec4();
+                [coords[0], coords[1], coords[2]]
+            })
+            .collect();
+
+        let texture = if let Some(texture) = texture {
+            texture
+        } else {
+            // sampling an incomplete texture in OpenGl gives (0, 0, 0, 1)
+            return vec![Value::from_slice(Type::Vec4, &[0.0, 0.0, 0.0, 1.0]); self.lanes];
+        };
+
+        let lambda = if self.lanes >= 3 {
+            texture.lod(coords[0], coords[1], coords[2])
+        } else {
+            0.0
+        };
+
+        coords
+            .iter()
+            .map(|&coords| Value::from_slice(Type::Vec4, &texture.sample(coords, lambda)))
+            .collect()
+    }
+}
+
+/// A texture decoded into linear floating point colors with a full mipmap chain
+struct SoftwareTexture {
+    options: TextureOptions,
+
+    /// The mipmap chain of each layer, a 2D texture has a single layer, array
+    /// and 3D textures have one per image and cubemaps have one per face.
+    /// 3D textures are mipmapped one slice at a time.
+    layers: Vec<Vec<MipLevel>>,
+
+    /// Whether the mipmap chain is generated from the base level
+    generates_mipmaps: bool,
+}
+
+struct MipLevel {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl SoftwareTexture {
+    fn new(texture: &Texture) -> Self {
+        let options = texture.config();
+
+        // decode a single layer of a mipmap level
+        let decode = |level: u32, layer: usize| {
+            let (width, height) = texture.level_size(level);
+            let (width, height) = (width as usize, height as usize);
+            let bytes = texture.level_bytes(level);
+
+            if texture.is_compressed() {
+                let size = texture.level_layer_size(level);
+                let bytes = bytes.get(layer * size..(layer + 1) * size);
+
+                let texels =
+                    decode_blocks(options.storage, bytes.unwrap_or_default(), width, height)
+                        .into_iter()
+                        .map(|texel| store(options.storage, texel))
+                        .collect();
+
+                return MipLevel {
+                    width,
+                    height,
+                    texels,
+                };
+            }
+
+            let texels = (0..width * height)
+                .map(|pixel| {
+                    let pixel = layer * width * height + pixel;
+                    store(options.storage, read_texel(&options, &bytes, pixel))
+                })
+                .collect();
+
+            MipLevel {
+                width,
+                height,
+                texels,
+            }
+        };
+
+        // 3D textures are mipmapped one slice at a time, so their mipmaps are
+        // always generated as the slices in prebuilt levels would not match
+        let generate = texture.generates_mipmaps() || options.kind == TextureKind::Texture3D;
+
+        let layers = (0..texture.layers() as usize)
+            .map(|layer| {
+                if !generate {
+                    return (0..texture.mip_levels())
+                        .map(|level| decode(level, layer))
+                        .collect();
+                }
+
+                let mut levels = vec![decode(0, layer)];
+                while let Some(level) = levels.last().and_then(MipLevel::next) {
+                    levels.push(level);
+                }
+
+                levels
+            })
+            .collect();
+
+        SoftwareTexture {
+            options,
+            layers,
+            generates_mipmaps: generate,
+        }
+    }
+
+    /// Create a 2D texture without mipmaps from already decoded texels
+    fn from_texels(
+        options: TextureOptions,
+        width: usize,
+        height: usize,
+        texels: Vec<[f32; 4]>,
+    ) -> Self {
+        SoftwareTexture {
+            options,
+            layers: vec![vec![MipLevel {
+                width,
+                height,
+                texels,
+            }]],
+            generates_mipmaps: false,
+        }
+    }
+
+    /// Replace a region of the texture with new data, the region should have
+    /// already been checked against the texture's size
+    fn update(&mut self, region: TextureRegion, data: &[u8]) {
+        let level = region.level as usize;
+        let (x, y) = (region.x as usize, region.y as usize);
+        let (width, height) = (region.width as usize, region.height as usize);
+
+        for i in 0..region.layers as usize {
+            let layer = &mut self.layers[region.layer as usize + i];
+            let stride = layer[level].width;
+
+            for row in 0..height {
+                for col in 0..width {
+                    let pixel = (i * height + row) * width + col;
+                    let texel = read_texel(&self.options, data, pixel);
+                    layer[level].texels[(y + row) * stride + x + col] =
+                        store(self.options.storage, texel);
+                }
+            }
+
+            // the rest of the chain is rebuilt from the updated base level
+            if level == 0 && self.generates_mipmaps {
+                layer.truncate(1);
+                while let Some(level) = layer.last().and_then(MipLevel::next) {
+                    layer.push(level);
+                }
+            }
+        }
+    }
+
+    /// Find the layer and the 2D texture coordinates within it to sample, the
+    /// layer of a 3D texture is found when sampling, so is always 0
+    fn project(&self, coords: [f32; 3]) -> (usize, [f32; 2]) {
+        match self.options.kind {
+            TextureKind::Texture2D | TextureKind::Texture3D => (0, [coords[0], coords[1]]),
+            TextureKind::Array2D => {
+                let max_layer = self.layers.len() as f32 - 1.0;
+                let layer = coords[2].round().max(0.0).min(max_layer);
+                (layer as usize, [coords[0], coords[1]])
+            }
+            TextureKind::Cubemap => cube_face(coords),
+        }
+    }
+
+    /// Calculate the level of detail from the texture coordinates of a pixel
+    /// and its neighbours in the x and y directions
+    fn lod(&self, coords: [f32; 3], coords_dx: [f32; 3], coords_dy: [f32; 3]) -> f32 {
+        let (_, uv) = self.project(coords);
+        let (_, uv_dx) = self.project(coords_dx);
+        let (_, uv_dy) = self.project(coords_dy);
+
+        let level = &self.layers[0][0];
+        let (width, height) = (level.width as f32, level.height as f32);
+
+        let dx = ((uv_dx[0] - uv[0]) * width, (uv_dx[1] - uv[1]) * height);
+        let dy = ((uv_dy[0] - uv[0]) * width, (uv_dy[1] - uv[1]) * height);
+
+        let rho = (dx.0 * dx.0 + dx.1 * dx.1)
+            .sqrt()
+            .max((dy.0 * dy.0 + dy.1 * dy.1).sqrt());
+
+        rho.log2()
+    }
+
+    fn sample(&self, coords: [f32; 3], lambda: f32) -> [f32; 4] {
+        if self.layers.is_empty() || self.layers[0][0].texels.is_empty() {
+            return [0.0, 0.0, 0.0, 1.0];
+        }
+
+        let (layer, uv) = self.project(coords);
+        if self.options.kind != TextureKind::Texture3D {
+            return self.sample_layer(layer, uv, lambda);
+        }
+
+        // 3D textures are also filtered between the two closest slices
+        let depth = self.layers.len();
+        let linear = if lambda.is_nan() || lambda <= 0.0 {
+            self.options.mag_filter == MagFilter::Linear
+        } else {
+            matches!(
+                self.options.min_filter,
+                MinFilter::Linear | MinFilter::LinearMipmapNearest | MinFilter::LinearMipmapLinear
+            )
+        };
+
+        let w = coords[2] * depth as f32;
+        let slice = |k: f32| wrap(k as i64, depth, self.options.wrap_r);
+        if !linear {
+            return self.sample_layer(slice(w.floor()), uv, lambda);
+        }
+
+        let w = w - 0.5;
+        let k = w.floor();
+        let a = self.sample_layer(slice(k), uv, lambda);
+        let b = self.sample_layer(slice(k + 1.0), uv, lambda);
+
+        lerp4(a, b, w - k)
+    }
+
+    fn sample_layer(&self, layer: usize, uv: [f32; 2], lambda: f32) -> [f32; 4] {
+        // not a number, or less than zero means magnification
+        if lambda.is_nan() || lambda <= 0.0 {
+            return self.filter(layer, 0, uv, self.options.mag_filter == MagFilter::Linear);
+        }
+
+        let max_level = self.layers[layer].len() - 1;
+        let nearest_level = if lambda <= 0.5 {
+            0
+        } else {
+            ((lambda + 0.5).ceil() as usize - 1).min(max_level)
+        };
+
+        match self.options.min_filter {
+            MinFilter::Nearest => self.filter(layer, 0, uv, false),
+            MinFilter::Linear => self.filter(layer, 0, uv, true),
+            MinFilter::NearestMipmapNearest => self.filter(layer, nearest_level, uv, false),
+            MinFilter::LinearMipmapNearest => self.filter(layer, nearest_level, uv, true),
+            MinFilter::NearestMipmapLinear => self.filter_levels(layer, lambda, uv, false),
+            MinFilter::LinearMipmapLinear => self.filter_levels(layer, lambda, uv, true),
+        }
+    }
+
+    /// Linearly interpolate between the two mipmap levels closest to lambda
+    fn filter_levels(&self, layer: usize, lambda: f32, uv: [f32; 2], linear: bool) -> [f32; 4] {
+        let max_level = self.layers[layer].len() - 1;
+        let lower = (lambda.floor() as usize).min(max_level);
+        if lower == max_level {
+            return self.filter(layer, lower, uv, linear);
+        }
+
+        let a = self.filter(layer, lower, uv, linear);
+        let b = self.filter(layer, lower + 1, uv, linear);
+
+        lerp4(a, b, lambda.fract())
+    }
+
+    /// Sample a single mipmap level using nearest or bilinear filtering
+    fn filter(&self, layer: usize, level: usize, uv: [f32; 2], linear: bool) -> [f32; 4] {
+        let level = &self.layers[layer][level];
+        let u = uv[0] * level.width as f32;
+        let v = uv[1] * level.height as f32;
+
+        // cubemap faces are always clamped, the same as seamless cubemap
+        // filtering in OpenGl, except that filtering does not cross faces
+        let (wrap_s, wrap_t) = if self.options.kind == TextureKind::Cubemap {
+            (WrappingMode::ClampToEdge, WrappingMode::ClampToEdge)
+        } else {
+            (self.options.wrap_s, self.options.wrap_t)
+        };
+
+        let texel = |i: f32, j: f32| {
+            let i = wrap(i as i64, level.width, wrap_s);
+            let j = wrap(j as i64, level.height, wrap_t);
+
+            level.texels[j * level.width + i]
+        };
+
+        if !linear {
+            return texel(u.floor(), v.floor());
+        }
+
+        let (u, v) = (u - 0.5, v - 0.5);
+        let (i, j) = (u.floor(), v.floor());
+        let (a, b) = (u - i, v - j);
+
+        let bottom = lerp4(texel(i, j), texel(i + 1.0, j), a);
+        let top = lerp4(texel(i, j + 1.0), texel(i + 1.0, j + 1.0), a);
+
+        lerp4(bottom, top, b)
+    }
+}
+
+/// Find which face of a cubemap a direction points to, along with the
+/// texture coordinates within that face, using the same rules as OpenGl
+fn cube_face(direction: [f32; 3]) -> (usize, [f32; 2]) {
+    let [x, y, z] = direction;
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+    // (face, s, t, major axis)
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if x >= 0.0 {
+            (0, -z, -y, ax)
+        } else {
+            (1, z, -y, ax)
+        }
+    } else if ay >= az {
+        if y >= 0.0 {
+            (2, x, z, ay)
+        } else {
+            (3, x, -z, ay)
+        }
+    } else if z >= 0.0 {
+        (4, x, -y, az)
+    } else {
+        (5, -x, -y, az)
+    };
+
+    if ma == 0.0 {
+        return (face, [0.5, 0.5]);
+    }
+
+    (face, [(sc / ma + 1.0) / 2.0, (tc / ma + 1.0) / 2.0])
+}
+
+impl MipLevel {
+    /// Create the next smaller mipmap level by averaging 2x2 blocks of texels
+    fn next(&self) -> Option<MipLevel> {
+        if self.width <= 1 && self.height <= 1 {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut texel = [0.0; 4];
+                for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(self.width - 1);
+                    let sy = (y * 2 + dy).min(self.height - 1);
+                    let source = self.texels[sy * self.width + sx];
+
+                    for (texel, source) in texel.iter_mut().zip(&source) {
+                        *texel += source / 4.0;
+                    }
+                }
+                texels.push(texel);
+            }
+        }
+
+        Some(MipLevel {
+            width,
+            height,
+            texels,
+        })
+    }
+}
+
+/// Apply a texture wrapping mode to a texel coordinate
+fn wrap(i: i64, size: usize, mode: WrappingMode) -> usize {
+    let size = size as i64;
+
+    let i = match mode {
+        WrappingMode::Repeat => i.rem_euclid(size),
+        WrappingMode::MirroredRepeat => {
+            let i = i.rem_euclid(size * 2);
+            if i >= size {
+                size * 2 - 1 - i
+            } else {
+                i
+            }
+        }
+        WrappingMode::ClampToEdge => i.max(0).min(size - 1),
+    };
+
+    i as usize
+}
+
+/// Read a single texel from texture source data, missing components are
+/// filled in with 0 for colors and 1 for alpha
+fn read_texel(options: &TextureOptions, bytes: &[u8], pixel: usize) -> [f32; 4] {
+    let size = options.source_type.size();
+    let components = options.source_format.components();
+
+    let component = |idx: usize| {
+        let start = idx * size;
+        let bytes = if let Some(bytes) = bytes.get(start..start + size) {
+            bytes
+        } else {
+            return 0.0;
+        };
+
+        // values are clamped to the range of the storage type when stored
+        match options.source_type {
+            TextureSourceType::U8 => bytes[0] as f32 / u8::MAX as f32,
+            TextureSourceType::I8 => bytes[0] as i8 as f32 / i8::MAX as f32,
+            TextureSourceType::U16 => {
+                u16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32
+            }
+            TextureSourceType::I16 => {
+                i16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32
+            }
+            TextureSourceType::U32 => {
+                u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / u32::MAX as f32
+            }
+            TextureSourceType::I32 => {
+                i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / i32::MAX as f32
+            }
+            TextureSourceType::F16 => f16_to_f32(u16::from_ne_bytes([bytes[0], bytes[1]])),
+            TextureSourceType::F32 => f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    };
+
+    let mut texel = [0.0, 0.0, 0.0, 1.0];
+    for (i, value) in texel.iter_mut().enumerate().take(components) {
+        *value = component(pixel * components + i);
+    }
+
+    if let TextureSourceFormat::BGR | TextureSourceFormat::BGRA = options.source_format {
+        texel.swap(0, 2);
+    }
+
+    texel
+}
+
+/// Convert a texel into the value that would be read back from a texture with
+/// the given storage type
+fn store(storage: TextureStorageType, texel: [f32; 4]) -> [f32; 4] {
+    use TextureStorageType::*;
+
+    // signed compressed formats are normalised to [-1, 1] instead, float
+    // formats are not clamped
+    let min = match storage {
+        BC4SignedR | BC5SignedRG => -1.0,
+        _ => 0.0,
+    };
+
+    let texel = if storage.is_float() {
+        texel
+    } else {
+        [
+            texel[0].clamp(min, 1.0),
+            texel[1].clamp(min, 1.0),
+            texel[2].clamp(min, 1.0),
+            texel[3].clamp(0.0, 1.0),
+        ]
+    };
+
+    match storage {
+        R | R16F | R32F | BC4R | BC4SignedR => [texel[0], 0.0, 0.0, 1.0],
+        RG | RG16F | RG32F | BC5RG | BC5SignedRG => [texel[0], texel[1], 0.0, 1.0],
+        RGB | RGB16F | RGB32F | BC1RGB | BC6HUnsignedFloat | BC6HSignedFloat => {
+            [texel[0], texel[1], texel[2], 1.0]
+        }
+        SRGB | BC1SRGB => [
+            srgb_to_linear(texel[0]),
+            srgb_to_linear(texel[1]),
+            srgb_to_linear(texel[2]),
+            1.0,
+        ],
+        RGBA | RGBA16F | RGBA32F | BC1RGBA | BC2RGBA | BC3RGBA | BC7RGBA => texel,
+        SRGBA | BC1SRGBA | BC2SRGBA | BC3SRGBA | BC7SRGBA => [
+            srgb_to_linear(texel[0]),
+            srgb_to_linear(texel[1]),
+            srgb_to_linear(texel[2]),
+            texel[3],
+        ],
+    }
+}
+
+/// Decode the compressed blocks of a single layer into rows of texels, parts
+/// of blocks outside of the layer are discarded
+fn decode_blocks(
+    storage: TextureStorageType,
+    bytes: &[u8],
+    width: usize,
+    height: usize,
+) -> Vec<[f32; 4]> {
+    let block_size = storage.block_size().unwrap_or(16);
+    let blocks_x = width.div_ceil(4);
+
+    let mut texels = vec![[0.0, 0.0, 0.0, 1.0]; width * height];
+    for (idx, block) in bytes.chunks_exact(block_size).enumerate() {
+        let (block_x, block_y) = (idx % blocks_x * 4, idx / blocks_x * 4);
+
+        for (i, &texel) in decode_block(storage, block).iter().enumerate() {
+            let (x, y) = (block_x + i % 4, block_y + i / 4);
+            if x < width && y < height {
+                texels[y * width + x] = texel;
+            }
+        }
+    }
+
+    texels
+}
+
+/// Decode a single 4x4 block of a compressed texture, texels are in rows.
+/// BC6H and BC7 blocks are not decoded and are read as opaque black.
+fn decode_block(storage: TextureStorageType, block: &[u8]) -> [[f32; 4]; 16] {
+    use TextureStorageType::*;
+
+    match storage {
+        BC1RGB | BC1SRGB | BC1RGBA | BC1SRGBA => bc1_colors(block, true),
+        BC2RGBA | BC2SRGBA => {
+            let mut texels = bc1_colors(&block[8..], false);
+            let bits = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = ((bits >> (4 * i)) & 15) as f32 / 15.0;
+            }
+            texels
+        }
+        BC3RGBA | BC3SRGBA => {
+            let mut texels = bc1_colors(&block[8..], false);
+            for (texel, alpha) in texels.iter_mut().zip(&bc4_values(&block[..8], false)) {
+                texel[3] = *alpha;
+            }
+            texels
+        }
+        BC4R | BC4SignedR => {
+            let red = bc4_values(block, storage == BC4SignedR);
+            let mut texels = [[0.0, 0.0, 0.0, 1.0]; 16];
+            for (texel, red) in texels.iter_mut().zip(&red) {
+                texel[0] = *red;
+            }
+            texels
+        }
+        BC5RG | BC5SignedRG => {
+            let signed = storage == BC5SignedRG;
+            let red = bc4_values(&block[..8], signed);
+            let green = bc4_values(&block[8..], signed);
+            let mut texels = [[0.0, 0.0, 0.0, 1.0]; 16];
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[0] = red[i];
+                texel[1] = green[i];
+            }
+            texels
+        }
+        _ => [[0.0, 0.0, 0.0, 1.0]; 16],
+    }
+}
+
+/// Decode the color part of a BC1, BC2 or BC3 block, only BC1 blocks can use
+/// three colors and transparent black
+fn bc1_colors(block: &[u8], three_color: bool) -> [[f32; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+
+    let rgb565 = |c: u16| {
+        [
+            (c >> 11) as f32 / 31.0,
+            ((c >> 5) & 63) as f32 / 63.0,
+            (c & 31) as f32 / 31.0,
+            1.0,
+        ]
+    };
+    let (a, b) = (rgb565(c0), rgb565(c1));
+
+    let palette = if c0 > c1 || !three_color {
+        [a, b, lerp4(a, b, 1.0 / 3.0), lerp4(a, b, 2.0 / 3.0)]
+    } else {
+        [a, b, lerp4(a, b, 0.5), [0.0; 4]]
+    };
+
+    let bits = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0.0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(bits >> (2 * i)) as usize & 3];
+    }
+
+    texels
+}
+
+/// Decode a BC4 block, also used for the alpha of BC3 and each channel of BC5
+fn bc4_values(block: &[u8], signed: bool) -> [f32; 16] {
+    let (e0, e1) = if signed {
+        (block[0] as i8 as i32, block[1] as i8 as i32)
+    } else {
+        (block[0] as i32, block[1] as i32)
+    };
+    let scale = |e: i32| {
+        if signed {
+            (e as f32 / 127.0).max(-1.0)
+        } else {
+            e as f32 / 255.0
+        }
+    };
+    let (a, b) = (scale(e0), scale(e1));
+
+    let mut palette = [a, b, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = (a * (7 - i) as f32 + b * i as f32) / 7.0;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (a * (5 - i) as f32 + b * i as f32) / 5.0;
+        }
+        palette[6] = if signed { -1.0 } else { 0.0 };
+        palette[7] = 1.0;
+    }
+
+    let bits = block[2..8]
+        .iter()
+        .rev()
+        .fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+
+    let mut values = [0.0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(bits >> (3 * i)) as usize & 7];
+    }
+
+    values
+}
+
+fn lerp4(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for (i, result) in result.iter_mut().enumerate() {
+        *result = a[i] + (b[i] - a[i]) * t;
+    }
+
+    result
+}
+
+/// Convert the bits of a half precision float into a float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = (bits & 0x3FF) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        exponent => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use image::{codecs::hdr::HdrEncoder, DynamicImage, ImageOutputFormat, Rgb};
+    use nalgebra_glm as glm;
+
+    use super::{SoftwareFramebuffer, SoftwareRenderer};
+    use crate::{
+        renderer::{
+            backend::RendererBackend,
+            shader::{BuiltinVariable, Expression, Program, Type},
+            BlendMode, CullingMode, DepthTesting, DepthTestingFunction, DrawArraysIndirectCommand,
+            DrawingMode, PipelineDescriptor, PipelineId, PipelineState, PixelFormat, PixelRegion,
+            Pixels, RenderTargetOptions, Renderer, ResourceIds, ResourceKind, StencilTesting,
+            TextureId, UniformValue, VertexComponentType, VertexFormat,
+        },
+        texture::{
+            MagFilter, MinFilter, Texture, TextureError, TextureKind, TextureOptions,
+            TextureRegion, TextureSourceType, TextureStorageType,
+        },
+    };
+
+    fn renderer(width: u32, height: u32) -> (Renderer, SoftwareFramebuffer) {
+        let backend = SoftwareRenderer::new(width, height);
+        let framebuffer = backend.framebuffer();
+
+        (Renderer::new(Box::new(backend)), framebuffer)
+    }
+
+    fn bytes(data: &[f32]) -> Vec<u8> {
+        data.iter().flat_map(|f| f.to_ne_bytes().to_vec()).collect()
+    }
+
+    /// A program that draws (x, y, z) positions in a single color
+    fn color_program(color: [f32; 4]) -> Result<Program> {
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec3);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position, 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let output = ctx.output("frag_color", Type::Vec4);
+                let color: Vec<Expression> = color.iter().map(|&c| c.into()).collect();
+                ctx.set_output(output, Expression::vec(&color));
+            });
+        });
+        program.ok()?;
+
+        Ok(program)
+    }
+
+    fn color_pipeline(renderer: &mut Renderer, color: [f32; 4]) -> Result<PipelineId> {
+        renderer.load_pipeline(color_program(color)?)
+    }
+
+    fn draw(renderer: &mut Renderer, pipeline: PipelineId, positions: &[f32]) -> Result<()> {
+        let buffer = renderer.load_vertex_buffer(&bytes(positions));
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_vertex_arrays(&[buffer], &[0], &[0])?;
+            bound.draw(DrawingMode::Triangles, 0, positions.len() as u64 / 3);
+        }
+
+        renderer.unload_vertex_buffer(buffer)
+    }
+
+    /// A pipeline that draws (x, y) positions, sampling the texture "tex" using
+    /// the position mapped to [0, 1] as the uv coordinates
+    fn texture_pipeline(renderer: &mut Renderer) -> Result<PipelineId> {
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec2);
+                let uv = ctx.output("uv", Type::Vec2);
+                ctx.set_output(uv, position.clone() * 0.5.into() + 0.5.into());
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position, 0.0.into(), 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let tex = ctx.uniform("tex", Type::Sampler2D);
+                let uv = ctx.input("uv", Type::Vec2);
+                let output = ctx.output("frag_color", Type::Vec4);
+                ctx.set_output(output, Expression::texture(tex, uv));
+            });
+        });
+        program.ok()?;
+
+        renderer.load_pipeline(program)
+    }
+
+    /// Draw a texture over the whole viewport
+    fn draw_texture(
+        renderer: &mut Renderer,
+        pipeline: PipelineId,
+        texture: TextureId,
+    ) -> Result<()> {
+        let buffer = renderer.load_vertex_buffer(&bytes(&[
+            -1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0,
+        ]));
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_texture("tex", texture)?;
+            bound.bind_vertex_arrays(&[buffer], &[0], &[8])?;
+            bound.draw(DrawingMode::Triangles, 0, 6);
+        }
+
+        renderer.unload_vertex_buffer(buffer)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_triangle() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(8, 8);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+
+        renderer.clear(0.0, 0.0, 0.0);
+
+        // covers the bottom left half of the screen
+        draw(
+            &mut renderer,
+            red,
+            &[-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, -1.0, 1.0, 0.0],
+        )?;
+
+        assert_eq!(framebuffer.pixel(0, 7), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(2, 4), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(7, 0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(5, 3), [0.0, 0.0, 0.0, 1.0]);
+
+        // pixels on the diagonal are drawn exactly once between the two halves
+        let blue = color_pipeline(&mut renderer, [0.0, 0.0, 1.0, 1.0])?;
+        renderer.depth_testing(DepthTesting::None);
+        draw(
+            &mut renderer,
+            blue,
+            &[1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0],
+        )?;
+
+        let image = framebuffer.image();
+        let red_count = image.pixels().filter(|p| p.0 == [255, 0, 0, 255]).count();
+        let blue_count = image.pixels().filter(|p| p.0 == [0, 0, 255, 255]).count();
+        assert_eq!(red_count + blue_count, 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_depth_and_culling() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+        let green = color_pipeline(&mut renderer, [0.0, 1.0, 0.0, 1.0])?;
+
+        let quad = |z: f32| {
+            vec![
+                -1.0, -1.0, z, 1.0, -1.0, z, 1.0, 1.0, z, -1.0, -1.0, z, 1.0, 1.0, z, -1.0, 1.0, z,
+            ]
+        };
+
+        renderer.clear(0.0, 0.0, 0.0);
+        renderer.depth_testing(DepthTesting::Default);
+
+        // the further quad is drawn second so fails the depth test
+        draw(&mut renderer, green, &quad(-0.5))?;
+        draw(&mut renderer, red, &quad(0.5))?;
+        assert_eq!(framebuffer.pixel(1, 1), [0.0, 1.0, 0.0, 1.0]);
+        assert!((framebuffer.depth(1, 1) - 0.25).abs() < 1e-6);
+
+        // clockwise triangles are back faces
+        let z = -0.9;
+        let clockwise = [
+            -1.0, -1.0, z, 1.0, 1.0, z, 1.0, -1.0, z, -1.0, -1.0, z, -1.0, 1.0, z, 1.0, 1.0, z,
+        ];
+        renderer.backface_culling(CullingMode::Back);
+        draw(&mut renderer, red, &clockwise)?;
+        assert_eq!(framebuffer.pixel(1, 1), [0.0, 1.0, 0.0, 1.0]);
+
+        renderer.backface_culling(CullingMode::Front);
+        draw(&mut renderer, red, &clockwise)?;
+        assert_eq!(framebuffer.pixel(1, 1), [1.0, 0.0, 0.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_sampling() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+
+        // 2x2 texture, the first row is at v = 0, the bottom of the screen
+        let texture = Texture::from_raw_config(
+            &[
+                255, 0, 0, 255, 0, 255, 0, 255, //
+                0, 0, 255, 255, 255, 255, 255, 255,
+            ],
+            TextureOptions {
+                width: 2,
+                height: 2,
+                ..Default::default()
+            },
+        )?;
+        let texture: TextureId = renderer.load_texture(texture);
+
+        let pipeline = texture_pipeline(&mut renderer)?;
+
+        renderer.clear(0.0, 0.0, 0.0);
+        draw_texture(&mut renderer, pipeline, texture)?;
+
+        assert_eq!(framebuffer.pixel(0, 3), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 0), [1.0, 1.0, 1.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_target() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+        let textured = texture_pipeline(&mut renderer)?;
+
+        let target = renderer.load_render_target(RenderTargetOptions {
+            min_filter: MinFilter::Nearest,
+            mag_filter: MagFilter::Nearest,
+            ..RenderTargetOptions::new(2, 2)
+        })?;
+        let color = target.color().unwrap();
+
+        renderer.clear(0.0, 0.0, 0.0);
+
+        // draw into the left half of the target
+        renderer.bind_render_target(Some(target))?;
+        renderer.clear(0.0, 1.0, 0.0);
+
+        // the window's viewport is kept while drawing to a target, so frames
+        // can be captured at the window's size
+        renderer.viewport(2, 2);
+        assert_eq!(renderer.screen_viewport(), (4, 4));
+        draw(
+            &mut renderer,
+            red,
+            &[
+                -1.0, -1.0, 0.5, 0.0, -1.0, 0.5, 0.0, 1.0, 0.5, -1.0, -1.0, 0.5, 0.0, 1.0, 0.5,
+                -1.0, 1.0, 0.5,
+            ],
+        )?;
+        renderer.bind_render_target(None)?;
+
+        // nothing drawn to the target is in the main framebuffer
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 0.0, 1.0]);
+
+        // the viewport is restored to the whole screen, not the target's size
+        renderer.depth_testing(DepthTesting::None);
+        draw_texture(&mut renderer, textured, color)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(1, 3), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(2, 0), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [0.0, 1.0, 0.0, 1.0]);
+
+        // the depth buffer can be sampled, it is stored in the red component
+        draw_texture(&mut renderer, textured, target.depth().unwrap())?;
+        assert_eq!(framebuffer.pixel(0, 0), [0.75, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 0), [1.0, 0.0, 0.0, 1.0]);
+
+        renderer.unload_render_target(target)?;
+        assert!(renderer
+            .bind_pipeline(textured)?
+            .bind_texture("tex", color)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uniforms() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(2, 2);
+
+        let mut program = Program::new(|ctx| {
+            let tint = ctx.uniform_block("Material", &[("tint", Type::Vec4)]);
+
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec3);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position, 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let palette = ctx.uniform("palette", Type::Array(&Type::Vec4, 2));
+                let index = ctx.uniform("index", Type::Integer);
+                let scale = ctx.uniform("scale", Type::Floating);
+                ctx.uniform("unused", Type::Floating);
+                let output = ctx.output("frag_color", Type::Vec4);
+
+                let color = Expression::index(palette, index) * scale * tint[0].clone();
+                ctx.set_output(output, color);
+            });
+        });
+        program.ok()?;
+        let pipeline = renderer.load_pipeline(program)?;
+
+        let material = renderer
+            .load_uniform_buffer(&UniformValue::std140(&[
+                glm::vec4(1.0, 1.0, 1.0, 2.0).into()
+            ]));
+        let too_small = renderer.load_uniform_buffer(&[0; 4]);
+
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_uniform(
+                "palette",
+                vec![glm::vec4(1.0, 0.0, 0.0, 1.0), glm::vec4(0.0, 1.0, 0.0, 1.0)],
+            )?;
+            bound.bind_uniform("index", 1)?;
+            bound.bind_uniform("scale", 0.5)?;
+            bound.bind_uniform_buffer("Material", material)?;
+
+            // wrong names and types are errors instead of being ignored
+            assert!(bound.bind_uniform("missing", 1.0).is_err());
+            assert!(bound.bind_uniform("scale", 1).is_err());
+            assert!(bound.bind_uniform("index", glm::vec2(1.0, 0.0)).is_err());
+            assert!(bound.bind_uniform("palette", vec![1.0, 2.0]).is_err());
+            assert!(bound
+                .bind_uniform("palette", vec![glm::vec4(0.0, 0.0, 0.0, 0.0); 3])
+                .is_err());
+            assert!(bound
+                .bind_uniform("tint", glm::vec4(1.0, 1.0, 1.0, 1.0))
+                .is_err());
+            assert!(bound.bind_uniform_buffer("Missing", material).is_err());
+            assert!(bound.bind_uniform_buffer("Material", too_small).is_err());
+
+            // the same as OpenGl, uniforms that are never read are optimized out
+            assert!(bound.bind_uniform("unused", 1.0).is_err());
+        }
+
+        // binding to a pipeline that was never loaded is an error
+        let mut backend = SoftwareRenderer::new(1, 1);
+        let unknown = PipelineId(ResourceIds::default().next(ResourceKind::Pipeline));
+        assert!(backend
+            .pipeline_bind_uniform(unknown, "scale", &0.5.into())
+            .is_err());
+
+        renderer.clear(0.0, 0.0, 0.0);
+        draw(
+            &mut renderer,
+            pipeline,
+            &[
+                -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 1.0, 0.0,
+                -1.0, 1.0, 0.0,
+            ],
+        )?;
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.5, 0.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instancing() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let palette = ctx.uniform("palette", Type::Array(&Type::Vec4, 2));
+                let position = ctx.input("position", Type::Vec2);
+                let offset = ctx.input("offset", Type::Vec2);
+                let color = ctx.output("color", Type::Vec4);
+
+                let instance = ctx.get_builtin(BuiltinVariable::InstanceId);
+                ctx.set_output(color, Expression::index(palette, instance));
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position + offset, 0.0.into(), 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let color = ctx.input("color", Type::Vec4);
+                let output = ctx.output("frag_color", Type::Vec4);
+                ctx.set_output(output, color);
+            });
+        });
+        program.ok()?;
+        let pipeline = renderer.load_pipeline(program)?;
+
+        // a quad covering the left half of the screen, drawn a second time
+        // moved into the right half
+        let positions = renderer.load_vertex_buffer(&bytes(&[
+            -1.0, -1.0, 0.0, -1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 1.0, -1.0, 1.0,
+        ]));
+        let offsets = renderer.load_vertex_buffer(&bytes(&[0.0, 0.0, 1.0, 0.0]));
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_uniform(
+                "palette",
+                vec![glm::vec4(1.0, 0.0, 0.0, 1.0), glm::vec4(0.0, 1.0, 0.0, 1.0)],
+            )?;
+            bound.bind_vertex_arrays_instanced(&[positions, offsets], &[0, 0], &[8, 8], &[0, 1])?;
+            bound.draw_instanced(DrawingMode::Triangles, 0, 6, 2);
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(1, 3), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(2, 0), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [0.0, 1.0, 0.0, 1.0]);
+
+        // the base instance selects the offset, but not the instance id
+        let draws = [
+            DrawArraysIndirectCommand {
+                count: 6,
+                instances: 1,
+                first: 0,
+                base_instance: 0,
+            },
+            DrawArraysIndirectCommand {
+                count: 6,
+                instances: 1,
+                first: 0,
+                base_instance: 1,
+            },
+        ];
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_uniform(
+                "palette",
+                vec![glm::vec4(1.0, 0.0, 0.0, 1.0), glm::vec4(0.0, 1.0, 0.0, 1.0)],
+            )?;
+            bound.bind_vertex_arrays_instanced(&[positions, offsets], &[0, 0], &[8, 8], &[0, 1])?;
+            bound.draw_indirect(DrawingMode::Triangles, &draws);
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [1.0, 0.0, 0.0, 1.0]);
+
+        // the base instance can index a storage buffer, rather than offsets
+        // being read from a per instance vertex array
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let offsets = ctx.storage_buffer("offsets", &Type::Vec2);
+                let position = ctx.input("position", Type::Vec2);
+                let color = ctx.output("color", Type::Vec4);
+
+                let base_instance = ctx.get_builtin(BuiltinVariable::BaseInstance);
+                let offset = Expression::index(offsets, base_instance);
+                ctx.set_output(
+                    color,
+                    Expression::vec(&[offset.clone(), 0.0.into(), 1.0.into()]),
+                );
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position + offset, 0.0.into(), 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let color = ctx.input("color", Type::Vec4);
+                let output = ctx.output("frag_color", Type::Vec4);
+                ctx.set_output(output, color);
+            });
+        });
+        program.ok()?;
+        let pipeline = renderer.load_pipeline(program)?;
+        let offsets = renderer.load_storage_buffer(&bytes(&[0.0, 0.0, 1.0, 0.0]));
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_storage_buffer("offsets", offsets)?;
+            bound.bind_vertex_arrays(&[positions], &[0], &[8])?;
+            bound.draw_indirect(DrawingMode::Triangles, &draws);
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [1.0, 0.0, 0.0, 1.0]);
+
+        // commands can be uploaded once into an indirect buffer, then drawn
+        // starting from any command
+        let commands: Vec<u8> = draws.iter().flat_map(|draw| draw.to_bytes()).collect();
+        let commands = renderer.load_indirect_buffer(&commands);
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_storage_buffer("offsets", offsets)?;
+            bound.bind_vertex_arrays(&[positions], &[0], &[8])?;
+            bound.draw_indirect_buffer(
+                DrawingMode::Triangles,
+                commands,
+                DrawArraysIndirectCommand::SIZE,
+                1,
+            )?;
+
+            // reading past the end of the buffer, or from an unaligned offset
+            // is an error
+            assert!(bound
+                .draw_indirect_buffer(DrawingMode::Triangles, commands, 0, 3)
+                .is_err());
+            assert!(bound
+                .draw_indirect_buffer(DrawingMode::Triangles, commands, 2, 1)
+                .is_err());
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 3), [1.0, 0.0, 0.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vertex_formats() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(2, 2);
+
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec2);
+                let color_in = ctx.input("color_in", Type::Vec4);
+                let color = ctx.output("color", Type::Vec4);
+                ctx.set_output(color, color_in);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position, 0.0.into(), 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let color = ctx.input("color", Type::Vec4);
+                let output = ctx.output("frag_color", Type::Vec4);
+                ctx.set_output(output, color);
+            });
+        });
+        program.ok()?;
+
+        // normalized shorts covering the screen, with normalized byte colors
+        let mut descriptor = PipelineDescriptor::from(program);
+        descriptor.vertex_formats = vec![
+            VertexFormat::new(VertexComponentType::I16, true),
+            VertexFormat::new(VertexComponentType::U8, true),
+        ];
+        let pipeline = renderer.load_pipeline(descriptor)?;
+
+        let positions: Vec<u8> = [-1, -1, 1, -1, 1, 1, -1, -1, 1, 1, -1, 1]
+            .iter()
+            .flat_map(|&p: &i16| (p * i16::MAX).to_ne_bytes().to_vec())
+            .collect();
+        let positions = renderer.load_vertex_buffer(&positions);
+        let colors = renderer.load_vertex_buffer(&[255, 0, 51, 255].repeat(6));
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_vertex_arrays(&[positions, colors], &[0, 0], &[0, 0])?;
+            bound.draw(DrawingMode::Triangles, 0, 6);
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.2, 1.0]);
+        assert_eq!(framebuffer.pixel(1, 1), [1.0, 0.0, 0.2, 1.0]);
+
+        // unsigned short joint indices read by an integer vector input
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let position = ctx.input("position", Type::Vec2);
+                let joints = ctx.input("joints", Type::UVec4);
+                let color = ctx.output("color", Type::Vec4);
+                ctx.set_output(color, Expression::vec(&[joints]) * 0.25.into());
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[position, 0.0.into(), 1.0.into()]),
+                );
+            });
+
+            ctx.frag(|ctx| {
+                let color = ctx.input("color", Type::Vec4);
+                let output = ctx.output("frag_color", Type::Vec4);
+                ctx.set_output(output, color);
+            });
+        });
+        program.ok()?;
+
+        let mut descriptor = PipelineDescriptor::from(program);
+        descriptor.vertex_formats = vec![
+            VertexFormat::new(VertexComponentType::I16, true),
+            VertexFormat::new(VertexComponentType::U16, false),
+        ];
+        let pipeline = renderer.load_pipeline(descriptor)?;
+
+        let joints: Vec<u8> = [1, 0, 2, 4]
+            .repeat(6)
+            .iter()
+            .flat_map(|&j: &u16| j.to_ne_bytes().to_vec())
+            .collect();
+        let joints = renderer.load_vertex_buffer(&joints);
+
+        renderer.clear(0.0, 0.0, 0.0);
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_vertex_arrays(&[positions, joints], &[0, 0], &[0, 0])?;
+            bound.draw(DrawingMode::Triangles, 0, 6);
+        }
+
+        assert_eq!(framebuffer.pixel(0, 0), [0.25, 0.0, 0.5, 1.0]);
+        assert_eq!(framebuffer.pixel(1, 1), [0.25, 0.0, 0.5, 1.0]);
+
+        // unsigned inputs read u32 by default
+        assert_eq!(
+            VertexFormat::default_for(&Type::UnsignedInteger),
+            VertexFormat::new(VertexComponentType::U32, false)
+        );
+
+        // integer inputs cannot be normalized
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                ctx.input("joint", Type::Integer);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()]),
+                );
+            });
+        });
+        program.ok()?;
+
+        let mut descriptor = PipelineDescriptor::from(program);
+        descriptor.vertex_formats = vec![VertexFormat::new(VertexComponentType::U8, true)];
+        assert!(renderer.load_pipeline(descriptor).is_err());
+
+        // or read floats, including integer vector inputs
+        let mut program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                ctx.input("joints", Type::UVec4);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()]),
+                );
+            });
+        });
+        program.ok()?;
+
+        let mut descriptor = PipelineDescriptor::from(program);
+        descriptor.vertex_formats = vec![VertexFormat::new(VertexComponentType::F32, false)];
+        assert!(renderer.load_pipeline(descriptor).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_blending() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(2, 2);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 0.5])?;
+        let quad = [
+            -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0,
+            1.0, 0.0,
+        ];
+
+        renderer.clear(0.0, 0.0, 1.0);
+        renderer.depth_testing(DepthTesting::None);
+        renderer.blending(BlendMode::Alpha);
+        draw(&mut renderer, red, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0), [0.5, 0.0, 0.5, 1.0]);
+
+        renderer.blending(BlendMode::Additive);
+        draw(&mut renderer, red, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.5, 1.0]);
+
+        renderer.blending(BlendMode::None);
+        draw(&mut renderer, red, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 0.0, 0.0, 0.5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline_state() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(2, 2);
+        let state = PipelineState {
+            depth: DepthTesting::None,
+            blend: BlendMode::Additive,
+            ..Default::default()
+        };
+        let additive = PipelineDescriptor::new(color_program([0.5, 0.0, 0.0, 1.0])?, state);
+        let additive = renderer.load_pipeline(additive)?;
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+        let quad = [
+            -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0,
+            1.0, 0.0,
+        ];
+
+        // the pipeline's state replaces the state set before it was bound
+        renderer.clear(0.0, 0.0, 1.0);
+        renderer.blending(BlendMode::None);
+        draw(&mut renderer, additive, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0)[..3], [0.5, 0.0, 1.0]);
+
+        renderer.blending(BlendMode::None);
+        draw(&mut renderer, additive, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0)[..3], [1.0, 0.0, 1.0]);
+
+        // pipelines without state use the last state set
+        renderer.blending(BlendMode::None);
+        draw(&mut renderer, red, &quad)?;
+        assert_eq!(framebuffer.pixel(0, 0)[..3], [1.0, 0.0, 0.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stencil() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+        let green = color_pipeline(&mut renderer, [0.0, 1.0, 0.0, 1.0])?;
+
+        let left = [
+            -1.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0, 1.0, 0.0, -1.0, -1.0, 0.0, 0.0, 1.0, 0.0, -1.0,
+            1.0, 0.0,
+        ];
+        let full = [
+            -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0,
+            1.0, 0.0,
+        ];
+
+        renderer.clear(0.0, 0.0, 0.0);
+        renderer.depth_testing(DepthTesting::None);
+
+        // mark the left half, then only draw where it was marked
+        renderer.stencil_testing(StencilTesting::write(1));
+        draw(&mut renderer, red, &left)?;
+        assert_eq!(framebuffer.stencil(0, 0), 1);
+        assert_eq!(framebuffer.stencil(3, 0), 0);
+
+        renderer.stencil_testing(StencilTesting::test(DepthTestingFunction::Equal, 1));
+        draw(&mut renderer, green, &full)?;
+        assert_eq!(framebuffer.pixel(1, 2), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(2, 2), [0.0, 0.0, 0.0, 1.0]);
+
+        // clears change every bit, even when the write mask is 0
+        renderer.clear_stencil(2);
+        assert_eq!(framebuffer.stencil(0, 0), 2);
+
+        renderer.clear(0.0, 0.0, 0.0);
+        assert_eq!(framebuffer.stencil(0, 0), 0);
+
+        // read only depth testing does not stop the depth buffer being cleared
+        renderer.depth_testing(DepthTesting::Enabled {
+            read_only: true,
+            func: DepthTestingFunction::Less,
+        });
+        renderer.clear(0.0, 0.0, 0.0);
+        assert_eq!(framebuffer.depth(0, 0), 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_kinds() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(1, 1);
+
+        // samples a texture at the coordinates in a uniform
+        let mut pipeline = |sampler: Type| {
+            let mut program = Program::new(|ctx| {
+                ctx.vertex(|ctx| {
+                    let position = ctx.input("position", Type::Vec2);
+                    ctx.set_builtin(
+                        BuiltinVariable::VertexPosition,
+                        Expression::vec(&[position, 0.0.into(), 1.0.into()]),
+                    );
+                });
+
+                ctx.frag(|ctx| {
+                    let tex = ctx.uniform("tex", sampler);
+                    let coords = ctx.uniform("coords", Type::Vec3);
+                    let output = ctx.output("frag_color", Type::Vec4);
+                    ctx.set_output(output, Expression::texture(tex, coords));
+                });
+            });
+            program.ok()?;
+
+            renderer.load_pipeline(program)
+        };
+        let cube = pipeline(Type::SamplerCube)?;
+        let array = pipeline(Type::Sampler2DArray)?;
+        let volume = pipeline(Type::Sampler3D)?;
+
+        // one texel per layer, the nth layer has a red value of n / 5
+        let layers = |kind: TextureKind, depth: u32| {
+            let data: Vec<u8> = (0..depth)
+                .flat_map(|n| vec![n as u8 * 51, 0, 0, 255])
+                .collect();
+            Texture::from_raw_config(
+                &data,
+                TextureOptions {
+                    width: 1,
+                    height: 1,
+                    depth,
+                    kind,
+                    ..Default::default()
+                },
+            )
+        };
+        renderer.depth_testing(DepthTesting::None);
+        let cubemap = renderer.load_texture(layers(TextureKind::Cubemap, 6)?);
+        let layered = renderer.load_texture(layers(TextureKind::Array2D, 3)?);
+        let texture_3d = renderer.load_texture(layers(TextureKind::Texture3D, 2)?);
+
+        let quad = renderer.load_vertex_buffer(&bytes(&[
+            -1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0,
+        ]));
+        let mut sample = |pipeline, texture, coords: glm::Vec3| -> Result<f32> {
+            {
+                let mut bound = renderer.bind_pipeline(pipeline)?;
+                bound.bind_texture("tex", texture)?;
+                bound.bind_uniform("coords", coords)?;
+                bound.bind_vertex_arrays(&[quad], &[0], &[8])?;
+                bound.draw(DrawingMode::Triangles, 0, 6);
+            }
+
+            Ok(framebuffer.pixel(0, 0)[0])
+        };
+
+        // -y is the fourth face
+        assert_eq!(sample(cube, cubemap, glm::vec3(0.1, -1.0, 0.2))?, 0.6);
+        assert_eq!(sample(array, layered, glm::vec3(0.5, 0.5, 2.0))?, 0.4);
+        assert_eq!(sample(volume, texture_3d, glm::vec3(0.5, 0.5, 0.75))?, 0.2);
+
+        // textures must be bound to a sampler of the same kind
+        let mut bound = renderer.bind_pipeline(cube)?;
+        assert!(bound.bind_texture("tex", layered).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mip_levels() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(1, 1);
+        let pipeline = texture_pipeline(&mut renderer)?;
+        renderer.depth_testing(DepthTesting::None);
+
+        let options = TextureOptions {
+            width: 2,
+            height: 2,
+            min_filter: MinFilter::NearestMipmapNearest,
+            ..Default::default()
+        };
+        let base = vec![255; 16];
+
+        // drawing the 2x2 texture into a single pixel uses the 1x1 level
+        let texture =
+            Texture::from_raw_config(&base, options)?.with_mip_level(vec![0, 255, 0, 255])?;
+        assert!(texture.clone().with_mip_level(vec![0; 4]).is_err());
+
+        let texture = renderer.load_texture(texture);
+        draw_texture(&mut renderer, pipeline, texture)?;
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 1.0, 0.0, 1.0]);
+
+        // generated levels replace the ones provided
+        let texture = Texture::from_raw_config(
+            &base,
+            TextureOptions {
+                generate_mipmaps: true,
+                ..options
+            },
+        )?
+        .with_mip_level(vec![0, 255, 0, 255])?;
+
+        let texture = renderer.load_texture(texture);
+        draw_texture(&mut renderer, pipeline, texture)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 1.0, 1.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_textures() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+        let pipeline = texture_pipeline(&mut renderer)?;
+        renderer.depth_testing(DepthTesting::None);
+
+        // a single BC1 block, the left two columns are red, the right two blue
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0x50, 0x50, 0x50, 0x50];
+        let words = |words: &[u32]| words.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        let mut dds: Vec<u8> = b"DDS ".to_vec();
+        let mut header: Vec<u8> = words(&[124, 0x1007, 4, 4, 8, 0, 1]);
+        header.resize(72, 0);
+        header.extend(words(&[32, 0x4]));
+        header.extend(b"DXT1");
+        header.resize(124, 0);
+        dds.extend(header);
+        dds.extend(&block);
+
+        let mut ktx2 = vec![
+            0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+        ];
+        ktx2.extend(words(&[131, 1, 4, 4, 0, 0, 1, 1, 0]));
+        ktx2.resize(80, 0);
+        ktx2.extend(words(&[104, 0, 8, 0, 8, 0]));
+        ktx2.extend(&block);
+
+        let textures = [
+            Texture::from_dds_config(&dds, Default::default())?,
+            Texture::from_ktx2_config(&ktx2, Default::default())?,
+        ];
+
+        for texture in textures.iter() {
+            assert!(texture.is_compressed());
+            assert_eq!((texture.width(), texture.height()), (4, 4));
+
+            let texture = renderer.load_texture(texture.clone());
+            draw_texture(&mut renderer, pipeline, texture)?;
+            for y in 0..4 {
+                assert_eq!(framebuffer.pixel(0, y), [1.0, 0.0, 0.0, 1.0]);
+                assert_eq!(framebuffer.pixel(3, y), [0.0, 0.0, 1.0, 1.0]);
+            }
+        }
+
+        // basis universal files are supercompressed
+        ktx2[44] = 1;
+        assert!(Texture::from_ktx2_config(&ktx2, Default::default()).is_err());
+        assert!(Texture::from_dds_config(&dds[..130], Default::default()).is_err());
+
+        // bc7 blocks cannot be decoded, so they are reported as unsupported
+        let capabilities = renderer.capabilities();
+        assert!(capabilities.check_texture(&textures[0]).is_ok());
+        let bc7 = Texture::from_raw_config(
+            &[0; 16],
+            TextureOptions {
+                width: 4,
+                height: 4,
+                storage: TextureStorageType::BC7RGBA,
+                ..Default::default()
+            },
+        )?;
+        assert!(capabilities.check_texture(&bc7).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_textures() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(1, 1);
+        let pipeline = texture_pipeline(&mut renderer)?;
+        renderer.depth_testing(DepthTesting::None);
+
+        // 1.5, 2.0, 0.0, 1.0 as half floats
+        let half = [0x3E00, 0x4000, 0x0000, 0x3C00];
+        let options = TextureOptions {
+            width: 1,
+            height: 1,
+            source_type: TextureSourceType::F16,
+            storage: TextureStorageType::RGBA16F,
+            ..Default::default()
+        };
+
+        let texture = renderer.load_texture(Texture::from_raw_config16(&half, options)?);
+        draw_texture(&mut renderer, pipeline, texture)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.5, 2.0, 0.0, 1.0]);
+
+        // normalized storage clamps the values
+        let texture = renderer.load_texture(Texture::from_raw_config16(
+            &half,
+            TextureOptions {
+                storage: TextureStorageType::RGBA,
+                ..options
+            },
+        )?);
+        draw_texture(&mut renderer, pipeline, texture)?;
+        assert_eq!(framebuffer.pixel(0, 0), [1.0, 1.0, 0.0, 1.0]);
+
+        let bright = renderer.load_texture(Texture::from_raw_config_f32(
+            &[4.0, 0.5, 0.0, 1.0],
+            TextureOptions {
+                source_type: TextureSourceType::F32,
+                storage: TextureStorageType::RGBA32F,
+                ..options
+            },
+        )?);
+        draw_texture(&mut renderer, pipeline, bright)?;
+        assert_eq!(framebuffer.pixel(0, 0), [4.0, 0.5, 0.0, 1.0]);
+
+        // blending into a float render target is not clamped, the clear color
+        // has an alpha of 1
+        let target = renderer.load_render_target(RenderTargetOptions {
+            color: Some(TextureStorageType::RGBA16F),
+            ..RenderTargetOptions::new(1, 1)
+        })?;
+        renderer.bind_render_target(Some(target))?;
+        renderer.clear(0.0, 0.0, 0.0);
+        renderer.blending(BlendMode::Additive);
+        draw_texture(&mut renderer, pipeline, bright)?;
+        draw_texture(&mut renderer, pipeline, bright)?;
+        renderer.bind_render_target(None)?;
+
+        renderer.blending(BlendMode::None);
+        draw_texture(&mut renderer, pipeline, target.color().unwrap())?;
+        assert_eq!(framebuffer.pixel(0, 0), [8.0, 1.0, 0.0, 3.0]);
+
+        // every layer of a texture must decode to the same format
+        let mut png = vec![];
+        DynamicImage::new_rgba8(1, 1).write_to(&mut png, ImageOutputFormat::Png)?;
+        let mut hdr = vec![];
+        HdrEncoder::new(&mut hdr).encode(&[Rgb([4.0, 0.5, 0.0])], 1, 1)?;
+        assert!(matches!(
+            Texture::from_encoding_array_config(&[&png, &hdr], Default::default()),
+            Err(TextureError::LayerFormat { layer: 1, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_updates() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(2, 2);
+        let pipeline = texture_pipeline(&mut renderer)?;
+        renderer.depth_testing(DepthTesting::None);
+
+        let options = TextureOptions {
+            width: 2,
+            height: 2,
+            ..Default::default()
+        };
+        let texture = renderer.load_texture(Texture::from_raw_config(&[0; 16], options)?);
+
+        renderer.update_texture(texture, TextureRegion::new(1, 0, 1, 1), &[255, 0, 0, 255])?;
+        draw_texture(&mut renderer, pipeline, texture)?;
+        // the first row of a texture is the bottom of the framebuffer
+        assert_eq!(framebuffer.pixel(0, 1), [0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(framebuffer.pixel(1, 1), [1.0, 0.0, 0.0, 1.0]);
+
+        // regions must be inside the texture and match the data's size
+        let region = TextureRegion::new(1, 1, 2, 1);
+        assert!(renderer.update_texture(texture, region, &[0; 8]).is_err());
+        let region = TextureRegion::new(0, 0, 2, 1);
+        assert!(renderer.update_texture(texture, region, &[0; 4]).is_err());
+
+        // reloading keeps the id, but can change the size
+        let texture_data = [0, 0, 255, 255].repeat(4);
+        let texture_data = Texture::from_raw_config(
+            &texture_data,
+            TextureOptions {
+                width: 4,
+                height: 1,
+                ..options
+            },
+        )?;
+        renderer.reload_texture(texture, texture_data)?;
+        renderer.update_texture(texture, TextureRegion::new(3, 0, 1, 1), &[0, 255, 0, 255])?;
+        draw_texture(&mut renderer, pipeline, texture)?;
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(framebuffer.pixel(1, 0), [0.0, 1.0, 0.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_updates() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(4, 4);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+        renderer.depth_testing(DepthTesting::None);
+
+        let draw_buffer = |renderer: &mut Renderer, buffer, offset, count| -> Result<()> {
+            renderer.clear(0.0, 0.0, 0.0);
+            let mut bound = renderer.bind_pipeline(red)?;
+            bound.bind_vertex_arrays(&[buffer], &[offset], &[0])?;
+            bound.draw(DrawingMode::Triangles, 0, count);
+            Ok(())
+        };
+
+        let bottom_left = [-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, -1.0, 1.0, 0.0];
+        let top_right = [1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0];
+
+        let buffer = renderer.load_vertex_buffer_stream(&bytes(&bottom_left));
+        renderer.update_vertex_buffer(buffer, 0, &bytes(&top_right))?;
+        draw_buffer(&mut renderer, buffer, 0, 3)?;
+        assert_eq!(framebuffer.pixel(0, 3), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 0), [1.0, 0.0, 0.0, 1.0]);
+
+        // updates cannot grow the buffer, orphaning can
+        assert!(renderer
+            .update_vertex_buffer(buffer, 4, &bytes(&top_right))
+            .is_err());
+        renderer.orphan_vertex_buffer(buffer, &bytes(&[bottom_left, top_right].concat()))?;
+        draw_buffer(&mut renderer, buffer, 0, 6)?;
+        assert_eq!(framebuffer.pixel(0, 3), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 0), [1.0, 0.0, 0.0, 1.0]);
+
+        // ring buffer allocations are aligned and wrap around when full
+        let ring = renderer.load_vertex_ring_buffer(128);
+        assert_eq!(
+            renderer.write_vertex_ring_buffer(ring, &bytes(&top_right))?,
+            0
+        );
+        assert_eq!(
+            renderer.write_vertex_ring_buffer(ring, &bytes(&bottom_left))?,
+            48
+        );
+        draw_buffer(&mut renderer, ring, 48, 3)?;
+        assert_eq!(framebuffer.pixel(0, 3), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(framebuffer.pixel(3, 0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            renderer.write_vertex_ring_buffer(ring, &bytes(&top_right))?,
+            0
+        );
+
+        assert!(renderer.write_vertex_ring_buffer(ring, &[0; 256]).is_err());
+        assert!(renderer.write_vertex_ring_buffer(buffer, &[0; 4]).is_err());
+        assert!(renderer.orphan_vertex_buffer(ring, &[0; 4]).is_err());
+        assert!(renderer.update_vertex_buffer(ring, 0, &[0; 4]).is_err());
+
+        Ok(())
+    }
+    #[test]
+    fn test_read_pixels() -> Result<()> {
+        let (mut renderer, framebuffer) = renderer(8, 8);
+        let red = color_pipeline(&mut renderer, [1.0, 0.0, 0.0, 1.0])?;
+
+        renderer.clear(0.0, 0.0, 0.5);
+        draw(
+            &mut renderer,
+            red,
+            &[-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, -1.0, 1.0, 0.0],
+        )?;
+
+        let region = PixelRegion::new(0, 0, 8, 8);
+        let pixels = renderer.read_pixels(region, PixelFormat::Rgba8)?;
+        assert_eq!(pixels.to_rgba8(), framebuffer.image());
+
+        // regions are measured from the bottom left, images start at the top
+        let pixels = renderer.read_pixels(PixelRegion::new(6, 0, 2, 3), PixelFormat::Rgba32F)?;
+        match pixels {
+            Pixels::Rgba32F(image) => {
+                assert_eq!((image.width(), image.height()), (2, 3));
+                assert_eq!(image.get_pixel(0, 2).0, [1.0, 0.0, 0.0, 1.0]);
+                assert_eq!(image.get_pixel(1, 0).0, [0.0, 0.0, 0.5, 1.0]);
+            }
+            Pixels::Rgba8(_) => panic!("expected float pixels"),
+        }
+
+        assert!(renderer
+            .read_pixels(PixelRegion::new(4, 4, 5, 1), PixelFormat::Rgba8)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute() -> Result<()> {
+        let (mut renderer, _) = renderer(1, 1);
+
+        let mut program = Program::new(|ctx| {
+            let values = ctx.storage_buffer("values", &Type::Floating);
+
+            ctx.compute([2, 1, 1], |ctx| {
+                let scale = ctx.uniform("scale", Type::Floating);
+                let id = ctx.get_builtin(BuiltinVariable::GlobalInvocationX);
+                let value = Expression::index(values.clone(), id.clone()) * scale;
+                ctx.store(values, id, value);
+            });
+        });
+        program.ok()?;
+        let pipeline = renderer.load_pipeline(program)?;
+
+        let buffer = renderer.load_storage_buffer(&bytes(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        {
+            let mut bound = renderer.bind_pipeline(pipeline)?;
+            bound.bind_uniform("scale", 2.0)?;
+            bound.bind_storage_buffer("values", buffer)?;
+            assert!(bound.bind_storage_buffer("missing", buffer).is_err());
+
+            // only the first two work groups run, so the last two are unchanged
+            bound.dispatch(2, 1, 1);
+        }
+
+        let data = renderer.read_storage_buffer(buffer)?;
+        let values: Vec<_> = data
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, [2.0, 4.0, 6.0, 8.0, 5.0, 6.0]);
+        assert_eq!(renderer.stats().dispatches, 1);
+
+        // compute shaders cannot be mixed with other stages
+        let program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::vec(&[0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()]),
+                );
+            });
+            ctx.compute([1, 1, 1], |_| ());
+        });
+        assert!(renderer.load_pipeline(program).is_err());
+
+        // storage buffer elements must have the same layout in every backend
+        let mut program = Program::new(|ctx| {
+            ctx.storage_buffer("values", &Type::Vec3);
+            ctx.compute([1, 1, 1], |_| ());
+        });
+        assert!(program.ok().is_err());
+
+        // invocation ids are only available to compute shaders
+        let program = Program::new(|ctx| {
+            ctx.vertex(|ctx| {
+                let positions = ctx.uniform("positions", Type::Array(&Type::Vec4, 2));
+                let id = ctx.get_builtin(BuiltinVariable::GlobalInvocationX);
+                ctx.set_builtin(
+                    BuiltinVariable::VertexPosition,
+                    Expression::index(positions, id),
+                );
+            });
+        });
+        assert!(renderer.load_pipeline(program).is_err());
+
+        renderer.unload_storage_buffer(buffer)
+    }
+}
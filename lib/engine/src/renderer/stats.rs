@@ -0,0 +1,76 @@
+use std::fmt;
+
+use super::DrawingMode;
+
+/// Counts of the work submitted to a renderer, reset at the start of each
+/// frame by the main loop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RendererStats {
+    pub draw_calls: u64,
+
+    /// Vertices submitted by draw calls, including every instance
+    pub vertices: u64,
+
+    /// Triangles submitted by draw calls, including every instance
+    pub triangles: u64,
+
+    pub pipeline_binds: u64,
+
+    /// Compute shader dispatches
+    pub dispatches: u64,
+
+    /// Textures bound to pipelines
+    pub texture_binds: u64,
+
+    /// State changes that the backend skipped as the state was already set
+    pub skipped_state_changes: u64,
+
+    /// Bytes of texture and buffer data uploaded
+    pub bytes_uploaded: u64,
+}
+
+impl RendererStats {
+    /// Count a draw call
+    pub(crate) fn draw(&mut self, mode: DrawingMode, count: u64, instances: u64) {
+        self.draw_indirect(mode, std::iter::once((count, instances)));
+    }
+
+    /// Count a single draw call that makes several draws, each given as its
+    /// vertex count and number of instances
+    pub(crate) fn draw_indirect(
+        &mut self,
+        mode: DrawingMode,
+        draws: impl Iterator<Item = (u64, u64)>,
+    ) {
+        self.draw_calls += 1;
+
+        for (count, instances) in draws {
+            let triangles = match mode {
+                DrawingMode::Triangles => count / 3,
+                DrawingMode::TriangleStrip | DrawingMode::TriangleFan => count.saturating_sub(2),
+                _ => 0,
+            };
+
+            self.vertices += count * instances;
+            self.triangles += triangles * instances;
+        }
+    }
+
+    /// Count data uploaded to the gpu
+    pub(crate) fn upload(&mut self, bytes: usize) {
+        self.bytes_uploaded += bytes as u64;
+    }
+}
+
+impl fmt::Display for RendererStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Draw calls: {}", self.draw_calls)?;
+        writeln!(f, "Vertices: {}", self.vertices)?;
+        writeln!(f, "Triangles: {}", self.triangles)?;
+        writeln!(f, "Pipeline binds: {}", self.pipeline_binds)?;
+        writeln!(f, "Dispatches: {}", self.dispatches)?;
+        writeln!(f, "Texture binds: {}", self.texture_binds)?;
+        writeln!(f, "Skipped state changes: {}", self.skipped_state_changes)?;
+        write!(f, "Bytes uploaded: {}", self.bytes_uploaded)
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::VecDeque, time::Duration};
+
+use super::backend::RendererBackend;
+use crate::profiler::ProfileScope;
+
+/// The maximum number of frames waiting for their timestamps to be read back,
+/// if the gpu falls further behind than this the oldest frame is dropped
+const MAX_PENDING_FRAMES: usize = 8;
+
+/// The gpu timings of a frame, read back some frames after it was rendered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuFrame {
+    pub frame: u64,
+    pub scopes: Vec<ProfileScope>,
+}
+
+/// A scope whose timestamps have not been read back yet
+#[derive(Debug)]
+struct PendingScope {
+    name: String,
+    depth: usize,
+
+    /// Timestamp queries written at the start and end of the scope
+    begin: u32,
+    end: Option<u32>,
+}
+
+/// Allocates the timestamp queries used by gpu profiling scopes, reading them
+/// back once the gpu has finished with them rather than waiting for them
+#[derive(Debug, Default)]
+pub(crate) struct GpuTimers {
+    /// Timestamp queries that can be reused
+    free: Vec<u32>,
+
+    /// The number of timestamp queries that have been used
+    count: u32,
+
+    /// Scopes recorded this frame
+    scopes: Vec<PendingScope>,
+
+    /// Indices into `scopes` of the scopes that have not been ended
+    open: Vec<usize>,
+
+    /// Frames waiting for their timestamps, oldest first
+    pending: VecDeque<(u64, Vec<PendingScope>)>,
+}
+
+impl GpuTimers {
+    fn query(&mut self) -> u32 {
+        self.free.pop().unwrap_or_else(|| {
+            self.count += 1;
+            self.count - 1
+        })
+    }
+
+    /// Start a scope, returns the query to write a timestamp to
+    pub fn begin_scope(&mut self, name: &str) -> u32 {
+        let begin = self.query();
+
+        self.open.push(self.scopes.len());
+        self.scopes.push(PendingScope {
+            name: name.to_string(),
+            depth: self.open.len() - 1,
+            begin,
+            end: None,
+        });
+
+        begin
+    }
+
+    /// End the most recent scope, returns the query to write a timestamp to
+    pub fn end_scope(&mut self) -> Option<u32> {
+        debug_assert!(!self.open.is_empty());
+
+        let index = self.open.pop()?;
+        let end = self.query();
+        self.scopes[index].end = Some(end);
+
+        Some(end)
+    }
+
+    /// Finish recording a frame, returns all the earlier frames whose
+    /// timestamps are available
+    pub fn end_frame(&mut self, frame: u64, backend: &mut dyn RendererBackend) -> Vec<GpuFrame> {
+        // end any scopes left open so their queries are still released
+        while !self.open.is_empty() {
+            if let Some(query) = self.end_scope() {
+                backend.timestamp(query);
+            }
+        }
+
+        self.pending
+            .push_back((frame, std::mem::take(&mut self.scopes)));
+
+        if self.pending.len() > MAX_PENDING_FRAMES {
+            if let Some((_, scopes)) = self.pending.pop_front() {
+                self.release(&scopes);
+            }
+        }
+
+        let mut frames = vec![];
+        while let Some((frame, scopes)) = self.pending.front() {
+            let mut times = Vec::with_capacity(scopes.len());
+            for scope in scopes {
+                let begin = backend.timestamp_result(scope.begin);
+                let end = scope.end.and_then(|end| backend.timestamp_result(end));
+
+                match (begin, end) {
+                    (Some(begin), Some(end)) => times.push((begin, end)),
+                    _ => break,
+                }
+            }
+
+            // results become available in order, so later frames will not
+            // be ready either
+            if times.len() < scopes.len() {
+                break;
+            }
+
+            // gpu timestamps are not related to the cpu's clock, so measure
+            // from the start of the first scope
+            let base = times.first().map_or(0, |(begin, _)| *begin);
+            let timings = scopes
+                .iter()
+                .zip(times)
+                .map(|(scope, (begin, end))| ProfileScope {
+                    name: scope.name.clone(),
+                    depth: scope.depth,
+                    start: Duration::from_nanos(begin.saturating_sub(base)),
+                    duration: Duration::from_nanos(end.saturating_sub(begin)),
+                })
+                .collect();
+
+            frames.push(GpuFrame {
+                frame: *frame,
+                scopes: timings,
+            });
+
+            if let Some((_, scopes)) = self.pending.pop_front() {
+                self.release(&scopes);
+            }
+        }
+
+        frames
+    }
+
+    /// Allow a frame's queries to be reused
+    fn release(&mut self, scopes: &[PendingScope]) {
+        for scope in scopes {
+            self.free.push(scope.begin);
+            self.free.extend(scope.end);
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use nalgebra_glm as glm;
+use thiserror::Error;
+
+use super::shader::{Program, Type, Variable};
+use crate::texture::TextureKind;
+
+/// Errors from binding a value to a uniform that does not match the program
+#[derive(Debug, Error)]
+pub enum UniformError {
+    #[error("Pipeline has no uniform named {name}")]
+    UnknownUniform { name: String },
+
+    #[error("Uniform {name} has type {expected}, cannot bind a value of type {got} to it")]
+    WrongType {
+        name: String,
+        expected: Type,
+        got: String,
+    },
+
+    #[error("Uniform {name} is a member of uniform block {block}, it must be set using a uniform buffer")]
+    BlockMember { name: String, block: String },
+
+    #[error("Array uniform {name} has length {len}, cannot bind {got} values to it")]
+    ArrayLength {
+        name: String,
+        len: usize,
+        got: usize,
+    },
+
+    #[error("Uniform {name} is not used by the pipeline's shaders so was optimized out, it cannot be bound")]
+    OptimizedOut { name: String },
+
+    #[error("Pipeline has no uniform block named {name}")]
+    UnknownUniformBlock { name: String },
+
+    #[error("Uniform block {name} requires {required} bytes, the buffer only has {size}")]
+    UniformBufferTooSmall {
+        name: String,
+        required: usize,
+        size: usize,
+    },
+
+    #[error("Pipeline has no storage buffer named {name}")]
+    UnknownStorageBuffer { name: String },
+}
+
+/// A value that can be bound to a uniform, matrices are column major
+#[derive(Debug, Clone, PartialEq)]
+pub enum UniformValue {
+    Float(f32),
+    Int(i32),
+    Vec2(glm::Vec2),
+    Vec3(glm::Vec3),
+    Vec4(glm::Vec4),
+    Mat2(glm::Mat2),
+    Mat3(glm::Mat3),
+    Mat4(glm::Mat4),
+
+    /// An array of values, all of the elements must have the same type and
+    /// cannot be arrays themselves.  Fewer elements than the uniform's length
+    /// can be bound, the remaining elements are left unchanged.
+    Array(Vec<UniformValue>),
+}
+
+impl UniformValue {
+    /// The shader type of a single element of the value
+    pub fn element_type(&self) -> Type {
+        match self {
+            UniformValue::Float(_) => Type::Floating,
+            UniformValue::Int(_) => Type::Integer,
+            UniformValue::Vec2(_) => Type::Vec2,
+            UniformValue::Vec3(_) => Type::Vec3,
+            UniformValue::Vec4(_) => Type::Vec4,
+            UniformValue::Mat2(_) => Type::Mat2,
+            UniformValue::Mat3(_) => Type::Mat3,
+            UniformValue::Mat4(_) => Type::Mat4,
+            UniformValue::Array(values) => values
+                .first()
+                .map(UniformValue::element_type)
+                .unwrap_or(Type::Unknown),
+        }
+    }
+
+    /// The number of elements in the value, 1 if it is not an array
+    pub fn len(&self) -> usize {
+        match self {
+            UniformValue::Array(values) => values.len(),
+            _ => 1,
+        }
+    }
+
+    /// Is this an array with no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get every element of the value as a list of floats, integers are
+    /// converted to floats
+    pub(crate) fn floats(&self) -> Vec<f32> {
+        match self {
+            UniformValue::Float(value) => vec![*value],
+            UniformValue::Int(value) => vec![*value as f32],
+            UniformValue::Vec2(value) => value.as_slice().to_vec(),
+            UniformValue::Vec3(value) => value.as_slice().to_vec(),
+            UniformValue::Vec4(value) => value.as_slice().to_vec(),
+            UniformValue::Mat2(value) => value.as_slice().to_vec(),
+            UniformValue::Mat3(value) => value.as_slice().to_vec(),
+            UniformValue::Mat4(value) => value.as_slice().to_vec(),
+            UniformValue::Array(values) => values.iter().flat_map(|v| v.floats()).collect(),
+        }
+    }
+
+    /// Get every element of the value as a list of integers, non integer
+    /// values are ignored
+    pub(crate) fn ints(&self) -> Vec<i32> {
+        match self {
+            UniformValue::Int(value) => vec![*value],
+            UniformValue::Array(values) => values.iter().flat_map(|v| v.ints()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Get the elements of the value, a non array value is its own element
+    pub(crate) fn elements(&self) -> &[UniformValue] {
+        match self {
+            UniformValue::Array(values) => values,
+            value => std::slice::from_ref(value),
+        }
+    }
+
+    /// A description of the value's type, for error messages
+    fn type_name(&self) -> String {
+        match self {
+            UniformValue::Array(values) => {
+                format!("[{}; {}]", self.element_type(), values.len())
+            }
+            _ => self.element_type().to_string(),
+        }
+    }
+
+    /// Lay out a list of values using the std140 rules, the same layout as a
+    /// uniform block with members of the same types in the same order
+    pub fn std140(values: &[UniformValue]) -> Vec<u8> {
+        let mut data = vec![];
+
+        for value in values {
+            let (align, _) = value.std140_layout();
+            data.resize(round_up(data.len(), align), 0);
+
+            for element in value.elements() {
+                let start = data.len();
+                let ty = element.element_type();
+
+                match element {
+                    UniformValue::Int(value) => data.extend_from_slice(&value.to_ne_bytes()),
+                    UniformValue::Mat2(_) | UniformValue::Mat3(_) | UniformValue::Mat4(_) => {
+                        // each matrix column is padded to the size of a vec4
+                        let rows = match ty {
+                            Type::Matrix(rows, _) => rows,
+                            _ => unreachable!(),
+                        };
+
+                        for column in element.floats().chunks(rows) {
+                            let column_start = data.len();
+                            for value in column {
+                                data.extend_from_slice(&value.to_ne_bytes());
+                            }
+                            data.resize(column_start + 16, 0);
+                        }
+                    }
+                    _ => {
+                        for value in element.floats() {
+                            data.extend_from_slice(&value.to_ne_bytes());
+                        }
+                    }
+                }
+
+                // array elements are padded to the size of a vec4
+                if let UniformValue::Array(_) = value {
+                    data.resize(start + round_up(std140_layout(ty).1, 16), 0);
+                }
+            }
+        }
+
+        data.resize(round_up(data.len(), 16), 0);
+        data
+    }
+
+    /// The alignment and size of the value in a std140 uniform block
+    fn std140_layout(&self) -> (usize, usize) {
+        match self {
+            UniformValue::Array(values) => {
+                let (_, size) = std140_layout(self.element_type());
+                (16, round_up(size, 16) * values.len())
+            }
+            value => std140_layout(value.element_type()),
+        }
+    }
+}
+
+impl From<f32> for UniformValue {
+    fn from(value: f32) -> Self {
+        UniformValue::Float(value)
+    }
+}
+
+impl From<i32> for UniformValue {
+    fn from(value: i32) -> Self {
+        UniformValue::Int(value)
+    }
+}
+
+impl From<glm::Vec2> for UniformValue {
+    fn from(value: glm::Vec2) -> Self {
+        UniformValue::Vec2(value)
+    }
+}
+
+impl From<glm::Vec3> for UniformValue {
+    fn from(value: glm::Vec3) -> Self {
+        UniformValue::Vec3(value)
+    }
+}
+
+impl From<glm::Vec4> for UniformValue {
+    fn from(value: glm::Vec4) -> Self {
+        UniformValue::Vec4(value)
+    }
+}
+
+impl From<glm::Mat2> for UniformValue {
+    fn from(value: glm::Mat2) -> Self {
+        UniformValue::Mat2(value)
+    }
+}
+
+impl From<glm::Mat3> for UniformValue {
+    fn from(value: glm::Mat3) -> Self {
+        UniformValue::Mat3(value)
+    }
+}
+
+impl From<glm::Mat4> for UniformValue {
+    fn from(value: glm::Mat4) -> Self {
+        UniformValue::Mat4(value)
+    }
+}
+
+impl<T: Into<UniformValue>> From<Vec<T>> for UniformValue {
+    fn from(values: Vec<T>) -> Self {
+        UniformValue::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Find a uniform that is not part of a uniform block by name, the uniform
+/// must be used by the program
+fn find_uniform<'a>(
+    program: &'a Program,
+    name: &str,
+) -> Result<(usize, &'a Variable), UniformError> {
+    let (idx, uniform) = program
+        .uniforms()
+        .iter()
+        .enumerate()
+        .find(|(_, uniform)| uniform.name == name)
+        .ok_or_else(|| UniformError::UnknownUniform {
+            name: name.to_string(),
+        })?;
+
+    if let Some(block) = program.uniform_block_of(idx) {
+        return Err(UniformError::BlockMember {
+            name: name.to_string(),
+            block: block.name.clone(),
+        });
+    }
+
+    if !program.uniform_used(idx) {
+        return Err(UniformError::OptimizedOut {
+            name: name.to_string(),
+        });
+    }
+
+    Ok((idx, uniform))
+}
+
+/// Check that a value can be bound to a uniform, returns the index of the
+/// uniform in the program
+pub(crate) fn check_uniform(
+    program: &Program,
+    name: &str,
+    value: &UniformValue,
+) -> Result<usize, UniformError> {
+    let (idx, uniform) = find_uniform(program, name)?;
+
+    let wrong_type = || UniformError::WrongType {
+        name: name.to_string(),
+        expected: uniform.ty,
+        got: value.type_name(),
+    };
+
+    match (uniform.ty, value) {
+        (Type::Array(element, len), UniformValue::Array(values)) => {
+            if values.is_empty() || values.len() > len {
+                return Err(UniformError::ArrayLength {
+                    name: name.to_string(),
+                    len,
+                    got: values.len(),
+                });
+            }
+
+            let matches = values
+                .iter()
+                .all(|value| value.len() == 1 && value.element_type() == *element);
+            if !matches {
+                return Err(wrong_type());
+            }
+        }
+        (_, UniformValue::Array(_)) => return Err(wrong_type()),
+        (ty, value) if ty != value.element_type() => return Err(wrong_type()),
+        _ => (),
+    }
+
+    Ok(idx)
+}
+
+/// Check that a texture of the given kind can be bound to a uniform, returns
+/// the index of the uniform in the program
+pub(crate) fn check_sampler(
+    program: &Program,
+    name: &str,
+    kind: TextureKind,
+) -> Result<usize, UniformError> {
+    let (idx, uniform) = find_uniform(program, name)?;
+
+    let ty = sampler_type(kind);
+    if uniform.ty != ty {
+        return Err(UniformError::WrongType {
+            name: name.to_string(),
+            expected: uniform.ty,
+            got: ty.to_string(),
+        });
+    }
+
+    Ok(idx)
+}
+
+/// The type of sampler used to sample a kind of texture
+pub fn sampler_type(kind: TextureKind) -> Type {
+    match kind {
+        TextureKind::Texture2D => Type::Sampler2D,
+        TextureKind::Cubemap => Type::SamplerCube,
+        TextureKind::Array2D => Type::Sampler2DArray,
+        TextureKind::Texture3D => Type::Sampler3D,
+    }
+}
+
+/// Check that a buffer of the given size can be bound to a uniform block,
+/// returns the index of the block in the program
+pub(crate) fn check_uniform_block(
+    program: &Program,
+    name: &str,
+    size: usize,
+) -> Result<usize, UniformError> {
+    let (idx, block) = program
+        .uniform_blocks()
+        .iter()
+        .enumerate()
+        .find(|(_, block)| block.name == name)
+        .ok_or_else(|| UniformError::UnknownUniformBlock {
+            name: name.to_string(),
+        })?;
+
+    let (_, required) = std140_offsets(block.members.iter().map(|&m| program.uniforms()[m].ty));
+    if size < required {
+        return Err(UniformError::UniformBufferTooSmall {
+            name: name.to_string(),
+            required,
+            size,
+        });
+    }
+
+    Ok(idx)
+}
+
+/// Find the storage buffer a buffer is bound to by name, returns the index of
+/// the storage buffer in the program, which is also its binding point
+pub(crate) fn check_storage_buffer(program: &Program, name: &str) -> Result<usize, UniformError> {
+    program
+        .storage_buffers()
+        .iter()
+        .position(|buffer| buffer.name == name)
+        .ok_or_else(|| UniformError::UnknownStorageBuffer {
+            name: name.to_string(),
+        })
+}
+
+/// The offset of each member of a std140 uniform block with members of the
+/// given types, and the total size of the block in bytes
+pub fn std140_offsets(types: impl Iterator<Item = Type>) -> (Vec<usize>, usize) {
+    let mut offsets = vec![];
+    let mut offset = 0;
+
+    for ty in types {
+        let (align, size) = std140_layout(ty);
+        offset = round_up(offset, align);
+        offsets.push(offset);
+        offset += size;
+    }
+
+    (offsets, round_up(offset, 16))
+}
+
+/// The alignment and size in bytes of a type in a std140 uniform block
+fn std140_layout(ty: Type) -> (usize, usize) {
+    match ty {
+        Type::Floating | Type::Integer | Type::UnsignedInteger | Type::Vector(1) => (4, 4),
+        Type::Vector(2) | Type::IntVector(2) | Type::UIntVector(2) => (8, 8),
+        Type::Vector(n) | Type::IntVector(n) | Type::UIntVector(n) => (16, 4 * n),
+
+        // stored as an array of columns, each column is padded to a vec4
+        Type::Matrix(_, cols) => (16, 16 * cols),
+        Type::Array(element, len) => {
+            let (_, size) = std140_layout(*element);
+            (16, round_up(size, 16) * len)
+        }
+
+        // opaque types cannot be stored in a buffer
+        Type::Sampler2D
+        | Type::SamplerCube
+        | Type::Sampler2DArray
+        | Type::Sampler3D
+        | Type::Buffer(..)
+        | Type::Unknown => (1, 0),
+    }
+}
+
+/// Round a value up to a multiple of an alignment, which must be a power of two
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
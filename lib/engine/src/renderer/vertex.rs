@@ -0,0 +1,104 @@
+use thiserror::Error;
+
+use super::shader::{Program, Type};
+
+/// Errors from giving a pipeline vertex formats that cannot be used
+#[derive(Debug, Error)]
+pub enum VertexFormatError {
+    #[error("Pipeline has {formats} vertex formats, but only {inputs} vertex inputs")]
+    TooManyFormats { inputs: usize, formats: usize },
+
+    #[error("Integer vertex input {name} cannot read {format:?}, it must read integers that are not normalized")]
+    IntegerFormat { name: String, format: VertexFormat },
+}
+
+/// The type of each component of a vertex attribute, as stored in its buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexComponentType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+}
+
+impl VertexComponentType {
+    /// The size of a single component in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            VertexComponentType::I8 | VertexComponentType::U8 => 1,
+            VertexComponentType::I16 | VertexComponentType::U16 => 2,
+            VertexComponentType::I32 | VertexComponentType::U32 | VertexComponentType::F32 => 4,
+        }
+    }
+}
+
+/// How a vertex attribute is read from its buffer.  Integer components read
+/// by float inputs are converted to floats, normalized integers are mapped to
+/// 0..1, or -1..1 for signed types, other integers keep their value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexFormat {
+    pub component: VertexComponentType,
+    pub normalized: bool,
+}
+
+impl VertexFormat {
+    pub fn new(component: VertexComponentType, normalized: bool) -> Self {
+        Self {
+            component,
+            normalized,
+        }
+    }
+
+    /// The format used for inputs without one, f32 for float inputs, u32 for
+    /// unsigned integer inputs and i32 for other integer inputs
+    pub fn default_for(ty: &Type) -> Self {
+        match ty {
+            ty if ty.is_unsigned() => Self::new(VertexComponentType::U32, false),
+            ty if ty.is_integer() => Self::new(VertexComponentType::I32, false),
+            _ => Self::new(VertexComponentType::F32, false),
+        }
+    }
+}
+
+/// Get the format of each of a program's vertex inputs, formats are given in
+/// the same order as the inputs, inputs after the last format given use
+/// [`VertexFormat::default_for`].  Integer inputs can only read integers that
+/// are not normalized.
+pub(crate) fn vertex_formats(
+    program: &Program,
+    formats: &[VertexFormat],
+) -> Result<Vec<VertexFormat>, VertexFormatError> {
+    let inputs = program.vertex_main().map_or(&[][..], |vert| vert.inputs());
+
+    if formats.len() > inputs.len() {
+        return Err(VertexFormatError::TooManyFormats {
+            inputs: inputs.len(),
+            formats: formats.len(),
+        });
+    }
+
+    inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            let format = formats
+                .get(i)
+                .copied()
+                .unwrap_or_else(|| VertexFormat::default_for(&input.ty));
+
+            if input.ty.is_integer()
+                && (format.normalized || format.component == VertexComponentType::F32)
+            {
+                return Err(VertexFormatError::IntegerFormat {
+                    name: input.name.clone(),
+                    format,
+                });
+            }
+
+            Ok(format)
+        })
+        .collect()
+}
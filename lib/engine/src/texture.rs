@@ -1,6 +1,8 @@
+use std::{borrow::Cow, convert::TryInto, io::Cursor};
+
 use crate::resources::{Error as ResourceError, Resources};
 use anyhow::Result;
-use image::GenericImageView;
+use image::{codecs::hdr::HdrDecoder, GenericImageView, ImageFormat};
 use thiserror::Error;
 
 /// Errors representing issues loading and decoding images
@@ -19,6 +21,71 @@ pub enum TextureError {
 
     #[error("Unable to convert encoded image into {ty:?}")]
     BadDecodeFormat { ty: TextureSourceType },
+
+    #[error("A {kind:?} texture requires {expected} images, got {got}")]
+    LayerCount {
+        kind: TextureKind,
+        expected: &'static str,
+        got: usize,
+    },
+
+    #[error("Image {layer} is {got:?} pixels, all images in a texture must be {expected:?}")]
+    LayerSize {
+        layer: usize,
+        expected: (u32, u32),
+        got: (u32, u32),
+    },
+
+    #[error("Image {layer} is {got:?}, all images in a texture must be {expected:?}")]
+    LayerFormat {
+        layer: usize,
+        expected: (TextureSourceFormat, TextureSourceType),
+        got: (TextureSourceFormat, TextureSourceType),
+    },
+
+    #[error("Mipmap level {level} should contain {expected} bytes of pixel data, got {got}")]
+    MipLevelSize {
+        level: u32,
+        expected: usize,
+        got: usize,
+    },
+
+    #[error("Mipmap levels must use the same source type as the base image")]
+    MipLevelType,
+
+    #[error("Texture already has all {levels} of its mipmap levels")]
+    TooManyMipLevels { levels: u32 },
+
+    #[error("Invalid {container} file: {reason}")]
+    InvalidContainer {
+        container: &'static str,
+        reason: &'static str,
+    },
+
+    #[error("Unsupported {container} pixel format {format}")]
+    UnsupportedFormat {
+        container: &'static str,
+        format: String,
+    },
+
+    #[error("KTX2 supercompression scheme {scheme} is not supported")]
+    UnsupportedSupercompression { scheme: u32 },
+
+    #[error("Region {region:?} is outside of the texture")]
+    RegionBounds { region: TextureRegion },
+
+    #[error("Texture region should contain {expected} bytes of pixel data, got {got}")]
+    RegionSize { expected: usize, got: usize },
+
+    #[error("Regions of compressed textures cannot be updated")]
+    CompressedRegion,
+
+    /// OpenEXR decoding errors
+    #[error("Error decoding OpenEXR image: {source}")]
+    Exr {
+        #[from]
+        source: exr::error::Error,
+    },
 }
 
 /// Filtering mode to use when increasing the size of a texture
@@ -66,6 +133,13 @@ impl Default for MinFilter {
     }
 }
 
+impl MinFilter {
+    /// Does the filter sample from mipmap levels other than the base level
+    pub fn uses_mipmaps(&self) -> bool {
+        !matches!(self, MinFilter::Nearest | MinFilter::Linear)
+    }
+}
+
 /// Description of what should be done if sampling outside of [0, 1]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum WrappingMode {
@@ -116,6 +190,18 @@ impl Default for TextureSourceFormat {
     }
 }
 
+impl TextureSourceFormat {
+    /// The number of components in each pixel
+    pub fn components(&self) -> usize {
+        match self {
+            TextureSourceFormat::R => 1,
+            TextureSourceFormat::RG => 2,
+            TextureSourceFormat::RGB | TextureSourceFormat::BGR => 3,
+            TextureSourceFormat::RGBA | TextureSourceFormat::BGRA => 4,
+        }
+    }
+}
+
 /// Description of the type of each component in the provided pixel data.
 /// Values represent the primitive type with the same name
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -126,6 +212,10 @@ pub enum TextureSourceType {
     I16,
     U32,
     I32,
+
+    /// Half precision float, each component is the bits of an IEEE 754
+    /// binary16 value
+    F16,
     F32,
 }
 
@@ -135,6 +225,38 @@ impl Default for TextureSourceType {
     }
 }
 
+impl TextureSourceType {
+    /// The size of a single component in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            TextureSourceType::U8 | TextureSourceType::I8 => 1,
+            TextureSourceType::U16 | TextureSourceType::I16 | TextureSourceType::F16 => 2,
+            TextureSourceType::U32 | TextureSourceType::I32 | TextureSourceType::F32 => 4,
+        }
+    }
+}
+
+/// The shape of a texture, each kind is sampled using a different sampler type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum TextureKind {
+    /// A single 2D image, sampled using (u, v) coordinates
+    #[default]
+    Texture2D,
+
+    /// Six square 2D images, the faces of a cube in the order +x, -x, +y, -y,
+    /// +z, -z.  Sampled using a direction from the center of the cube.
+    Cubemap,
+
+    /// A list of 2D images of the same size, sampled using (u, v, layer)
+    /// coordinates where the layer is not normalised.  Each layer is filtered
+    /// separately.
+    Array2D,
+
+    /// A volume made from a stack of 2D images, sampled using (u, v, w)
+    /// coordinates, filtering happens between the images as well
+    Texture3D,
+}
+
 /// The format of the GPU storage buffer requested
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TextureStorageType {
@@ -159,6 +281,85 @@ pub enum TextureStorageType {
     /// treated as being in the sRGB color space, instead of the default linear
     /// color space
     SRGBA,
+
+    /// Single component red texture of half floats, not clamped to [0, 1]
+    R16F,
+
+    /// Two component red/green texture of half floats
+    RG16F,
+
+    /// Three component red/green/blue texture of half floats
+    RGB16F,
+
+    /// Four component red/green/blue/alpha texture of half floats
+    RGBA16F,
+
+    /// Single component red texture of floats, not clamped to [0, 1]
+    R32F,
+
+    /// Two component red/green texture of floats
+    RG32F,
+
+    /// Three component red/green/blue texture of floats
+    RGB32F,
+
+    /// Four component red/green/blue/alpha texture of floats
+    RGBA32F,
+
+    /// BC1 (DXT1) compressed red/green/blue texture vec3 [0, 1]
+    BC1RGB,
+
+    /// BC1 (DXT1) compressed red/green/blue texture vec3 [0, 1] in the sRGB
+    /// color space
+    BC1SRGB,
+
+    /// BC1 (DXT1) compressed red/green/blue/alpha texture vec4 [0, 1] with 1
+    /// bit alpha
+    BC1RGBA,
+
+    /// BC1 (DXT1) compressed red/green/blue/alpha texture vec4 [0, 1] with 1
+    /// bit alpha in the sRGB color space
+    BC1SRGBA,
+
+    /// BC2 (DXT3) compressed red/green/blue/alpha texture vec4 [0, 1] with 4
+    /// bit alpha
+    BC2RGBA,
+
+    /// BC2 (DXT3) compressed red/green/blue/alpha texture vec4 [0, 1] with 4
+    /// bit alpha in the sRGB color space
+    BC2SRGBA,
+
+    /// BC3 (DXT5) compressed red/green/blue/alpha texture vec4 [0, 1]
+    BC3RGBA,
+
+    /// BC3 (DXT5) compressed red/green/blue/alpha texture vec4 [0, 1] in the
+    /// sRGB color space
+    BC3SRGBA,
+
+    /// BC4 compressed single component red texture, float [0, 1]
+    BC4R,
+
+    /// BC4 compressed single component red texture, float [-1, 1]
+    BC4SignedR,
+
+    /// BC5 compressed two component red/green texture vec2 [0, 1]
+    BC5RG,
+
+    /// BC5 compressed two component red/green texture vec2 [-1, 1]
+    BC5SignedRG,
+
+    /// BC6H compressed red/green/blue texture of positive half floats
+    BC6HUnsignedFloat,
+
+    /// BC6H compressed red/green/blue texture of signed half floats
+    BC6HSignedFloat,
+
+    /// BC7 compressed red/green/blue/alpha texture vec4 [0, 1]
+    BC7RGBA,
+
+    /// BC7 compressed red/green/blue/alpha texture vec4 [0, 1] in the sRGB
+    /// color space
+    BC7SRGBA,
 }
 
 impl Default for TextureStorageType {
@@ -167,6 +368,45 @@ impl Default for TextureStorageType {
     }
 }
 
+impl TextureStorageType {
+    /// Is the texture stored as compressed 4x4 pixel blocks
+    pub fn is_compressed(&self) -> bool {
+        self.block_size().is_some()
+    }
+
+    /// Does the texture store floating point values that are not clamped to
+    /// [0, 1] or [-1, 1]
+    pub fn is_float(&self) -> bool {
+        use TextureStorageType::*;
+
+        matches!(
+            self,
+            R16F | RG16F
+                | RGB16F
+                | RGBA16F
+                | R32F
+                | RG32F
+                | RGB32F
+                | RGBA32F
+                | BC6HUnsignedFloat
+                | BC6HSignedFloat
+        )
+    }
+
+    /// The size in bytes of each 4x4 pixel block of a compressed format
+    pub fn block_size(&self) -> Option<usize> {
+        use TextureStorageType::*;
+
+        match self {
+            R | RG | RGB | SRGB | RGBA | SRGBA => None,
+            R16F | RG16F | RGB16F | RGBA16F | R32F | RG32F | RGB32F | RGBA32F => None,
+            BC1RGB | BC1SRGB | BC1RGBA | BC1SRGBA | BC4R | BC4SignedR => Some(8),
+            BC2RGBA | BC2SRGBA | BC3RGBA | BC3SRGBA | BC5RG | BC5SignedRG | BC6HUnsignedFloat
+            | BC6HSignedFloat | BC7RGBA | BC7SRGBA => Some(16),
+        }
+    }
+}
+
 /// The options related to texture loading
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TextureOptions {
@@ -176,6 +416,9 @@ pub struct TextureOptions {
     /// How the 't' uv coordinate wraps
     pub wrap_t: WrappingMode,
 
+    /// How the 'r' coordinate wraps, only used by cubemaps and 3D textures
+    pub wrap_r: WrappingMode,
+
     /// Minification filtering setting
     pub min_filter: MinFilter,
 
@@ -194,14 +437,182 @@ pub struct TextureOptions {
     /// The pixel height of the image
     pub height: u32,
 
-    /// the format to store the texture as on the GPU
+    /// The number of images in an array or 3D texture, layers are stored one
+    /// after the other in the data provided.  Ignored by 2D textures and
+    /// cubemaps, which always have 6 faces.
+    pub depth: u32,
+
+    /// The shape of the texture
+    pub kind: TextureKind,
+
+    /// Generate a full mipmap chain from the base image when the texture is
+    /// loaded, replacing any mipmap levels provided with the texture.  Mipmaps
+    /// are always generated if the min filter uses them and none were provided.
+    pub generate_mipmaps: bool,
+
+    /// The maximum number of samples taken by anisotropic filtering, 0 or 1
+    /// disables it.  Backends clamp this to the maximum they support, see
+    /// [`crate::renderer::Renderer::max_anisotropy`].
+    pub anisotropy: u32,
+
+    /// the format to store the texture as on the GPU.  If this is a
+    /// compressed format the data provided must already be compressed blocks
+    /// and the source format and type are ignored.
     pub storage: TextureStorageType,
 }
 
+impl TextureOptions {
+    /// get the number of layers in the base level, 6 for cubemaps
+    pub fn layers(&self) -> u32 {
+        match self.kind {
+            TextureKind::Texture2D => 1,
+            TextureKind::Cubemap => 6,
+            TextureKind::Array2D | TextureKind::Texture3D => self.depth,
+        }
+    }
+
+    /// get the width and height in pixels of a mipmap level
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    /// get the number of layers in a mipmap level, only 3D textures have
+    /// fewer layers in smaller levels
+    pub fn level_layers(&self, level: u32) -> u32 {
+        match self.kind {
+            TextureKind::Texture3D => (self.depth >> level).max(1),
+            _ => self.layers(),
+        }
+    }
+
+    /// get the size of a single layer of a mipmap level in bytes, saturating
+    /// at `usize::MAX` if it is too large to address
+    pub fn level_layer_size(&self, level: u32) -> usize {
+        self.checked_level_layer_size(level).unwrap_or(usize::MAX)
+    }
+
+    /// get the size of a single layer of a mipmap level in bytes, None if it
+    /// is too large to address
+    pub fn checked_level_layer_size(&self, level: u32) -> Option<usize> {
+        let (width, height) = self.level_size(level);
+
+        // compressed blocks cover 4x4 pixels, partial blocks are padded
+        if let Some(block_size) = self.storage.block_size() {
+            let blocks_x = (width as usize).div_ceil(4);
+            let blocks_y = (height as usize).div_ceil(4);
+            return blocks_x.checked_mul(blocks_y)?.checked_mul(block_size);
+        }
+
+        (width as usize)
+            .checked_mul(height as usize)?
+            .checked_mul(self.pixel_size())
+    }
+
+    /// get the number of mipmap levels in a full mipmap chain, down to a
+    /// level that is 1 pixel in size
+    pub fn full_mip_levels(&self) -> u32 {
+        let largest = match self.kind {
+            TextureKind::Texture3D => self.width.max(self.height).max(self.depth),
+            _ => self.width.max(self.height),
+        };
+
+        32 - largest.max(1).leading_zeros()
+    }
+
+    /// get the size of a single pixel of source data in bytes
+    pub fn pixel_size(&self) -> usize {
+        self.source_format.components() * self.source_type.size()
+    }
+}
+
+/// A box of pixels within a single mipmap level of a texture, used to update
+/// part of a texture that has already been loaded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureRegion {
+    /// The mipmap level to update, 0 is the base image
+    pub level: u32,
+
+    /// The offset of the region from the start of each row in pixels
+    pub x: u32,
+
+    /// The offset of the region from the first row in pixels
+    pub y: u32,
+
+    /// The first array layer, cubemap face or 3D texture slice to update
+    pub layer: u32,
+
+    /// The width of the region in pixels
+    pub width: u32,
+
+    /// The height of the region in pixels
+    pub height: u32,
+
+    /// The number of layers to update
+    pub layers: u32,
+}
+
+impl TextureRegion {
+    /// A rectangle in the base level of the first layer
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            level: 0,
+            x,
+            y,
+            layer: 0,
+            width,
+            height,
+            layers: 1,
+        }
+    }
+
+    /// Check the region is inside a texture with `levels` mipmap levels and
+    /// that the data is the right size to fill it.  The data must be tightly
+    /// packed using the texture's source format and type, the same as when
+    /// it was loaded.
+    pub fn check(
+        &self,
+        options: &TextureOptions,
+        levels: u32,
+        data: &[u8],
+    ) -> Result<(), TextureError> {
+        if options.storage.is_compressed() {
+            return Err(TextureError::CompressedRegion);
+        }
+
+        let inside = |start: u32, len: u32, max: u32| matches!(start.checked_add(len), Some(end) if end <= max);
+
+        let (width, height) = options.level_size(self.level);
+        if self.level >= levels
+            || !inside(self.x, self.width, width)
+            || !inside(self.y, self.height, height)
+            || !inside(self.layer, self.layers, options.level_layers(self.level))
+        {
+            return Err(TextureError::RegionBounds { region: *self });
+        }
+
+        let expected = self.width as usize
+            * self.height as usize
+            * self.layers as usize
+            * options.pixel_size();
+        if data.len() != expected {
+            return Err(TextureError::RegionSize {
+                expected,
+                got: data.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
 /// An image and settings about how to interpret the data
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Texture {
     image: TextureData,
+
+    /// Prebuilt mipmap levels after the base image, largest first
+    mip_levels: Vec<TextureData>,
+
     config: TextureOptions,
 }
 
@@ -212,6 +623,30 @@ enum TextureData {
     U16(Vec<u16>),
 }
 
+impl TextureData {
+    /// The size of the data in bytes
+    fn byte_len(&self) -> usize {
+        match self {
+            TextureData::U8(a) => a.len(),
+            TextureData::U16(a) => a.len() * 2,
+        }
+    }
+
+    fn ptr(&self) -> *const std::ffi::c_void {
+        match self {
+            TextureData::U8(a) => a.as_ptr() as _,
+            TextureData::U16(a) => a.as_ptr() as _,
+        }
+    }
+
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            TextureData::U8(a) => Cow::Borrowed(a),
+            TextureData::U16(a) => Cow::Owned(a.iter().flat_map(|c| c.to_ne_bytes()).collect()),
+        }
+    }
+}
+
 impl Texture {
     /// Load a named resource image file using the default settings
     pub fn from_res_encoding(res: &Resources, name: &str) -> Result<Self, TextureError> {
@@ -239,11 +674,19 @@ impl Texture {
     /// details of the detection see [`image`]'s file detection.  This will
     /// ignore the [`TextureOptions::width`], [`TextureOptions::height`] and
     /// [`TextureOptions::source_format`] options provided and instead derives
-    /// them from the provided image data.
+    /// them from the provided image data.  Radiance HDR and OpenEXR files are
+    /// loaded using [`Self::from_hdr_config`] and [`Self::from_exr_config`].
     pub fn from_encoding_config(
         data: &[u8],
         mut config: TextureOptions,
     ) -> Result<Self, TextureError> {
+        if data.starts_with(&EXR_MAGIC) {
+            return Self::from_exr_config(data, config);
+        }
+        if let Ok(ImageFormat::Hdr) = image::guess_format(data) {
+            return Self::from_hdr_config(data, config);
+        }
+
         let image = image::load_from_memory(data)?;
 
         config.width = image.width();
@@ -260,7 +703,514 @@ impl Texture {
             }
         };
 
-        Ok(Texture { image, config })
+        Ok(Texture {
+            image,
+            mip_levels: vec![],
+            config,
+        })
+    }
+
+    /// Loads a Radiance HDR image as 32 bit float RGB data.  The width, height,
+    /// source format and source type are derived from the image, if the
+    /// storage format is not a float format, [`TextureStorageType::RGB16F`]
+    /// is used so values above 1 are kept.
+    pub fn from_hdr_config(data: &[u8], mut config: TextureOptions) -> Result<Self, TextureError> {
+        let decoder = HdrDecoder::new(data)?;
+        let metadata = decoder.metadata();
+
+        let mut image = Vec::with_capacity(metadata.width as usize * metadata.height as usize * 12);
+        for pixel in decoder.read_image_hdr()? {
+            for component in pixel.0.iter() {
+                image.extend_from_slice(&component.to_ne_bytes());
+            }
+        }
+
+        config.width = metadata.width;
+        config.height = metadata.height;
+        config.source_format = TextureSourceFormat::RGB;
+        config.source_type = TextureSourceType::F32;
+        if !config.storage.is_float() {
+            config.storage = TextureStorageType::RGB16F;
+        }
+
+        Self::from_raw_owned_config(image, config)
+    }
+
+    /// Loads the first layer of an OpenEXR image as 32 bit float RGBA data,
+    /// alpha is 1 if the image has no alpha channel.  The width, height,
+    /// source format and source type are derived from the image, if the
+    /// storage format is not a float format, [`TextureStorageType::RGBA16F`]
+    /// is used so values above 1 are kept.
+    pub fn from_exr_config(data: &[u8], mut config: TextureOptions) -> Result<Self, TextureError> {
+        use exr::prelude::{ReadChannels, ReadLayers};
+
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .rgba_channels(
+                |size, _| (size.width(), vec![0.0; size.area() * 4]),
+                |(width, pixels): &mut (usize, Vec<f32>),
+                 pos,
+                 (r, g, b, a): (f32, f32, f32, f32)| {
+                    let idx = (pos.y() * *width + pos.x()) * 4;
+                    pixels[idx..idx + 4].copy_from_slice(&[r, g, b, a]);
+                },
+            )
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(data))?;
+
+        let size = image.layer_data.size;
+        let (_, pixels) = image.layer_data.channel_data.pixels;
+
+        config.width = size.width() as _;
+        config.height = size.height() as _;
+        config.source_format = TextureSourceFormat::RGBA;
+        config.source_type = TextureSourceType::F32;
+        if !config.storage.is_float() {
+            config.storage = TextureStorageType::RGBA16F;
+        }
+
+        Self::from_raw_config_f32(&pixels, config)
+    }
+
+    /// Loads the six faces of a cubemap from encoded image files, in the order
+    /// +x, -x, +y, -y, +z, -z.  See [`Self::from_encoding_layers_config`].
+    pub fn from_encoding_cubemap_config(
+        faces: [&[u8]; 6],
+        mut config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        config.kind = TextureKind::Cubemap;
+        Self::from_encoding_layers_config(&faces, config)
+    }
+
+    /// Loads a 2D array texture from encoded image files, one per layer.  See
+    /// [`Self::from_encoding_layers_config`].
+    pub fn from_encoding_array_config(
+        layers: &[&[u8]],
+        mut config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        config.kind = TextureKind::Array2D;
+        Self::from_encoding_layers_config(layers, config)
+    }
+
+    /// Loads a 3D texture from encoded image files, one per slice from w = 0
+    /// to w = 1.  See [`Self::from_encoding_layers_config`].
+    pub fn from_encoding_3d_config(
+        slices: &[&[u8]],
+        mut config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        config.kind = TextureKind::Texture3D;
+        Self::from_encoding_layers_config(slices, config)
+    }
+
+    /// Loads a texture with the kind in [`TextureOptions::kind`] from a list
+    /// of encoded image files that must all be the same size.  The same as
+    /// [`Self::from_encoding_config`], the width, height, depth and source
+    /// format are derived from the images provided.
+    pub fn from_encoding_layers_config(
+        layers: &[&[u8]],
+        mut config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let count_ok = match config.kind {
+            TextureKind::Texture2D => layers.len() == 1,
+            TextureKind::Cubemap => layers.len() == 6,
+            TextureKind::Array2D | TextureKind::Texture3D => !layers.is_empty(),
+        };
+
+        if !count_ok {
+            let expected = match config.kind {
+                TextureKind::Texture2D => "1",
+                TextureKind::Cubemap => "6",
+                TextureKind::Array2D | TextureKind::Texture3D => "at least 1",
+            };
+
+            return Err(TextureError::LayerCount {
+                kind: config.kind,
+                expected,
+                got: layers.len(),
+            });
+        }
+
+        let kind = config.kind;
+        let mut image: Option<TextureData> = None;
+        for (idx, layer) in layers.iter().enumerate() {
+            let layer = Self::from_encoding_config(layer, config)?;
+
+            if idx == 0 {
+                config = layer.config;
+            } else if (layer.width(), layer.height()) != (config.width, config.height) {
+                return Err(TextureError::LayerSize {
+                    layer: idx,
+                    expected: (config.width, config.height),
+                    got: (layer.width(), layer.height()),
+                });
+            }
+
+            let format = (config.source_format, config.source_type);
+            let layer_format = (layer.config.source_format, layer.config.source_type);
+            if layer_format != format {
+                return Err(TextureError::LayerFormat {
+                    layer: idx,
+                    expected: format,
+                    got: layer_format,
+                });
+            }
+
+            image = Some(match (image, layer.image) {
+                (None, b) => b,
+                (Some(TextureData::U8(mut a)), TextureData::U8(b)) => {
+                    a.extend(b);
+                    TextureData::U8(a)
+                }
+                (Some(TextureData::U16(mut a)), TextureData::U16(b)) => {
+                    a.extend(b);
+                    TextureData::U16(a)
+                }
+                (Some(_), _) => {
+                    return Err(TextureError::LayerFormat {
+                        layer: idx,
+                        expected: format,
+                        got: layer_format,
+                    })
+                }
+            });
+        }
+
+        config.kind = kind;
+        config.depth = layers.len() as _;
+
+        Ok(Texture {
+            // there is always at least one layer
+            image: image.unwrap(),
+            mip_levels: vec![],
+            config,
+        })
+    }
+
+    /// Loads a named resource file for each layer of a texture using the
+    /// provided settings.  See [`Self::from_encoding_layers_config`].
+    pub fn from_res_encoding_layers_config(
+        res: &Resources,
+        names: &[&str],
+        config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let data = names
+            .iter()
+            .map(|name| {
+                res.load_bytes(name)
+                    .map_err(|e| TextureError::ResourceLoad {
+                        name: name.to_string(),
+                        source: e,
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let layers: Vec<&[u8]> = data.iter().map(|data| &data[..]).collect();
+        Self::from_encoding_layers_config(&layers, config)
+    }
+
+    /// Loads a named DDS resource file using the provided settings.  See
+    /// [`Self::from_dds_config`].
+    pub fn from_res_dds_config(
+        res: &Resources,
+        name: &str,
+        config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let data = res
+            .load_bytes(name)
+            .map_err(|e| TextureError::ResourceLoad {
+                name: name.to_string(),
+                source: e,
+            })?;
+
+        Self::from_dds_config(&data, config)
+    }
+
+    /// Loads a DDS file containing BC1 to BC7 compressed blocks, the blocks
+    /// are kept compressed when uploaded.  The size, kind, storage format and
+    /// mipmap levels are read from the file, the sampler settings are taken
+    /// from the config provided.
+    pub fn from_dds_config(data: &[u8], mut config: TextureOptions) -> Result<Self, TextureError> {
+        use TextureStorageType::*;
+
+        let invalid = |reason| TextureError::InvalidContainer {
+            container: "DDS",
+            reason,
+        };
+        let unsupported = |format| TextureError::UnsupportedFormat {
+            container: "DDS",
+            format,
+        };
+
+        if data.get(0..4) != Some(b"DDS ") {
+            return Err(invalid("missing magic number"));
+        }
+
+        let header =
+            |offset: usize| read_u32(data, 4 + offset).ok_or_else(|| invalid("truncated header"));
+        if header(0)? != 124 {
+            return Err(invalid("bad header size"));
+        }
+
+        let height = header(8)?;
+        let width = header(12)?;
+        let depth = header(20)?;
+        let levels = header(24)?.max(1);
+        let pixel_flags = header(76)?;
+        let four_cc = header(80)?.to_le_bytes();
+        let caps2 = header(108)?;
+
+        if pixel_flags & DDPF_FOURCC == 0 {
+            return Err(unsupported("without a FourCC code".to_string()));
+        }
+
+        let mut offset = 128;
+        let mut layers = 1;
+        let mut cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+        config.storage = match &four_cc {
+            b"DXT1" => BC1RGBA,
+            b"DXT2" | b"DXT3" => BC2RGBA,
+            b"DXT4" | b"DXT5" => BC3RGBA,
+            b"ATI1" | b"BC4U" => BC4R,
+            b"BC4S" => BC4SignedR,
+            b"ATI2" | b"BC5U" => BC5RG,
+            b"BC5S" => BC5SignedRG,
+            b"DX10" => {
+                let dx10 = |offset: usize| {
+                    read_u32(data, 128 + offset).ok_or_else(|| invalid("truncated DX10 header"))
+                };
+
+                offset += 20;
+                layers = dx10(12)?.max(1);
+                cubemap |= dx10(8)? & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+
+                match dx10(0)? {
+                    70 | 71 => BC1RGBA,
+                    72 => BC1SRGBA,
+                    73 | 74 => BC2RGBA,
+                    75 => BC2SRGBA,
+                    76 | 77 => BC3RGBA,
+                    78 => BC3SRGBA,
+                    79 | 80 => BC4R,
+                    81 => BC4SignedR,
+                    82 | 83 => BC5RG,
+                    84 => BC5SignedRG,
+                    94 | 95 => BC6HUnsignedFloat,
+                    96 => BC6HSignedFloat,
+                    97 | 98 => BC7RGBA,
+                    99 => BC7SRGBA,
+                    format => return Err(unsupported(format!("DXGI format {}", format))),
+                }
+            }
+            _ => {
+                return Err(unsupported(format!(
+                    "FourCC {:?}",
+                    String::from_utf8_lossy(&four_cc)
+                )))
+            }
+        };
+
+        if cubemap && layers > 1 {
+            return Err(invalid("cubemap arrays are not supported"));
+        }
+
+        let (kind, depth) = if cubemap {
+            (TextureKind::Cubemap, 6)
+        } else if caps2 & DDSCAPS2_VOLUME != 0 {
+            (TextureKind::Texture3D, depth.max(1))
+        } else if layers > 1 {
+            (TextureKind::Array2D, layers)
+        } else {
+            (TextureKind::Texture2D, 1)
+        };
+        config.kind = kind;
+        config.depth = depth;
+        config.width = width;
+        config.height = height;
+        config.generate_mipmaps = false;
+
+        if levels > 32 {
+            return Err(invalid("too many mipmap levels"));
+        }
+
+        // DDS files store every mipmap level of a layer before the next layer,
+        // the whole volume of a 3D texture is stored in each level
+        let shape = Texture {
+            image: TextureData::U8(vec![]),
+            mip_levels: vec![],
+            config,
+        };
+        let chunks = match config.kind {
+            TextureKind::Texture3D => 1,
+            _ => shape.layers(),
+        };
+
+        let mut level_data = vec![vec![]; levels as usize];
+        for _ in 0..chunks {
+            for (level, out) in level_data.iter_mut().enumerate() {
+                let level = level as u32;
+                let layers = match config.kind {
+                    TextureKind::Texture3D => shape.level_layers(level) as usize,
+                    _ => 1,
+                };
+                let end = config
+                    .checked_level_layer_size(level)
+                    .and_then(|size| size.checked_mul(layers))
+                    .and_then(|size| size.checked_add(offset))
+                    .ok_or_else(|| invalid("image size is too large"))?;
+
+                let bytes = data
+                    .get(offset..end)
+                    .ok_or_else(|| invalid("truncated image data"))?;
+                out.extend_from_slice(bytes);
+                offset = end;
+            }
+        }
+
+        Self::from_compressed_levels(level_data, config)
+    }
+
+    /// Loads a named KTX2 resource file using the provided settings.  See
+    /// [`Self::from_ktx2_config`].
+    pub fn from_res_ktx2_config(
+        res: &Resources,
+        name: &str,
+        config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let data = res
+            .load_bytes(name)
+            .map_err(|e| TextureError::ResourceLoad {
+                name: name.to_string(),
+                source: e,
+            })?;
+
+        Self::from_ktx2_config(&data, config)
+    }
+
+    /// Loads a KTX2 file containing BC1 to BC7 compressed blocks, the blocks
+    /// are kept compressed when uploaded.  Supercompressed files, including
+    /// Basis Universal files, are not supported.  The size, kind, storage
+    /// format and mipmap levels are read from the file, the sampler settings
+    /// are taken from the config provided.
+    pub fn from_ktx2_config(data: &[u8], mut config: TextureOptions) -> Result<Self, TextureError> {
+        use TextureStorageType::*;
+
+        let invalid = |reason| TextureError::InvalidContainer {
+            container: "KTX2",
+            reason,
+        };
+
+        if data.get(0..12) != Some(&KTX2_IDENTIFIER[..]) {
+            return Err(invalid("missing file identifier"));
+        }
+
+        let header =
+            |offset: usize| read_u32(data, 12 + offset).ok_or_else(|| invalid("truncated header"));
+        let vk_format = header(0)?;
+        let width = header(8)?;
+        let height = header(12)?;
+        let depth = header(16)?;
+        let layers = header(20)?;
+        let faces = header(24)?;
+        let levels = header(28)?.max(1);
+        let scheme = header(32)?;
+
+        if scheme != 0 {
+            return Err(TextureError::UnsupportedSupercompression { scheme });
+        }
+
+        config.storage = match vk_format {
+            131 => BC1RGB,
+            132 => BC1SRGB,
+            133 => BC1RGBA,
+            134 => BC1SRGBA,
+            135 => BC2RGBA,
+            136 => BC2SRGBA,
+            137 => BC3RGBA,
+            138 => BC3SRGBA,
+            139 => BC4R,
+            140 => BC4SignedR,
+            141 => BC5RG,
+            142 => BC5SignedRG,
+            143 => BC6HUnsignedFloat,
+            144 => BC6HSignedFloat,
+            145 => BC7RGBA,
+            146 => BC7SRGBA,
+            format => {
+                return Err(TextureError::UnsupportedFormat {
+                    container: "KTX2",
+                    format: format!("VkFormat {}", format),
+                })
+            }
+        };
+
+        let (kind, depth) = match (faces, layers, depth) {
+            (6, 0, 0) => (TextureKind::Cubemap, 6),
+            (6, _, _) => return Err(invalid("cubemap arrays are not supported")),
+            (1, 0, 0) => (TextureKind::Texture2D, 1),
+            (1, 0, depth) => (TextureKind::Texture3D, depth),
+            (1, layers, 0) => (TextureKind::Array2D, layers),
+            (1, _, _) => return Err(invalid("3D texture arrays are not supported")),
+            _ => return Err(invalid("face count must be 1 or 6")),
+        };
+        config.kind = kind;
+        config.depth = depth;
+        config.width = width;
+        config.height = height.max(1);
+        config.generate_mipmaps = false;
+
+        if levels > 32 {
+            return Err(invalid("too many mipmap levels"));
+        }
+
+        // the level index follows the 80 byte header, each level is stored
+        // with all of its layers and faces
+        let level_data = (0..levels as usize)
+            .map(|level| {
+                let index = 80 + level * 24;
+                let offset =
+                    read_u64(data, index).ok_or_else(|| invalid("truncated level index"))?;
+                let len =
+                    read_u64(data, index + 8).ok_or_else(|| invalid("truncated level index"))?;
+
+                let start = offset as usize;
+                let end = start.checked_add(len as usize);
+                end.and_then(|end| data.get(start..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| invalid("truncated image data"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_compressed_levels(level_data, config)
+    }
+
+    /// Create a compressed texture from the data of each of its mipmap levels,
+    /// checking every level is the size expected
+    fn from_compressed_levels(
+        levels: Vec<Vec<u8>>,
+        config: TextureOptions,
+    ) -> Result<Self, TextureError> {
+        let mut levels = levels.into_iter();
+
+        let texture = Texture {
+            image: TextureData::U8(levels.next().unwrap_or_default()),
+            mip_levels: vec![],
+            config,
+        };
+
+        let expected = texture
+            .level_layer_size(0)
+            .saturating_mul(texture.level_layers(0) as usize);
+        if texture.image.byte_len() != expected {
+            return Err(TextureError::MipLevelSize {
+                level: 0,
+                expected,
+                got: texture.image.byte_len(),
+            });
+        }
+
+        levels.try_fold(texture, Texture::with_mip_level)
     }
 
     /// Loads an image from a slice of raw 8 bit pixel data using the provided settings.
@@ -277,6 +1227,7 @@ impl Texture {
     ) -> Result<Self, TextureError> {
         Ok(Self {
             image: TextureData::U8(data),
+            mip_levels: vec![],
             config,
         })
     }
@@ -295,10 +1246,119 @@ impl Texture {
     ) -> Result<Self, TextureError> {
         Ok(Self {
             image: TextureData::U16(data),
+            mip_levels: vec![],
             config,
         })
     }
 
+    /// Loads an image from a slice of raw 32 bit float pixel data using the
+    /// provided settings, the source type should be
+    /// [`TextureSourceType::F32`].  The data is stored as native endian bytes,
+    /// the same as passing them to [`Self::from_raw_config`].
+    pub fn from_raw_config_f32(data: &[f32], config: TextureOptions) -> Result<Self, TextureError> {
+        Self::from_raw_owned_config(f32_bytes(data), config)
+    }
+
+    /// Add the next mipmap level from raw 8 bit pixel data, using the same
+    /// format as the base image.  Each level is half the size of the previous
+    /// one, rounded down to no less than 1 pixel, and contains every layer.
+    pub fn with_mip_level(self, data: Vec<u8>) -> Result<Self, TextureError> {
+        self.push_mip_level(TextureData::U8(data))
+    }
+
+    /// Add the next mipmap level from raw 16 bit pixel data, see
+    /// [`Self::with_mip_level`]
+    pub fn with_mip_level16(self, data: Vec<u16>) -> Result<Self, TextureError> {
+        self.push_mip_level(TextureData::U16(data))
+    }
+
+    /// Add the next mipmap level from raw 32 bit float pixel data, see
+    /// [`Self::with_mip_level`]
+    pub fn with_mip_level_f32(self, data: &[f32]) -> Result<Self, TextureError> {
+        self.push_mip_level(TextureData::U8(f32_bytes(data)))
+    }
+
+    fn push_mip_level(mut self, data: TextureData) -> Result<Self, TextureError> {
+        let level = self.mip_levels();
+
+        let levels = self.config.full_mip_levels();
+        if level >= levels {
+            return Err(TextureError::TooManyMipLevels { levels });
+        }
+
+        if std::mem::discriminant(&data) != std::mem::discriminant(&self.image) {
+            return Err(TextureError::MipLevelType);
+        }
+
+        let expected = self
+            .level_layer_size(level)
+            .saturating_mul(self.level_layers(level) as usize);
+        if data.byte_len() != expected {
+            return Err(TextureError::MipLevelSize {
+                level,
+                expected,
+                got: data.byte_len(),
+            });
+        }
+
+        self.mip_levels.push(data);
+        Ok(self)
+    }
+
+    /// get the number of mipmap levels provided, including the base image
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels.len() as u32 + 1
+    }
+
+    /// Should backends generate the mipmap chain when loading the texture,
+    /// instead of using the levels provided.  Mipmaps are never generated for
+    /// compressed textures.
+    pub fn generates_mipmaps(&self) -> bool {
+        !self.is_compressed()
+            && (self.config.generate_mipmaps
+                || (self.config.min_filter.uses_mipmaps() && self.mip_levels.is_empty()))
+    }
+
+    /// Is the image data made of compressed blocks, see
+    /// [`TextureStorageType::is_compressed`]
+    pub fn is_compressed(&self) -> bool {
+        self.config.storage.is_compressed()
+    }
+
+    /// get the width and height in pixels of a mipmap level
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        self.config.level_size(level)
+    }
+
+    /// get the number of layers in a mipmap level, only 3D textures have
+    /// fewer layers in smaller levels
+    pub fn level_layers(&self, level: u32) -> u32 {
+        self.config.level_layers(level)
+    }
+
+    /// get the size of a single layer of a mipmap level in bytes, see
+    /// [`TextureOptions::level_layer_size`]
+    pub fn level_layer_size(&self, level: u32) -> usize {
+        self.config.level_layer_size(level)
+    }
+
+    /// get a void pointer to the pixel data of a mipmap level, level 0 is the
+    /// base image
+    pub fn level_ptr(&self, level: u32) -> *const std::ffi::c_void {
+        match level {
+            0 => self.image.ptr(),
+            level => self.mip_levels[level as usize - 1].ptr(),
+        }
+    }
+
+    /// get the pixel data of a mipmap level as native endian bytes
+    pub fn level_bytes(&self, level: u32) -> Cow<'_, [u8]> {
+        match level {
+            0 => self.image.bytes(),
+            level => self.mip_levels[level as usize - 1].bytes(),
+        }
+    }
+
     /// get the width of the image in pixels
     pub fn width(&self) -> u32 {
         self.config.width
@@ -309,12 +1369,33 @@ impl Texture {
         self.config.height
     }
 
+    /// get the number of layers stored in the image data, 6 for cubemaps
+    pub fn layers(&self) -> u32 {
+        self.config.layers()
+    }
+
+    /// get the size of a single layer of the image data in bytes
+    pub fn layer_size(&self) -> usize {
+        self.level_layer_size(0)
+    }
+
     /// get a void pointer to the image data for loading into graphics apis
     pub fn img_ptr(&self) -> *const std::ffi::c_void {
-        match &self.image {
-            TextureData::U8(a) => a.as_ptr() as _,
-            TextureData::U16(a) => a.as_ptr() as _,
-        }
+        self.image.ptr()
+    }
+
+    /// get the image data as native endian bytes, for backends that interpret
+    /// the pixel data themselves instead of passing it to a graphics api
+    pub fn bytes(&self) -> Cow<'_, [u8]> {
+        self.image.bytes()
+    }
+
+    /// get the total size in bytes of the image data, including all the mipmap
+    /// levels provided
+    pub fn data_size(&self) -> usize {
+        (0..self.mip_levels())
+            .map(|level| self.level_layer_size(level) * self.level_layers(level) as usize)
+            .sum()
     }
 
     /// get the configuration settings used when creating the image
@@ -322,3 +1403,220 @@ impl Texture {
         self.config
     }
 }
+
+/// DDS pixel format flag set when the format is described by a FourCC code
+const DDPF_FOURCC: u32 = 0x4;
+
+/// DDS caps2 flag set for cubemaps
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+
+/// DDS caps2 flag set for volume textures
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+/// DDS DX10 header flag set for cubemaps
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// The first 12 bytes of every KTX2 file
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// The first 4 bytes of every OpenEXR file
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+
+fn f32_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|value| value.to_ne_bytes()).collect()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// build a DDS file with a FourCC pixel format, followed by a DX10
+    /// header if one is provided
+    fn dds(
+        four_cc: &[u8; 4],
+        (width, height, depth, levels): (u32, u32, u32, u32),
+        caps2: u32,
+        dx10: Option<[u32; 5]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut header = [0u32; 31];
+        header[0] = 124;
+        header[2] = height;
+        header[3] = width;
+        header[5] = depth;
+        header[6] = levels;
+        header[19] = DDPF_FOURCC;
+        header[20] = u32::from_le_bytes(*four_cc);
+        header[27] = caps2;
+
+        let mut file = b"DDS ".to_vec();
+        file.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+        file.extend(dx10.iter().flatten().flat_map(|v| v.to_le_bytes()));
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn invalid_reason(result: Result<Texture, TextureError>) -> &'static str {
+        match result {
+            Err(TextureError::InvalidContainer { reason, .. }) => reason,
+            other => panic!("expected an invalid container, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dds_truncated() {
+        let config = TextureOptions::default();
+        let file = dds(b"DXT1", (8, 8, 0, 2), 0, None, &[0; 40]);
+
+        let texture = Texture::from_dds_config(&file, config).unwrap();
+        assert_eq!(texture.config().storage, TextureStorageType::BC1RGBA);
+        assert_eq!(texture.mip_levels(), 2);
+        assert_eq!(texture.level_bytes(0).len(), 32);
+        assert_eq!(texture.level_bytes(1).len(), 8);
+
+        let reason = invalid_reason(Texture::from_dds_config(&file[..100], config));
+        assert_eq!(reason, "truncated header");
+
+        let reason = invalid_reason(Texture::from_dds_config(&file[..file.len() - 1], config));
+        assert_eq!(reason, "truncated image data");
+
+        let file = dds(b"DX10", (8, 8, 0, 1), 0, None, &[0; 8]);
+        let reason = invalid_reason(Texture::from_dds_config(&file, config));
+        assert_eq!(reason, "truncated DX10 header");
+
+        let reason = invalid_reason(Texture::from_dds_config(b"DDX ", config));
+        assert_eq!(reason, "missing magic number");
+    }
+
+    #[test]
+    fn test_dds_size_overflow() {
+        let config = TextureOptions::default();
+
+        // 2^30 * 2^30 blocks of 16 bytes does not fit in a usize
+        let file = dds(b"DXT5", (u32::MAX, u32::MAX, 0, 1), 0, None, &[]);
+        let reason = invalid_reason(Texture::from_dds_config(&file, config));
+        assert_eq!(reason, "image size is too large");
+
+        let config = TextureOptions {
+            width: u32::MAX,
+            height: u32::MAX,
+            ..Default::default()
+        };
+        assert_eq!(config.checked_level_layer_size(0), None);
+        assert_eq!(config.level_layer_size(0), usize::MAX);
+    }
+
+    #[test]
+    fn test_dds_layers() {
+        let config = TextureOptions::default();
+
+        let file = dds(b"DXT1", (4, 4, 0, 1), DDSCAPS2_CUBEMAP, None, &[0; 48]);
+        let texture = Texture::from_dds_config(&file, config).unwrap();
+        assert_eq!(texture.config().kind, TextureKind::Cubemap);
+        assert_eq!(texture.layers(), 6);
+        assert_eq!(texture.data_size(), 48);
+
+        let file = dds(b"DXT1", (4, 4, 3, 1), DDSCAPS2_VOLUME, None, &[0; 24]);
+        let texture = Texture::from_dds_config(&file, config).unwrap();
+        assert_eq!(texture.config().kind, TextureKind::Texture3D);
+        assert_eq!(texture.layers(), 3);
+
+        let dx10 = [71, 3, 0, 3, 0];
+        let file = dds(b"DX10", (4, 4, 0, 1), 0, Some(dx10), &[0; 24]);
+        let texture = Texture::from_dds_config(&file, config).unwrap();
+        assert_eq!(texture.config().kind, TextureKind::Array2D);
+        assert_eq!(texture.layers(), 3);
+
+        let dx10 = [71, 3, DDS_RESOURCE_MISC_TEXTURECUBE, 1, 0];
+        let file = dds(b"DX10", (4, 4, 0, 1), 0, Some(dx10), &[0; 48]);
+        let texture = Texture::from_dds_config(&file, config).unwrap();
+        assert_eq!(texture.config().kind, TextureKind::Cubemap);
+
+        let dx10 = [71, 3, DDS_RESOURCE_MISC_TEXTURECUBE, 2, 0];
+        let file = dds(b"DX10", (4, 4, 0, 1), 0, Some(dx10), &[0; 96]);
+        let reason = invalid_reason(Texture::from_dds_config(&file, config));
+        assert_eq!(reason, "cubemap arrays are not supported");
+    }
+
+    #[test]
+    fn test_exr() {
+        use exr::prelude::{Image, SpecificChannels, WritableImage};
+
+        let pixel = |x: usize, y: usize| (x as f32 * 2.5, y as f32, -0.5, 0.25);
+        let image = Image::from_channels(
+            (2, 2),
+            SpecificChannels::rgba(|pos: exr::prelude::Vec2<usize>| pixel(pos.x(), pos.y())),
+        );
+        let mut file = vec![];
+        image.write().to_buffered(Cursor::new(&mut file)).unwrap();
+
+        let texture = Texture::from_encoding_config(&file, TextureOptions::default()).unwrap();
+        let config = texture.config();
+        assert_eq!((config.width, config.height), (2, 2));
+        assert_eq!(config.source_format, TextureSourceFormat::RGBA);
+        assert_eq!(config.source_type, TextureSourceType::F32);
+        assert_eq!(config.storage, TextureStorageType::RGBA16F);
+
+        let expected: Vec<f32> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .flat_map(|&(x, y)| {
+                let (r, g, b, a) = pixel(x, y);
+                [r, g, b, a]
+            })
+            .collect();
+        assert_eq!(texture.bytes(), f32_bytes(&expected));
+
+        // float storage formats are kept
+        let config = TextureOptions {
+            storage: TextureStorageType::RGBA32F,
+            ..Default::default()
+        };
+        let texture = Texture::from_exr_config(&file, config).unwrap();
+        assert_eq!(texture.config().storage, TextureStorageType::RGBA32F);
+    }
+
+    #[test]
+    fn test_mip_level_size() {
+        let config = TextureOptions {
+            width: 4,
+            height: 2,
+            ..Default::default()
+        };
+        let texture = Texture::from_raw_config(&[0; 32], config).unwrap();
+
+        match texture.clone().with_mip_level(vec![0; 4]) {
+            Err(TextureError::MipLevelSize {
+                level: 1,
+                expected: 8,
+                got: 4,
+            }) => {}
+            other => panic!("expected a mip level size error, got {:?}", other),
+        }
+
+        let texture = texture.with_mip_level(vec![0; 8]).unwrap();
+        let texture = texture.with_mip_level(vec![0; 4]).unwrap();
+        assert_eq!(texture.mip_levels(), 3);
+        assert!(matches!(
+            texture.with_mip_level(vec![0; 4]),
+            Err(TextureError::TooManyMipLevels { levels: 3 })
+        ));
+
+        // compressed levels are padded to whole 4x4 blocks
+        let file = dds(b"DXT5", (8, 8, 0, 2), 0, None, &[0; 80]);
+        let texture = Texture::from_dds_config(&file, TextureOptions::default()).unwrap();
+        assert_eq!(texture.level_layer_size(1), 16);
+        assert_eq!(texture.level_layer_size(2), 16);
+    }
+}
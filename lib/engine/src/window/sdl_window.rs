@@ -75,6 +75,10 @@ impl Window for SdlWindow {
         gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
         gl_attr.set_context_version(config.gl_version.0, config.gl_version.1);
 
+        // the default framebuffer always has a depth and stencil buffer
+        gl_attr.set_depth_size(24);
+        gl_attr.set_stencil_size(8);
+
         if config.debug {
             gl_attr.set_context_flags().debug().set();
         }
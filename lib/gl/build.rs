@@ -11,7 +11,12 @@ fn main() {
         (4, 5),
         Profile::Core,
         Fallbacks::All,
-        ["GL_NV_command_list"],
+        [
+            "GL_NV_command_list",
+            "GL_EXT_texture_filter_anisotropic",
+            "GL_EXT_texture_compression_s3tc",
+            "GL_EXT_texture_sRGB",
+        ],
     );
 
     if env::var("CARGO_FEATURE_DEBUG").is_ok() {